@@ -1,17 +1,21 @@
-use std::hash::Hash;
-use std::{fmt::Debug, rc::Rc};
+use std::hash::{Hash, Hasher};
+use std::{fmt::Debug, sync::Arc};
+
+use crate::{digest::Digest, source_map::FileId, symbol::Symbol};
+
+pub mod build;
 
 /// File definition, it contains all the statements,
 /// the module name, and a base location for it as anchor
 /// for the statements.
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct File {
     pub name: String,
     pub expression: Term,
     pub location: Location,
 }
 
-impl<T: Element> Element for Rc<T> {
+impl<T: Element> Element for Arc<T> {
     fn location(&self) -> &Location {
         self.as_ref().location()
     }
@@ -23,11 +27,14 @@ impl<T: Element> Element for Box<T> {
     }
 }
 
-#[derive(Default, Hash, PartialEq, Eq, Clone, serde::Deserialize)]
+#[derive(Default, Hash, PartialEq, Eq, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Location {
     pub start: usize,
     pub end: usize,
-    pub filename: String,
+
+    /// The file the element is in, interned as the AST repeats it for
+    /// every element.
+    pub filename: FileId,
 }
 
 impl Location {
@@ -36,7 +43,7 @@ impl Location {
         Self {
             start,
             end,
-            filename: filename.into(),
+            filename: FileId::new(filename),
         }
     }
 }
@@ -52,10 +59,50 @@ pub trait Element {
     fn location(&self) -> &Location;
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Var {
-    pub text: String,
+    pub text: Symbol,
     pub location: Location,
+
+    /// Where the variable lives in the frame of the enclosing function,
+    /// filled in by [`resolve`](crate::resolve::resolve). Variables left
+    /// unresolved, like the ones outside of functions, are looked up by
+    /// name.
+    #[serde(skip)]
+    pub slot: Option<Slot>,
+}
+
+/// Where a resolved variable is read from, relative to the function it
+/// is referred to in.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Slot {
+    /// A parameter or `let` binding of the function, by its index in the
+    /// frame.
+    Local(usize),
+
+    /// A value captured by the function when its closure was created.
+    Captured(usize),
+
+    /// The function itself, referred to by the name its `let` binds.
+    Current,
+}
+
+/// The frame of a resolved function.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Layout {
+    /// Number of local slots of a frame, parameters included.
+    pub locals: usize,
+
+    /// Where each captured value is taken from when a closure of the
+    /// function is created, in the frame creating it.
+    pub captures: Vec<Slot>,
+
+    /// The names of the captured values, in the order of `captures`.
+    pub captured_names: Vec<Symbol>,
+
+    /// Whether the body refers to the function itself, directly or
+    /// through the functions it creates.
+    pub recursive: bool,
 }
 
 impl Element for Var {
@@ -64,24 +111,48 @@ impl Element for Var {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct If {
-    pub condition: Box<Term>,
-    pub then: Box<Term>,
-    pub otherwise: Box<Term>,
+    pub condition: Arc<Term>,
+    pub then: Arc<Term>,
+    pub otherwise: Arc<Term>,
     pub location: Location,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+/// Evaluates terms from left to right, resulting in the value of the last
+/// one, or unit when there are none.
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
+pub struct Seq {
+    pub terms: Vec<Term>,
+    pub location: Location,
+}
+
+/// A branch of a [`Cond`], taken when its condition holds.
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
+pub struct Arm {
+    pub condition: Term,
+    pub then: Term,
+}
+
+/// A chain of conditions checked from top to bottom, resulting in the
+/// branch of the first one holding, or `otherwise` when none does.
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
+pub struct Cond {
+    pub arms: Vec<Arm>,
+    pub otherwise: Arc<Term>,
+    pub location: Location,
+}
+
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Let {
     pub name: Var,
-    pub value: Box<Term>,
-    pub next: Box<Term>,
+    pub value: Arc<Term>,
+    pub next: Arc<Term>,
     pub location: Location,
 }
 
 /// Int is a integer value like `0`, `1`, `2`, etc.
-#[derive(Default, Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Str {
     pub value: String,
 
@@ -95,7 +166,7 @@ impl Element for Str {
     }
 }
 
-#[derive(Default, Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Bool {
     pub value: bool,
     pub location: Location,
@@ -107,8 +178,21 @@ impl Element for Bool {
     }
 }
 
+/// Unit is the value `()`, the result of functions evaluated only for
+/// their side effects, like printing.
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
+pub struct Unit {
+    pub location: Location,
+}
+
+impl Element for Unit {
+    fn location(&self) -> &Location {
+        &self.location
+    }
+}
+
 /// Int is a integer value like `0`, `1`, `2`, etc.
-#[derive(Default, Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Int {
     /// The value of the integer.
     pub value: i64,
@@ -123,8 +207,9 @@ impl Element for Int {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub enum BinaryOp {
+    #[default]
     Add, // Add
     Sub, // Subtract
     Mul, // Multiply
@@ -140,11 +225,11 @@ pub enum BinaryOp {
     Or,  // Or
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Binary {
-    pub lhs: Box<Term>,
+    pub lhs: Arc<Term>,
     pub op: BinaryOp,
-    pub rhs: Box<Term>,
+    pub rhs: Arc<Term>,
     pub location: Location,
 }
 
@@ -154,9 +239,9 @@ impl Element for Binary {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Call {
-    pub callee: Box<Term>,
+    pub callee: Arc<Term>,
     pub arguments: Vec<Term>,
     pub location: Location,
 }
@@ -167,11 +252,45 @@ impl Element for Call {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
 pub struct Function {
-    pub parameters: Vec<Var>,
-    pub value: Box<Term>,
+    pub parameters: Arc<[Var]>,
+    pub value: Arc<Term>,
     pub location: Location,
+
+    /// The names the body refers to besides the parameters, filled in by
+    /// [`free::annotate`](crate::free::annotate). Closures of the function
+    /// capture only these, or every binding in scope when `None`.
+    #[serde(skip)]
+    pub free: Option<Arc<[Symbol]>>,
+
+    /// The frame of the function, filled in by
+    /// [`resolve`](crate::resolve::resolve) along with its variables.
+    #[serde(skip)]
+    pub layout: Option<Arc<Layout>>,
+
+    /// The digest of the body, filled in by
+    /// [`digest::annotate`](crate::digest::annotate).
+    #[serde(skip)]
+    pub digest: Option<Digest>,
+
+    /// Whether the body prints nothing itself, filled in by
+    /// [`purity::annotate`](crate::purity::annotate).
+    #[serde(skip)]
+    pub pure: Option<bool>,
+}
+
+impl Hash for Function {
+    // Hashing the digest in place of the body keeps hashing a term linear
+    // in its size, however deep its functions nest.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parameters.hash(state);
+        match self.digest {
+            Some(digest) => digest.hash(state),
+            None => self.value.hash(state),
+        }
+        self.location.hash(state);
+    }
 }
 
 impl Element for Function {
@@ -180,9 +299,9 @@ impl Element for Function {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Print {
-    pub value: Box<Term>,
+    pub value: Arc<Term>,
     pub location: Location,
 }
 
@@ -192,9 +311,9 @@ impl Element for Print {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct First {
-    pub value: Box<Term>,
+    pub value: Arc<Term>,
     pub location: Location,
 }
 
@@ -204,9 +323,9 @@ impl Element for First {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Second {
-    pub value: Box<Term>,
+    pub value: Arc<Term>,
     pub location: Location,
 }
 
@@ -216,10 +335,10 @@ impl Element for Second {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 pub struct Tuple {
-    pub first: Box<Term>,
-    pub second: Box<Term>,
+    pub first: Arc<Term>,
+    pub second: Arc<Term>,
     pub location: Location,
 }
 
@@ -229,7 +348,10 @@ impl Element for Tuple {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, Hash, PartialEq, Eq)]
+/// A term of the program. Terms share their children behind [`Arc`], so
+/// cloning one, like the body of a closure on every call, copies the node
+/// itself and leaves its children where they are.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, Hash, PartialEq, Eq)]
 #[serde(tag = "kind")]
 pub enum Term {
     Int(Int),
@@ -245,6 +367,36 @@ pub enum Term {
     Bool(Bool),
     Tuple(Tuple),
     Var(Var),
+    Unit(Unit),
+    Seq(Seq),
+    Cond(Cond),
+}
+
+/// A unit, the term a term is left as once what it held was taken out of
+/// it, as terms can't be moved out of.
+impl Default for Term {
+    fn default() -> Self {
+        Term::Unit(Unit::default())
+    }
+}
+
+/// Terms are dropped without recursing into their children, which would
+/// overflow the stack for terms nesting as deep as long chains of `let` or
+/// `+` do. The children only this term holds are taken out onto a stack
+/// of their own instead, until none is left holding any.
+///
+/// Matching on a term by value is not allowed as a result: its node is
+/// taken out with [`std::mem::take`] instead, like
+/// `match &mut term { Term::Let(let_) => std::mem::take(let_), .. }`.
+impl Drop for Term {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+
+        while let Some(mut term) = stack.pop() {
+            term.take_children(&mut stack);
+        }
+    }
 }
 
 impl Element for Term {
@@ -263,16 +415,98 @@ impl Element for Term {
             Term::If(arg0) => &arg0.location,
             Term::Bool(arg0) => &arg0.location,
             Term::Tuple(arg0) => arg0.location(),
+            Term::Unit(arg0) => &arg0.location,
+            Term::Seq(arg0) => &arg0.location,
+            Term::Cond(arg0) => &arg0.location,
         }
     }
 }
 
 impl Term {
-    pub fn is_pure(&self) -> bool {
+    /// Moves the children only this term holds onto `stack`, leaving units
+    /// in their place. Children shared with other terms are left, as
+    /// dropping them only lets go of them.
+    fn take_children(&mut self, stack: &mut Vec<Term>) {
+        let mut take = |child: &mut Arc<Term>| {
+            if let Some(child) = Arc::get_mut(child) {
+                stack.push(std::mem::take(child));
+            }
+        };
+
         match self {
-            Term::Function(function) => function.value.is_pure(),
-            Term::Print(_) => false,
-            _term => true,
+            Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Var(_) => {}
+            Term::Function(function) => take(&mut function.value),
+            Term::Let(let_) => {
+                take(&mut let_.value);
+                take(&mut let_.next);
+            }
+            Term::Binary(binary) => {
+                take(&mut binary.lhs);
+                take(&mut binary.rhs);
+            }
+            Term::If(if_) => {
+                take(&mut if_.condition);
+                take(&mut if_.then);
+                take(&mut if_.otherwise);
+            }
+            Term::Print(print) => take(&mut print.value),
+            Term::First(first) => take(&mut first.value),
+            Term::Second(second) => take(&mut second.value),
+            Term::Tuple(tuple) => {
+                take(&mut tuple.first);
+                take(&mut tuple.second);
+            }
+            Term::Call(call) => {
+                take(&mut call.callee);
+                stack.append(&mut call.arguments);
+            }
+            Term::Seq(seq) => stack.append(&mut seq.terms),
+            Term::Cond(cond) => {
+                take(&mut cond.otherwise);
+                for arm in std::mem::take(&mut cond.arms) {
+                    let Arm { condition, then } = arm;
+                    stack.extend([condition, then]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Binary, File, Int, Term};
+
+    #[test]
+    fn files_are_written_back_as_they_were_read() {
+        for name in ["fib", "sum", "combination", "hello-world"] {
+            let json = std::fs::read_to_string(format!("examples/{name}.json")).unwrap();
+            let file: File = serde_json::from_str(&json).unwrap();
+
+            let written = serde_json::to_value(&file).unwrap();
+            let read = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+            assert_eq!(written, read, "{name} was written differently");
+        }
+    }
+
+    #[test]
+    fn deep_terms_are_dropped_without_recursion() {
+        let mut term = Term::Int(Int::default());
+        for _ in 0..1_000_000 {
+            term = Term::Binary(Binary {
+                lhs: Arc::new(term),
+                rhs: Arc::new(Term::Int(Int::default())),
+                ..Binary::default()
+            });
         }
+
+        // A small stack overflows well before a million frames.
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || drop(term))
+            .unwrap()
+            .join()
+            .unwrap();
     }
 }
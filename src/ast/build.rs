@@ -0,0 +1,264 @@
+//! Functions building terms, for writing programs in Rust rather than in
+//! JSON or struct literals:
+//!
+//! ```
+//! use lipsum::ast::build::{function, int, let_, print, var};
+//!
+//! let program = let_("double", function(["x"], var("x") * int(2)))
+//!     .in_(print(var("double").call([int(21)])));
+//!
+//! assert_eq!(
+//!     program.to_source(),
+//!     "let double = fn (x) => {\n  x * 2\n};\nprint(double(21))"
+//! );
+//! ```
+//!
+//! Terms built here are all at the default [`Location`], as they aren't
+//! anywhere in a file.
+
+use std::{
+    ops::{Add, Div, Mul, Rem, Sub},
+    sync::Arc,
+};
+
+use super::{
+    Arm, Binary, BinaryOp, Bool, Call, Cond, First, Function, If, Int, Let, Location, Print,
+    Second, Seq, Str, Term, Tuple, Unit, Var,
+};
+
+fn name(text: &str) -> Var {
+    Var {
+        text: text.into(),
+        location: Location::default(),
+        slot: None,
+    }
+}
+
+pub fn int(value: i64) -> Term {
+    Term::Int(Int {
+        value,
+        location: Location::default(),
+    })
+}
+
+pub fn str(value: &str) -> Term {
+    Term::Str(Str {
+        value: value.to_string(),
+        location: Location::default(),
+    })
+}
+
+pub fn bool(value: bool) -> Term {
+    Term::Bool(Bool {
+        value,
+        location: Location::default(),
+    })
+}
+
+pub fn unit() -> Term {
+    Term::Unit(Unit {
+        location: Location::default(),
+    })
+}
+
+pub fn var(text: &str) -> Term {
+    Term::Var(name(text))
+}
+
+/// A function taking `parameters` and evaluating to `body`.
+pub fn function<'a>(parameters: impl IntoIterator<Item = &'a str>, body: Term) -> Term {
+    Term::Function(Function {
+        parameters: parameters.into_iter().map(name).collect(),
+        value: Arc::new(body),
+        location: Location::default(),
+        free: None,
+        layout: None,
+        digest: None,
+        pure: None,
+    })
+}
+
+/// Binds `name` to `value`, in the term given to [`LetBuilder::in_`].
+pub fn let_(name: &str, value: Term) -> LetBuilder {
+    LetBuilder {
+        name: self::name(name),
+        value,
+    }
+}
+
+/// A `let` waiting for the term its name is bound in.
+#[derive(Debug, Clone)]
+pub struct LetBuilder {
+    name: Var,
+    value: Term,
+}
+
+impl LetBuilder {
+    pub fn in_(self, next: Term) -> Term {
+        Term::Let(Let {
+            name: self.name,
+            value: Arc::new(self.value),
+            next: Arc::new(next),
+            location: Location::default(),
+        })
+    }
+}
+
+pub fn if_(condition: Term, then: Term, otherwise: Term) -> Term {
+    Term::If(If {
+        condition: Arc::new(condition),
+        then: Arc::new(then),
+        otherwise: Arc::new(otherwise),
+        location: Location::default(),
+    })
+}
+
+/// The `then` of the first arm whose condition holds, or `otherwise`.
+pub fn cond(arms: impl IntoIterator<Item = (Term, Term)>, otherwise: Term) -> Term {
+    Term::Cond(Cond {
+        arms: arms
+            .into_iter()
+            .map(|(condition, then)| Arm { condition, then })
+            .collect(),
+        otherwise: Arc::new(otherwise),
+        location: Location::default(),
+    })
+}
+
+/// Evaluates `terms` in order, to the value of the last one.
+pub fn seq(terms: impl IntoIterator<Item = Term>) -> Term {
+    Term::Seq(Seq {
+        terms: terms.into_iter().collect(),
+        location: Location::default(),
+    })
+}
+
+pub fn tuple(first: Term, second: Term) -> Term {
+    Term::Tuple(Tuple {
+        first: Arc::new(first),
+        second: Arc::new(second),
+        location: Location::default(),
+    })
+}
+
+pub fn first(value: Term) -> Term {
+    Term::First(First {
+        value: Arc::new(value),
+        location: Location::default(),
+    })
+}
+
+pub fn second(value: Term) -> Term {
+    Term::Second(Second {
+        value: Arc::new(value),
+        location: Location::default(),
+    })
+}
+
+pub fn print(value: Term) -> Term {
+    Term::Print(Print {
+        value: Arc::new(value),
+        location: Location::default(),
+    })
+}
+
+pub fn binary(lhs: Term, op: BinaryOp, rhs: Term) -> Term {
+    Term::Binary(Binary {
+        lhs: Arc::new(lhs),
+        op,
+        rhs: Arc::new(rhs),
+        location: Location::default(),
+    })
+}
+
+pub fn eq(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Eq, rhs)
+}
+
+pub fn neq(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Neq, rhs)
+}
+
+pub fn lt(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Lt, rhs)
+}
+
+pub fn gt(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Gt, rhs)
+}
+
+pub fn lte(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Lte, rhs)
+}
+
+pub fn gte(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Gte, rhs)
+}
+
+pub fn and(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::And, rhs)
+}
+
+pub fn or(lhs: Term, rhs: Term) -> Term {
+    binary(lhs, BinaryOp::Or, rhs)
+}
+
+impl Term {
+    /// Calls the term with `arguments`.
+    pub fn call(self, arguments: impl IntoIterator<Item = Term>) -> Term {
+        Term::Call(Call {
+            callee: Arc::new(self),
+            arguments: arguments.into_iter().collect(),
+            location: Location::default(),
+        })
+    }
+}
+
+macro_rules! arithmetic {
+    ($($trait:ident, $method:ident, $op:ident;)*) => {
+        $(
+            impl $trait for Term {
+                type Output = Term;
+
+                fn $method(self, rhs: Term) -> Term {
+                    binary(self, BinaryOp::$op, rhs)
+                }
+            }
+        )*
+    };
+}
+
+arithmetic! {
+    Add, add, Add;
+    Sub, sub, Sub;
+    Mul, mul, Mul;
+    Div, div, Div;
+    Rem, rem, Rem;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{function, if_, int, let_, lt, print, var};
+    use crate::interpreter::{eval, Capture, Context, State};
+
+    #[test]
+    fn built_programs_run() {
+        // let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; print(fib(10))
+        let fib = |n| var("fib").call([var("n") - int(n)]);
+        let program = let_(
+            "fib",
+            function(["n"], if_(lt(var("n"), int(2)), var("n"), fib(1) + fib(2))),
+        )
+        .in_(print(var("fib").call([int(10)])));
+
+        let mut capture = Capture::new();
+        eval(
+            program,
+            &mut Context::new(),
+            &mut State::new(),
+            &mut capture,
+        )
+        .unwrap();
+        assert_eq!(capture.output(), "55\n");
+    }
+}
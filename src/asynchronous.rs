@@ -0,0 +1,197 @@
+use std::{future::Future, io, panic, thread};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    ast::File,
+    embed::Builder,
+    interpreter::{Cancellation, Capture, Printer, RuntimeError, Value},
+};
+
+/// Prints values for programs run with [`Builder::run_async`], like a
+/// [`Printer`] that awaits rather than blocks while writing.
+pub trait AsyncPrinter: Send {
+    fn print(&mut self, value: Value) -> impl Future<Output = io::Result<Value>> + Send;
+
+    /// Writes out any output still buffered by the printer.
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+impl<P: AsyncPrinter + ?Sized> AsyncPrinter for &mut P {
+    fn print(&mut self, value: Value) -> impl Future<Output = io::Result<Value>> + Send {
+        (**self).print(value)
+    }
+
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + Send {
+        (**self).flush()
+    }
+}
+
+impl AsyncPrinter for Capture {
+    async fn print(&mut self, value: Value) -> io::Result<Value> {
+        Printer::print(self, value)
+    }
+}
+
+/// A printer writing values to an [`AsyncWrite`], like the standard
+/// output of tokio, each terminated by a `\n` as [`IO`](crate::interpreter::IO)
+/// writes them.
+#[derive(Debug)]
+pub struct AsyncIO<W> {
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncIO<W> {
+    /// Creates a new instance of [`AsyncIO`].
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncPrinter for AsyncIO<W> {
+    async fn print(&mut self, value: Value) -> io::Result<Value> {
+        let text = value.to_string().replace("\r\n", "\n");
+        self.writer
+            .write_all(format!("{text}\n").as_bytes())
+            .await?;
+
+        Ok(value)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+}
+
+/// What the evaluating thread asks of the [`AsyncPrinter`], with where to
+/// send the answer back.
+enum Request {
+    Print(Value, oneshot::Sender<io::Result<Value>>),
+    Flush(oneshot::Sender<io::Result<()>>),
+}
+
+/// The printer of the evaluating thread, blocking it until the
+/// [`AsyncPrinter`] is done with each request.
+struct Forward(mpsc::Sender<Request>);
+
+impl Forward {
+    fn ask<T>(&self, request: impl FnOnce(oneshot::Sender<T>) -> Request) -> io::Result<T> {
+        let (answer, answered) = oneshot::channel();
+        let gone = || io::Error::other("the evaluation was dropped");
+
+        self.0.blocking_send(request(answer)).map_err(|_| gone())?;
+        answered.blocking_recv().map_err(|_| gone())
+    }
+}
+
+impl Printer for Forward {
+    fn print(&mut self, value: Value) -> io::Result<Value> {
+        self.ask(|answer| Request::Print(value, answer))?
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ask(Request::Flush)?
+    }
+}
+
+/// Cancels the evaluation when the future running it is dropped before
+/// it ends, so its thread doesn't keep running for no one.
+struct CancelOnDrop(Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+impl<P: Printer> Builder<P> {
+    /// Runs the program of `file` as [`Interpreter::run`](crate::embed::Interpreter::run)
+    /// does, printing with `printer` rather than the printer of the
+    /// builder.
+    ///
+    /// The program is evaluated on a thread of its own, so awaiting it
+    /// never blocks the executor, even while printing. Dropping the future
+    /// cancels the evaluation.
+    pub async fn run_async(
+        self,
+        file: File,
+        mut printer: impl AsyncPrinter,
+    ) -> Result<Value, RuntimeError> {
+        let (requests, mut requested) = mpsc::channel(1);
+        let (finished, result) = oneshot::channel();
+
+        let interpreter = self.printer(Forward(requests)).build();
+        let _cancel = CancelOnDrop(interpreter.state().cancellation.clone());
+
+        let evaluation = thread::spawn(move || {
+            let mut interpreter = interpreter;
+            let result = interpreter.run(file);
+            // Closes the requests before sending the result.
+            drop(interpreter);
+            let _ = finished.send(result);
+        });
+
+        while let Some(request) = requested.recv().await {
+            // Nobody waits for the answer once the evaluating thread is gone.
+            match request {
+                Request::Print(value, answer) => {
+                    let _ = answer.send(printer.print(value).await);
+                }
+                Request::Flush(answer) => {
+                    let _ = answer.send(printer.flush().await);
+                }
+            }
+        }
+
+        match result.await {
+            Ok(result) => result,
+            Err(_) => match evaluation.join() {
+                Err(panic) => panic::resume_unwind(panic),
+                Ok(()) => unreachable!("the evaluation sends its result before ending"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncIO;
+    use crate::{ast::File, embed::Interpreter, interpreter::RuntimeErrorKind};
+
+    fn file(name: &str) -> File {
+        let json = std::fs::read_to_string(format!("examples/{name}.json")).unwrap();
+
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn programs_print_through_async_printers() {
+        let mut output = AsyncIO::new(Vec::new());
+
+        let value = crate::eval_async(file("fib"), &mut output).await.unwrap();
+        assert_eq!(value.to_string(), "55");
+        assert_eq!(output.into_inner(), b"55\n");
+    }
+
+    #[tokio::test]
+    async fn async_runs_are_configured_by_the_builder() {
+        let mut output = AsyncIO::new(Vec::new());
+
+        let error = Interpreter::builder()
+            .fuel(10)
+            .run_async(file("fib"), &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfFuel);
+        assert!(output.into_inner().is_empty());
+    }
+}
@@ -1,13 +1,134 @@
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
 use crate::{
     ast::{Binary, BinaryOp, Element, Location},
-    interpreter::{RuntimeError, Value},
+    interpreter::{RuntimeError, RuntimeErrorKind, Type, Value},
+    text::Text,
 };
 
-fn invalid_comparison(l_value: &Value, r_value: &Value, location: &Location) -> RuntimeError {
+/// How integer division rounds when the result isn't exact, which only
+/// matters for operands of different signs. The remainder always has the
+/// sign that keeps `(a / b) * b + a % b == a`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Division {
+    /// Rounds towards zero, like Rust and C: `-7 / 2 == -3` and
+    /// `-7 % 2 == -1`, the remainder having the sign of the dividend.
+    #[default]
+    Truncating,
+
+    /// Rounds towards negative infinity, like Python and mathematics:
+    /// `-7 / 2 == -4` and `-7 % 2 == 1`, the remainder having the sign of
+    /// the divisor.
+    Flooring,
+}
+
+impl Division {
+    /// The quotient and remainder of `l_int` by `r_int`, or `None` when
+    /// they overflow, as dividing `i64::MIN` by `-1` does. The divisor is
+    /// never zero.
+    fn apply(self, l_int: i64, r_int: i64) -> Option<(i64, i64)> {
+        let quotient = l_int.checked_div(r_int)?;
+        let remainder = l_int.checked_rem(r_int)?;
+
+        match self {
+            Division::Flooring if remainder != 0 && (remainder < 0) != (r_int < 0) => {
+                Some((quotient - 1, remainder + r_int))
+            }
+            _division => Some((quotient, remainder)),
+        }
+    }
+}
+
+impl Display for Division {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Division::Truncating => write!(f, "truncating"),
+            Division::Flooring => write!(f, "flooring"),
+        }
+    }
+}
+
+impl FromStr for Division {
+    type Err = String;
+
+    fn from_str(division: &str) -> Result<Self, Self::Err> {
+        match division {
+            "truncating" => Ok(Division::Truncating),
+            "flooring" => Ok(Division::Flooring),
+            division => Err(format!(
+                "invalid division {division}, expected truncating or flooring"
+            )),
+        }
+    }
+}
+
+fn overflow(l_int: i64, r_int: i64, location: &Location) -> RuntimeError {
     RuntimeError {
+        kind: RuntimeErrorKind::Overflow,
+        message: String::from("integer overflow"),
+        full_text: format!("dividing {l_int} by {r_int} overflows"),
+        location: location.clone(),
+        backtrace: Vec::new(),
+    }
+}
+
+/// The kind of an operation expecting operands of the `expected` types
+/// failing on `l_value` and `r_value`: the first of them not of those types
+/// is the one found.
+fn mismatch(expected: &'static [Type], l_value: &Value, r_value: &Value) -> RuntimeErrorKind {
+    let found = match expected.contains(&l_value.type_of()) {
+        true => r_value.type_of(),
+        false => l_value.type_of(),
+    };
+
+    RuntimeErrorKind::TypeMismatch { expected, found }
+}
+
+/// The types that can be compared for equality.
+const EQUATABLE: &[Type] = &[Type::Int, Type::Str, Type::Bool, Type::Unit];
+
+/// The types that can be ordered.
+const ORDERED: &[Type] = &[Type::Int, Type::Str, Type::Bool, Type::Tuple];
+
+/// The error of comparing `l_value` to `r_value`, with a comparison taking
+/// values of the `comparable` types. Values of different types are expected
+/// to be of the type of the first.
+fn invalid_comparison(
+    comparable: &'static [Type],
+    l_value: &Value,
+    r_value: &Value,
+    location: &Location,
+) -> RuntimeError {
+    let expected = match l_value.type_of() == r_value.type_of() {
+        true => comparable,
+        false => alone(l_value.type_of()),
+    };
+    let kind = RuntimeErrorKind::TypeMismatch {
+        expected,
+        found: r_value.type_of(),
+    };
+
+    RuntimeError {
+        kind,
         message: String::from("invalid comparison"),
         full_text: format!("{} and {} cannot be compared", l_value, r_value),
         location: location.clone(),
+        backtrace: Vec::new(),
+    }
+}
+
+/// `ty` as the only type expected.
+fn alone(ty: Type) -> &'static [Type] {
+    match ty {
+        Type::Int => &[Type::Int],
+        Type::Str => &[Type::Str],
+        Type::Bool => &[Type::Bool],
+        Type::Unit => &[Type::Unit],
+        Type::Tuple => &[Type::Tuple],
+        Type::Closure => &[Type::Closure],
+        Type::Map => &[Type::Map],
+        Type::Set => &[Type::Set],
+        Type::Generator => &[Type::Generator],
     }
 }
 
@@ -17,7 +138,8 @@ impl Value {
             (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool == r_bool)),
             (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str == r_str)),
             (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int == r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
+            (Value::Unit, Value::Unit) => Ok(Value::Bool(true)),
+            (l_value, r_value) => Err(invalid_comparison(EQUATABLE, l_value, r_value, location)),
         }
     }
 
@@ -26,53 +148,53 @@ impl Value {
             (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool != r_bool)),
             (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str != r_str)),
             (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int != r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
+            (Value::Unit, Value::Unit) => Ok(Value::Bool(false)),
+            (l_value, r_value) => Err(invalid_comparison(EQUATABLE, l_value, r_value, location)),
         }
     }
 
-    pub fn lt(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
+    /// Orders values of the same type: integers numerically, strings
+    /// lexicographically, `false` before `true` and tuples component-wise.
+    fn compare(&self, value: &Value, location: &Location) -> Result<Ordering, RuntimeError> {
         match (self, value) {
-            (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool < r_bool)),
-            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str < r_str)),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int < r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
+            (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(l_bool.cmp(r_bool)),
+            (Value::Str(l_str), Value::Str(r_str)) => Ok(l_str.cmp(r_str)),
+            (Value::Int(l_int), Value::Int(r_int)) => Ok(l_int.cmp(r_int)),
+            (Value::Tuple(l_tuple), Value::Tuple(r_tuple)) => {
+                match l_tuple.first().compare(r_tuple.first(), location)? {
+                    Ordering::Equal => l_tuple.second().compare(r_tuple.second(), location),
+                    ordering => Ok(ordering),
+                }
+            }
+            (l_value, r_value) => Err(invalid_comparison(ORDERED, l_value, r_value, location)),
         }
     }
 
+    pub fn lt(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
+        Ok(Value::Bool(self.compare(value, location)?.is_lt()))
+    }
+
     pub fn lte(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
-        match (self, value) {
-            (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool <= r_bool)),
-            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str <= r_str)),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int <= r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
-        }
+        Ok(Value::Bool(self.compare(value, location)?.is_le()))
     }
 
     pub fn gt(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
-        match (self, value) {
-            (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool > r_bool)),
-            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str > r_str)),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int > r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
-        }
+        Ok(Value::Bool(self.compare(value, location)?.is_gt()))
     }
 
     pub fn gte(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
-        match (self, value) {
-            (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(l_bool >= r_bool)),
-            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Bool(l_str >= r_str)),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Bool(l_int >= r_int)),
-            (l_value, r_value) => Err(invalid_comparison(l_value, r_value, location)),
-        }
+        Ok(Value::Bool(self.compare(value, location)?.is_ge()))
     }
 
     pub fn and(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
         match (self, value) {
             (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(*l_bool && *r_bool)),
-            (_l_val, _r_val) => Err(RuntimeError {
+            (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Bool], l_val, r_val),
                 message: String::from("invalid AND operation"),
-                full_text: format!("only booleans can be used on short-circuit operations"),
+                full_text: String::from("only booleans can be used on short-circuit operations"),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
@@ -80,118 +202,189 @@ impl Value {
     pub fn or(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
         match (self, value) {
             (Value::Bool(l_bool), Value::Bool(r_bool)) => Ok(Value::Bool(*l_bool || *r_bool)),
-            (_l_val, _r_val) => Err(RuntimeError {
+            (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Bool], l_val, r_val),
                 message: String::from("invalid OR operation"),
-                full_text: format!("only booleans can be used on short-circuit operations"),
+                full_text: String::from("only booleans can be used on short-circuit operations"),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
     pub fn add(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
         match (self, value) {
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int + r_int)),
-            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Str(format!("{l_str}{r_str}"))),
-            (Value::Str(l_str), Value::Int(r_int)) => Ok(Value::Str(format!("{l_str}{r_int}"))),
-            (Value::Int(l_int), Value::Str(r_str)) => Ok(Value::Str(format!("{l_int}{r_str}"))),
+            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int.wrapping_add(*r_int))),
+            (Value::Str(l_str), Value::Str(r_str)) => Ok(Value::Str(l_str.concat(r_str))),
+            (Value::Str(l_str), Value::Int(r_int)) => {
+                Ok(Value::Str(l_str.concat(&r_int.to_string().into())))
+            }
+            (Value::Int(l_int), Value::Str(r_str)) => {
+                Ok(Value::Str(Text::from(l_int.to_string()).concat(r_str)))
+            }
             (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Int, Type::Str], l_val, r_val),
                 message: String::from("invalid addition"),
                 full_text: format!("{l_val} cannot be added to {r_val}",),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
     pub fn sub(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
         match (self, value) {
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int - r_int)),
+            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int.wrapping_sub(*r_int))),
             (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Int], l_val, r_val),
                 message: String::from("invalid subtraction"),
                 full_text: format!("{l_val} cannot be subtracted by {r_val}",),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
     pub fn mul(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
         match (self, value) {
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int * r_int)),
+            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int.wrapping_mul(*r_int))),
             (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Int], l_val, r_val),
                 message: String::from("invalid multiplication"),
                 full_text: format!("{l_val} cannot be multiplied by {r_val} ",),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
-    pub fn div(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
+    pub fn div(
+        &self,
+        value: &Value,
+        division: Division,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
         match (self, value) {
             (Value::Int(_l_int), Value::Int(0)) => Err(RuntimeError {
+                kind: RuntimeErrorKind::DivisionByZero,
                 message: String::from("division by zero"),
                 full_text: String::from("zero cannot be divised"),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int / r_int)),
+            (Value::Int(l_int), Value::Int(r_int)) => division
+                .apply(*l_int, *r_int)
+                .map(|(quotient, _remainder)| Value::Int(quotient))
+                .ok_or_else(|| overflow(*l_int, *r_int, location)),
             (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Int], l_val, r_val),
                 message: String::from("invalid division"),
                 full_text: format!("{l_val} cannot be divised by {r_val}",),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
-    pub fn rem(&self, value: &Value, location: &Location) -> Result<Value, RuntimeError> {
+    pub fn rem(
+        &self,
+        value: &Value,
+        division: Division,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
         match (self, value) {
             (Value::Int(_l_val), Value::Int(0)) => Err(RuntimeError {
+                kind: RuntimeErrorKind::DivisionByZero,
                 message: String::from("division by zero"),
                 full_text: String::from("cannot get remainder from a zero division"),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
-            (Value::Int(l_int), Value::Int(r_int)) => Ok(Value::Int(l_int % r_int)),
+            (Value::Int(l_int), Value::Int(r_int)) => division
+                .apply(*l_int, *r_int)
+                .map(|(_quotient, remainder)| Value::Int(remainder))
+                .ok_or_else(|| overflow(*l_int, *r_int, location)),
             (l_val, r_val) => Err(RuntimeError {
+                kind: mismatch(&[Type::Int], l_val, r_val),
                 message: String::from("invalid remainder operation"),
                 full_text: format!("cannot get remainder from {l_val} and {r_val} division"),
                 location: location.clone(),
+                backtrace: Vec::new(),
             }),
         }
     }
 
-    pub fn binary_op(self, binary: Binary, rhs: Value) -> Result<Value, RuntimeError> {
-        match binary.op {
-            BinaryOp::Eq => self.eq(&rhs, binary.lhs.location()),
-            BinaryOp::Neq => self.neq(&rhs, binary.lhs.location()),
-            BinaryOp::Lt => self.lt(&rhs, binary.lhs.location()),
-            BinaryOp::Lte => self.lte(&rhs, binary.lhs.location()),
-            BinaryOp::Gt => self.gt(&rhs, binary.lhs.location()),
-            BinaryOp::Gte => self.gte(&rhs, binary.lhs.location()),
-            BinaryOp::And => self.and(&rhs, binary.lhs.location()),
-            BinaryOp::Or => self.or(&rhs, binary.lhs.location()),
-            BinaryOp::Add => self.add(&rhs, binary.lhs.location()),
-            BinaryOp::Sub => self.sub(&rhs, binary.lhs.location()),
-            BinaryOp::Mul => self.mul(&rhs, binary.lhs.location()),
-            BinaryOp::Div => self.div(&rhs, binary.lhs.location()),
-            BinaryOp::Rem => self.rem(&rhs, binary.lhs.location()),
+    pub fn binary_op(
+        self,
+        binary: Binary,
+        rhs: Value,
+        division: Division,
+    ) -> Result<Value, RuntimeError> {
+        self.operate(&binary.op, &rhs, division, binary.lhs.location())
+    }
+
+    /// Applies `op` to this value and `rhs`, reporting errors at `location`.
+    pub fn operate(
+        &self,
+        op: &BinaryOp,
+        rhs: &Value,
+        division: Division,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
+        match op {
+            BinaryOp::Eq => self.eq(rhs, location),
+            BinaryOp::Neq => self.neq(rhs, location),
+            BinaryOp::Lt => self.lt(rhs, location),
+            BinaryOp::Lte => self.lte(rhs, location),
+            BinaryOp::Gt => self.gt(rhs, location),
+            BinaryOp::Gte => self.gte(rhs, location),
+            BinaryOp::And => self.and(rhs, location),
+            BinaryOp::Or => self.or(rhs, location),
+            BinaryOp::Add => self.add(rhs, location),
+            BinaryOp::Sub => self.sub(rhs, location),
+            BinaryOp::Mul => self.mul(rhs, location),
+            BinaryOp::Div => self.div(rhs, division, location),
+            BinaryOp::Rem => self.rem(rhs, division, location),
+        }
+    }
+
+    /// Whether applying `op` to this value and `rhs` overflows, as adding,
+    /// subtracting and multiplying integers may, wrapping around.
+    pub fn overflows(&self, op: &BinaryOp, rhs: &Value) -> bool {
+        let (Value::Int(l_int), Value::Int(r_int)) = (self, rhs) else {
+            return false;
+        };
+
+        match op {
+            BinaryOp::Add => l_int.checked_add(*r_int).is_none(),
+            BinaryOp::Sub => l_int.checked_sub(*r_int).is_none(),
+            BinaryOp::Mul => l_int.checked_mul(*r_int).is_none(),
+            _op => false,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ast::Location, interpreter::Value};
+    use super::Division;
+    use crate::{
+        ast::Location,
+        interpreter::{Tuple, Value},
+    };
 
     fn int(int: i64) -> Value {
         Value::Int(int)
     }
 
     fn str(str: &str) -> Value {
-        Value::Str(str.to_string())
+        Value::Str(str.into())
     }
 
     fn location() -> Location {
         Location {
             start: 0,
             end: 0,
-            filename: "tests".to_string(),
+            filename: "tests".into(),
         }
     }
 
@@ -240,26 +433,78 @@ mod tests {
 
     #[test]
     fn div() {
-        let three_div_two = int(3).div(&int(2), &location()).unwrap();
+        let three_div_two = int(3)
+            .div(&int(2), Division::Truncating, &location())
+            .unwrap();
         assert!(eq(&three_div_two, &int(1)));
     }
 
+    #[test]
+    fn div_rem_negative() {
+        let div_rem = |l: i64, r: i64, division: Division| {
+            let quotient = int(l).div(&int(r), division, &location()).unwrap();
+            let remainder = int(l).rem(&int(r), division, &location()).unwrap();
+
+            (quotient.to_string(), remainder.to_string())
+        };
+
+        assert_eq!(
+            div_rem(-7, 2, Division::Truncating),
+            ("-3".into(), "-1".into())
+        );
+        assert_eq!(
+            div_rem(7, -2, Division::Truncating),
+            ("-3".into(), "1".into())
+        );
+        assert_eq!(
+            div_rem(-7, 2, Division::Flooring),
+            ("-4".into(), "1".into())
+        );
+        assert_eq!(
+            div_rem(7, -2, Division::Flooring),
+            ("-4".into(), "-1".into())
+        );
+        assert_eq!(
+            div_rem(-7, -2, Division::Flooring),
+            ("3".into(), "-1".into())
+        );
+        assert_eq!(
+            div_rem(-6, 2, Division::Flooring),
+            ("-3".into(), "0".into())
+        );
+    }
+
+    #[test]
+    fn div_overflow() {
+        let is_err = int(i64::MIN)
+            .div(&int(-1), Division::Flooring, &location())
+            .is_err();
+
+        assert!(is_err);
+    }
+
     #[test]
     fn div_by_zero() {
-        let is_err = int(1).div(&int(0), &location()).is_err();
+        let is_err = int(1)
+            .div(&int(0), Division::Truncating, &location())
+            .is_err();
 
         assert!(is_err);
     }
 
     #[test]
     fn rem() {
-        let four_rem_two = int(4).rem(&int(2), &location()).unwrap();
+        let four_rem_two = int(4)
+            .rem(&int(2), Division::Truncating, &location())
+            .unwrap();
         assert!(eq(&four_rem_two, &int(0)));
     }
 
     #[test]
     fn rem_with_zero() {
-        let is_err = int(1).rem(&int(0), &location()).is_err();
+        let is_err = int(1)
+            .rem(&int(0), Division::Truncating, &location())
+            .is_err();
 
         assert!(is_err);
     }
@@ -347,4 +592,36 @@ mod tests {
             .unwrap();
         assert!(eq(&false_or_true, &Value::Bool(true)));
     }
+
+    #[test]
+    fn lt_str() {
+        let apple_lt_banana = str("apple").lt(&str("banana"), &location()).unwrap();
+        assert!(eq(&apple_lt_banana, &Value::Bool(true)));
+    }
+
+    #[test]
+    fn compare_tuples() {
+        let tuple = |first: Value, second: Value| Value::Tuple(Tuple::new(first, second));
+
+        let lower = tuple(str("ana"), int(9));
+        let higher = tuple(str("ana"), int(10));
+        assert!(eq(
+            &lower.lt(&higher, &location()).unwrap(),
+            &Value::Bool(true)
+        ));
+        assert!(eq(
+            &lower.gte(&lower, &location()).unwrap(),
+            &Value::Bool(true)
+        ));
+
+        let mixed = tuple(int(1), int(9));
+        assert!(lower.lt(&mixed, &location()).is_err());
+    }
+
+    #[test]
+    fn eq_unit() {
+        let unit_eq_unit = Value::Unit.eq(&Value::Unit, &location()).unwrap();
+        assert!(eq(&unit_eq_unit, &Value::Bool(true)));
+        assert!(Value::Unit.eq(&int(0), &location()).is_err());
+    }
 }
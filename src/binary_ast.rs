@@ -0,0 +1,323 @@
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use crate::{
+    ast::{
+        Arm, Binary, Bool, Call, Cond, File, First, Function, If, Int, Let, Print, Second, Seq,
+        Str, Term, Tuple, Unit, Var,
+    },
+    encoding::{Decoder, Encoder},
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    symbol::Symbol,
+};
+
+/// Bytes every encoded AST starts with.
+pub const MAGIC: &[u8; 4] = b"LPAS";
+
+/// Version of the format written by [`File::to_binary`]. ASTs of other
+/// versions are refused rather than misread.
+pub const VERSION: u16 = 1;
+
+impl File {
+    /// The file in a compact binary encoding of its AST, read back with
+    /// [`File::from_reader_binary`] much faster than its JSON is parsed.
+    ///
+    /// The format is [`MAGIC`] and [`VERSION`], then the filenames, the
+    /// name and location of the file, and its expression, every term as
+    /// a tag for its kind followed by its fields. What the passes fill
+    /// in, like slots and digests, is left out.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut writer = Encoder::new(MAGIC, VERSION);
+        writer.str(&self.name);
+        writer.location(&self.location);
+        writer.term(&self.expression);
+
+        writer.finish()
+    }
+
+    /// Writes [`File::to_binary`] to `writer`.
+    pub fn to_writer_binary(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writer.write_all(&self.to_binary())
+    }
+
+    /// Reads a file written by [`File::to_binary`], the same as parsing the
+    /// JSON of its AST would.
+    pub fn from_reader_binary(mut reader: impl Read) -> Result<File, String> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|error| error.to_string())?;
+
+        let mut reader = Decoder::new(&bytes, MAGIC, VERSION, "encoded AST")?;
+        let file = File {
+            name: reader.str()?,
+            location: reader.location()?,
+            expression: reader.term()?,
+        };
+        reader.finish()?;
+
+        Ok(file)
+    }
+}
+
+impl Encoder {
+    fn term(&mut self, term: &Term) {
+        // Generated ASTs can nest deeper than the stack allows, like long
+        // chains of `let`s.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+            Term::Int(int) => {
+                self.byte(0);
+                self.i64(int.value);
+                self.location(&int.location);
+            }
+            Term::Str(str) => {
+                self.byte(1);
+                self.str(&str.value);
+                self.location(&str.location);
+            }
+            Term::Bool(bool) => {
+                self.byte(2);
+                self.byte(bool.value as u8);
+                self.location(&bool.location);
+            }
+            Term::Unit(unit) => {
+                self.byte(3);
+                self.location(&unit.location);
+            }
+            Term::Var(var) => {
+                self.byte(4);
+                self.var(var);
+            }
+            Term::Function(function) => {
+                self.byte(5);
+                self.usize(function.parameters.len());
+                for parameter in function.parameters.iter() {
+                    self.var(parameter);
+                }
+                self.term(&function.value);
+                self.location(&function.location);
+            }
+            Term::Let(let_) => {
+                self.byte(6);
+                self.var(&let_.name);
+                self.term(&let_.value);
+                self.term(&let_.next);
+                self.location(&let_.location);
+            }
+            Term::Call(call) => {
+                self.byte(7);
+                self.term(&call.callee);
+                self.usize(call.arguments.len());
+                for argument in &call.arguments {
+                    self.term(argument);
+                }
+                self.location(&call.location);
+            }
+            Term::Binary(binary) => {
+                self.byte(8);
+                self.binary_op(&binary.op);
+                self.term(&binary.lhs);
+                self.term(&binary.rhs);
+                self.location(&binary.location);
+            }
+            Term::If(if_) => {
+                self.byte(9);
+                self.term(&if_.condition);
+                self.term(&if_.then);
+                self.term(&if_.otherwise);
+                self.location(&if_.location);
+            }
+            Term::Cond(cond) => {
+                self.byte(10);
+                self.usize(cond.arms.len());
+                for arm in &cond.arms {
+                    self.term(&arm.condition);
+                    self.term(&arm.then);
+                }
+                self.term(&cond.otherwise);
+                self.location(&cond.location);
+            }
+            Term::Seq(seq) => {
+                self.byte(11);
+                self.usize(seq.terms.len());
+                for term in &seq.terms {
+                    self.term(term);
+                }
+                self.location(&seq.location);
+            }
+            Term::Print(print) => {
+                self.byte(12);
+                self.term(&print.value);
+                self.location(&print.location);
+            }
+            Term::First(first) => {
+                self.byte(13);
+                self.term(&first.value);
+                self.location(&first.location);
+            }
+            Term::Second(second) => {
+                self.byte(14);
+                self.term(&second.value);
+                self.location(&second.location);
+            }
+            Term::Tuple(tuple) => {
+                self.byte(15);
+                self.term(&tuple.first);
+                self.term(&tuple.second);
+                self.location(&tuple.location);
+            }
+        })
+    }
+
+    fn var(&mut self, var: &Var) {
+        self.str(var.text.as_str());
+        self.location(&var.location);
+    }
+}
+
+impl Decoder<'_> {
+    fn term(&mut self) -> Result<Term, String> {
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            let term = match self.byte()? {
+                0 => Term::Int(Int {
+                    value: self.i64()?,
+                    location: self.location()?,
+                }),
+                1 => Term::Str(Str {
+                    value: self.str()?,
+                    location: self.location()?,
+                }),
+                2 => Term::Bool(Bool {
+                    value: match self.byte()? {
+                        0 => false,
+                        1 => true,
+                        _byte => return Err(String::from("malformed boolean")),
+                    },
+                    location: self.location()?,
+                }),
+                3 => Term::Unit(Unit {
+                    location: self.location()?,
+                }),
+                4 => Term::Var(self.var()?),
+                5 => Term::Function(Function {
+                    parameters: (0..self.len()?)
+                        .map(|_| self.var())
+                        .collect::<Result<_, _>>()?,
+                    value: Arc::new(self.term()?),
+                    location: self.location()?,
+                    free: None,
+                    layout: None,
+                    digest: None,
+                    pure: None,
+                }),
+                6 => Term::Let(Let {
+                    name: self.var()?,
+                    value: Arc::new(self.term()?),
+                    next: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                7 => Term::Call(Call {
+                    callee: Arc::new(self.term()?),
+                    arguments: (0..self.len()?)
+                        .map(|_| self.term())
+                        .collect::<Result<_, _>>()?,
+                    location: self.location()?,
+                }),
+                8 => Term::Binary(Binary {
+                    op: self.binary_op()?,
+                    lhs: Arc::new(self.term()?),
+                    rhs: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                9 => Term::If(If {
+                    condition: Arc::new(self.term()?),
+                    then: Arc::new(self.term()?),
+                    otherwise: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                10 => Term::Cond(Cond {
+                    arms: (0..self.len()?)
+                        .map(|_| {
+                            Ok(Arm {
+                                condition: self.term()?,
+                                then: self.term()?,
+                            })
+                        })
+                        .collect::<Result<_, String>>()?,
+                    otherwise: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                11 => Term::Seq(Seq {
+                    terms: (0..self.len()?)
+                        .map(|_| self.term())
+                        .collect::<Result<_, _>>()?,
+                    location: self.location()?,
+                }),
+                12 => Term::Print(Print {
+                    value: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                13 => Term::First(First {
+                    value: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                14 => Term::Second(Second {
+                    value: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                15 => Term::Tuple(Tuple {
+                    first: Arc::new(self.term()?),
+                    second: Arc::new(self.term()?),
+                    location: self.location()?,
+                }),
+                tag => return Err(format!("unknown term {tag}")),
+            };
+
+            Ok(term)
+        })
+    }
+
+    fn var(&mut self) -> Result<Var, String> {
+        Ok(Var {
+            text: Symbol::new(&self.str()?),
+            location: self.location()?,
+            slot: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::File;
+
+    #[test]
+    fn files_read_back_the_same_as_their_json() {
+        let json = include_str!("../examples/combination.json");
+        let file: File = serde_json::from_str(json).unwrap();
+
+        let bytes = file.to_binary();
+        assert!(bytes.len() < json.len() / 4);
+
+        let decoded = File::from_reader_binary(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.name, file.name);
+        assert_eq!(decoded.location, file.location);
+        assert_eq!(decoded.expression, file.expression);
+    }
+
+    #[test]
+    fn malformed_files_are_refused() {
+        let file: File = serde_json::from_str(include_str!("../examples/sum.json")).unwrap();
+        let bytes = file.to_binary();
+
+        assert!(File::from_reader_binary(&b"{}"[..]).is_err());
+        assert!(File::from_reader_binary(&bytes[..bytes.len() - 1]).is_err());
+        assert!(File::from_reader_binary(crate::bytecode::MAGIC.as_slice()).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let error = File::from_reader_binary(trailing.as_slice()).unwrap_err();
+        assert!(error.contains("trailing"));
+    }
+}
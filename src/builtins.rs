@@ -0,0 +1,767 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::Location,
+    collections::{Key, Map, Set},
+    generator::{self, Generator},
+    interpreter::{
+        Arity, CallFrame, Clock, Context, Host, Native, RuntimeError, RuntimeErrorKind,
+        SystemClock, Tuple, Value,
+    },
+    symbol::Symbol,
+};
+
+/// Installs the builtin functions available to every program
+/// into the given context.
+pub fn install(context: &mut Context) {
+    define(context, Native::new("typeof", 1, typeof_));
+    define(context, Native::new("assert", 2, assert));
+    define(context, Native::new("parse_int", 1, parse_int));
+    define(context, Native::new("int_to_str", 1, int_to_str));
+    define(context, Native::new("str_to_bool", 1, str_to_bool));
+    define(context, Native::new("map", 0, map));
+    define(context, Native::new("get", 2, get));
+    define(
+        context,
+        Native::with_arity("insert", Arity::Between(2, 3), insert),
+    );
+    define(context, Native::new("remove", 2, remove));
+    define(context, Native::new("set", 0, set));
+    define(context, Native::new("contains", 2, contains));
+    define(context, Native::new("union", 2, union));
+    define(context, Native::new("size", 1, size));
+    define(context, Native::new("generator", 1, generator).impure());
+    define(context, Native::hosted("yield", 1, yield_).impure());
+    define(context, Native::hosted("next", 1, next).impure());
+    define(context, Native::new("fix", 1, fix));
+    define(context, Native::introspective("depth", 0, depth).impure());
+    define(
+        context,
+        Native::with_arity("format", Arity::AtLeast(1), format),
+    );
+    install_clock(context, SystemClock);
+}
+
+/// Installs `now()`, the current time in milliseconds, and
+/// `elapsed(start)`, the milliseconds passed since `start`, reading the
+/// time from `clock`.
+pub fn install_clock(context: &mut Context, clock: impl Clock + 'static) {
+    let clock = Arc::new(clock);
+
+    let now = Arc::clone(&clock);
+    let now = Native::new("now", 0, move |_arguments, _location| {
+        Ok(Value::Int(now.now() as i64))
+    });
+    define(context, now.impure());
+
+    let elapsed = Native::new("elapsed", 1, move |arguments, location| {
+        match &arguments[0] {
+            Value::Int(start) => Ok(Value::Int((clock.now() as i64).wrapping_sub(*start))),
+            value => Err(invalid_argument("elapsed", "an int", value, location)),
+        }
+    });
+    define(context, elapsed.impure());
+}
+
+/// Binds `args` to the command-line arguments given to the program, as a
+/// map from their index, starting from 0, to each argument.
+pub fn define_args(context: &mut Context, arguments: impl IntoIterator<Item = String>) {
+    let args = arguments
+        .into_iter()
+        .enumerate()
+        .fold(Map::new(), |args, (index, argument)| {
+            let index = Key::new(Value::Int(index as i64)).expect("ints are hashable");
+            args.insert(index, Value::Str(argument.into()))
+        });
+
+    context.insert(Symbol::new("args"), Value::Map(args));
+}
+
+/// Access to the host that the embedder may grant to programs. Programs
+/// are sandboxed by default, with none of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// `read_file(path)` and `write_file(path, contents)`.
+    FileSystem,
+
+    /// `env(name)`.
+    Environment,
+}
+
+/// Installs the builtins enabled by `capability` into the given context.
+pub fn grant(context: &mut Context, capability: Capability) {
+    match capability {
+        Capability::FileSystem => {
+            define(context, Native::new("read_file", 1, read_file).impure());
+            define(context, Native::new("write_file", 2, write_file).impure());
+        }
+        Capability::Environment => define(context, Native::new("env", 1, env).impure()),
+    }
+}
+
+fn define(context: &mut Context, native: Native) {
+    context.insert(Symbol::new(native.name()), Value::Native(native.builtin()));
+}
+
+fn invalid_argument(
+    name: &str,
+    expected: &str,
+    found: &Value,
+    location: &Location,
+) -> RuntimeError {
+    RuntimeError {
+        kind: RuntimeErrorKind::InvalidArgument,
+        message: String::from("invalid argument"),
+        full_text: format!(
+            "{name} expects {expected} but got {found}, which is {}",
+            found.type_name()
+        ),
+        location: location.clone(),
+        backtrace: Vec::new(),
+    }
+}
+
+fn key(name: &str, value: Value, location: &Location) -> Result<Key, RuntimeError> {
+    let type_name = value.type_name();
+
+    Key::new(value).ok_or_else(|| RuntimeError {
+        kind: RuntimeErrorKind::InvalidArgument,
+        message: String::from("invalid key"),
+        full_text: format!("{name} can't use a {type_name} as a key, as it can't be hashed"),
+        location: location.clone(),
+        backtrace: Vec::new(),
+    })
+}
+
+/// The result of a conversion that may fail: `(true, value)` on success
+/// and `(false, reason)` otherwise, so programs can branch on it.
+fn conversion(result: Result<Value, String>) -> Value {
+    let tuple = match result {
+        Ok(value) => Tuple::new(Value::Bool(true), value),
+        Err(reason) => Tuple::new(Value::Bool(false), Value::Str(reason.into())),
+    };
+
+    Value::Tuple(tuple)
+}
+
+/// `typeof(value)`: the name of the runtime type of `value`, one of
+/// `"int"`, `"str"`, `"bool"`, `"unit"`, `"tuple"`, `"map"`, `"set"`,
+/// `"generator"` or `"closure"`.
+fn typeof_(arguments: Vec<Value>, _location: &Location) -> Result<Value, RuntimeError> {
+    Ok(Value::Str(arguments[0].type_name().into()))
+}
+
+/// `assert(condition, message)`: `true` when `condition` holds, otherwise
+/// fails the evaluation with `message`, pointing at the assertion.
+fn assert(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Bool(true), _message) => Ok(Value::Bool(true)),
+        (Value::Bool(false), message) => Err(RuntimeError {
+            kind: RuntimeErrorKind::AssertionFailed,
+            message: String::from("assertion failed"),
+            full_text: message.to_string(),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+        (condition, _message) => Err(RuntimeError {
+            kind: RuntimeErrorKind::InvalidArgument,
+            message: String::from("invalid assertion"),
+            full_text: format!(
+                "{} can't be used as an assertion condition. use a boolean instead",
+                condition
+            ),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+/// `parse_int(str)`: the integer written in `str`, as a conversion result.
+fn parse_int(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(str) => {
+            Ok(conversion(str.parse::<i64>().map(Value::Int).map_err(
+                |error| format!("\"{str}\" is not a valid integer: {error}"),
+            )))
+        }
+        value => Err(invalid_argument("parse_int", "a str", value, location)),
+    }
+}
+
+/// `int_to_str(int)`: the decimal representation of `int`.
+fn int_to_str(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Int(int) => Ok(Value::Str(int.to_string().into())),
+        value => Err(invalid_argument("int_to_str", "an int", value, location)),
+    }
+}
+
+/// `str_to_bool(str)`: `"true"` or `"false"` as a boolean, as a conversion
+/// result.
+fn str_to_bool(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(str) => Ok(conversion(match &**str {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            str => Err(format!("\"{str}\" is not a valid bool")),
+        })),
+        value => Err(invalid_argument("str_to_bool", "a str", value, location)),
+    }
+}
+
+/// `read_file(path)`: the contents of the file at `path`, as a conversion
+/// result.
+fn read_file(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(path) => Ok(conversion(
+            std::fs::read_to_string(&**path)
+                .map(|contents| Value::Str(contents.into()))
+                .map_err(|error| format!("failed to read {path}: {error}")),
+        )),
+        value => Err(invalid_argument("read_file", "a str", value, location)),
+    }
+}
+
+/// `write_file(path, contents)`: writes `contents` as printed to the file
+/// at `path`, replacing it, and results in the number of bytes written,
+/// as a conversion result.
+fn write_file(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(path) => {
+            let contents = arguments[1].to_string();
+
+            Ok(conversion(
+                std::fs::write(&**path, &contents)
+                    .map(|()| Value::Int(contents.len() as i64))
+                    .map_err(|error| format!("failed to write {path}: {error}")),
+            ))
+        }
+        value => Err(invalid_argument("write_file", "a str", value, location)),
+    }
+}
+
+/// `env(name)`: the value of the environment variable `name`, as a
+/// conversion result failing when it isn't set.
+fn env(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Str(name) => Ok(conversion(
+            std::env::var(&**name)
+                .map(|value| Value::Str(value.into()))
+                .map_err(|error| format!("{name}: {error}")),
+        )),
+        value => Err(invalid_argument("env", "a str", value, location)),
+    }
+}
+
+/// `map()`: a new, empty, map.
+fn map(_arguments: Vec<Value>, _location: &Location) -> Result<Value, RuntimeError> {
+    Ok(Value::Map(Map::new()))
+}
+
+/// `get(map, key)`: the value associated to `key`, as a conversion result
+/// failing when `map` has no such key.
+fn get(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let [map, key_] = <[Value; 2]>::try_from(arguments).expect("arity is checked on call");
+
+    match map {
+        Value::Map(map) => {
+            let key_ = key("get", key_, location)?;
+            let found = map
+                .get(&key_)
+                .cloned()
+                .ok_or_else(|| format!("{} is not in the map", key_.value()));
+
+            Ok(conversion(found))
+        }
+        value => Err(invalid_argument("get", "a map", &value, location)),
+    }
+}
+
+/// `insert(map, key, value)`: a map with `key` associated to `value`.
+/// `insert(set, element)`: a set with `element` in it.
+fn insert(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let mut arguments = arguments.into_iter();
+
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(Value::Map(map)), Some(key_), Some(value)) => Ok(Value::Map(
+            map.insert(key("insert", key_, location)?, value),
+        )),
+        (Some(Value::Set(set)), Some(element), None) => {
+            Ok(Value::Set(set.insert(key("insert", element, location)?)))
+        }
+        (Some(Value::Map(_map)), _key, None) => Err(RuntimeError {
+            kind: RuntimeErrorKind::ArityMismatch,
+            message: String::from("invalid function call"),
+            full_text: String::from("insert expects a key and a value to insert into a map"),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+        (Some(Value::Set(_set)), _element, Some(_value)) => Err(RuntimeError {
+            kind: RuntimeErrorKind::ArityMismatch,
+            message: String::from("invalid function call"),
+            full_text: String::from("insert expects a single element to insert into a set"),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+        (Some(value), _key, _value) => Err(invalid_argument(
+            "insert",
+            "a map or a set",
+            &value,
+            location,
+        )),
+        (None, _key, _value) => unreachable!("arity is checked on call"),
+    }
+}
+
+/// `remove(map, key)`: a map without the entry of `key`.
+/// `remove(set, element)`: a set without `element`.
+fn remove(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let [collection, key_] = <[Value; 2]>::try_from(arguments).expect("arity is checked on call");
+
+    match collection {
+        Value::Map(map) => Ok(Value::Map(map.remove(&key("remove", key_, location)?))),
+        Value::Set(set) => Ok(Value::Set(set.remove(&key("remove", key_, location)?))),
+        value => Err(invalid_argument(
+            "remove",
+            "a map or a set",
+            &value,
+            location,
+        )),
+    }
+}
+
+/// `set()`: a new, empty, set.
+fn set(_arguments: Vec<Value>, _location: &Location) -> Result<Value, RuntimeError> {
+    Ok(Value::Set(Set::new()))
+}
+
+/// `contains(set, element)`: whether `element` is in `set`.
+/// `contains(map, key)`: whether `map` has an entry for `key`.
+fn contains(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let [collection, key_] = <[Value; 2]>::try_from(arguments).expect("arity is checked on call");
+
+    match collection {
+        Value::Map(map) => Ok(Value::Bool(
+            map.get(&key("contains", key_, location)?).is_some(),
+        )),
+        Value::Set(set) => Ok(Value::Bool(set.contains(&key("contains", key_, location)?))),
+        value => Err(invalid_argument(
+            "contains",
+            "a map or a set",
+            &value,
+            location,
+        )),
+    }
+}
+
+/// `union(l_set, r_set)`: a set with the elements of both sets.
+fn union(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let [l_set, r_set] = <[Value; 2]>::try_from(arguments).expect("arity is checked on call");
+
+    match (l_set, r_set) {
+        (Value::Set(l_set), Value::Set(r_set)) => Ok(Value::Set(l_set.union(&r_set))),
+        (Value::Set(_l_set), value) => Err(invalid_argument("union", "two sets", &value, location)),
+        (value, _r_set) => Err(invalid_argument("union", "two sets", &value, location)),
+    }
+}
+
+/// `size(collection)`: the number of elements of a set or entries of a map.
+fn size(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Map(map) => Ok(Value::Int(map.len() as i64)),
+        Value::Set(set) => Ok(Value::Int(set.len() as i64)),
+        value => Err(invalid_argument("size", "a map or a set", value, location)),
+    }
+}
+
+/// `generator(body)`: a generator running the function `body`, taking no
+/// arguments, once it is first resumed by `next`, which fails if
+/// [`MAX_GENERATORS`](generator::MAX_GENERATORS) are running or suspended
+/// already. Creating generators is impure, as each one is resumed apart
+/// from the others.
+fn generator(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Closure(closure) if closure.arity() == 0 => {
+            Ok(Value::Generator(Generator::new(arguments[0].clone())))
+        }
+        Value::Compiled(closure) if closure.arity() == 0 => {
+            Ok(Value::Generator(Generator::new(arguments[0].clone())))
+        }
+        Value::Native(_) => Ok(Value::Generator(Generator::new(arguments[0].clone()))),
+        value => Err(invalid_argument(
+            "generator",
+            "a function taking no arguments",
+            value,
+            location,
+        )),
+    }
+}
+
+/// `yield(value)`: hands `value` to the `next` that resumed the generator
+/// running it, suspending it there until it is resumed again.
+fn yield_(
+    arguments: Vec<Value>,
+    location: &Location,
+    host: &mut dyn Host,
+) -> Result<Value, RuntimeError> {
+    let [value] = <[Value; 1]>::try_from(arguments).expect("arity is checked on call");
+
+    generator::suspend(value, location, host.state())
+}
+
+/// `next(generator)`: `(true, value)` with the next value the generator
+/// yields, or `(false, "done")` once its body returned.
+fn next(
+    arguments: Vec<Value>,
+    location: &Location,
+    host: &mut dyn Host,
+) -> Result<Value, RuntimeError> {
+    let generator = match &arguments[0] {
+        Value::Generator(generator) => generator,
+        value => return Err(invalid_argument("next", "a generator", value, location)),
+    };
+
+    match host.resume(generator)? {
+        Some(value) => Ok(conversion(Ok(value))),
+        None => Ok(conversion(Err(String::from("done")))),
+    }
+}
+
+/// `format(template, args...)`: `template` with each `{}` replaced by the
+/// next argument, written as `print` would. `{{` and `}}` stand for
+/// literal braces.
+fn format(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    let mut arguments = arguments.into_iter();
+    let template = match arguments.next().expect("arity is checked on call") {
+        Value::Str(template) => template,
+        value => {
+            return Err(invalid_argument(
+                "format",
+                "a str template",
+                &value,
+                location,
+            ))
+        }
+    };
+
+    let invalid_template = |full_text: String| RuntimeError {
+        kind: RuntimeErrorKind::InvalidArgument,
+        message: String::from("invalid format template"),
+        full_text,
+        location: location.clone(),
+        backtrace: Vec::new(),
+    };
+
+    let mut formatted = String::with_capacity(template.len());
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(char) = chars.next() {
+        match (char, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                formatted.push(char);
+                chars.next();
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                placeholders += 1;
+                match arguments.next() {
+                    Some(argument) => formatted.push_str(&argument.to_string()),
+                    None => {
+                        return Err(invalid_template(format!(
+                            "{template:?} has more placeholders than the arguments given"
+                        )))
+                    }
+                }
+            }
+            ('{' | '}', _next) => {
+                return Err(invalid_template(format!(
+                    "{template:?} has an unmatched {char}, write {char}{char} for a literal one"
+                )))
+            }
+            (char, _next) => formatted.push(char),
+        }
+    }
+
+    match arguments.len() {
+        0 => Ok(Value::Str(formatted.into())),
+        unused => Err(invalid_template(format!(
+            "{template:?} has {placeholders} placeholder(s) but got {} argument(s)",
+            placeholders + unused
+        ))),
+    }
+}
+
+/// `fix(function)`: `function` with itself given as the first argument, so
+/// anonymous functions can recurse. `fix(fn (self, n) => ...)` is a
+/// function of `n` calling itself through `self`.
+fn fix(arguments: Vec<Value>, location: &Location) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Closure(closure) if closure.arity() > 0 => Ok(Value::Native(fixed(
+            arguments[0].clone(),
+            closure.arity() - 1,
+        ))),
+        Value::Compiled(closure) if closure.arity() > 0 => Ok(Value::Native(fixed(
+            arguments[0].clone(),
+            closure.arity() - 1,
+        ))),
+        value => Err(invalid_argument(
+            "fix",
+            "a function taking itself as its first parameter",
+            value,
+            location,
+        )),
+    }
+}
+
+/// `depth()`: how many calls are being evaluated, where calls in tail
+/// position take the place of their caller, so programs can stop recursing
+/// before [`State::max_call_depth`](crate::interpreter::State) is reached.
+/// It is impure, as calls of the same function return different depths.
+fn depth(
+    _arguments: Vec<Value>,
+    _location: &Location,
+    frames: &[CallFrame],
+) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(frames.len() as i64))
+}
+
+fn fixed(function: Value, arity: usize) -> Native {
+    let wrapped = function.clone();
+
+    Native::higher_order("fix", arity, move |arguments, _location, apply| {
+        let self_ = Value::Native(fixed(function.clone(), arity));
+
+        apply(
+            function.clone(),
+            std::iter::once(self_).chain(arguments).collect(),
+        )
+    })
+    .wrapping(wrapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::Location, interpreter::Value};
+
+    fn location() -> Location {
+        Location {
+            start: 0,
+            end: 0,
+            filename: "tests".into(),
+        }
+    }
+
+    fn typeof_(value: Value) -> String {
+        super::typeof_(vec![value], &location())
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn typeof_primitives() {
+        assert_eq!(typeof_(Value::Int(1)), "int");
+        assert_eq!(typeof_(Value::Str("a".into())), "str");
+        assert_eq!(typeof_(Value::Bool(true)), "bool");
+    }
+
+    #[test]
+    fn typeof_native() {
+        let native = super::Native::new("id", 1, |mut arguments, _| Ok(arguments.remove(0)));
+
+        assert_eq!(typeof_(Value::Native(native)), "closure");
+    }
+
+    #[test]
+    fn assert_holds() {
+        let arguments = vec![Value::Bool(true), Value::Str("unreachable".into())];
+        let result = super::assert(arguments, &location()).unwrap();
+
+        assert_eq!(result.to_string(), "true");
+    }
+
+    #[test]
+    fn assert_fails_at_location() {
+        let location = Location::new(3, 27, "tests");
+        let arguments = vec![Value::Bool(false), Value::Str("sum is wrong".into())];
+        let error = super::assert(arguments, &location).unwrap_err();
+
+        assert_eq!(error.full_text, "sum is wrong");
+        assert_eq!(error.location, location);
+    }
+
+    #[test]
+    fn assert_needs_boolean() {
+        let arguments = vec![Value::Int(1), Value::Str("not a condition".into())];
+        let is_err = super::assert(arguments, &location()).is_err();
+
+        assert!(is_err);
+    }
+
+    fn str(str: &str) -> Value {
+        Value::Str(str.into())
+    }
+
+    #[test]
+    fn parse_int() {
+        let parsed = super::parse_int(vec![str("-42")], &location()).unwrap();
+        assert_eq!(parsed.to_string(), "(true, -42)");
+
+        let failed = super::parse_int(vec![str("abc")], &location()).unwrap();
+        assert!(failed
+            .to_string()
+            .starts_with("(false, \"abc\" is not a valid integer"));
+
+        let is_err = super::parse_int(vec![Value::Int(1)], &location()).is_err();
+        assert!(is_err);
+    }
+
+    #[test]
+    fn write_then_read_file() {
+        let path = std::env::temp_dir().join(format!("lipsum-{}.txt", std::process::id()));
+        let path = str(path.to_str().unwrap());
+
+        let written = super::write_file(vec![path.clone(), str("hello")], &location()).unwrap();
+        assert_eq!(written.to_string(), "(true, 5)");
+
+        let read = super::read_file(vec![path.clone()], &location()).unwrap();
+        assert_eq!(read.to_string(), "(true, hello)");
+
+        std::fs::remove_file(path.to_string()).unwrap();
+        let missing = super::read_file(vec![path], &location()).unwrap();
+        assert!(missing.to_string().starts_with("(false, failed to read"));
+    }
+
+    #[test]
+    fn file_system_is_not_installed_by_default() {
+        let mut context = crate::interpreter::Context::new();
+        super::install(&mut context);
+        assert!(!context.contains_key("read_file".into()));
+
+        super::grant(&mut context, super::Capability::FileSystem);
+        assert!(context.contains_key("read_file".into()));
+        assert!(context.contains_key("write_file".into()));
+    }
+
+    #[test]
+    fn env() {
+        let path = super::env(vec![str("PATH")], &location()).unwrap();
+        assert!(path.to_string().starts_with("(true, "));
+
+        let unset = super::env(vec![str("LIPSUM_UNSET_VARIABLE")], &location()).unwrap();
+        assert_eq!(
+            unset.to_string(),
+            "(false, LIPSUM_UNSET_VARIABLE: environment variable not found)"
+        );
+    }
+
+    #[test]
+    fn args_by_index() {
+        let mut context = crate::interpreter::Context::new();
+        super::define_args(&mut context, vec![String::from("a"), String::from("b")]);
+
+        assert_eq!(
+            context.get("args".into()).unwrap().to_string(),
+            "{0: a, 1: b}"
+        );
+    }
+
+    #[test]
+    fn int_to_str() {
+        let converted = super::int_to_str(vec![Value::Int(7)], &location()).unwrap();
+        assert_eq!(converted.to_string(), "7");
+    }
+
+    #[test]
+    fn str_to_bool() {
+        let converted = super::str_to_bool(vec![str("false")], &location()).unwrap();
+        assert_eq!(converted.to_string(), "(true, false)");
+
+        let failed = super::str_to_bool(vec![str("yes")], &location()).unwrap();
+        assert_eq!(failed.to_string(), "(false, \"yes\" is not a valid bool)");
+    }
+
+    #[test]
+    fn map_operations() {
+        let map = super::map(vec![], &location()).unwrap();
+        let map = super::insert(vec![map, str("a"), Value::Int(1)], &location()).unwrap();
+        assert_eq!(map.to_string(), "{a: 1}");
+
+        let found = super::get(vec![map.clone(), str("a")], &location()).unwrap();
+        assert_eq!(found.to_string(), "(true, 1)");
+
+        let map = super::remove(vec![map, str("a")], &location()).unwrap();
+        let missing = super::get(vec![map, str("a")], &location()).unwrap();
+        assert_eq!(missing.to_string(), "(false, a is not in the map)");
+    }
+
+    #[test]
+    fn map_needs_hashable_keys() {
+        let map = super::map(vec![], &location()).unwrap();
+        let typeof_ = super::Native::new("typeof", 1, super::typeof_);
+        let is_err = super::insert(
+            vec![map, Value::Native(typeof_), Value::Int(1)],
+            &location(),
+        )
+        .is_err();
+
+        assert!(is_err);
+    }
+
+    #[test]
+    fn set_operations() {
+        let set = super::set(vec![], &location()).unwrap();
+        let set = super::insert(vec![set, Value::Int(1)], &location()).unwrap();
+        let set = super::insert(vec![set, Value::Int(1)], &location()).unwrap();
+        let other = super::insert(
+            vec![super::set(vec![], &location()).unwrap(), Value::Int(2)],
+            &location(),
+        )
+        .unwrap();
+        let union = super::union(vec![set.clone(), other], &location()).unwrap();
+
+        assert_eq!(union.to_string(), "{1, 2}");
+        assert_eq!(
+            super::size(vec![set.clone()], &location())
+                .unwrap()
+                .to_string(),
+            "1"
+        );
+
+        let found = super::contains(vec![set, Value::Int(1)], &location()).unwrap();
+        assert_eq!(found.to_string(), "true");
+    }
+
+    #[test]
+    fn insert_checks_collection_arguments() {
+        let set = super::set(vec![], &location()).unwrap();
+        let map = super::map(vec![], &location()).unwrap();
+
+        assert!(super::insert(vec![set, Value::Int(1), Value::Int(2)], &location()).is_err());
+        assert!(super::insert(vec![map, Value::Int(1)], &location()).is_err());
+    }
+
+    #[test]
+    fn generator_needs_a_function() {
+        let is_err = super::generator(vec![Value::Int(1)], &location()).is_err();
+
+        assert!(is_err);
+    }
+
+    #[test]
+    fn format() {
+        let pair = Value::Tuple(super::Tuple::new(Value::Int(1), Value::Bool(true)));
+        let arguments = vec![str("{} is {{{}}}: {}"), str("x"), Value::Int(-2), pair];
+        let formatted = super::format(arguments, &location()).unwrap();
+
+        assert_eq!(formatted.to_string(), "x is {-2}: (1, true)");
+    }
+
+    #[test]
+    fn format_checks_placeholders() {
+        let missing = super::format(vec![str("{} and {}"), Value::Int(1)], &location());
+        let unused = super::format(vec![str("{}"), Value::Int(1), Value::Int(2)], &location());
+        let unmatched = super::format(vec![str("{")], &location());
+
+        assert!(missing.is_err() && unused.is_err() && unmatched.is_err());
+    }
+}
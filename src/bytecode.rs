@@ -0,0 +1,336 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::{
+    compiler::{Capture, Function, Instruction, Program},
+    encoding::{Decoder, Encoder},
+    interpreter::Value,
+    symbol::Symbol,
+};
+
+/// Bytes every serialized program starts with.
+pub const MAGIC: &[u8; 4] = b"LPBC";
+
+/// Version of the format written by [`Program::serialize`]. Programs of
+/// other versions are refused rather than misread.
+pub const VERSION: u16 = 2;
+
+impl Program {
+    /// The program in a compact binary format, to be started later with
+    /// [`Program::deserialize`] without parsing and compiling its AST.
+    ///
+    /// The format is [`MAGIC`] and [`VERSION`], then the names of the
+    /// filenames and globals, then the functions. Integers are written as
+    /// LEB128 varints, and locations refer to their filename by index.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Encoder::new(MAGIC, VERSION);
+        writer.usize(self.globals.len());
+        for global in &self.globals {
+            writer.str(global.as_str());
+        }
+        writer.usize(self.functions.len());
+        for function in &self.functions {
+            writer.function(function);
+        }
+
+        writer.finish()
+    }
+
+    /// Reads back a program written by [`Program::serialize`], checking it
+    /// only refers to functions, globals, slots and instructions it has.
+    pub fn deserialize(bytes: &[u8]) -> Result<Program, String> {
+        let mut reader = Decoder::new(bytes, MAGIC, VERSION, "compiled program")?;
+        let globals = (0..reader.len()?)
+            .map(|_| reader.str().map(|global| Symbol::new(&global)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let functions = (0..reader.len()?)
+            .map(|_| reader.function().map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        reader.finish()?;
+
+        let program = Program { functions, globals };
+        check(&program)?;
+
+        Ok(program)
+    }
+}
+
+impl Encoder {
+    fn function(&mut self, function: &Function) {
+        match &function.name {
+            Some(name) => {
+                self.byte(1);
+                self.str(name);
+            }
+            None => self.byte(0),
+        }
+        self.usize(function.arity);
+        self.usize(function.locals);
+        self.location(&function.location);
+
+        self.usize(function.captures.len());
+        for capture in &function.captures {
+            match capture {
+                Capture::Local(slot) => {
+                    self.byte(0);
+                    self.usize(*slot);
+                }
+                Capture::Captured(index) => {
+                    self.byte(1);
+                    self.usize(*index);
+                }
+                Capture::Current => self.byte(2),
+            }
+        }
+
+        self.usize(function.code.len());
+        for (instruction, location) in function.code.iter().zip(&function.locations) {
+            self.instruction(instruction);
+            self.location(location);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let (opcode, operand) = match instruction {
+            Instruction::Push(value) => {
+                self.byte(0);
+                return self.constant(value);
+            }
+            Instruction::Local(slot) => (1, Some(*slot)),
+            Instruction::SetLocal(slot) => (2, Some(*slot)),
+            Instruction::Captured(index) => (3, Some(*index)),
+            Instruction::Current => (4, None),
+            Instruction::Global(index) => (5, Some(*index)),
+            Instruction::Closure(index) => (6, Some(*index)),
+            Instruction::Callee(arguments) => (7, Some(*arguments)),
+            Instruction::Call(arguments) => (8, Some(*arguments)),
+            Instruction::TailCall(arguments) => (9, Some(*arguments)),
+            Instruction::Return => (10, None),
+            Instruction::Jump(target) => (11, Some(*target)),
+            Instruction::JumpUnless(target) => (12, Some(*target)),
+            Instruction::ShortCircuit(op, target) => {
+                self.byte(13);
+                self.binary_op(op);
+                return self.usize(*target);
+            }
+            Instruction::Binary(op) => {
+                self.byte(14);
+                return self.binary_op(op);
+            }
+            Instruction::Tuple => (15, None),
+            Instruction::First => (16, None),
+            Instruction::Second => (17, None),
+            Instruction::Print => (18, None),
+            Instruction::Pop => (19, None),
+            Instruction::BinaryConst(op, constant) => {
+                self.byte(20);
+                self.binary_op(op);
+                return self.constant(constant);
+            }
+            Instruction::CompareJumpUnless(op, constant, target) => {
+                self.byte(21);
+                self.binary_op(op);
+                match constant {
+                    Some(constant) => {
+                        self.byte(1);
+                        self.constant(constant);
+                    }
+                    None => self.byte(0),
+                }
+                return self.usize(*target);
+            }
+        };
+
+        self.byte(opcode);
+        if let Some(operand) = operand {
+            self.usize(operand);
+        }
+    }
+
+    fn constant(&mut self, value: &Value) {
+        match value {
+            Value::Int(int) => {
+                self.byte(0);
+                self.i64(*int);
+            }
+            Value::Str(str) => {
+                self.byte(1);
+                self.str(str);
+            }
+            Value::Bool(bool) => self.byte(2 + *bool as u8),
+            Value::Unit => self.byte(4),
+            value => unreachable!("only literals are compiled to constants, not {value}"),
+        }
+    }
+}
+
+impl Decoder<'_> {
+    fn function(&mut self) -> Result<Function, String> {
+        let name = match self.byte()? {
+            0 => None,
+            1 => Some(self.str()?),
+            _tag => return Err(String::from("malformed function name")),
+        };
+        let arity = self.usize()?;
+        let locals = self.usize()?;
+        let location = self.location()?;
+
+        let captures = (0..self.len()?)
+            .map(|_| match self.byte()? {
+                0 => Ok(Capture::Local(self.usize()?)),
+                1 => Ok(Capture::Captured(self.usize()?)),
+                2 => Ok(Capture::Current),
+                _tag => Err(String::from("malformed capture")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let len = self.len()?;
+        let mut code = Vec::with_capacity(len);
+        let mut locations = Vec::with_capacity(len);
+        for _ in 0..len {
+            code.push(self.instruction()?);
+            locations.push(self.location()?);
+        }
+
+        Ok(Function {
+            name,
+            arity,
+            locals,
+            captures,
+            code,
+            locations,
+            location,
+            registers: OnceLock::new(),
+        })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, String> {
+        let instruction = match self.byte()? {
+            0 => Instruction::Push(self.constant()?),
+            1 => Instruction::Local(self.usize()?),
+            2 => Instruction::SetLocal(self.usize()?),
+            3 => Instruction::Captured(self.usize()?),
+            4 => Instruction::Current,
+            5 => Instruction::Global(self.usize()?),
+            6 => Instruction::Closure(self.usize()?),
+            7 => Instruction::Callee(self.usize()?),
+            8 => Instruction::Call(self.usize()?),
+            9 => Instruction::TailCall(self.usize()?),
+            10 => Instruction::Return,
+            11 => Instruction::Jump(self.usize()?),
+            12 => Instruction::JumpUnless(self.usize()?),
+            13 => Instruction::ShortCircuit(self.binary_op()?, self.usize()?),
+            14 => Instruction::Binary(self.binary_op()?),
+            15 => Instruction::Tuple,
+            16 => Instruction::First,
+            17 => Instruction::Second,
+            18 => Instruction::Print,
+            19 => Instruction::Pop,
+            20 => Instruction::BinaryConst(self.binary_op()?, self.constant()?),
+            21 => {
+                let op = self.binary_op()?;
+                let constant = match self.byte()? {
+                    0 => None,
+                    1 => Some(self.constant()?),
+                    _tag => return Err(String::from("malformed constant")),
+                };
+
+                Instruction::CompareJumpUnless(op, constant, self.usize()?)
+            }
+            opcode => return Err(format!("unknown opcode {opcode}")),
+        };
+
+        Ok(instruction)
+    }
+
+    fn constant(&mut self) -> Result<Value, String> {
+        let constant = match self.byte()? {
+            0 => Value::Int(self.i64()?),
+            1 => Value::Str(self.str()?.into()),
+            2 => Value::Bool(false),
+            3 => Value::Bool(true),
+            4 => Value::Unit,
+            _tag => return Err(String::from("malformed constant")),
+        };
+
+        Ok(constant)
+    }
+}
+
+/// Fails unless every instruction of `program` refers to a function,
+/// global, slot and jump target that exists, as the vm indexes them
+/// without checking.
+fn check(program: &Program) -> Result<(), String> {
+    if program.functions.is_empty() {
+        return Err(String::from("no entrypoint"));
+    }
+
+    for function in &program.functions {
+        let valid = |instruction: &Instruction| match instruction {
+            Instruction::Local(slot) | Instruction::SetLocal(slot) => *slot < function.locals,
+            Instruction::Captured(index) => *index < function.captures.len(),
+            Instruction::Global(index) => *index < program.globals.len(),
+            Instruction::Closure(index) => *index < program.functions.len(),
+            Instruction::Jump(target)
+            | Instruction::JumpUnless(target)
+            | Instruction::ShortCircuit(_, target)
+            | Instruction::CompareJumpUnless(_, _, target) => *target <= function.code.len(),
+            _instruction => true,
+        };
+        if function.arity > function.locals {
+            return Err(String::from("malformed function"));
+        }
+        if let Some(ip) = function
+            .code
+            .iter()
+            .position(|instruction| !valid(instruction))
+        {
+            return Err(format!("malformed instruction {ip} of a function"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::File,
+        compiler::{self, Program},
+        interpreter::{Capture, Context, State},
+        vm,
+    };
+
+    #[test]
+    fn programs_run_the_same_once_serialized() {
+        let file: File = serde_json::from_str(include_str!("../examples/fib.json")).unwrap();
+        let program = compiler::compile(file.expression);
+
+        let bytes = program.serialize();
+        let deserialized = Program::deserialize(&bytes).unwrap();
+        assert_eq!(deserialized.serialize(), bytes);
+
+        let mut output = Capture::new();
+        let value = vm::run(
+            &deserialized,
+            &Context::new(),
+            &mut State::new(),
+            &mut output,
+        );
+        assert_eq!(value.unwrap().to_string(), "55");
+    }
+
+    #[test]
+    fn malformed_programs_are_refused() {
+        let file: File = serde_json::from_str(include_str!("../examples/sum.json")).unwrap();
+        let bytes = compiler::compile(file.expression).serialize();
+
+        assert!(Program::deserialize(b"{}").is_err());
+        assert!(Program::deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        let error = Program::deserialize(&newer).unwrap_err();
+        assert!(error.contains("version 3"));
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde_json::json;
+
+use crate::{
+    binary::Division,
+    collections::{Key, Map, Set},
+    digest::Digest,
+    interpreter::{Cache, Tuple, Value},
+};
+
+/// Where the results of memoized calls are kept, by the 128-bit hash of
+/// the call.
+pub trait CacheStore: Debug + Send {
+    /// The value cached for the call hashed as `key`, if any.
+    fn get(&self, key: u128) -> Option<Value>;
+
+    fn insert(&mut self, key: u128, value: Value);
+
+    /// Writes out the values inserted so far, for stores keeping them
+    /// somewhere else than in memory.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Every value cached with its key, for [snapshots](crate::snapshot).
+    /// Stores that can't list their values return none.
+    fn entries(&self) -> Vec<(u128, Value)> {
+        Vec::new()
+    }
+}
+
+impl CacheStore for Cache {
+    fn get(&self, key: u128) -> Option<Value> {
+        Cache::get(self, &key).cloned()
+    }
+
+    fn insert(&mut self, key: u128, value: Value) {
+        Cache::insert(self, key, value);
+    }
+
+    fn entries(&self) -> Vec<(u128, Value)> {
+        self.iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect()
+    }
+}
+
+impl Default for Box<dyn CacheStore> {
+    fn default() -> Self {
+        Box::new(Cache::default())
+    }
+}
+
+/// How many shards a [`SharedCache`] spreads its entries over.
+const SHARDS: usize = 16;
+
+/// A cache shared by evaluations running on many threads at once, like the
+/// runs of a server. Entries are spread over shards, each behind a lock of
+/// its own, so threads rarely wait on each other. Clones share the same
+/// entries.
+#[derive(Debug, Clone)]
+pub struct SharedCache {
+    shards: Arc<[Mutex<Cache>]>,
+}
+
+impl SharedCache {
+    /// Creates a new, empty instance of [`SharedCache`].
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// How many values are cached, across all shards.
+    pub fn len(&self) -> usize {
+        (0..SHARDS).map(|shard| self.lock(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self, shard: usize) -> std::sync::MutexGuard<'_, Cache> {
+        self.shards[shard]
+            .lock()
+            .expect("the cache is never poisoned")
+    }
+
+    fn shard(key: u128) -> usize {
+        // Keys are hashes already, so their low bits are evenly spread.
+        key as usize % SHARDS
+    }
+}
+
+impl Default for SharedCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheStore for SharedCache {
+    fn get(&self, key: u128) -> Option<Value> {
+        self.lock(Self::shard(key)).get(&key).cloned()
+    }
+
+    fn insert(&mut self, key: u128, value: Value) {
+        self.lock(Self::shard(key)).insert(key, value);
+    }
+
+    fn entries(&self) -> Vec<(u128, Value)> {
+        (0..SHARDS)
+            .flat_map(|shard| self.lock(shard).entries())
+            .collect()
+    }
+}
+
+/// A cache persisted to a file, so later runs of the same program reuse
+/// the results of the calls of earlier ones, like when a judge runs it
+/// again and again.
+///
+/// Each program has a file of its own, named after the digest of its
+/// syntax tree, so changing the program leaves the results of its former
+/// versions behind, and after the [`Division`] it ran with, as results
+/// differ between them. Values that can't be written out, like closures,
+/// are only cached for the current run.
+#[derive(Debug)]
+pub struct DiskCache {
+    path: PathBuf,
+    division: Division,
+    entries: Cache,
+
+    /// Whether values were inserted since the file was last written.
+    dirty: bool,
+}
+
+impl DiskCache {
+    /// Opens the cache of the program digested as `program`, run with
+    /// `division`, in `directory`, creating the directory if needed. Files
+    /// written for another division fail to open.
+    pub fn open(
+        directory: impl AsRef<Path>,
+        program: Digest,
+        division: Division,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+
+        let path = directory.join(format!("{program}-{division}.json"));
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let invalid = |message| io::Error::new(ErrorKind::InvalidData, message);
+                let (written, entries) = parse(&contents).ok_or_else(|| {
+                    invalid(format!("malformed cache file at {}", path.display()))
+                })?;
+                if written != division {
+                    return Err(invalid(format!(
+                        "the cache file at {} was written with {written} division, not {division}",
+                        path.display()
+                    )));
+                }
+
+                entries
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => Cache::default(),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self {
+            path,
+            division,
+            entries,
+            dirty: false,
+        })
+    }
+}
+
+impl CacheStore for DiskCache {
+    fn get(&self, key: u128) -> Option<Value> {
+        self.entries.get(&key).cloned()
+    }
+
+    fn insert(&mut self, key: u128, value: Value) {
+        self.entries.insert(key, value);
+        self.dirty = true;
+    }
+
+    fn entries(&self) -> Vec<(u128, Value)> {
+        self.entries.entries()
+    }
+
+    /// Writes the persistable values to the file, replacing it at once so
+    /// an interrupted write never leaves it half written.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, value)| Some(json!([format!("{key:032x}"), encode(value)?])))
+            .collect::<Vec<_>>();
+
+        let file = json!({ "division": self.division.to_string(), "entries": entries });
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_string(&file)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+/// The division a cache file was written with, and its entries.
+fn parse(contents: &str) -> Option<(Division, Cache)> {
+    #[derive(serde::Deserialize)]
+    struct File {
+        division: String,
+        entries: Vec<(String, serde_json::Value)>,
+    }
+
+    let file: File = serde_json::from_str(contents).ok()?;
+    let entries = file
+        .entries
+        .into_iter()
+        .map(|(key, value)| Some((u128::from_str_radix(&key, 16).ok()?, decode(&value)?)))
+        .collect::<Option<_>>()?;
+
+    Some((file.division.parse().ok()?, entries))
+}
+
+/// The value as written to a cache file, or `None` for values that can't
+/// be written, like closures, anywhere inside.
+fn encode(value: &Value) -> Option<serde_json::Value> {
+    let encoded = match value {
+        Value::Int(int) => json!({ "int": int }),
+        Value::Str(str) => json!({ "str": str.as_str() }),
+        Value::Bool(bool) => json!({ "bool": bool }),
+        Value::Unit => json!({ "unit": null }),
+        Value::Tuple(tuple) => {
+            json!({ "tuple": [encode(tuple.first())?, encode(tuple.second())?] })
+        }
+        Value::Map(map) => json!({
+            "map": map
+                .iter()
+                .map(|(key, value)| Some(json!([encode(key.value())?, encode(value)?])))
+                .collect::<Option<Vec<_>>>()?
+        }),
+        Value::Set(set) => json!({
+            "set": set
+                .iter()
+                .map(|key| encode(key.value()))
+                .collect::<Option<Vec<_>>>()?
+        }),
+        Value::Closure(_) | Value::Native(_) | Value::Generator(_) | Value::Compiled(_) => {
+            return None
+        }
+    };
+
+    Some(encoded)
+}
+
+fn decode(encoded: &serde_json::Value) -> Option<Value> {
+    let (kind, inner) = encoded.as_object()?.iter().next()?;
+
+    let value = match kind.as_str() {
+        "int" => Value::Int(inner.as_i64()?),
+        "str" => Value::Str(inner.as_str()?.into()),
+        "bool" => Value::Bool(inner.as_bool()?),
+        "unit" => Value::Unit,
+        "tuple" => match inner.as_array()?.as_slice() {
+            [first, second] => Value::Tuple(Tuple::new(decode(first)?, decode(second)?)),
+            _elements => return None,
+        },
+        "map" => Value::Map(
+            inner
+                .as_array()?
+                .iter()
+                .try_fold(Map::new(), |map, entry| {
+                    match entry.as_array()?.as_slice() {
+                        [key, value] => Some(map.insert(Key::new(decode(key)?)?, decode(value)?)),
+                        _elements => None,
+                    }
+                })?,
+        ),
+        "set" => Value::Set(inner.as_array()?.iter().try_fold(Set::new(), |set, key| {
+            Some(set.insert(Key::new(decode(key)?)?))
+        })?),
+        _kind => return None,
+    };
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheStore, DiskCache, SharedCache};
+    use crate::{
+        binary::Division,
+        collections::{Key, Map},
+        digest,
+        interpreter::{Tuple, Value},
+        text::Text,
+    };
+
+    fn program(source: &str) -> digest::Digest {
+        let term = serde_json::from_str(&format!(
+            r#"{{ "kind": "Str", "value": "{source}",
+                  "location": {{ "start": 0, "end": 0, "filename": "" }} }}"#
+        ))
+        .unwrap();
+
+        digest::of(&term)
+    }
+
+    #[test]
+    fn shared_caches_are_filled_from_many_threads() {
+        let cache = SharedCache::new();
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let mut cache = cache.clone();
+                scope.spawn(move || {
+                    for key in 0..100 {
+                        cache.insert(thread * 100 + key, Value::Int(key as i64));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(cache.len(), 400);
+        assert_eq!(cache.get(342).unwrap().to_string(), "42");
+    }
+
+    #[test]
+    fn values_persist_for_the_same_program_only() {
+        let directory = std::env::temp_dir().join(format!("lipsum-cache-{}", std::process::id()));
+        let map = Map::new().insert(
+            Key::new(Value::Int(1)).unwrap(),
+            Value::Str(Text::from("a")),
+        );
+        let tuple = Value::Tuple(Tuple::new(Value::Bool(true), Value::Map(map)));
+
+        let mut cache =
+            DiskCache::open(&directory, program("first"), Division::Truncating).unwrap();
+        cache.insert(1, tuple.clone());
+        cache.insert(2, Value::Unit);
+        cache.flush().unwrap();
+
+        let reopened = DiskCache::open(&directory, program("first"), Division::Truncating).unwrap();
+        assert_eq!(reopened.get(1).unwrap().to_string(), tuple.to_string());
+        assert_eq!(reopened.get(2).unwrap().to_string(), "()");
+
+        let changed = DiskCache::open(&directory, program("second"), Division::Truncating).unwrap();
+        assert!(changed.get(1).is_none());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn values_persist_for_the_same_division_only() {
+        let directory =
+            std::env::temp_dir().join(format!("lipsum-cache-division-{}", std::process::id()));
+
+        let mut truncating =
+            DiskCache::open(&directory, program("divide"), Division::Truncating).unwrap();
+        truncating.insert(1, Value::Int(-3));
+        truncating.flush().unwrap();
+
+        let flooring = DiskCache::open(&directory, program("divide"), Division::Flooring).unwrap();
+        assert!(flooring.get(1).is_none());
+
+        // A file renamed to the name of the other division is rejected.
+        let file = |division| directory.join(format!("{}-{division}.json", program("divide")));
+        std::fs::rename(file(Division::Truncating), file(Division::Flooring)).unwrap();
+        let error = DiskCache::open(&directory, program("divide"), Division::Flooring).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
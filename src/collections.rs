@@ -0,0 +1,280 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::interpreter::Value;
+
+/// A value used as a key of a [`Map`] or an element of a [`Set`]. Keys
+/// are hashed with [`Value::structural_hash`] and compared structurally,
+/// so only values without closures can be keys.
+#[derive(Clone, Debug)]
+pub struct Key(Value);
+
+impl Key {
+    /// Creates a new instance of [`Key`], or `None` if the value can't be
+    /// hashed, like closures.
+    pub fn new(value: Value) -> Option<Self> {
+        value.structural_hash(&mut DefaultHasher::new())?;
+
+        Some(Self(value))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0
+            .structural_hash(state)
+            .expect("keys are checked to be hashable on creation")
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Key {}
+
+/// An immutable hash map from [`Key`]s to values. Inserting or removing
+/// an entry produces a new map, leaving the original untouched.
+#[derive(Clone, Debug, Default)]
+pub struct Map {
+    entries: Arc<HashMap<Key, Value>>,
+
+    /// Approximate bytes of the keys and values, kept up to date as
+    /// entries change.
+    size: usize,
+}
+
+impl Map {
+    /// Creates a new, empty, instance of [`Map`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// A map with `key` associated to `value`, replacing any previous entry.
+    pub fn insert(mut self, key: Key, value: Value) -> Self {
+        let key_size = key.value().heap_size();
+        self.size = self.size.saturating_add(key_size + value.heap_size());
+
+        if let Some(replaced) = Arc::make_mut(&mut self.entries).insert(key, value) {
+            self.size -= key_size + replaced.heap_size();
+        }
+
+        self
+    }
+
+    /// A map without the entry of `key`, if there was one.
+    pub fn remove(mut self, key: &Key) -> Self {
+        if self.entries.contains_key(key) {
+            if let Some((key, value)) = Arc::make_mut(&mut self.entries).remove_entry(key) {
+                self.size -= key.value().heap_size() + value.heap_size();
+            }
+        }
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter()
+    }
+
+    /// Approximate bytes held by the keys and values of the map.
+    pub fn heap_size(&self) -> usize {
+        self.size
+    }
+
+    /// The entries rendered as `key: value`, sorted so that the output
+    /// doesn't depend on the hash map iteration order.
+    fn entries(&self) -> Vec<String> {
+        let mut entries = self
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key.value(), value))
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        entries
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}}}", self.entries().join(", "))
+    }
+}
+
+/// An immutable hash set of [`Key`]s. Inserting or removing an element
+/// produces a new set, leaving the original untouched.
+#[derive(Clone, Debug, Default)]
+pub struct Set {
+    elements: Arc<HashSet<Key>>,
+
+    /// Approximate bytes of the elements, kept up to date as they change.
+    size: usize,
+}
+
+impl Set {
+    /// Creates a new, empty, instance of [`Set`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.elements.contains(key)
+    }
+
+    /// A set with `key` as an element.
+    pub fn insert(mut self, key: Key) -> Self {
+        if !self.elements.contains(&key) {
+            self.size = self.size.saturating_add(key.value().heap_size());
+            Arc::make_mut(&mut self.elements).insert(key);
+        }
+
+        self
+    }
+
+    /// A set without `key` as an element.
+    pub fn remove(mut self, key: &Key) -> Self {
+        if self.elements.contains(key) {
+            self.size -= key.value().heap_size();
+            Arc::make_mut(&mut self.elements).remove(key);
+        }
+
+        self
+    }
+
+    /// A set with the elements of both sets.
+    pub fn union(self, other: &Set) -> Self {
+        other.iter().cloned().fold(self, Set::insert)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Key> {
+        self.elements.iter()
+    }
+
+    /// Approximate bytes held by the elements of the set.
+    pub fn heap_size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Display for Set {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut elements = self
+            .iter()
+            .map(|key| key.value().to_string())
+            .collect::<Vec<_>>();
+        elements.sort();
+
+        write!(f, "{{{}}}", elements.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, Map, Set};
+    use crate::interpreter::{Tuple, Value};
+
+    fn key(value: Value) -> Key {
+        Key::new(value).unwrap()
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let empty = Map::new();
+        let map = empty.clone().insert(key(Value::Int(1)), Value::Bool(true));
+
+        assert!(empty.is_empty());
+        assert_eq!(map.get(&key(Value::Int(1))).unwrap().to_string(), "true");
+        assert!(map.remove(&key(Value::Int(1))).is_empty());
+    }
+
+    #[test]
+    fn keys_compare_structurally() {
+        let int_first = Value::Tuple(Tuple::new(Value::Int(1), Value::Int(2)));
+        let str_first = Value::Tuple(Tuple::new(Value::Str("1".into()), Value::Int(2)));
+        let map = Map::new().insert(key(int_first.clone()), Value::Int(0));
+
+        assert!(map.get(&key(int_first)).is_some());
+        assert!(map.get(&key(str_first)).is_none());
+    }
+
+    #[test]
+    fn closures_are_not_keys() {
+        let native =
+            crate::interpreter::Native::new("id", 1, |mut arguments, _| Ok(arguments.remove(0)));
+
+        assert!(Key::new(Value::Native(native)).is_none());
+    }
+
+    #[test]
+    fn sizes_follow_the_entries() {
+        let one = key(Value::Int(1));
+        let empty = Map::new();
+        let map = empty.clone().insert(one.clone(), Value::Str("a".into()));
+        let replaced = map.clone().insert(one.clone(), Value::Str("abc".into()));
+
+        assert_eq!(replaced.heap_size(), map.heap_size() + 2);
+        assert_eq!(replaced.remove(&one).heap_size(), empty.heap_size());
+
+        let set = Set::new().insert(one.clone()).insert(one.clone());
+        assert_eq!(set.heap_size(), one.value().heap_size());
+        assert_eq!(set.remove(&one).heap_size(), 0);
+    }
+
+    #[test]
+    fn display_is_sorted() {
+        let map = Map::new()
+            .insert(key(Value::Int(2)), Value::Str("b".into()))
+            .insert(key(Value::Int(1)), Value::Str("a".into()));
+
+        assert_eq!(map.to_string(), "{1: a, 2: b}");
+    }
+
+    #[test]
+    fn set_operations() {
+        let odds = Set::new()
+            .insert(key(Value::Int(1)))
+            .insert(key(Value::Int(3)))
+            .insert(key(Value::Int(1)));
+        let small = Set::new()
+            .insert(key(Value::Int(1)))
+            .insert(key(Value::Int(2)));
+        let union = odds.clone().union(&small);
+
+        assert_eq!(odds.len(), 2);
+        assert!(odds.contains(&key(Value::Int(3))));
+        assert_eq!(union.to_string(), "{1, 2, 3}");
+        assert!(!union
+            .remove(&key(Value::Int(2)))
+            .contains(&key(Value::Int(2))));
+    }
+}
@@ -0,0 +1,706 @@
+use std::{
+    collections::HashSet,
+    mem::take,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    ast::{BinaryOp, Element, Location, Term},
+    interpreter::{Value, RED_ZONE, STACK_SEGMENT},
+    register,
+    symbol::Symbol,
+};
+
+/// An instruction of the stack [`vm`](crate::vm). Operands are popped
+/// from the value stack and results pushed back to it.
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    /// Pushes a constant.
+    Push(Value),
+
+    /// Pushes the value in a local slot of the current frame.
+    Local(usize),
+
+    /// Pops a value into a local slot of the current frame.
+    SetLocal(usize),
+
+    /// Pushes a value captured by the running closure.
+    Captured(usize),
+
+    /// Pushes the running closure itself, so functions bound by a `let`
+    /// can call themselves.
+    Current,
+
+    /// Pushes a global, like a builtin, by its index in
+    /// [`Program::globals`].
+    Global(usize),
+
+    /// Creates a closure of a function of [`Program::functions`],
+    /// capturing the values it refers to.
+    Closure(usize),
+
+    /// Checks that a native callee takes the given number of arguments
+    /// before they are evaluated, as the interpreter does.
+    Callee(usize),
+
+    /// Calls the callee below the given number of arguments.
+    Call(usize),
+
+    /// Calls in tail position, reusing the frame of the running function.
+    TailCall(usize),
+
+    Return,
+    Jump(usize),
+
+    /// Pops a condition, jumping when it doesn't hold.
+    JumpUnless(usize),
+
+    /// Jumps, keeping the left-hand side, when it alone decides the
+    /// result of `&&` or `||`.
+    ShortCircuit(BinaryOp, usize),
+
+    Binary(BinaryOp),
+
+    /// Applies the operator to the value popped and the constant, fused
+    /// from a `Push` and a `Binary`.
+    BinaryConst(BinaryOp, Value),
+
+    /// Pops the operands of a comparison, or only its left-hand side when
+    /// the constant is its right-hand side, jumping unless it holds. Fused
+    /// from a `Binary` or `BinaryConst` comparing and a `JumpUnless`.
+    CompareJumpUnless(BinaryOp, Option<Value>, usize),
+
+    Tuple,
+    First,
+    Second,
+    Print,
+    Pop,
+}
+
+/// Where a closure takes a captured value from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// A local slot of the function creating the closure.
+    Local(usize),
+
+    /// A value captured by the function creating the closure.
+    Captured(usize),
+
+    /// The closure of the function creating the closure.
+    Current,
+}
+
+/// A function lowered to bytecode.
+#[derive(Debug)]
+pub struct Function {
+    /// The name of the `let` binding holding the function, if any.
+    pub name: Option<String>,
+    pub arity: usize,
+
+    /// Number of local slots of a frame, parameters included.
+    pub locals: usize,
+
+    pub captures: Vec<Capture>,
+    pub code: Vec<Instruction>,
+
+    /// The location of the term each instruction was compiled from, where
+    /// errors raised by it are reported.
+    pub locations: Vec<Location>,
+
+    /// The location of the function definition in the source code.
+    pub location: Location,
+
+    /// The code translated for the register machine, the first time it
+    /// runs there.
+    pub(crate) registers: OnceLock<register::Code>,
+}
+
+impl Function {
+    /// The code of the function for the [`register`] machine.
+    pub fn registers(&self) -> &register::Code {
+        self.registers.get_or_init(|| register::translate(self))
+    }
+}
+
+/// A program lowered to bytecode, ready to be run by the
+/// [`vm`](crate::vm).
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// Every function of the program, the entrypoint first.
+    pub functions: Vec<Arc<Function>>,
+
+    /// Names of the free variables of the program, like the builtins,
+    /// looked up in the initial context when it runs.
+    pub globals: Vec<Symbol>,
+}
+
+/// How a variable is accessed from the function being compiled.
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Local(usize),
+    Captured(usize),
+    Current,
+}
+
+/// A function being compiled, with the bindings in scope.
+struct Scope {
+    function: Function,
+
+    /// Bound names with their slot, innermost last.
+    locals: Vec<(Symbol, usize)>,
+    slots: usize,
+
+    /// The name the function is bound to by its `let`, which refers to
+    /// the function itself inside its body.
+    bound_to: Option<Symbol>,
+
+    /// Names already captured, in the order of [`Function::captures`].
+    captured: Vec<Symbol>,
+}
+
+impl Scope {
+    fn new(name: Option<Symbol>, parameters: Vec<Symbol>, location: Location) -> Self {
+        let mut scope = Self {
+            function: Function {
+                name: name.map(|name| name.to_string()),
+                arity: parameters.len(),
+                locals: 0,
+                captures: Vec::new(),
+                code: Vec::new(),
+                locations: Vec::new(),
+                registers: OnceLock::new(),
+                location,
+            },
+            locals: Vec::new(),
+            slots: 0,
+            bound_to: name,
+            captured: Vec::new(),
+        };
+
+        for parameter in parameters {
+            scope.bind(parameter);
+        }
+
+        scope
+    }
+
+    fn bind(&mut self, name: Symbol) -> usize {
+        let slot = self.slots;
+        self.slots += 1;
+        self.function.locals = self.function.locals.max(self.slots);
+        self.locals.push((name, slot));
+
+        slot
+    }
+
+    fn unbind(&mut self) {
+        self.locals.pop();
+        self.slots -= 1;
+    }
+}
+
+/// Lowers `term` into bytecode, as the body of a function taking no
+/// arguments.
+pub fn compile(term: Term) -> Program {
+    let mut compiler = Compiler::default();
+    compiler.function(None, Vec::new(), term.location().clone(), term);
+
+    Program {
+        functions: compiler
+            .functions
+            .into_iter()
+            .map(|function| function.expect("every function is compiled"))
+            .collect(),
+        globals: compiler.globals,
+    }
+}
+
+#[derive(Default)]
+struct Compiler {
+    /// The functions being compiled, innermost last.
+    scopes: Vec<Scope>,
+    functions: Vec<Option<Arc<Function>>>,
+    globals: Vec<Symbol>,
+}
+
+impl Compiler {
+    /// Compiles a function, returning its index in [`Program::functions`].
+    fn function(
+        &mut self,
+        name: Option<Symbol>,
+        parameters: Vec<Symbol>,
+        location: Location,
+        body: Term,
+    ) -> usize {
+        let index = self.functions.len();
+        self.functions.push(None);
+
+        self.scopes.push(Scope::new(name, parameters, location));
+        let end = body.location().clone();
+        self.term(body, true);
+        self.emit(Instruction::Return, &end);
+
+        let mut scope = self.scopes.pop().expect("the function has a scope");
+        fuse(&mut scope.function);
+        self.functions[index] = Some(Arc::new(scope.function));
+
+        index
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn emit(&mut self, instruction: Instruction, location: &Location) -> usize {
+        let function = &mut self.scope().function;
+        function.code.push(instruction);
+        function.locations.push(location.clone());
+
+        function.code.len() - 1
+    }
+
+    /// The address the next instruction is emitted at.
+    fn next(&mut self) -> usize {
+        self.scope().function.code.len()
+    }
+
+    /// Points the jump emitted at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.next();
+
+        match &mut self.scope().function.code[at] {
+            Instruction::Jump(address)
+            | Instruction::JumpUnless(address)
+            | Instruction::ShortCircuit(_, address) => *address = target,
+            instruction => unreachable!("{instruction:?} is not a jump"),
+        }
+    }
+
+    /// Resolves `name` in the function at `depth`, capturing it from the
+    /// enclosing functions when needed.
+    fn resolve(&mut self, depth: usize, name: Symbol) -> Option<Access> {
+        let scope = &self.scopes[depth];
+
+        if let Some((_name, slot)) = scope.locals.iter().rev().find(|(local, _)| *local == name) {
+            return Some(Access::Local(*slot));
+        }
+        if scope.bound_to == Some(name) {
+            return Some(Access::Current);
+        }
+        if let Some(index) = scope.captured.iter().position(|captured| *captured == name) {
+            return Some(Access::Captured(index));
+        }
+        if depth == 0 {
+            return None;
+        }
+
+        let capture = match self.resolve(depth - 1, name)? {
+            Access::Local(slot) => Capture::Local(slot),
+            Access::Captured(index) => Capture::Captured(index),
+            Access::Current => Capture::Current,
+        };
+
+        let scope = &mut self.scopes[depth];
+        scope.function.captures.push(capture);
+        scope.captured.push(name);
+
+        Some(Access::Captured(scope.captured.len() - 1))
+    }
+
+    fn global(&mut self, name: Symbol) -> usize {
+        match self.globals.iter().position(|global| *global == name) {
+            Some(index) => index,
+            None => {
+                self.globals.push(name);
+                self.globals.len() - 1
+            }
+        }
+    }
+
+    /// Compiles `term`, leaving its value on the stack. Calls in `tail`
+    /// position reuse the frame of the running function.
+    fn term(&mut self, mut term: Term, tail: bool) {
+        // As deep as the term nests.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match &mut term {
+            Term::Int(int) => {
+                self.emit(Instruction::Push(Value::Int(int.value)), &int.location);
+            }
+            Term::Str(str) => {
+                let str = take(str);
+
+                self.emit(
+                    Instruction::Push(Value::Str(str.value.into())),
+                    &str.location,
+                );
+            }
+            Term::Bool(bool) => {
+                self.emit(Instruction::Push(Value::Bool(bool.value)), &bool.location);
+            }
+            Term::Unit(unit) => {
+                self.emit(Instruction::Push(Value::Unit), &unit.location);
+            }
+            Term::Var(var) => {
+                let depth = self.scopes.len() - 1;
+                let instruction = match self.resolve(depth, var.text) {
+                    Some(Access::Local(slot)) => Instruction::Local(slot),
+                    Some(Access::Captured(index)) => Instruction::Captured(index),
+                    Some(Access::Current) => Instruction::Current,
+                    None => Instruction::Global(self.global(var.text)),
+                };
+                self.emit(instruction, &var.location);
+            }
+            Term::Let(let_) => {
+                let let_ = take(let_);
+
+                let mut value = Arc::unwrap_or_clone(let_.value);
+                let value = match &mut value {
+                    Term::Function(function) => {
+                        let function = take(function);
+                        let index = self.function(
+                            Some(let_.name.text),
+                            function.parameters.iter().map(|var| var.text).collect(),
+                            function.location.clone(),
+                            Arc::unwrap_or_clone(function.value),
+                        );
+                        self.emit(Instruction::Closure(index), &function.location);
+                        None
+                    }
+                    _ => Some(value),
+                };
+                if let Some(value) = value {
+                    self.term(value, false);
+                }
+
+                let slot = self.scope().bind(let_.name.text);
+                self.emit(Instruction::SetLocal(slot), &let_.location);
+                self.term(Arc::unwrap_or_clone(let_.next), tail);
+                self.scope().unbind();
+            }
+            Term::Function(function) => {
+                let function = take(function);
+
+                let index = self.function(
+                    None,
+                    function.parameters.iter().map(|var| var.text).collect(),
+                    function.location.clone(),
+                    Arc::unwrap_or_clone(function.value),
+                );
+                self.emit(Instruction::Closure(index), &function.location);
+            }
+            Term::Call(call) => {
+                let call = take(call);
+
+                let arguments = call.arguments.len();
+                self.term(Arc::unwrap_or_clone(call.callee), false);
+                self.emit(Instruction::Callee(arguments), &call.location);
+                for argument in call.arguments {
+                    self.term(argument, false);
+                }
+
+                let instruction = match tail {
+                    true => Instruction::TailCall(arguments),
+                    false => Instruction::Call(arguments),
+                };
+                self.emit(instruction, &call.location);
+            }
+            Term::If(if_) => {
+                let if_ = take(if_);
+
+                let location = if_.condition.location().clone();
+                self.term(Arc::unwrap_or_clone(if_.condition), false);
+                let otherwise = self.emit(Instruction::JumpUnless(0), &location);
+                self.term(Arc::unwrap_or_clone(if_.then), tail);
+                let end = self.emit(Instruction::Jump(0), &if_.location);
+                self.patch(otherwise);
+                self.term(Arc::unwrap_or_clone(if_.otherwise), tail);
+                self.patch(end);
+            }
+            Term::Cond(cond) => {
+                let cond = take(cond);
+
+                let mut ends = Vec::new();
+                for arm in cond.arms {
+                    let location = arm.condition.location().clone();
+                    self.term(arm.condition, false);
+                    let next = self.emit(Instruction::JumpUnless(0), &location);
+                    self.term(arm.then, tail);
+                    ends.push(self.emit(Instruction::Jump(0), &cond.location));
+                    self.patch(next);
+                }
+                self.term(Arc::unwrap_or_clone(cond.otherwise), tail);
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            Term::Seq(seq) => {
+                let seq = take(seq);
+
+                let mut terms = seq.terms;
+                let Some(last) = terms.pop() else {
+                    self.emit(Instruction::Push(Value::Unit), &seq.location);
+                    return;
+                };
+
+                for term in terms {
+                    let location = term.location().clone();
+                    self.term(term, false);
+                    self.emit(Instruction::Pop, &location);
+                }
+                self.term(last, tail);
+            }
+            Term::Binary(binary) => {
+                let binary = take(binary);
+
+                let location = binary.lhs.location().clone();
+                self.term(Arc::unwrap_or_clone(binary.lhs), false);
+
+                let short_circuit = match binary.op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        Some(self.emit(Instruction::ShortCircuit(binary.op.clone(), 0), &location))
+                    }
+                    _ => None,
+                };
+
+                self.term(Arc::unwrap_or_clone(binary.rhs), false);
+                self.emit(Instruction::Binary(binary.op), &location);
+
+                if let Some(short_circuit) = short_circuit {
+                    self.patch(short_circuit);
+                }
+            }
+            Term::Tuple(tuple) => {
+                let tuple = take(tuple);
+
+                self.term(Arc::unwrap_or_clone(tuple.first), false);
+                self.term(Arc::unwrap_or_clone(tuple.second), false);
+                self.emit(Instruction::Tuple, &tuple.location);
+            }
+            Term::First(first) => {
+                let first = take(first);
+
+                self.term(Arc::unwrap_or_clone(first.value), false);
+                self.emit(Instruction::First, &first.location);
+            }
+            Term::Second(second) => {
+                let second = take(second);
+
+                self.term(Arc::unwrap_or_clone(second.value), false);
+                self.emit(Instruction::Second, &second.location);
+            }
+            Term::Print(print) => {
+                let print = take(print);
+
+                self.term(Arc::unwrap_or_clone(print.value), false);
+                self.emit(Instruction::Print, &print.location);
+            }
+        })
+    }
+}
+
+/// Fuses the common sequences of instructions of `function` into
+/// superinstructions, so the vm dispatches fewer of them:
+///
+/// - A `Push` of a constant and the `Binary` using it, as in `n - 1`,
+///   into a `BinaryConst`.
+/// - A comparison and the `JumpUnless` branching on it, as in the
+///   condition of an `if`, into a `CompareJumpUnless`.
+/// - The running closure and the `Callee` checking its arity, as in a
+///   recursive call, into the `Current` alone: a compiled closure is never
+///   a native, whose arity is what `Callee` checks.
+///
+/// Instructions jumped to are never fused into the one before them, and
+/// jumps are pointed again at where their target ended up.
+fn fuse(function: &mut Function) {
+    let targets = function
+        .code
+        .iter()
+        .filter_map(Instruction::target)
+        .collect::<HashSet<_>>();
+
+    let mut code = Vec::with_capacity(function.code.len());
+    let mut locations: Vec<Location> = Vec::with_capacity(function.code.len());
+    // Where each instruction ended up, the end of the code included.
+    let mut moved = Vec::with_capacity(function.code.len() + 1);
+
+    for (ip, (instruction, location)) in take(&mut function.code)
+        .into_iter()
+        .zip(take(&mut function.locations))
+        .enumerate()
+    {
+        let fused = match (code.last(), instruction) {
+            (Some(_), instruction) if targets.contains(&ip) => Err(instruction),
+            // The operator fails where the `Binary` did.
+            (Some(Instruction::Push(constant)), Instruction::Binary(op)) => {
+                Ok((Instruction::BinaryConst(op, constant.clone()), true))
+            }
+            (
+                Some(Instruction::Binary(op) | Instruction::BinaryConst(op, _)),
+                Instruction::JumpUnless(target),
+            ) if compares(op) => {
+                let constant = match code.last() {
+                    Some(Instruction::BinaryConst(_op, constant)) => Some(constant.clone()),
+                    _binary => None,
+                };
+
+                Ok((
+                    Instruction::CompareJumpUnless(op.clone(), constant, target),
+                    false,
+                ))
+            }
+            (Some(Instruction::Current), Instruction::Callee(_arguments)) => {
+                Ok((Instruction::Current, false))
+            }
+            (_last, instruction) => Err(instruction),
+        };
+
+        match fused {
+            Ok((fused, relocated)) => {
+                moved.push(code.len() - 1);
+                *code
+                    .last_mut()
+                    .expect("only instructions after another are fused") = fused;
+                if relocated {
+                    *locations.last_mut().expect("every instruction has one") = location;
+                }
+            }
+            Err(instruction) => {
+                moved.push(code.len());
+                code.push(instruction);
+                locations.push(location);
+            }
+        }
+    }
+    moved.push(code.len());
+
+    for instruction in &mut code {
+        if let Some(target) = instruction.target_mut() {
+            *target = moved[*target];
+        }
+    }
+
+    function.code = code;
+    function.locations = locations;
+}
+
+/// Whether `op` compares its operands, always giving a boolean when it
+/// doesn't fail.
+fn compares(op: &BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Neq | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Gte
+    )
+}
+
+impl Instruction {
+    /// Where the instruction may jump to.
+    fn target(&self) -> Option<usize> {
+        match self {
+            Instruction::Jump(target)
+            | Instruction::JumpUnless(target)
+            | Instruction::ShortCircuit(_, target)
+            | Instruction::CompareJumpUnless(_, _, target) => Some(*target),
+            _instruction => None,
+        }
+    }
+
+    fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Instruction::Jump(target)
+            | Instruction::JumpUnless(target)
+            | Instruction::ShortCircuit(_, target)
+            | Instruction::CompareJumpUnless(_, _, target) => Some(target),
+            _instruction => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{compile, Capture, Instruction};
+    use crate::{
+        ast::{BinaryOp, Function, Let, Location, Term, Var},
+        interpreter::Value,
+        symbol::Symbol,
+    };
+
+    fn var(text: &str) -> Var {
+        Var {
+            text: Symbol::new(text),
+            location: Location::default(),
+            slot: None,
+        }
+    }
+
+    #[test]
+    fn deep_terms_are_compiled() {
+        let source = format!("print({})", vec!["1"; 40_000].join(" + "));
+        let program = compile(crate::parser::parse_term(&source, "tests").unwrap());
+
+        // The first operand, each of the others fused with its operator,
+        // the print and the return.
+        assert_eq!(program.functions[0].code.len(), 40_000 + 2);
+    }
+
+    #[test]
+    fn common_sequences_are_fused() {
+        let source = "let f = fn (n) => { if (n < 2) { n } else { f(n - 1) } }; f(3)";
+        let program = compile(crate::parser::parse_term(source, "tests").unwrap());
+
+        assert!(matches!(
+            program.functions[1].code[..],
+            [
+                Instruction::Local(0),
+                Instruction::CompareJumpUnless(BinaryOp::Lt, Some(Value::Int(2)), 4),
+                Instruction::Local(0),
+                Instruction::Jump(8),
+                Instruction::Current,
+                Instruction::Local(0),
+                Instruction::BinaryConst(BinaryOp::Sub, Value::Int(1)),
+                Instruction::TailCall(1),
+                Instruction::Return,
+            ]
+        ));
+    }
+
+    #[test]
+    fn variables_are_resolved_statically() {
+        // let x = print; fn (y) => x(y)
+        let inner = Term::Function(Function {
+            parameters: Arc::from([var("y")]),
+            value: Arc::new(Term::Call(crate::ast::Call {
+                callee: Arc::new(Term::Var(var("x"))),
+                arguments: vec![Term::Var(var("y"))],
+                location: Location::default(),
+            })),
+            location: Location::default(),
+            free: None,
+            layout: None,
+            digest: None,
+            pure: None,
+        });
+        let program = compile(Term::Let(Let {
+            name: var("x"),
+            value: Arc::new(Term::Var(var("typeof"))),
+            next: Arc::new(inner),
+            location: Location::default(),
+        }));
+
+        assert_eq!(program.globals, [Symbol::new("typeof")]);
+        assert_eq!(program.functions.len(), 2);
+        assert_eq!(program.functions[1].captures, [Capture::Local(0)]);
+        assert!(matches!(
+            program.functions[1].code[..],
+            [
+                Instruction::Captured(0),
+                Instruction::Callee(1),
+                Instruction::Local(0),
+                Instruction::TailCall(1),
+                Instruction::Return,
+            ]
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+use std::fmt::Display;
+
+use crate::{
+    ast::Location,
+    collections::{Map, Set},
+    interpreter::{Native, RuntimeError, RuntimeErrorKind, Tuple, Value},
+    text::Text,
+};
+
+/// Rust types that values of programs can be converted to, for the
+/// arguments of natives created with [`Native::from_fn`].
+pub trait FromValue: Sized {
+    /// What the values converted from look like, like "an int", for error
+    /// messages.
+    fn expected() -> String;
+
+    /// Converts `value`, or returns `None` when it isn't of the right type.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+/// Rust types that can be converted to values of programs, for what
+/// natives created with [`Native::from_fn`] return.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// What natives created with [`Native::from_fn`] may return: a value, or
+/// a [`Result`] whose errors fail the program.
+pub trait IntoResult {
+    fn into_result(self, name: &str, location: &Location) -> Result<Value, RuntimeError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self, _name: &str, _location: &Location) -> Result<Value, RuntimeError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: Display> IntoResult for Result<T, E> {
+    fn into_result(self, name: &str, location: &Location) -> Result<Value, RuntimeError> {
+        self.map(IntoValue::into_value)
+            .map_err(|error| RuntimeError {
+                kind: RuntimeErrorKind::Failed,
+                message: format!("{name} failed"),
+                full_text: error.to_string(),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            })
+    }
+}
+
+/// Rust functions callable by programs, taking arguments that are
+/// [`FromValue`] and returning an [`IntoResult`]. `Arguments` is the tuple
+/// of their argument types, telling apart the implementations for each
+/// number of arguments.
+pub trait HostFunction<Arguments>: Send + Sync + 'static {
+    const ARITY: usize;
+
+    /// Calls the function with `arguments`, of which there are
+    /// [`ARITY`](Self::ARITY), converting them and its result.
+    fn call(
+        &self,
+        arguments: Vec<Value>,
+        name: &str,
+        location: &Location,
+    ) -> Result<Value, RuntimeError>;
+}
+
+macro_rules! host_function {
+    ($($argument:ident),*) => {
+        impl<F, R, $($argument),*> HostFunction<($($argument,)*)> for F
+        where
+            F: Fn($($argument),*) -> R + Send + Sync + 'static,
+            R: IntoResult,
+            $($argument: FromValue,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($argument)),*]);
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(
+                &self,
+                arguments: Vec<Value>,
+                name: &str,
+                location: &Location,
+            ) -> Result<Value, RuntimeError> {
+                let mut arguments = arguments.into_iter().enumerate();
+                $(
+                    let (index, value) = arguments
+                        .next()
+                        .expect("natives are called with as many arguments as their arity");
+                    let $argument = argument::<$argument>(value, index, name, location)?;
+                )*
+
+                self($($argument),*).into_result(name, location)
+            }
+        }
+    };
+}
+
+host_function!();
+host_function!(A);
+host_function!(A, B);
+host_function!(A, B, C);
+host_function!(A, B, C, D);
+host_function!(A, B, C, D, E);
+host_function!(A, B, C, D, E, G);
+
+fn argument<T: FromValue>(
+    value: Value,
+    index: usize,
+    name: &str,
+    location: &Location,
+) -> Result<T, RuntimeError> {
+    T::from_value(&value).ok_or_else(|| RuntimeError {
+        kind: RuntimeErrorKind::InvalidArgument,
+        message: String::from("invalid argument"),
+        full_text: format!(
+            "{name} expects {} as argument {} but got {value}, which is {}",
+            T::expected(),
+            index + 1,
+            value.type_name()
+        ),
+        location: location.clone(),
+        backtrace: Vec::new(),
+    })
+}
+
+impl Native {
+    /// Creates a new instance of [`Native`] calling a Rust function, whose
+    /// arguments and result are converted from and to values, so
+    /// `Native::from_fn("add", |a: i64, b: i64| a + b)` can be called with
+    /// two ints. Arguments of the wrong type fail the call.
+    pub fn from_fn<Arguments, F>(name: &str, function: F) -> Self
+    where
+        F: HostFunction<Arguments>,
+    {
+        let owned = name.to_string();
+
+        Self::new(name, F::ARITY, move |arguments, location| {
+            function.call(arguments, &owned, location)
+        })
+    }
+}
+
+impl FromValue for Value {
+    fn expected() -> String {
+        String::from("any value")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for i64 {
+    fn expected() -> String {
+        String::from("an int")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromValue for bool {
+    fn expected() -> String {
+        String::from("a bool")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for Text {
+    fn expected() -> String {
+        String::from("a str")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Str(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for Text {
+    fn into_value(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl FromValue for String {
+    fn expected() -> String {
+        Text::expected()
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Text::from_value(value).map(|text| text.as_str().to_string())
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl FromValue for () {
+    fn expected() -> String {
+        String::from("unit")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Unit => Some(()),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Unit
+    }
+}
+
+impl<A: FromValue, B: FromValue> FromValue for (A, B) {
+    fn expected() -> String {
+        format!("a tuple of {} and {}", A::expected(), B::expected())
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let Value::Tuple(tuple) = value else {
+            return None;
+        };
+
+        Some((
+            A::from_value(tuple.first())?,
+            B::from_value(tuple.second())?,
+        ))
+    }
+}
+
+impl<A: IntoValue, B: IntoValue> IntoValue for (A, B) {
+    fn into_value(self) -> Value {
+        Value::Tuple(Tuple::new(self.0.into_value(), self.1.into_value()))
+    }
+}
+
+impl FromValue for Map {
+    fn expected() -> String {
+        String::from("a map")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Map(map) => Some(map.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for Map {
+    fn into_value(self) -> Value {
+        Value::Map(self)
+    }
+}
+
+impl FromValue for Set {
+    fn expected() -> String {
+        String::from("a set")
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Set(set) => Some(set.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for Set {
+    fn into_value(self) -> Value {
+        Value::Set(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostFunction;
+    use crate::{
+        ast::Location,
+        interpreter::{RuntimeError, Tuple, Value},
+    };
+
+    fn call<A>(
+        name: &str,
+        function: impl HostFunction<A>,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let location = Location {
+            start: 0,
+            end: 0,
+            filename: "tests".into(),
+        };
+
+        function.call(arguments, name, &location)
+    }
+
+    #[test]
+    fn arguments_and_results_are_converted() {
+        let sum = call(
+            "add",
+            |a: i64, b: i64| a + b,
+            vec![Value::Int(1), Value::Int(2)],
+        );
+        assert_eq!(sum.unwrap().to_string(), "3");
+
+        let pair = Value::Tuple(Tuple::new(Value::Str("a".into()), Value::Bool(true)));
+        let swapped = call("swap", |(a, b): (String, bool)| (b, a), vec![pair]);
+        assert_eq!(swapped.unwrap().to_string(), "(true, a)");
+    }
+
+    #[test]
+    fn mismatches_fail_the_call() {
+        let add = |a: i64, b: i64| a + b;
+        let error = call("add", add, vec![Value::Int(1), Value::Bool(true)]).unwrap_err();
+        assert_eq!(
+            error.full_text,
+            "add expects an int as argument 2 but got true, which is bool"
+        );
+
+        let parse = |text: String| text.parse::<i64>();
+        let error = call("parse", parse, vec![Value::Str("x".into())]).unwrap_err();
+        assert_eq!(error.message, "parse failed");
+        assert_eq!(error.full_text, "invalid digit found in string");
+    }
+}
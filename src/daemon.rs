@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    ast::Term,
+    interpreter::{RuntimeError, Value},
+    session::Session,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Error code of requests whose evaluation failed, carrying the
+/// [`RuntimeError`] as data.
+pub const RUNTIME_ERROR: i64 = 1;
+
+#[derive(Debug)]
+struct Error {
+    code: i64,
+    message: String,
+    data: Option<Json>,
+}
+
+impl Error {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Self {
+            code: RUNTIME_ERROR,
+            message: error.message.clone(),
+            data: Some(json!(error)),
+        }
+    }
+}
+
+/// Evaluation sessions driven by JSON-RPC 2.0 requests, so editors and
+/// notebooks can evaluate programs interactively.
+///
+/// The methods are:
+/// - `session/create`: creates a session, returning its `session` id.
+/// - `session/define` `{ session, name, term }`: evaluates `term` and
+///   binds it to `name` in the session.
+/// - `session/eval` `{ session, term }`: evaluates `term` in the session.
+/// - `session/env` `{ session }`: the bindings of the session.
+/// - `session/destroy` `{ session }`: drops the session.
+///
+/// Terms are given as rinha JSON AST nodes. Evaluations answer with the
+/// `value`, its `type` and the `output` printed meanwhile. Failed ones
+/// answer with the [`RuntimeError`] and the `output` as the data of the
+/// error.
+#[derive(Debug, Default)]
+pub struct Daemon {
+    sessions: HashMap<u64, Session>,
+    next_id: u64,
+}
+
+impl Daemon {
+    /// Creates a new instance of [`Daemon`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests read line by line from `input`, writing one
+    /// response per line to `output`, until `input` ends.
+    pub fn serve<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> std::io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line) {
+                writeln!(output, "{response}")?;
+                output.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers a single JSON-RPC message. Notifications, the requests
+    /// without an id, get no response.
+    pub fn handle(&mut self, message: &str) -> Option<Json> {
+        let request = match serde_json::from_str::<Json>(message) {
+            Ok(request) => request,
+            Err(error) => {
+                let error = Error::new(PARSE_ERROR, error.to_string());
+                return Some(response(Json::Null, Err(error)));
+            }
+        };
+
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(Json::as_str) {
+            Some(method) => self.call(method, request.get("params").unwrap_or(&Json::Null)),
+            None => Err(Error::new(INVALID_REQUEST, "missing method")),
+        };
+
+        id.map(|id| response(id, result))
+    }
+
+    fn call(&mut self, method: &str, params: &Json) -> Result<Json, Error> {
+        match method {
+            "session/create" => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.sessions.insert(id, Session::new());
+
+                Ok(json!({ "session": id }))
+            }
+            "session/define" => {
+                let name = param::<String>(params, "name")?;
+                let term = param::<Term>(params, "term")?;
+                let session = self.session(params)?;
+                let value = session.define(&name, term);
+
+                evaluated(value, session.take_output())
+            }
+            "session/eval" => {
+                let term = param::<Term>(params, "term")?;
+                let session = self.session(params)?;
+                let value = session.eval(term);
+
+                evaluated(value, session.take_output())
+            }
+            "session/env" => {
+                let bindings = self
+                    .session(params)?
+                    .bindings()
+                    .into_iter()
+                    .map(|(name, value)| {
+                        json!({
+                            "name": name,
+                            "value": value.to_string(),
+                            "type": value.type_name(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok(json!({ "bindings": bindings }))
+            }
+            "session/destroy" => {
+                let id = param::<u64>(params, "session")?;
+
+                match self.sessions.remove(&id) {
+                    Some(_session) => Ok(Json::Bool(true)),
+                    None => Err(unknown_session(id)),
+                }
+            }
+            method => Err(Error::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method}"),
+            )),
+        }
+    }
+
+    fn session(&mut self, params: &Json) -> Result<&mut Session, Error> {
+        let id = param::<u64>(params, "session")?;
+
+        self.sessions
+            .get_mut(&id)
+            .ok_or_else(|| unknown_session(id))
+    }
+}
+
+fn unknown_session(id: u64) -> Error {
+    Error::new(INVALID_PARAMS, format!("unknown session {id}"))
+}
+
+fn param<T: serde::de::DeserializeOwned>(params: &Json, name: &str) -> Result<T, Error> {
+    let param = params
+        .get(name)
+        .cloned()
+        .ok_or_else(|| Error::new(INVALID_PARAMS, format!("missing parameter {name}")))?;
+
+    serde_json::from_value(param)
+        .map_err(|error| Error::new(INVALID_PARAMS, format!("invalid parameter {name}: {error}")))
+}
+
+/// The answer to an evaluation, with the `output` printed meanwhile even
+/// when it failed, so it doesn't show up in the next evaluation instead.
+fn evaluated(value: Result<Value, RuntimeError>, output: String) -> Result<Json, Error> {
+    match value {
+        Ok(value) => Ok(json!({
+            "value": value.to_string(),
+            "type": value.type_name(),
+            "output": output,
+        })),
+        Err(error) => {
+            let mut error = Error::from(error);
+            if let Some(data) = &mut error.data {
+                data["output"] = json!(output);
+            }
+
+            Err(error)
+        }
+    }
+}
+
+fn response(id: Json, result: Result<Json, Error>) -> Json {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => {
+            let mut error_ = json!({ "code": error.code, "message": error.message });
+
+            if let Some(data) = error.data {
+                error_["data"] = data;
+            }
+
+            json!({ "jsonrpc": "2.0", "id": id, "error": error_ })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Daemon, METHOD_NOT_FOUND, RUNTIME_ERROR};
+
+    fn location() -> serde_json::Value {
+        json!({ "start": 0, "end": 0, "filename": "tests" })
+    }
+
+    fn request(daemon: &mut Daemon, method: &str, params: serde_json::Value) -> serde_json::Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+
+        daemon.handle(&request.to_string()).unwrap()
+    }
+
+    #[test]
+    fn session_lifecycle() {
+        let mut daemon = Daemon::new();
+        let created = request(&mut daemon, "session/create", json!(null));
+        let session = created["result"]["session"].clone();
+
+        let int = json!({ "kind": "Int", "value": 2, "location": location() });
+        let defined = request(
+            &mut daemon,
+            "session/define",
+            json!({ "session": session, "name": "x", "term": int }),
+        );
+        assert_eq!(defined["result"]["value"], "2");
+
+        let var = json!({ "kind": "Var", "text": "x", "location": location() });
+        let print = json!({ "kind": "Print", "value": var, "location": location() });
+        let evaluated = request(
+            &mut daemon,
+            "session/eval",
+            json!({ "session": session, "term": print }),
+        );
+        assert_eq!(evaluated["result"]["output"], "2\n");
+
+        let env = request(&mut daemon, "session/env", json!({ "session": session }));
+        assert_eq!(env["result"]["bindings"][0]["name"], "x");
+
+        let destroyed = request(
+            &mut daemon,
+            "session/destroy",
+            json!({ "session": session }),
+        );
+        assert_eq!(destroyed["result"], true);
+
+        let gone = request(&mut daemon, "session/env", json!({ "session": session }));
+        assert!(gone["error"].is_object());
+    }
+
+    #[test]
+    fn runtime_errors_carry_data() {
+        let mut daemon = Daemon::new();
+        request(&mut daemon, "session/create", json!(null));
+
+        let var = json!({ "kind": "Var", "text": "nope", "location": location() });
+        let failed = request(
+            &mut daemon,
+            "session/eval",
+            json!({ "session": 0, "term": var }),
+        );
+
+        assert_eq!(failed["error"]["code"], RUNTIME_ERROR);
+        assert_eq!(failed["error"]["data"]["location"]["filename"], "tests");
+    }
+
+    #[test]
+    fn output_of_failed_evaluations_is_not_carried_over() {
+        let mut daemon = Daemon::new();
+        request(&mut daemon, "session/create", json!(null));
+
+        // print(1); nope
+        let print = json!({ "kind": "Print", "location": location(),
+                            "value": { "kind": "Int", "value": 1, "location": location() } });
+        let var = json!({ "kind": "Var", "text": "nope", "location": location() });
+        let seq = json!({ "kind": "Seq", "terms": [print, var], "location": location() });
+        let failed = request(
+            &mut daemon,
+            "session/eval",
+            json!({ "session": 0, "term": seq }),
+        );
+        assert_eq!(failed["error"]["data"]["output"], "1\n");
+
+        let int = json!({ "kind": "Int", "value": 2, "location": location() });
+        let evaluated = request(
+            &mut daemon,
+            "session/eval",
+            json!({ "session": 0, "term": int }),
+        );
+        assert_eq!(evaluated["result"]["value"], "2");
+        assert_eq!(evaluated["result"]["output"], "");
+    }
+
+    #[test]
+    fn unknown_method() {
+        let mut daemon = Daemon::new();
+        let failed = request(&mut daemon, "session/nope", json!(null));
+
+        assert_eq!(failed["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let mut daemon = Daemon::new();
+        let notification = json!({ "jsonrpc": "2.0", "method": "session/create" });
+
+        assert!(daemon.handle(&notification.to_string()).is_none());
+    }
+}
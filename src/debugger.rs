@@ -0,0 +1,257 @@
+use crate::{
+    ast::{Element, Location, Term},
+    interpreter::{Context, RuntimeError, Value},
+    observe::EvalObserver,
+};
+
+/// How to go on from a [`Pause`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Pauses before the next term evaluated, nested in this one or not.
+    Step,
+
+    /// Pauses before the next term evaluated that isn't nested in this
+    /// one, stepping over it.
+    Next,
+
+    /// Pauses at the next breakpoint only.
+    Continue,
+}
+
+/// Why the evaluation paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The evaluation just started, or the last command was a step.
+    Step,
+    Breakpoint,
+}
+
+/// The evaluation stopped before evaluating a term, given to the callback
+/// of the [`Debugger`].
+#[derive(Debug)]
+pub struct Pause<'a> {
+    pub reason: Reason,
+
+    /// The term about to be evaluated.
+    pub term: &'a Term,
+
+    /// How many terms deep the term is nested.
+    pub depth: usize,
+
+    /// The variables in scope of the term, by name when the program isn't
+    /// [resolved](crate::embed::Builder::resolve).
+    pub context: &'a Context,
+
+    /// The values of the terms evaluated so far inside the ones still being
+    /// evaluated, like the left operand of an addition while its right
+    /// one is, innermost last.
+    pub stack: &'a [Value],
+}
+
+/// Pauses evaluations before the terms at its breakpoints, or as told when
+/// stepping, calling back to learn how to go on. Set it as the observer of
+/// a run, with [`Builder::observer`](crate::embed::Builder::observer).
+///
+/// The evaluation pauses before its first term, as if stepping into it.
+/// A [`SourceMap`](crate::source_map::SourceMap) tells the line and column
+/// of the terms paused at.
+///
+/// ```
+/// use lipsum::{
+///     ast::{Element, Location},
+///     debugger::{Command, Debugger},
+///     embed::Interpreter,
+///     interpreter::Capture,
+///     parser::parse,
+///     source_map::{Position, SourceMap},
+/// };
+///
+/// let source = "let x = 20;\nlet y = x + 1;\nprint(x + y)";
+/// let file = parse(source, "main.rinha").unwrap();
+/// let mut sources = SourceMap::new();
+/// sources.add("main.rinha", source);
+///
+/// // Stops at `print(x + y)`, which is the term at 27..39.
+/// let debugger = Debugger::new(move |pause| {
+///     if pause.term.to_source() == "print(x + y)" {
+///         let start = sources.start(pause.term.location());
+///         assert_eq!(start, Some(Position { line: 3, column: 1 }));
+///
+///         let x = pause.context.get("x".into()).unwrap();
+///         assert_eq!(x.to_string(), "20");
+///     }
+///
+///     Command::Continue
+/// })
+/// .breakpoint(Location::new(27, 39, "main.rinha"));
+///
+/// let mut interpreter = Interpreter::builder()
+///     .printer(Capture::new())
+///     .resolve(false)
+///     .observer(debugger)
+///     .build();
+/// interpreter.run(file).unwrap();
+/// ```
+pub struct Debugger<F> {
+    on_pause: F,
+    breakpoints: Vec<Location>,
+    mode: Mode,
+
+    /// The values of [`Pause::stack`].
+    stack: Vec<Value>,
+
+    /// The length of the stack when each of the terms being evaluated was
+    /// entered, outermost first.
+    entered: Vec<usize>,
+}
+
+/// Where to pause next, besides breakpoints.
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Step,
+
+    /// At the next term no deeper than this.
+    Next(usize),
+    Continue,
+}
+
+impl<F: FnMut(&Pause) -> Command + Send> Debugger<F> {
+    /// Creates a new instance of [`Debugger`], calling `on_pause` whenever
+    /// the evaluation pauses.
+    pub fn new(on_pause: F) -> Self {
+        Self {
+            on_pause,
+            breakpoints: Vec::new(),
+            mode: Mode::Step,
+            stack: Vec::new(),
+            entered: Vec::new(),
+        }
+    }
+
+    /// Pauses before evaluating the term at `location`, as parsed, every
+    /// time it is evaluated.
+    pub fn breakpoint(mut self, location: Location) -> Self {
+        self.breakpoints.push(location);
+        self
+    }
+}
+
+impl<F: FnMut(&Pause) -> Command + Send> EvalObserver for Debugger<F> {
+    fn on_enter(&mut self, term: &Term, depth: usize, context: &Context) {
+        let reason = match self.mode {
+            Mode::Step => Some(Reason::Step),
+            Mode::Next(deepest) if depth <= deepest => Some(Reason::Step),
+            _ if self.breakpoints.contains(term.location()) => Some(Reason::Breakpoint),
+            _ => None,
+        };
+
+        if let Some(reason) = reason {
+            let command = (self.on_pause)(&Pause {
+                reason,
+                term,
+                depth,
+                context,
+                stack: &self.stack,
+            });
+
+            self.mode = match command {
+                Command::Step => Mode::Step,
+                Command::Next => Mode::Next(depth),
+                Command::Continue => Mode::Continue,
+            };
+        }
+
+        self.entered.push(self.stack.len());
+    }
+
+    fn on_exit(&mut self, _term: &Term, _depth: usize, result: Result<&Value, &RuntimeError>) {
+        let entered = self.entered.pop().expect("terms exit once entered");
+        self.stack.truncate(entered);
+
+        if let Ok(value) = result {
+            self.stack.push(value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Command, Debugger};
+    use crate::{
+        ast::Location, embed::Interpreter, interpreter::Capture, parser::parse, symbol::Symbol,
+    };
+
+    /// Runs `source` with a debugger answering the pauses with `commands`,
+    /// continuing once they run out, returning what was seen at each pause.
+    fn debug(source: &str, breakpoints: &[Location], commands: Vec<Command>) -> Vec<String> {
+        let pauses = Arc::new(Mutex::new(Vec::new()));
+        let mut commands = commands.into_iter();
+
+        let seen = pauses.clone();
+        let mut debugger = Debugger::new(move |pause| {
+            let x = pause
+                .context
+                .get(Symbol::new("x"))
+                .map_or(String::from("-"), ToString::to_string);
+            let stack = pause.stack.iter().map(ToString::to_string);
+            let stack = stack.collect::<Vec<_>>().join(" ");
+
+            seen.lock().unwrap().push(format!(
+                "{:?} {} x={x} [{stack}]",
+                pause.reason,
+                pause.term.to_source()
+            ));
+
+            commands.next().unwrap_or(Command::Continue)
+        });
+        for breakpoint in breakpoints {
+            debugger = debugger.breakpoint(breakpoint.clone());
+        }
+
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .resolve(false)
+            .observer(debugger)
+            .build();
+        interpreter
+            .run(parse(source, "main.rinha").unwrap())
+            .unwrap();
+
+        let pauses = pauses.lock().unwrap();
+        pauses.clone()
+    }
+
+    #[test]
+    fn steps_pause_at_every_term() {
+        let source = "let x = 1; x + (2 * x)";
+        let commands = vec![Command::Step, Command::Next, Command::Step, Command::Next];
+
+        assert_eq!(
+            debug(source, &[], commands),
+            [
+                "Step let x = 1;\nx + 2 * x x=- []",
+                "Step 1 x=- []",
+                "Step x + 2 * x x=1 [1]",
+                "Step x x=1 [1]",
+                "Step 2 * x x=1 [1 1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoints_pause_every_time() {
+        let source = "let f = fn (x) => { x * 2 }; f(1) + f(2)";
+        let body = Location::new(20, 25, "main.rinha");
+
+        assert_eq!(
+            debug(source, &[body], vec![Command::Continue]),
+            [
+                "Step let f = fn (x) => {\n  x * 2\n};\nf(1) + f(2) x=- []",
+                "Breakpoint x * 2 x=1 [[closure] [closure] 1]",
+                "Breakpoint x * 2 x=2 [[closure] 2 [closure] 2]",
+            ]
+        );
+    }
+}
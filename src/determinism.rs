@@ -0,0 +1,153 @@
+use crate::{
+    ast::Term,
+    interpreter::{eval, Capture, Context, State},
+};
+
+/// What a run of a program observably did: its result, what it printed
+/// and how much work it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The resulting value, or the error the run failed with.
+    pub result: Result<String, String>,
+    pub output: String,
+    pub steps: u64,
+    pub calls: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl Outcome {
+    fn run(term: Term, context: &mut Context, state: &mut State) -> Self {
+        let mut capture = Capture::new();
+        let result = eval(term, context, state, &mut capture)
+            .map(|value| value.to_string())
+            .map_err(|error| {
+                let location = error.location;
+                format!(
+                    "{}: {} at {}:{}..{}",
+                    error.message, error.full_text, location.filename, location.start, location.end
+                )
+            });
+
+        Self {
+            result,
+            output: capture.take(),
+            steps: state.stats.steps,
+            calls: state.stats.calls,
+            cache_hits: state.stats.cache_hits,
+            cache_misses: state.stats.cache_misses,
+        }
+    }
+
+    /// Describes how `other` differs from this outcome, if at all.
+    fn differences(&self, other: &Outcome) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if self.result != other.result {
+            let describe = |result: &Result<String, String>| match result {
+                Ok(value) => format!("the value {value}"),
+                Err(message) => format!("the error \"{message}\""),
+            };
+            differences.push(format!(
+                "resulted in {} and {}",
+                describe(&self.result),
+                describe(&other.result)
+            ));
+        }
+        if self.output != other.output {
+            differences.push(format!("printed {:?} and {:?}", self.output, other.output));
+        }
+
+        let counters = [
+            ("steps", self.steps, other.steps),
+            ("calls", self.calls, other.calls),
+            ("cache hits", self.cache_hits, other.cache_hits),
+            ("cache misses", self.cache_misses, other.cache_misses),
+        ];
+        for (counter, first, second) in counters {
+            if first != second {
+                differences.push(format!("took {first} and {second} {counter}"));
+            }
+        }
+
+        differences
+    }
+}
+
+/// Runs `term` twice, each time in a fresh context and state set up by
+/// `prepare` with the same builtins, capabilities and limits, and checks
+/// that both runs had the same outcome.
+///
+/// A difference means the program depends on something outside of it,
+/// like the time or the environment, so its results can't be reproduced.
+pub fn verify(term: Term, prepare: impl Fn(&mut Context, &mut State)) -> Result<Outcome, String> {
+    let run = || {
+        let mut context = Context::new();
+        let mut state = State::new();
+        prepare(&mut context, &mut state);
+
+        Outcome::run(term.clone(), &mut context, &mut state)
+    };
+
+    let first = run();
+    let second = run();
+
+    match first.differences(&second).as_slice() {
+        [] => Ok(first),
+        differences => Err(format!(
+            "nondeterministic evaluation: the runs {}",
+            differences.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::verify;
+    use crate::{
+        ast::{Call, Location, Print, Term, Var},
+        builtins,
+        interpreter::MockClock,
+    };
+
+    fn call(name: &str) -> Term {
+        Term::Print(Print {
+            value: Arc::new(Term::Call(Call {
+                callee: Arc::new(Term::Var(Var {
+                    text: name.into(),
+                    location: Location::default(),
+                    slot: None,
+                })),
+                arguments: vec![],
+                location: Location::default(),
+            })),
+            location: Location::default(),
+        })
+    }
+
+    #[test]
+    fn identical_runs_pass() {
+        let outcome = verify(call("set"), |context, _state| builtins::install(context)).unwrap();
+
+        assert_eq!(outcome.output, "{}\n");
+        assert_eq!(outcome.calls, 0);
+    }
+
+    #[test]
+    fn time_dependent_runs_fail() {
+        let clock = MockClock::new(0);
+        let error = verify(call("now"), |context, _state| {
+            clock.advance(5);
+            builtins::install_clock(context, clock.clone());
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            "nondeterministic evaluation: the runs resulted in the value 5 and the value 10, \
+             printed \"5\\n\" and \"10\\n\""
+        );
+    }
+}
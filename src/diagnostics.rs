@@ -0,0 +1,156 @@
+use std::fmt::Display;
+
+use crate::{
+    ast::Location,
+    warnings::{Warning, WarningKind},
+};
+
+/// Something worth knowing about a program that didn't stop it, found
+/// while checking, optimizing or evaluating it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+
+    /// What was found, and where.
+    pub message: String,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum DiagnosticKind {
+    /// See [`WarningKind::ShadowedBinding`].
+    ShadowedBinding,
+
+    /// See [`WarningKind::ShadowedCapture`].
+    ShadowedCapture,
+
+    /// A `let` binds a name never referred to after it. Names starting
+    /// with `_`, like the `let _ = print(x)` of effects, are left out.
+    UnusedBinding,
+
+    /// An integer operation overflowed, and its result wrapped around.
+    Overflow,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        let kind = match warning.kind {
+            WarningKind::ShadowedBinding => DiagnosticKind::ShadowedBinding,
+            WarningKind::ShadowedCapture => DiagnosticKind::ShadowedCapture,
+        };
+
+        Self {
+            kind,
+            message: warning.to_string(),
+            location: warning.location.clone(),
+        }
+    }
+}
+
+/// Collects the diagnostics of the programs checked, optimized and
+/// evaluated with it, in the order they were found, to report them
+/// together once a run ends. Set it as the
+/// [`diagnostics`](crate::interpreter::State::diagnostics) of the state of
+/// a run, or pass it to
+/// [`optimize_reporting`](crate::optimize::optimize_reporting).
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Creates a new instance of [`Diagnostics`], with nothing collected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// The diagnostics collected so far.
+    pub fn as_slice(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Takes the diagnostics collected so far, to report them.
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// A binding of `name` at `location` is never referred to.
+    pub(crate) fn unused_binding(&mut self, name: &str, location: &Location) {
+        let Location {
+            start,
+            end,
+            filename,
+        } = location;
+
+        self.push(Diagnostic {
+            kind: DiagnosticKind::UnusedBinding,
+            message: format!("`{name}` at {filename}:{start}..{end} is never used"),
+            location: location.clone(),
+        });
+    }
+
+    /// The operation at `location` overflowed, wrapping around to `value`.
+    pub(crate) fn overflow(&mut self, value: i64, location: &Location) {
+        let Location {
+            start,
+            end,
+            filename,
+        } = location;
+
+        self.push(Diagnostic {
+            kind: DiagnosticKind::Overflow,
+            message: format!("the operation at {filename}:{start}..{end} overflowed to {value}"),
+            location: location.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiagnosticKind;
+    use crate::{embed::Interpreter, interpreter::Capture, parser::parse};
+
+    #[test]
+    fn diagnostics_of_every_pass_are_collected() {
+        let source = "let x = 1; let unused = 2; let _ = print(x); \
+                      let x = 9223372036854775807; print(x + 2)";
+
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .optimize(true)
+            .diagnostics()
+            .build();
+        let value = interpreter.run(parse(source, "main.rinha").unwrap());
+        assert_eq!(value.unwrap().to_string(), "-9223372036854775807");
+        assert_eq!(interpreter.printer().output(), "1\n-9223372036854775807\n");
+
+        let diagnostics = interpreter.take_diagnostics();
+        let kinds = diagnostics.iter().map(|diagnostic| diagnostic.kind);
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            [
+                DiagnosticKind::UnusedBinding,
+                DiagnosticKind::ShadowedBinding,
+                DiagnosticKind::Overflow,
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "`unused` at main.rinha:15..21 is never used"
+        );
+        assert_eq!(
+            diagnostics[2].to_string(),
+            "the operation at main.rinha:80..85 overflowed to -9223372036854775807"
+        );
+    }
+}
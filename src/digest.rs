@@ -0,0 +1,256 @@
+use std::{fmt::Display, hash::Hasher, sync::Arc};
+
+use crate::{
+    ast::{Slot, Term, Var},
+    hashing::StableHasher,
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+};
+
+/// The 128-bit digest of a term. It is kept as two halves, aligned like a
+/// `u64` rather than a `u128`, so it doesn't pad the terms and closures
+/// holding it.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Digest([u64; 2]);
+
+impl Digest {
+    pub fn as_u128(self) -> u128 {
+        (self.0[0] as u128) << 64 | self.0[1] as u128
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.as_u128())
+    }
+}
+
+/// Fills in the digest of the body of every function in `term`, so the
+/// cache key of a call is built without hashing the body again on every
+/// call.
+///
+/// Functions are digested innermost first, and hashing a function with a
+/// digest only hashes the digest, so the whole term is hashed once.
+pub fn annotate(term: &mut Term) {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+        Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Var(_) => {}
+        Term::Function(function) => {
+            annotate(Arc::make_mut(&mut function.value));
+            function.digest = Some(of(&function.value));
+        }
+        Term::Let(let_) => {
+            annotate(Arc::make_mut(&mut let_.value));
+            annotate(Arc::make_mut(&mut let_.next));
+        }
+        Term::Call(call) => {
+            annotate(Arc::make_mut(&mut call.callee));
+            call.arguments.iter_mut().for_each(annotate);
+        }
+        Term::Binary(binary) => {
+            annotate(Arc::make_mut(&mut binary.lhs));
+            annotate(Arc::make_mut(&mut binary.rhs));
+        }
+        Term::If(if_) => {
+            annotate(Arc::make_mut(&mut if_.condition));
+            annotate(Arc::make_mut(&mut if_.then));
+            annotate(Arc::make_mut(&mut if_.otherwise));
+        }
+        Term::Cond(cond) => {
+            for arm in &mut cond.arms {
+                annotate(&mut arm.condition);
+                annotate(&mut arm.then);
+            }
+            annotate(Arc::make_mut(&mut cond.otherwise));
+        }
+        Term::Seq(seq) => seq.terms.iter_mut().for_each(annotate),
+        Term::Print(print) => annotate(Arc::make_mut(&mut print.value)),
+        Term::First(first) => annotate(Arc::make_mut(&mut first.value)),
+        Term::Second(second) => annotate(Arc::make_mut(&mut second.value)),
+        Term::Tuple(tuple) => {
+            annotate(Arc::make_mut(&mut tuple.first));
+            annotate(Arc::make_mut(&mut tuple.second));
+        }
+    })
+}
+
+/// The digest of `term`, the same on every platform and in every process,
+/// so it can name what is cached for the term on disk.
+///
+/// Locations are left out, so a function keeps its digest when the code
+/// around it moves, like when the lines above it are edited.
+pub fn of(term: &Term) -> Digest {
+    let mut hasher = StableHasher::new();
+    feed(term, &mut hasher);
+    let hash = hasher.finish128();
+
+    Digest([(hash >> 64) as u64, hash as u64])
+}
+
+/// Feeds `term` to `hasher` as a tag for its kind followed by its parts,
+/// lists prefixed by their length. Functions are fed their digest, the one
+/// annotated when there is one, so digesting them twice is avoided.
+fn feed(term: &Term, hasher: &mut StableHasher) {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+        Term::Int(int) => {
+            hasher.write_u8(0);
+            hasher.write_i64(int.value);
+        }
+        Term::Str(str) => {
+            hasher.write_u8(1);
+            feed_str(&str.value, hasher);
+        }
+        Term::Bool(bool) => {
+            hasher.write_u8(2);
+            hasher.write_u8(bool.value as u8);
+        }
+        Term::Unit(_) => hasher.write_u8(3),
+        Term::Var(var) => {
+            hasher.write_u8(4);
+            feed_var(var, hasher);
+        }
+        Term::Function(function) => {
+            hasher.write_u8(5);
+            hasher.write_u64(function.parameters.len() as u64);
+            for parameter in function.parameters.iter() {
+                feed_var(parameter, hasher);
+            }
+            let digest = function.digest.unwrap_or_else(|| of(&function.value));
+            hasher.write_u128(digest.as_u128());
+        }
+        Term::Let(let_) => {
+            hasher.write_u8(6);
+            feed_var(&let_.name, hasher);
+            feed(&let_.value, hasher);
+            feed(&let_.next, hasher);
+        }
+        Term::Call(call) => {
+            hasher.write_u8(7);
+            feed(&call.callee, hasher);
+            hasher.write_u64(call.arguments.len() as u64);
+            for argument in &call.arguments {
+                feed(argument, hasher);
+            }
+        }
+        Term::Binary(binary) => {
+            hasher.write_u8(8);
+            hasher.write_u8(binary.op.clone() as u8);
+            feed(&binary.lhs, hasher);
+            feed(&binary.rhs, hasher);
+        }
+        Term::If(if_) => {
+            hasher.write_u8(9);
+            feed(&if_.condition, hasher);
+            feed(&if_.then, hasher);
+            feed(&if_.otherwise, hasher);
+        }
+        Term::Cond(cond) => {
+            hasher.write_u8(10);
+            hasher.write_u64(cond.arms.len() as u64);
+            for arm in &cond.arms {
+                feed(&arm.condition, hasher);
+                feed(&arm.then, hasher);
+            }
+            feed(&cond.otherwise, hasher);
+        }
+        Term::Seq(seq) => {
+            hasher.write_u8(11);
+            hasher.write_u64(seq.terms.len() as u64);
+            for term in &seq.terms {
+                feed(term, hasher);
+            }
+        }
+        Term::Print(print) => {
+            hasher.write_u8(12);
+            feed(&print.value, hasher);
+        }
+        Term::First(first) => {
+            hasher.write_u8(13);
+            feed(&first.value, hasher);
+        }
+        Term::Second(second) => {
+            hasher.write_u8(14);
+            feed(&second.value, hasher);
+        }
+        Term::Tuple(tuple) => {
+            hasher.write_u8(15);
+            feed(&tuple.first, hasher);
+            feed(&tuple.second, hasher);
+        }
+    })
+}
+
+/// Feeds a variable by its name, rather than its interned symbol, which
+/// depends on the order names were seen in, followed by its slot.
+fn feed_var(var: &Var, hasher: &mut StableHasher) {
+    feed_str(var.text.as_str(), hasher);
+    match var.slot {
+        None => hasher.write_u8(0),
+        Some(Slot::Local(slot)) => {
+            hasher.write_u8(1);
+            hasher.write_u64(slot as u64);
+        }
+        Some(Slot::Captured(slot)) => {
+            hasher.write_u8(2);
+            hasher.write_u64(slot as u64);
+        }
+        Some(Slot::Current) => hasher.write_u8(3),
+    }
+}
+
+fn feed_str(str: &str, hasher: &mut StableHasher) {
+    hasher.write_u64(str.len() as u64);
+    hasher.write(str.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{annotate, of};
+    use crate::ast::Term;
+
+    fn term(json: &str) -> Term {
+        serde_json::from_str(&json.replace("LOC", r#"{ "start": 0, "end": 0, "filename": "" }"#))
+            .unwrap()
+    }
+
+    #[test]
+    fn functions_are_digested_by_their_body() {
+        // fn (x) => fn (y) => x + y
+        let mut function = term(
+            r#"{ "kind": "Function", "location": LOC,
+                 "parameters": [{ "text": "x", "location": LOC }],
+                 "value": { "kind": "Function", "location": LOC,
+                   "parameters": [{ "text": "y", "location": LOC }],
+                   "value": { "kind": "Binary", "op": "Add", "location": LOC,
+                     "lhs": { "kind": "Var", "text": "x", "location": LOC },
+                     "rhs": { "kind": "Var", "text": "y", "location": LOC } } } }"#,
+        );
+        annotate(&mut function);
+
+        let Term::Function(outer) = &function else {
+            unreachable!("the term is a function")
+        };
+        let Term::Function(inner) = &*outer.value else {
+            unreachable!("the function returns a function")
+        };
+
+        assert_eq!(inner.digest, Some(of(&inner.value)));
+        assert_eq!(outer.digest, Some(of(&outer.value)));
+    }
+
+    #[test]
+    fn digests_are_stable_and_ignore_locations() {
+        let json = r#"{ "kind": "Binary", "op": "Add", "location": LOC,
+                        "lhs": { "kind": "Var", "text": "x", "location": LOC },
+                        "rhs": { "kind": "Int", "value": 1, "location": LOC } }"#;
+        let here = of(&term(json));
+        let moved = of(&serde_json::from_str(
+            &json.replace("LOC", r#"{ "start": 10, "end": 15, "filename": "moved" }"#),
+        )
+        .unwrap());
+
+        assert_eq!(here, moved);
+        // Computed once; a different digest means persisted caches broke.
+        assert_eq!(here.to_string(), "ca8e3cbf5b441670deb1bd26b1df09f8");
+    }
+}
@@ -0,0 +1,554 @@
+use crate::{
+    ast::{File, Term},
+    binary::Division,
+    builtins::{self, Capability},
+    cache::CacheStore,
+    convert::{HostFunction, IntoValue},
+    diagnostics::{Diagnostic, Diagnostics},
+    digest, free,
+    hashing::Hashing,
+    interpreter::{
+        eval, Context, Flush, Memoization, Native, Printer, RuntimeError, RuntimeErrorKind, State,
+        Value, IO,
+    },
+    observe::EvalObserver,
+    optimize::{optimize, optimize_reporting},
+    progress::Progress,
+    purity, resolve,
+    symbol::Symbol,
+    trace::Trace,
+    warnings::Warnings,
+};
+
+/// Runs programs with the builtins in scope, wrapping the context, state
+/// and printer [`eval`] takes, for hosts embedding the interpreter.
+///
+/// ```
+/// use lipsum::{ast::File, embed::Interpreter, interpreter::Capture};
+///
+/// let file: File = serde_json::from_str(include_str!("../examples/sum.json")).unwrap();
+/// let mut interpreter = Interpreter::builder()
+///     .printer(Capture::new())
+///     .fuel(1_000_000)
+///     .build();
+///
+/// interpreter.run(file).unwrap();
+/// assert_eq!(interpreter.printer().output(), "15\n");
+/// ```
+#[derive(Debug)]
+pub struct Interpreter<P = IO> {
+    context: Context,
+    state: State,
+    printer: P,
+
+    /// The fuel every run and evaluation starts with, as the fuel of the
+    /// state is used up by the one before.
+    fuel: Option<u64>,
+
+    optimize: bool,
+    resolve: bool,
+    print: bool,
+}
+
+impl Interpreter {
+    /// A builder of an [`Interpreter`] printing to the standard output, with
+    /// the builtins in scope, no capability granted and no limits.
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+}
+
+impl<P: Printer> Interpreter<P> {
+    /// Runs the program of `file`, writing out what it printed once it
+    /// ends. Bindings made by the program don't outlive the run.
+    pub fn run(&mut self, file: File) -> Result<Value, RuntimeError> {
+        let mut context = self.context.clone();
+        let result = self.eval_in(file.expression, &mut context);
+
+        let flushed = self.printer.flush().map_err(|error| RuntimeError {
+            kind: RuntimeErrorKind::Output,
+            message: String::from("failed to print"),
+            full_text: format!("the output could not be written: {error}"),
+            location: file.location,
+            backtrace: Vec::new(),
+        });
+
+        result.and_then(|value| flushed.map(|()| value))
+    }
+
+    /// Evaluates `term`, keeping the bindings it makes at its top level
+    /// for the next evaluations, like a [`Session`](crate::session::Session).
+    pub fn eval(&mut self, term: Term) -> Result<Value, RuntimeError> {
+        let mut context = std::mem::take(&mut self.context);
+        let result = self.eval_in(term, &mut context);
+        self.context = context;
+
+        result
+    }
+
+    fn eval_in(&mut self, term: Term, context: &mut Context) -> Result<Value, RuntimeError> {
+        self.state.fuel = self.fuel;
+
+        let mut term = match (self.optimize, &mut self.state.diagnostics) {
+            (true, Some(diagnostics)) => optimize_reporting(term, self.state.division, diagnostics),
+            (true, None) => optimize(term, self.state.division),
+            (false, _) => term,
+        };
+        if self.resolve {
+            resolve::resolve(&mut term);
+        }
+        free::annotate(&mut term);
+        digest::annotate(&mut term);
+        purity::annotate(&mut term);
+
+        match self.print {
+            true => eval(term, context, &mut self.state, &mut self.printer),
+            false => eval(term, context, &mut self.state, &mut Muted),
+        }
+    }
+
+    /// Binds `name` to `value` for the next runs and evaluations, like
+    /// configuration or data tables, replacing what `name` was bound to.
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.context.insert(Symbol::new(name), value.into_value());
+    }
+
+    /// The value bound to `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.context.get(Symbol::new(name))
+    }
+
+    /// Takes the diagnostics collected by the runs so far, when
+    /// [collecting them](Builder::diagnostics).
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.state
+            .diagnostics
+            .as_mut()
+            .map(Diagnostics::take)
+            .unwrap_or_default()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The state, where the fuel set is refilled at the start of every run
+    /// and evaluation, whatever it is set to here.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn printer(&self) -> &P {
+        &self.printer
+    }
+
+    pub fn printer_mut(&mut self) -> &mut P {
+        &mut self.printer
+    }
+
+    /// The printer, like a [`Capture`](crate::interpreter::Capture) holding
+    /// the output, once the interpreter is no longer needed.
+    pub fn into_printer(self) -> P {
+        self.printer
+    }
+}
+
+/// Configures an [`Interpreter`], created by [`Interpreter::builder`].
+#[derive(Debug)]
+pub struct Builder<P = IO> {
+    context: Context,
+    state: State,
+    printer: P,
+    optimize: bool,
+    resolve: bool,
+    print: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        let mut context = Context::new();
+        builtins::install(&mut context);
+
+        Self {
+            context,
+            state: State::new(),
+            printer: IO::new(Flush::Buffered),
+            optimize: false,
+            resolve: true,
+            print: true,
+        }
+    }
+}
+
+impl<P: Printer> Builder<P> {
+    /// Prints with `printer` rather than to the standard output.
+    pub fn printer<Q: Printer>(self, printer: Q) -> Builder<Q> {
+        Builder {
+            context: self.context,
+            state: self.state,
+            printer,
+            optimize: self.optimize,
+            resolve: self.resolve,
+            print: self.print,
+        }
+    }
+
+    /// Fails programs after `steps` evaluation steps, counted anew for
+    /// every run and evaluation.
+    pub fn fuel(mut self, steps: u64) -> Self {
+        self.state.fuel = Some(steps);
+        self
+    }
+
+    /// Fails programs nesting evaluations more than `depth` deep.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.state.max_depth = Some(depth);
+        self
+    }
+
+    /// Fails programs nesting calls more than `depth` deep, where calls in
+    /// tail position don't nest.
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.state.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Fails programs once the cache and the values they build take about
+    /// `bytes` together.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.state.max_memory = Some(bytes);
+        self
+    }
+
+    pub fn division(mut self, division: Division) -> Self {
+        self.state.division = division;
+        self
+    }
+
+    /// Keeps the results of memoized calls in `cache`, like a
+    /// [`SharedCache`](crate::cache::SharedCache) shared with other
+    /// interpreters.
+    pub fn cache(mut self, cache: impl CacheStore + 'static) -> Self {
+        self.state.cache = Box::new(cache);
+        self
+    }
+
+    pub fn hashing(mut self, hashing: Hashing) -> Self {
+        self.state.hashing = hashing;
+        self
+    }
+
+    pub fn memoization(mut self, memoization: Memoization) -> Self {
+        self.state.memoization = memoization;
+        self
+    }
+
+    pub fn trace(mut self, trace: Trace) -> Self {
+        self.state.trace = Some(trace);
+        self
+    }
+
+    pub fn progress(mut self, progress: Progress) -> Self {
+        self.state.progress = Some(progress);
+        self
+    }
+
+    /// Calls `observer` before and after every term is evaluated.
+    pub fn observer(mut self, observer: impl EvalObserver + 'static) -> Self {
+        self.state.observer = Some(Box::new(observer));
+        self
+    }
+
+    pub fn warnings(mut self, warnings: Warnings) -> Self {
+        self.state.warnings = Some(warnings);
+        self
+    }
+
+    /// Collects the warnings about programs, the bindings they never use
+    /// when optimizing them, and the overflows of their evaluation, for
+    /// [`Interpreter::take_diagnostics`].
+    pub fn diagnostics(mut self) -> Self {
+        self.state.diagnostics = Some(Diagnostics::new());
+        self
+    }
+
+    /// Optimizes programs before running them, as
+    /// [`optimize`](crate::optimize::optimize) does.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Whether the variables inside functions are [resolved](crate::resolve)
+    /// to slots before running programs, as they are by default. Unresolved
+    /// variables are slower to look up, but are all in the context by name,
+    /// for a [`Debugger`](crate::debugger::Debugger) to inspect.
+    pub fn resolve(mut self, resolve: bool) -> Self {
+        self.resolve = resolve;
+        self
+    }
+
+    /// Grants programs the `capabilities` and limits them as they say, in
+    /// place of the limits set before. Capabilities granted before, with
+    /// [`grant`](Self::grant), are kept.
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        let Capabilities {
+            print,
+            file_system,
+            environment,
+            fuel,
+            max_memory,
+            max_depth,
+            max_call_depth,
+        } = capabilities;
+
+        if file_system {
+            builtins::grant(&mut self.context, Capability::FileSystem);
+        }
+        if environment {
+            builtins::grant(&mut self.context, Capability::Environment);
+        }
+        self.print = print;
+        self.state.fuel = fuel;
+        self.state.max_memory = max_memory;
+        self.state.max_depth = max_depth;
+        self.state.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Gives programs access to the host through `capability`.
+    pub fn grant(mut self, capability: Capability) -> Self {
+        builtins::grant(&mut self.context, capability);
+        self
+    }
+
+    /// Binds `args` to `arguments`, as the command-line arguments of the
+    /// programs.
+    pub fn args(mut self, arguments: impl IntoIterator<Item = String>) -> Self {
+        builtins::define_args(&mut self.context, arguments);
+        self
+    }
+
+    /// Binds `name` to `value` for every program.
+    pub fn define(mut self, name: &str, value: impl IntoValue) -> Self {
+        self.context.insert(Symbol::new(name), value.into_value());
+        self
+    }
+
+    /// Binds `name` to a native calling `function`, with its arguments
+    /// and result converted as [`Native::from_fn`] does.
+    pub fn register_fn<Arguments>(
+        self,
+        name: &str,
+        function: impl HostFunction<Arguments>,
+    ) -> Self {
+        self.define(name, Value::Native(Native::from_fn(name, function)))
+    }
+
+    pub fn build(self) -> Interpreter<P> {
+        Interpreter {
+            context: self.context,
+            fuel: self.state.fuel,
+            state: self.state,
+            printer: self.printer,
+            optimize: self.optimize,
+            resolve: self.resolve,
+            print: self.print,
+        }
+    }
+}
+
+/// What programs run by an [`Interpreter`] may do, and how much of the
+/// host they may use, in one place, for hosts running programs they don't
+/// trust. Set with [`Builder::capabilities`].
+///
+/// The default lets programs print, grants them nothing else and sets no
+/// limits. Programs can't read input nor reach the network, as no builtin
+/// does.
+///
+/// ```
+/// use lipsum::{embed::{Capabilities, Interpreter}, interpreter::Capture};
+///
+/// let untrusted = Capabilities {
+///     print: false,
+///     fuel: Some(1_000_000),
+///     max_memory: Some(64 << 20),
+///     max_call_depth: Some(10_000),
+///     ..Capabilities::default()
+/// };
+/// let interpreter = Interpreter::builder()
+///     .printer(Capture::new())
+///     .capabilities(untrusted)
+///     .build();
+///
+/// assert!(interpreter.get("read_file").is_none());
+/// assert_eq!(interpreter.state().fuel, Some(1_000_000));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether what programs print is written out. When not, `print` still
+    /// evaluates to the value printed, but writes nothing.
+    pub print: bool,
+
+    /// See [`Capability::FileSystem`].
+    pub file_system: bool,
+
+    /// See [`Capability::Environment`].
+    pub environment: bool,
+
+    /// See [`State::fuel`].
+    pub fuel: Option<u64>,
+
+    /// See [`State::max_memory`].
+    pub max_memory: Option<usize>,
+
+    /// See [`State::max_depth`].
+    pub max_depth: Option<usize>,
+
+    /// See [`State::max_call_depth`].
+    pub max_call_depth: Option<usize>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            print: true,
+            file_system: false,
+            environment: false,
+            fuel: None,
+            max_memory: None,
+            max_depth: None,
+            max_call_depth: None,
+        }
+    }
+}
+
+/// A printer writing nothing, for programs not allowed to print.
+struct Muted;
+
+impl Printer for Muted {
+    fn print(&mut self, value: Value) -> std::io::Result<Value> {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, Interpreter};
+    use crate::{
+        ast::File,
+        interpreter::{Capture, RuntimeErrorKind, Value},
+        parser::parse,
+    };
+
+    fn file(name: &str) -> File {
+        let json = std::fs::read_to_string(format!("examples/{name}.json")).unwrap();
+
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn programs_run_as_configured() {
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .define("unused", Value::Int(1))
+            .optimize(true)
+            .build();
+
+        interpreter.run(file("fib")).unwrap();
+        assert_eq!(interpreter.printer().output(), "55\n");
+        assert!(interpreter.get("fib").is_none());
+        assert!(interpreter.get("unused").is_some());
+
+        let mut starved = Interpreter::builder()
+            .printer(Capture::new())
+            .fuel(10)
+            .build();
+        let error = starved.run(file("fib")).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfFuel);
+    }
+
+    #[test]
+    fn fuel_is_refilled_for_every_run() {
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .fuel(100)
+            .build();
+
+        // Each run takes most of the fuel, but not all of it, and leaves as
+        // much as the others, as printing keeps the calls from being cached.
+        let source = "let f = fn (n) => { if (n == 0) { print(0) } else { f(n - 1) } }; f(5)";
+        let mut left = Vec::new();
+        for _ in 0..3 {
+            interpreter
+                .run(parse(source, "main.rinha").unwrap())
+                .unwrap();
+            left.push(interpreter.state().fuel.unwrap());
+        }
+
+        let term = parse(source, "main.rinha").unwrap().expression;
+        interpreter.eval(term).unwrap();
+        left.push(interpreter.state().fuel.unwrap());
+
+        assert!(left[0] < 50, "{left:?}");
+        assert!(left.iter().all(|&fuel| fuel == left[0]), "{left:?}");
+    }
+
+    #[test]
+    fn registered_functions_can_be_called() {
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .register_fn("add", |a: i64, b: i64| a + b)
+            .build();
+
+        // add(1, 2)
+        let term = serde_json::from_str(
+            r#"{ "kind": "Call", "location": LOC,
+                 "callee": { "kind": "Var", "text": "add", "location": LOC },
+                 "arguments": [
+                   { "kind": "Int", "value": 1, "location": LOC },
+                   { "kind": "Int", "value": 2, "location": LOC } ] }"#
+                .replace("LOC", r#"{ "start": 0, "end": 9, "filename": "tests" }"#)
+                .as_str(),
+        )
+        .unwrap();
+        assert_eq!(interpreter.eval(term).unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn globals_are_in_scope_of_the_next_runs() {
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .define("greeting", "hello")
+            .build();
+        interpreter.set_global("config", (3, true));
+        interpreter.set_global("greeting", "hi");
+
+        let source = "if (second(config)) { print(greeting) } else { first(config) }";
+        let value = interpreter.run(parse(source, "main.rinha").unwrap());
+        assert_eq!(value.unwrap().to_string(), "hi");
+        assert_eq!(interpreter.get("config").unwrap().to_string(), "(3, true)");
+    }
+
+    #[test]
+    fn capabilities_grant_and_limit_in_one_place() {
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .capabilities(Capabilities {
+                print: false,
+                environment: true,
+                fuel: Some(10),
+                ..Capabilities::default()
+            })
+            .build();
+        assert!(interpreter.get("env").is_some());
+        assert!(interpreter.get("read_file").is_none());
+
+        let value = interpreter.run(parse("print(1 + 2)", "main.rinha").unwrap());
+        assert_eq!(value.unwrap().to_string(), "3");
+        assert_eq!(interpreter.printer().output(), "");
+
+        let error = interpreter.run(file("fib")).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfFuel);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    ast::{BinaryOp, Location},
+    source_map::FileId,
+};
+
+/// Operators by the byte they are written as.
+const BINARY_OPS: [BinaryOp; 13] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::Eq,
+    BinaryOp::Neq,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Lte,
+    BinaryOp::Gte,
+    BinaryOp::And,
+    BinaryOp::Or,
+];
+
+/// Writes the parts of the binary formats of programs, like compiled
+/// [bytecode](crate::bytecode) and [encoded ASTs](crate::binary_ast):
+/// integers as LEB128 varints, strings prefixed by their length, and
+/// locations referring to their filename by index in a table written
+/// before everything else.
+pub(crate) struct Encoder {
+    header: Vec<u8>,
+    bytes: Vec<u8>,
+    filenames: Vec<FileId>,
+}
+
+impl Encoder {
+    /// Creates a new instance of [`Encoder`], starting with `magic` and
+    /// the `version` of the format.
+    pub(crate) fn new(magic: &[u8; 4], version: u16) -> Self {
+        let mut header = magic.to_vec();
+        header.extend(version.to_le_bytes());
+
+        Self {
+            header,
+            bytes: Vec::new(),
+            filenames: Vec::new(),
+        }
+    }
+
+    pub(crate) fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    pub(crate) fn u64(&mut self, mut int: u64) {
+        loop {
+            let byte = (int & 0x7f) as u8;
+            int >>= 7;
+            match int {
+                0 => return self.bytes.push(byte),
+                _more => self.bytes.push(byte | 0x80),
+            }
+        }
+    }
+
+    pub(crate) fn usize(&mut self, int: usize) {
+        self.u64(int as u64);
+    }
+
+    pub(crate) fn i64(&mut self, int: i64) {
+        // Zigzag encoded, so small negative numbers stay short.
+        self.u64(((int << 1) ^ (int >> 63)) as u64);
+    }
+
+    pub(crate) fn binary_op(&mut self, op: &BinaryOp) {
+        self.byte(op.clone() as u8);
+    }
+
+    pub(crate) fn str(&mut self, str: &str) {
+        self.usize(str.len());
+        self.bytes.extend(str.as_bytes());
+    }
+
+    pub(crate) fn location(&mut self, location: &Location) {
+        let filename = match self
+            .filenames
+            .iter()
+            .position(|filename| *filename == location.filename)
+        {
+            Some(filename) => filename,
+            None => {
+                self.filenames.push(location.filename);
+                self.filenames.len() - 1
+            }
+        };
+        self.usize(filename);
+        self.usize(location.start);
+        self.usize(location.end);
+    }
+
+    /// The bytes written, after the header and the table of filenames.
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let mut table = Encoder {
+            header: self.header,
+            bytes: Vec::new(),
+            filenames: Vec::new(),
+        };
+        table.usize(self.filenames.len());
+        for filename in &self.filenames {
+            table.str(filename.as_str());
+        }
+
+        let mut bytes = table.header;
+        bytes.extend(table.bytes);
+        bytes.extend(self.bytes);
+
+        bytes
+    }
+}
+
+/// Reads back what an [`Encoder`] wrote, failing on malformed input
+/// rather than panicking.
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+    filenames: Vec<FileId>,
+
+    /// What is being read, like "compiled program", for error messages.
+    kind: &'static str,
+}
+
+impl<'a> Decoder<'a> {
+    /// Creates a new instance of [`Decoder`], checking `bytes` start with
+    /// `magic` and were written by the same `version` of the format, and
+    /// reading the table of filenames.
+    pub(crate) fn new(
+        bytes: &'a [u8],
+        magic: &[u8; 4],
+        version: u16,
+        kind: &'static str,
+    ) -> Result<Self, String> {
+        let rest = bytes
+            .strip_prefix(magic)
+            .ok_or_else(|| format!("not a {kind}"))?;
+        let (written, rest) = rest
+            .split_at_checked(2)
+            .ok_or_else(|| format!("truncated {kind}"))?;
+        let written = u16::from_le_bytes([written[0], written[1]]);
+        if written != version {
+            return Err(format!(
+                "written by version {written} of the format, but only version {version} is supported"
+            ));
+        }
+
+        let mut decoder = Self {
+            bytes: rest,
+            filenames: Vec::new(),
+            kind,
+        };
+        for _ in 0..decoder.len()? {
+            let filename = decoder.str()?;
+            decoder.filenames.push(FileId::new(&filename));
+        }
+
+        Ok(decoder)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, String> {
+        let (&byte, rest) = self
+            .bytes
+            .split_first()
+            .ok_or_else(|| format!("truncated {}", self.kind))?;
+        self.bytes = rest;
+
+        Ok(byte)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        let mut int = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            int |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(int);
+            }
+        }
+
+        Err(String::from("malformed integer"))
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, String> {
+        usize::try_from(self.u64()?).map_err(|_| String::from("integer out of range"))
+    }
+
+    /// A length, which can't be longer than what is left to read, so
+    /// malformed input doesn't make it allocate much.
+    pub(crate) fn len(&mut self) -> Result<usize, String> {
+        let len = self.usize()?;
+        match len <= self.bytes.len() {
+            true => Ok(len),
+            false => Err(format!("truncated {}", self.kind)),
+        }
+    }
+
+    pub(crate) fn i64(&mut self) -> Result<i64, String> {
+        let int = self.u64()?;
+
+        Ok((int >> 1) as i64 ^ -((int & 1) as i64))
+    }
+
+    pub(crate) fn str(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        let (str, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        String::from_utf8(str.to_vec()).map_err(|_| String::from("malformed string"))
+    }
+
+    pub(crate) fn binary_op(&mut self) -> Result<BinaryOp, String> {
+        let op = self.byte()?;
+
+        BINARY_OPS
+            .get(op as usize)
+            .cloned()
+            .ok_or_else(|| format!("unknown operator {op}"))
+    }
+
+    pub(crate) fn location(&mut self) -> Result<Location, String> {
+        let filename = self.usize()?;
+        let filename = self
+            .filenames
+            .get(filename)
+            .copied()
+            .ok_or("unknown filename")?;
+
+        Ok(Location {
+            start: self.usize()?,
+            end: self.usize()?,
+            filename,
+        })
+    }
+
+    /// Fails unless everything was read.
+    pub(crate) fn finish(self) -> Result<(), String> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(format!("trailing bytes after the {}", self.kind)),
+        }
+    }
+}
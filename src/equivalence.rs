@@ -0,0 +1,519 @@
+use std::{fmt::Display, mem::take, sync::Arc, time::Duration};
+
+use crate::{
+    ast::{
+        Arm, Binary, Bool, Call, Cond, First, Function, If, Int, Let, Location, Print, Second, Seq,
+        Str, Term, Tuple, Unit, Var,
+    },
+    binary::Division,
+    builtins,
+    interpreter::{eval, Capture, Context, State, Value},
+    optimize,
+    symbol::Symbol,
+};
+
+/// How long a single differential run may take before it is stopped.
+pub const RUN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Integers given as arguments when the programs evaluate to functions.
+const INPUTS: [i64; 8] = [0, 1, 2, 3, 7, 10, -1, -5];
+
+/// Maximum number of argument combinations tried per program.
+const MAX_RUNS: usize = 64;
+
+/// The outcome of comparing two programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Both programs normalize to the same AST.
+    Identical,
+
+    /// The programs behaved the same on every run tried, which is strong
+    /// evidence but not a proof that they are equivalent.
+    Equivalent {
+        runs: usize,
+    },
+
+    Different {
+        reason: String,
+    },
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Identical => write!(f, "identical: both programs normalize to the same AST"),
+            Verdict::Equivalent { runs } => {
+                write!(
+                    f,
+                    "equivalent: both programs behaved the same in {runs} run(s)"
+                )
+            }
+            Verdict::Different { reason } => write!(f, "different: {reason}"),
+        }
+    }
+}
+
+/// Heuristically checks whether two programs are equivalent, like two
+/// submissions where one is a disguised copy of the other.
+///
+/// Both programs are first normalized: bound variables are renamed in
+/// order of appearance, constant expressions are folded, conditions known
+/// ahead of time are resolved and locations are dropped. When that isn't
+/// enough, both are run and their value and output compared. Programs
+/// evaluating to functions are called with generated integer arguments.
+pub fn check(left: Term, right: Term) -> Verdict {
+    let left = normalize(left);
+    let right = normalize(right);
+
+    if left == right {
+        return Verdict::Identical;
+    }
+
+    let left = Run::new(left);
+    let right = Run::new(right);
+
+    if left.describe() != right.describe() {
+        return Verdict::Different {
+            reason: format!(
+                "the programs result in {} and {}",
+                left.describe(),
+                right.describe()
+            ),
+        };
+    }
+
+    let arity = match (&left.result, &right.result) {
+        (Ok(Value::Closure(l_closure)), Ok(Value::Closure(r_closure))) => {
+            match l_closure.arity() == r_closure.arity() {
+                true => l_closure.arity(),
+                false => {
+                    return Verdict::Different {
+                        reason: format!(
+                            "the functions take {} and {} argument(s)",
+                            l_closure.arity(),
+                            r_closure.arity()
+                        ),
+                    }
+                }
+            }
+        }
+        (_l_result, _r_result) => return Verdict::Equivalent { runs: 1 },
+    };
+
+    let inputs = inputs(arity);
+    for arguments in &inputs {
+        let l_called = left.call(arguments);
+        let r_called = right.call(arguments);
+
+        if l_called != r_called {
+            let arguments = arguments
+                .iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            return Verdict::Different {
+                reason: format!(
+                    "called with ({arguments}), the functions result in {l_called} and {r_called}"
+                ),
+            };
+        }
+    }
+
+    Verdict::Equivalent {
+        runs: inputs.len() + 1,
+    }
+}
+
+/// A program evaluated within [`RUN_TIMEOUT`], kept around so the
+/// function it results in can be called.
+struct Run {
+    result: Result<Value, String>,
+    output: String,
+}
+
+impl Run {
+    fn new(term: Term) -> Self {
+        let mut context = Context::new();
+        builtins::install(&mut context);
+
+        Self::eval(term, &mut context)
+    }
+
+    fn eval(term: Term, context: &mut Context) -> Self {
+        let mut state = State::new();
+        let mut capture = Capture::new();
+
+        let watchdog = state.cancellation.cancel_after(RUN_TIMEOUT);
+        let result = eval(term, context, &mut state, &mut capture);
+        drop(watchdog);
+
+        Self {
+            result: result.map_err(|error| error.message),
+            output: capture.take(),
+        }
+    }
+
+    fn call(&self, arguments: &[i64]) -> String {
+        let Ok(function) = self.result.clone() else {
+            unreachable!("only functions are called");
+        };
+
+        let mut context = Context::new();
+        context.insert(Symbol::new("function"), function);
+
+        let call = Term::Call(Call {
+            callee: Arc::new(Term::Var(Var {
+                text: Symbol::new("function"),
+                location: Location::default(),
+                slot: None,
+            })),
+            arguments: arguments
+                .iter()
+                .map(|argument| {
+                    Term::Int(Int {
+                        value: *argument,
+                        location: Location::default(),
+                    })
+                })
+                .collect(),
+            location: Location::default(),
+        });
+
+        Self::eval(call, &mut context).describe()
+    }
+
+    fn describe(&self) -> String {
+        let result = match &self.result {
+            Ok(value) => format!("the {} {value}", value.type_name()),
+            Err(message) => format!("the error \"{message}\""),
+        };
+
+        format!("{result} printing {:?}", self.output)
+    }
+}
+
+/// The combinations of [`INPUTS`] for a function of `arity` parameters,
+/// at most [`MAX_RUNS`] of them.
+fn inputs(arity: usize) -> Vec<Vec<i64>> {
+    let combinations = INPUTS
+        .len()
+        .checked_pow(arity as u32)
+        .map_or(MAX_RUNS, |combinations| combinations.min(MAX_RUNS));
+
+    (0..combinations)
+        .map(|mut index| {
+            (0..arity)
+                .map(|_parameter| {
+                    let input = INPUTS[index % INPUTS.len()];
+                    index /= INPUTS.len();
+                    input
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Normalizes `term` so that programs differing only in names, locations
+/// or constant expressions become equal.
+pub fn normalize(term: Term) -> Term {
+    Normalizer::default().term(term)
+}
+
+#[derive(Default)]
+struct Normalizer {
+    /// Bound names, innermost last, with the name they were renamed to.
+    scopes: Vec<(Symbol, Symbol)>,
+    bound: usize,
+}
+
+impl Normalizer {
+    fn bind(&mut self, var: Var) -> Var {
+        let renamed = Symbol::new(&format!("v{}", self.bound));
+        self.bound += 1;
+        self.scopes.push((var.text, renamed));
+
+        Var {
+            text: renamed,
+            location: Location::default(),
+            slot: None,
+        }
+    }
+
+    /// Renames a variable to the name of its binding. Free variables, like
+    /// the builtins, keep their name.
+    fn rename(&self, var: Var) -> Var {
+        let text = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|(name, _renamed)| *name == var.text)
+            .map_or(var.text, |(_name, renamed)| *renamed);
+
+        Var {
+            text,
+            location: Location::default(),
+            slot: None,
+        }
+    }
+
+    fn shared(&mut self, term: Term) -> Arc<Term> {
+        Arc::new(self.term(term))
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        let location = Location::default();
+
+        match &mut term {
+            Term::Int(int) => Term::Int(Int {
+                value: int.value,
+                location,
+            }),
+            Term::Str(str) => Term::Str(Str {
+                value: take(&mut str.value),
+                location,
+            }),
+            Term::Bool(bool) => Term::Bool(Bool {
+                value: bool.value,
+                location,
+            }),
+            Term::Unit(_) => Term::Unit(Unit { location }),
+            Term::Seq(seq) => {
+                let seq = take(seq);
+
+                Term::Seq(Seq {
+                    terms: seq.terms.into_iter().map(|term| self.term(term)).collect(),
+                    location,
+                })
+            }
+            Term::Var(var) => Term::Var(self.rename(take(var))),
+            Term::Call(call) => {
+                let call = take(call);
+
+                Term::Call(Call {
+                    callee: self.shared(Arc::unwrap_or_clone(call.callee)),
+                    arguments: call
+                        .arguments
+                        .into_iter()
+                        .map(|argument| self.term(argument))
+                        .collect(),
+                    location,
+                })
+            }
+            Term::Binary(binary) => {
+                let binary = take(binary);
+
+                optimize::fold(
+                    Binary {
+                        lhs: self.shared(Arc::unwrap_or_clone(binary.lhs)),
+                        op: binary.op,
+                        rhs: self.shared(Arc::unwrap_or_clone(binary.rhs)),
+                        location,
+                    },
+                    Division::default(),
+                )
+            }
+            Term::Function(function) => {
+                let function = take(function);
+
+                let scopes = self.scopes.len();
+                let parameters = function
+                    .parameters
+                    .iter()
+                    .map(|parameter| self.bind(parameter.clone()))
+                    .collect();
+                let value = self.shared(Arc::unwrap_or_clone(function.value));
+                self.scopes.truncate(scopes);
+
+                Term::Function(Function {
+                    parameters,
+                    value,
+                    location,
+                    free: None,
+                    layout: None,
+                    digest: None,
+                    pure: None,
+                })
+            }
+            Term::Let(let_) => {
+                let let_ = take(let_);
+
+                // The name is bound before the value, as functions may
+                // refer to themselves.
+                let name = self.bind(let_.name);
+                let value = self.shared(Arc::unwrap_or_clone(let_.value));
+                let next = self.shared(Arc::unwrap_or_clone(let_.next));
+                self.scopes.pop();
+
+                Term::Let(Let {
+                    name,
+                    value,
+                    next,
+                    location,
+                })
+            }
+            Term::If(if_) => {
+                let if_ = take(if_);
+
+                match self.term(Arc::unwrap_or_clone(if_.condition)) {
+                    Term::Bool(Bool { value: true, .. }) => {
+                        self.term(Arc::unwrap_or_clone(if_.then))
+                    }
+                    Term::Bool(Bool { value: false, .. }) => {
+                        self.term(Arc::unwrap_or_clone(if_.otherwise))
+                    }
+                    condition => Term::If(If {
+                        condition: Arc::new(condition),
+                        then: self.shared(Arc::unwrap_or_clone(if_.then)),
+                        otherwise: self.shared(Arc::unwrap_or_clone(if_.otherwise)),
+                        location,
+                    }),
+                }
+            }
+            Term::Cond(cond) => {
+                let cond = take(cond);
+
+                // Arms known to never be taken are dropped, and the first one
+                // known to be taken ends the chain.
+                let mut arms = Vec::new();
+                let mut otherwise = None;
+                for arm in cond.arms {
+                    match self.term(arm.condition) {
+                        Term::Bool(Bool { value: false, .. }) => {}
+                        Term::Bool(Bool { value: true, .. }) => {
+                            otherwise = Some(self.term(arm.then));
+                            break;
+                        }
+                        condition => arms.push(Arm {
+                            condition,
+                            then: self.term(arm.then),
+                        }),
+                    }
+                }
+                let otherwise =
+                    otherwise.unwrap_or_else(|| self.term(Arc::unwrap_or_clone(cond.otherwise)));
+
+                match arms.is_empty() {
+                    true => otherwise,
+                    false => Term::Cond(Cond {
+                        arms,
+                        otherwise: Arc::new(otherwise),
+                        location,
+                    }),
+                }
+            }
+            Term::Print(print) => {
+                let print = take(print);
+
+                Term::Print(Print {
+                    value: self.shared(Arc::unwrap_or_clone(print.value)),
+                    location,
+                })
+            }
+            Term::First(first) => {
+                let first = take(first);
+
+                Term::First(First {
+                    value: self.shared(Arc::unwrap_or_clone(first.value)),
+                    location,
+                })
+            }
+            Term::Second(second) => {
+                let second = take(second);
+
+                Term::Second(Second {
+                    value: self.shared(Arc::unwrap_or_clone(second.value)),
+                    location,
+                })
+            }
+            Term::Tuple(tuple) => {
+                let tuple = take(tuple);
+
+                Term::Tuple(Tuple {
+                    first: self.shared(Arc::unwrap_or_clone(tuple.first)),
+                    second: self.shared(Arc::unwrap_or_clone(tuple.second)),
+                    location,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Verdict};
+    use crate::ast::Term;
+
+    fn term(json: &str) -> Term {
+        let location = r#"{ "start": 0, "end": 0, "filename": "tests" }"#;
+
+        serde_json::from_str(&json.replace("LOC", location)).unwrap()
+    }
+
+    fn int(value: i64) -> String {
+        format!(r#"{{ "kind": "Int", "value": {value}, "location": LOC }}"#)
+    }
+
+    fn var(name: &str) -> String {
+        format!(r#"{{ "kind": "Var", "text": "{name}", "location": LOC }}"#)
+    }
+
+    fn binary(lhs: &str, op: &str, rhs: &str) -> String {
+        format!(
+            r#"{{ "kind": "Binary", "lhs": {lhs}, "op": "{op}", "rhs": {rhs}, "location": LOC }}"#
+        )
+    }
+
+    fn function(parameter: &str, value: &str) -> String {
+        format!(
+            r#"{{ "kind": "Function", "parameters": [{{ "text": "{parameter}", "location": LOC }}],
+                 "value": {value}, "location": LOC }}"#
+        )
+    }
+
+    #[test]
+    fn renamed_and_folded_programs_are_identical() {
+        let left = function("n", &binary(&var("n"), "Mul", &int(4)));
+        let right = function(
+            "x",
+            &binary(&var("x"), "Mul", &binary(&int(2), "Add", &int(2))),
+        );
+
+        assert_eq!(check(term(&left), term(&right)), Verdict::Identical);
+    }
+
+    #[test]
+    fn functions_are_compared_on_inputs() {
+        let left = function("n", &binary(&var("n"), "Add", &var("n")));
+        let right = function("n", &binary(&var("n"), "Mul", &int(2)));
+
+        assert!(matches!(
+            check(term(&left), term(&right)),
+            Verdict::Equivalent { .. }
+        ));
+    }
+
+    #[test]
+    fn different_functions_are_told_apart() {
+        let left = function("n", &binary(&var("n"), "Add", &var("n")));
+        let right = function("n", &binary(&var("n"), "Mul", &var("n")));
+
+        let Verdict::Different { reason } = check(term(&left), term(&right)) else {
+            panic!("the functions differ on most inputs");
+        };
+        assert!(reason.starts_with("called with (1)"));
+    }
+
+    #[test]
+    fn known_cond_arms_are_resolved() {
+        let cond = format!(
+            r#"{{ "kind": "Cond", "location": LOC, "otherwise": {},
+                 "arms": [{{ "condition": {}, "then": {} }}] }}"#,
+            int(2),
+            binary(&int(1), "Gt", &int(3)),
+            int(1)
+        );
+
+        assert_eq!(check(term(&cond), term(&int(2))), Verdict::Identical);
+    }
+}
@@ -0,0 +1,326 @@
+use std::{mem::take, sync::Arc};
+
+use crate::{
+    ast::{
+        Arm, Binary, Bool, Call, Cond, First, Function, If, Int, Let, Print, Second, Seq, Str,
+        Term, Tuple, Unit, Var,
+    },
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+};
+
+/// Rebuilds a term, with a hook for every kind of node returning the term
+/// that replaces it. Each hook rebuilds its node from its folded children
+/// by default, through the `walk_` function of the same name, keeping its
+/// location, so a folder only overrides the hooks of the nodes it rewrites.
+///
+/// Rebuilt functions lose their annotations, like their
+/// [digest](crate::digest), as their body may have changed.
+///
+/// ```
+/// use lipsum::{
+///     ast::{build::{int, var}, Term, Var},
+///     fold::TermFolder,
+/// };
+///
+/// /// Replaces the variable `x` by the literal 1.
+/// struct One;
+///
+/// impl TermFolder for One {
+///     fn fold_var(&mut self, var: Var) -> Term {
+///         match var.text.as_str() {
+///             "x" => int(1),
+///             _ => Term::Var(var),
+///         }
+///     }
+/// }
+///
+/// let folded = One.fold_term(var("x") + var("y"));
+/// assert_eq!(folded.to_source(), "1 + y");
+/// ```
+pub trait TermFolder {
+    fn fold_term(&mut self, term: Term) -> Term {
+        walk_term(self, term)
+    }
+
+    fn fold_int(&mut self, int: Int) -> Term {
+        Term::Int(int)
+    }
+
+    fn fold_str(&mut self, str: Str) -> Term {
+        Term::Str(str)
+    }
+
+    fn fold_bool(&mut self, bool: Bool) -> Term {
+        Term::Bool(bool)
+    }
+
+    fn fold_unit(&mut self, unit: Unit) -> Term {
+        Term::Unit(unit)
+    }
+
+    /// Folds a variable referred to, rather than bound.
+    fn fold_var(&mut self, var: Var) -> Term {
+        Term::Var(var)
+    }
+
+    /// Folds a name bound by a `let` or as a parameter, before the terms
+    /// it is bound in.
+    fn fold_binding(&mut self, var: Var) -> Var {
+        var
+    }
+
+    fn fold_function(&mut self, function: Function) -> Term {
+        walk_function(self, function)
+    }
+
+    fn fold_let(&mut self, let_: Let) -> Term {
+        walk_let(self, let_)
+    }
+
+    fn fold_call(&mut self, call: Call) -> Term {
+        walk_call(self, call)
+    }
+
+    fn fold_binary(&mut self, binary: Binary) -> Term {
+        walk_binary(self, binary)
+    }
+
+    fn fold_if(&mut self, if_: If) -> Term {
+        walk_if(self, if_)
+    }
+
+    fn fold_cond(&mut self, cond: Cond) -> Term {
+        walk_cond(self, cond)
+    }
+
+    fn fold_seq(&mut self, seq: Seq) -> Term {
+        walk_seq(self, seq)
+    }
+
+    fn fold_print(&mut self, print: Print) -> Term {
+        walk_print(self, print)
+    }
+
+    fn fold_first(&mut self, first: First) -> Term {
+        walk_first(self, first)
+    }
+
+    fn fold_second(&mut self, second: Second) -> Term {
+        walk_second(self, second)
+    }
+
+    fn fold_tuple(&mut self, tuple: Tuple) -> Term {
+        walk_tuple(self, tuple)
+    }
+}
+
+/// Folds a child shared behind an [`Arc`], taking it out when it isn't
+/// shared with another term.
+fn shared<F: TermFolder + ?Sized>(folder: &mut F, term: Arc<Term>) -> Arc<Term> {
+    Arc::new(folder.fold_term(Arc::unwrap_or_clone(term)))
+}
+
+/// Calls the hook of the kind of `term`.
+pub fn walk_term<F: TermFolder + ?Sized>(folder: &mut F, mut term: Term) -> Term {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match &mut term {
+        Term::Int(int) => folder.fold_int(take(int)),
+        Term::Str(str) => folder.fold_str(take(str)),
+        Term::Bool(bool) => folder.fold_bool(take(bool)),
+        Term::Unit(unit) => folder.fold_unit(take(unit)),
+        Term::Var(var) => folder.fold_var(take(var)),
+        Term::Function(function) => folder.fold_function(take(function)),
+        Term::Let(let_) => folder.fold_let(take(let_)),
+        Term::Call(call) => folder.fold_call(take(call)),
+        Term::Binary(binary) => folder.fold_binary(take(binary)),
+        Term::If(if_) => folder.fold_if(take(if_)),
+        Term::Cond(cond) => folder.fold_cond(take(cond)),
+        Term::Seq(seq) => folder.fold_seq(take(seq)),
+        Term::Print(print) => folder.fold_print(take(print)),
+        Term::First(first) => folder.fold_first(take(first)),
+        Term::Second(second) => folder.fold_second(take(second)),
+        Term::Tuple(tuple) => folder.fold_tuple(take(tuple)),
+    })
+}
+
+/// Folds the parameters and then the body.
+pub fn walk_function<F: TermFolder + ?Sized>(folder: &mut F, function: Function) -> Term {
+    let parameters = function
+        .parameters
+        .iter()
+        .map(|parameter| folder.fold_binding(parameter.clone()))
+        .collect();
+
+    Term::Function(Function {
+        parameters,
+        value: shared(folder, function.value),
+        location: function.location,
+        free: None,
+        layout: None,
+        digest: None,
+        pure: None,
+    })
+}
+
+/// Folds the name, the value and then the term the name is bound in, in
+/// the order [`walk_let`](crate::visit::walk_let) visits them.
+pub fn walk_let<F: TermFolder + ?Sized>(folder: &mut F, let_: Let) -> Term {
+    let name = folder.fold_binding(let_.name);
+    let value = shared(folder, let_.value);
+
+    Term::Let(Let {
+        name,
+        value,
+        next: shared(folder, let_.next),
+        location: let_.location,
+    })
+}
+
+pub fn walk_call<F: TermFolder + ?Sized>(folder: &mut F, call: Call) -> Term {
+    let callee = shared(folder, call.callee);
+
+    Term::Call(Call {
+        callee,
+        arguments: call
+            .arguments
+            .into_iter()
+            .map(|argument| folder.fold_term(argument))
+            .collect(),
+        location: call.location,
+    })
+}
+
+pub fn walk_binary<F: TermFolder + ?Sized>(folder: &mut F, binary: Binary) -> Term {
+    let lhs = shared(folder, binary.lhs);
+
+    Term::Binary(Binary {
+        lhs,
+        op: binary.op,
+        rhs: shared(folder, binary.rhs),
+        location: binary.location,
+    })
+}
+
+pub fn walk_if<F: TermFolder + ?Sized>(folder: &mut F, if_: If) -> Term {
+    let condition = shared(folder, if_.condition);
+    let then = shared(folder, if_.then);
+
+    Term::If(If {
+        condition,
+        then,
+        otherwise: shared(folder, if_.otherwise),
+        location: if_.location,
+    })
+}
+
+pub fn walk_cond<F: TermFolder + ?Sized>(folder: &mut F, cond: Cond) -> Term {
+    let arms = cond
+        .arms
+        .into_iter()
+        .map(|arm| {
+            let condition = folder.fold_term(arm.condition);
+
+            Arm {
+                condition,
+                then: folder.fold_term(arm.then),
+            }
+        })
+        .collect();
+
+    Term::Cond(Cond {
+        arms,
+        otherwise: shared(folder, cond.otherwise),
+        location: cond.location,
+    })
+}
+
+pub fn walk_seq<F: TermFolder + ?Sized>(folder: &mut F, seq: Seq) -> Term {
+    Term::Seq(Seq {
+        terms: seq
+            .terms
+            .into_iter()
+            .map(|term| folder.fold_term(term))
+            .collect(),
+        location: seq.location,
+    })
+}
+
+pub fn walk_print<F: TermFolder + ?Sized>(folder: &mut F, print: Print) -> Term {
+    Term::Print(Print {
+        value: shared(folder, print.value),
+        location: print.location,
+    })
+}
+
+pub fn walk_first<F: TermFolder + ?Sized>(folder: &mut F, first: First) -> Term {
+    Term::First(First {
+        value: shared(folder, first.value),
+        location: first.location,
+    })
+}
+
+pub fn walk_second<F: TermFolder + ?Sized>(folder: &mut F, second: Second) -> Term {
+    Term::Second(Second {
+        value: shared(folder, second.value),
+        location: second.location,
+    })
+}
+
+pub fn walk_tuple<F: TermFolder + ?Sized>(folder: &mut F, tuple: Tuple) -> Term {
+    let first = shared(folder, tuple.first);
+
+    Term::Tuple(Tuple {
+        first,
+        second: shared(folder, tuple.second),
+        location: tuple.location,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk_binary, TermFolder};
+    use crate::{
+        ast::{Binary, BinaryOp, Element, Int, Term},
+        parser::parse_term,
+    };
+
+    /// Folds additions of literals, innermost first.
+    struct Sums;
+
+    impl TermFolder for Sums {
+        fn fold_binary(&mut self, binary: Binary) -> Term {
+            let mut folded = walk_binary(self, binary);
+            let Term::Binary(binary) = &mut folded else {
+                unreachable!("binary terms are rebuilt as binary terms")
+            };
+            let binary = std::mem::take(binary);
+
+            match (&*binary.lhs, &binary.op, &*binary.rhs) {
+                (Term::Int(lhs), BinaryOp::Add, Term::Int(rhs)) => Term::Int(Int {
+                    value: lhs.value + rhs.value,
+                    location: binary.location,
+                }),
+                _ => Term::Binary(binary),
+            }
+        }
+    }
+
+    #[test]
+    fn folded_terms_keep_their_locations() {
+        let source = "print((1 + 2) + 3 * x)";
+        let term = parse_term(source, "tests").unwrap();
+
+        let folded = Sums.fold_term(term.clone());
+        assert_eq!(folded.to_source(), "print(3 + 3 * x)");
+        assert_eq!(folded.location(), term.location());
+
+        let Term::Print(print) = &folded else {
+            unreachable!("the print is kept")
+        };
+        let Term::Binary(sum) = &*print.value else {
+            unreachable!("the product isn't folded")
+        };
+        let location = sum.lhs.location();
+        assert_eq!(&source[location.start..location.end], "1 + 2");
+    }
+}
@@ -0,0 +1,155 @@
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    ast::Term,
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    symbol::Symbol,
+};
+
+/// Fills in the free variables of every function in `term`, so their
+/// closures capture only the bindings they use rather than the whole
+/// context they are created in.
+pub fn annotate(term: &mut Term) {
+    free(term);
+}
+
+/// The names `term` refers to without binding them itself, annotating the
+/// functions inside it like [`annotate`].
+pub fn variables(term: &mut Term) -> HashSet<Symbol> {
+    free(term)
+}
+
+/// The names `term` refers to without binding them itself, annotating the
+/// functions inside it along the way.
+///
+/// A `let` only binds its name in `next`, the rest of its scope, so
+/// functions referring to themselves keep their own name as free: it is
+/// bound in their context once the `let` binds them.
+fn free(term: &mut Term) -> HashSet<Symbol> {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+        // Variables resolved to slots are captured through the layout of
+        // the function instead.
+        Term::Var(var) if var.slot.is_some() => HashSet::new(),
+        Term::Var(var) => HashSet::from([var.text]),
+        Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) => HashSet::new(),
+        Term::Function(function) => {
+            let mut names = free(Arc::make_mut(&mut function.value));
+            for parameter in function.parameters.iter() {
+                names.remove(&parameter.text);
+            }
+            // Sorted by name, so closures of the function read their
+            // environment in the same order in every process.
+            let mut sorted = names.iter().copied().collect::<Vec<_>>();
+            sorted.sort_by_key(|name| name.as_str());
+            function.free = Some(sorted.into());
+
+            names
+        }
+        Term::Let(let_) => {
+            let mut next = free(Arc::make_mut(&mut let_.next));
+            next.remove(&let_.name.text);
+
+            union(free(Arc::make_mut(&mut let_.value)), next)
+        }
+        Term::Call(call) => call
+            .arguments
+            .iter_mut()
+            .fold(free(Arc::make_mut(&mut call.callee)), |names, argument| {
+                union(names, free(argument))
+            }),
+        Term::Binary(binary) => union(
+            free(Arc::make_mut(&mut binary.lhs)),
+            free(Arc::make_mut(&mut binary.rhs)),
+        ),
+        Term::If(if_) => union(
+            union(
+                free(Arc::make_mut(&mut if_.condition)),
+                free(Arc::make_mut(&mut if_.then)),
+            ),
+            free(Arc::make_mut(&mut if_.otherwise)),
+        ),
+        Term::Cond(cond) => cond
+            .arms
+            .iter_mut()
+            .fold(free(Arc::make_mut(&mut cond.otherwise)), |names, arm| {
+                union(union(names, free(&mut arm.condition)), free(&mut arm.then))
+            }),
+        Term::Seq(seq) => seq
+            .terms
+            .iter_mut()
+            .fold(HashSet::new(), |names, term| union(names, free(term))),
+        Term::Print(print) => free(Arc::make_mut(&mut print.value)),
+        Term::First(first) => free(Arc::make_mut(&mut first.value)),
+        Term::Second(second) => free(Arc::make_mut(&mut second.value)),
+        Term::Tuple(tuple) => union(
+            free(Arc::make_mut(&mut tuple.first)),
+            free(Arc::make_mut(&mut tuple.second)),
+        ),
+    })
+}
+
+/// Merges two sets of names, extending the larger one.
+fn union(lhs: HashSet<Symbol>, rhs: HashSet<Symbol>) -> HashSet<Symbol> {
+    let (mut larger, smaller) = match lhs.len() >= rhs.len() {
+        true => (lhs, rhs),
+        false => (rhs, lhs),
+    };
+    larger.extend(smaller);
+
+    larger
+}
+
+#[cfg(test)]
+mod tests {
+    use super::annotate;
+    use crate::{ast::Term, symbol::Symbol};
+
+    fn term(json: &str) -> Term {
+        serde_json::from_str(&json.replace("LOC", r#"{ "start": 0, "end": 0, "filename": "" }"#))
+            .unwrap()
+    }
+
+    #[test]
+    fn functions_capture_what_they_refer_to_besides_their_bindings() {
+        // fn (x) => let y = x; y + z + f
+        let mut function = term(
+            r#"{ "kind": "Function", "location": LOC,
+                 "parameters": [{ "text": "x", "location": LOC }],
+                 "value": { "kind": "Let", "location": LOC,
+                   "name": { "text": "y", "location": LOC },
+                   "value": { "kind": "Var", "text": "x", "location": LOC },
+                   "next": { "kind": "Binary", "op": "Add", "location": LOC,
+                     "lhs": { "kind": "Var", "text": "y", "location": LOC },
+                     "rhs": { "kind": "Binary", "op": "Add", "location": LOC,
+                       "lhs": { "kind": "Var", "text": "z", "location": LOC },
+                       "rhs": { "kind": "Var", "text": "f", "location": LOC } } } } }"#,
+        );
+        annotate(&mut function);
+
+        let Term::Function(function) = &function else {
+            unreachable!("the term is a function")
+        };
+        let mut free = function.free.as_ref().unwrap().to_vec();
+        free.sort_by_key(|name| name.as_str());
+
+        assert_eq!(free, [Symbol::new("f"), Symbol::new("z")]);
+    }
+
+    #[test]
+    fn deeply_nested_terms_are_annotated() {
+        // With the stack of a main thread, as dropping the term still
+        // recurses as deep as it nests.
+        let thread = std::thread::Builder::new().stack_size(8 << 20);
+        let annotated = thread.spawn(|| {
+            let source = format!("print({})", vec!["1"; 20_000].join(" + "));
+            let mut term = crate::parser::parse_term(&source, "tests").unwrap();
+
+            crate::resolve::resolve(&mut term);
+            annotate(&mut term);
+            crate::digest::annotate(&mut term);
+        });
+
+        annotated.unwrap().join().unwrap();
+    }
+}
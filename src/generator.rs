@@ -0,0 +1,412 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::Debug,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    ast::Location,
+    compiler::Program,
+    interpreter::{self, Printer, RuntimeError, RuntimeErrorKind, State, Value},
+    register,
+    vm::{self, Variant},
+};
+
+/// How many generators may be started and not done at once, in the whole
+/// process. Each holds a thread of its own while suspended, and the
+/// process aborts once the system can't set up another one.
+pub const MAX_GENERATORS: usize = 4_096;
+
+/// The threads of generators running or suspended, out of
+/// [`MAX_GENERATORS`].
+static THREADS: Slots = Slots::new(MAX_GENERATORS);
+
+thread_local! {
+    /// The turns of the generator whose body runs on this thread, if any.
+    static RUNNING: RefCell<Option<Turns>> = const { RefCell::new(None) };
+}
+
+/// A function producing values on demand: its body runs until it yields a
+/// value, and is suspended there until the next one is asked for. See the
+/// `generator`, `yield` and `next` builtins.
+///
+/// Evaluations can't be suspended halfway through the stack of the host,
+/// so the body runs on a thread of its own, taking turns with whoever
+/// resumes it: only one of them runs at a time, and the body spends the
+/// fuel and prints with the printer of the evaluation resuming it.
+#[derive(Clone)]
+pub struct Generator(Arc<Mutex<Status>>);
+
+enum Status {
+    /// Not resumed yet, holding the function of its body.
+    Created(Value),
+
+    /// Waiting in a `yield` for its next turn.
+    Suspended(Handle),
+
+    Running,
+
+    /// The body returned, failed, or its thread was dropped along the way.
+    Done,
+}
+
+/// What the evaluation resuming a generator holds of its body.
+struct Handle {
+    resumes: Sender<Resume>,
+    suspends: Receiver<Suspend>,
+    thread: JoinHandle<()>,
+}
+
+/// What the thread of a body holds of the evaluation resuming it.
+struct Turns {
+    resumes: Receiver<Resume>,
+    suspends: Sender<Suspend>,
+}
+
+/// Hands the turn to the body, with the fuel left.
+struct Resume {
+    fuel: Option<u64>,
+}
+
+/// What the body tells the evaluation that resumed it, handing the turn
+/// back with the fuel left unless it prints.
+enum Suspend {
+    Print(Value),
+    Yield(Value, Option<u64>),
+    Return(Result<(), RuntimeError>, Option<u64>),
+}
+
+impl Generator {
+    /// Creates a new instance of [`Generator`], running the function
+    /// `body` once it is first resumed.
+    pub fn new(body: Value) -> Self {
+        Self(Arc::new(Mutex::new(Status::Created(body))))
+    }
+
+    /// Whether both are the same generator, created once and copied since.
+    pub(crate) fn same(&self, other: &Generator) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Runs the body until it yields a value, giving it, or until it
+    /// returns, giving `None` then and on every later turn. Compiled
+    /// bodies are run with `program` and its `globals`, as the vm resuming
+    /// them resolved them, on the same machine.
+    pub(crate) fn resume<I: Printer>(
+        &self,
+        program: Option<(&Program, &[Option<Value>], Variant)>,
+        location: &Location,
+        state: &mut State,
+        io: &mut I,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let status = std::mem::replace(&mut *self.status(), Status::Running);
+        let handle = match status {
+            Status::Created(body) => match start(body, program, location, state) {
+                Ok(handle) => handle,
+                Err(error) => {
+                    *self.status() = Status::Done;
+                    return Err(error);
+                }
+            },
+            Status::Suspended(handle) => handle,
+            Status::Running => {
+                return Err(RuntimeError {
+                    kind: RuntimeErrorKind::Failed,
+                    message: String::from("generator already running"),
+                    full_text: String::from("a generator can't be resumed from its own body"),
+                    location: location.clone(),
+                    backtrace: Vec::new(),
+                })
+            }
+            Status::Done => {
+                *self.status() = Status::Done;
+                return Ok(None);
+            }
+        };
+
+        // The body is gone if this fails, which receiving tells apart.
+        let _ = handle.resumes.send(Resume { fuel: state.fuel });
+
+        loop {
+            let suspended = match handle.suspends.recv() {
+                Ok(suspended) => suspended,
+                Err(_) => {
+                    *self.status() = Status::Done;
+                    let panic = match handle.thread.join() {
+                        Err(panic) => panic,
+                        Ok(()) => unreachable!("the body returns before ending"),
+                    };
+
+                    return Err(RuntimeError {
+                        kind: RuntimeErrorKind::Failed,
+                        message: String::from("generator panicked"),
+                        full_text: format!(
+                            "the body of the generator panicked: {}",
+                            reason(&*panic)
+                        ),
+                        location: location.clone(),
+                        backtrace: Vec::new(),
+                    });
+                }
+            };
+
+            match suspended {
+                Suspend::Print(value) => {
+                    if let Err(error) = io.print(value) {
+                        // Dropping the handle stops the body at its next
+                        // turn.
+                        *self.status() = Status::Done;
+
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::Output,
+                            message: String::from("failed to print"),
+                            full_text: format!("the printed value could not be written: {error}"),
+                            location: location.clone(),
+                            backtrace: Vec::new(),
+                        });
+                    }
+                }
+                Suspend::Yield(value, fuel) => {
+                    state.fuel = fuel;
+                    *self.status() = Status::Suspended(handle);
+
+                    return Ok(Some(value));
+                }
+                Suspend::Return(result, fuel) => {
+                    state.fuel = fuel;
+                    *self.status() = Status::Done;
+
+                    return result.map(|()| None);
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> MutexGuard<'_, Status> {
+        self.0
+            .lock()
+            .expect("nothing panics while the status is locked")
+    }
+}
+
+impl Debug for Generator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Generator")
+    }
+}
+
+/// Spawns the thread running `body`, as called from `location`, waiting
+/// for its first turn, unless [`MAX_GENERATORS`] are started already.
+fn start(
+    body: Value,
+    program: Option<(&Program, &[Option<Value>], Variant)>,
+    location: &Location,
+    state: &State,
+) -> Result<Handle, RuntimeError> {
+    let Some(slot) = THREADS.take() else {
+        return Err(RuntimeError {
+            kind: RuntimeErrorKind::Failed,
+            message: String::from("too many generators"),
+            full_text: format!(
+                "more than {MAX_GENERATORS} generators were started and not done at once"
+            ),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        });
+    };
+
+    let (resumes, resumed) = mpsc::channel();
+    let (suspended, suspends) = mpsc::channel();
+
+    let program =
+        program.map(|(program, globals, variant)| (program.clone(), globals.to_vec(), variant));
+    let called_at = location.clone();
+    let mut state = state.nested();
+
+    let thread = thread::Builder::new()
+        .name(String::from("generator"))
+        .spawn(move || {
+            // Given back once the body ends, however it does.
+            let _slot = slot;
+
+            let Ok(Resume { fuel }) = resumed.recv() else {
+                return;
+            };
+            state.fuel = fuel;
+
+            let mut printer = Forward(suspended.clone());
+            RUNNING.with(|running| {
+                *running.borrow_mut() = Some(Turns {
+                    resumes: resumed,
+                    suspends: suspended.clone(),
+                })
+            });
+
+            let result = match (&body, program) {
+                (Value::Compiled(_), Some((program, globals, Variant::Stack))) => vm::call(
+                    &program,
+                    globals,
+                    body,
+                    &called_at,
+                    &mut state,
+                    &mut printer,
+                ),
+                (Value::Compiled(_), Some((program, globals, Variant::Register))) => {
+                    register::call(
+                        &program,
+                        globals,
+                        body,
+                        &called_at,
+                        &mut state,
+                        &mut printer,
+                    )
+                }
+                _body => interpreter::apply(
+                    body,
+                    Default::default(),
+                    &called_at,
+                    &mut state,
+                    &mut printer,
+                ),
+            };
+
+            // Nobody waits for the result once the generator is dropped.
+            let _ = suspended.send(Suspend::Return(result.map(drop), state.fuel));
+        })
+        .map_err(|error| RuntimeError {
+            kind: RuntimeErrorKind::Failed,
+            message: String::from("failed to start the generator"),
+            full_text: format!("the thread of the generator could not be started: {error}"),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        })?;
+
+    Ok(Handle {
+        resumes,
+        suspends,
+        thread,
+    })
+}
+
+/// What a panic was raised with, when it was a message.
+fn reason(panic: &(dyn Any + Send)) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _unknown => "no message",
+    }
+}
+
+/// Counts the threads taken out of a limited number of them.
+struct Slots {
+    taken: AtomicUsize,
+    max: usize,
+}
+
+impl Slots {
+    const fn new(max: usize) -> Self {
+        Self {
+            taken: AtomicUsize::new(0),
+            max,
+        }
+    }
+
+    /// Takes a slot, given back once the one returned is dropped, or
+    /// `None` if all are taken.
+    fn take(&self) -> Option<Slot<'_>> {
+        self.taken
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+                (taken < self.max).then_some(taken + 1)
+            })
+            .ok()
+            .map(|_taken| Slot(self))
+    }
+}
+
+struct Slot<'s>(&'s Slots);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.taken.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Hands `value` to the evaluation that resumed the generator whose body
+/// runs on this thread, waiting for its next turn.
+pub(crate) fn suspend(
+    value: Value,
+    location: &Location,
+    state: &mut State,
+) -> Result<Value, RuntimeError> {
+    RUNNING.with(|running| {
+        let running = running.borrow();
+        let Some(turns) = running.as_ref() else {
+            return Err(RuntimeError {
+                kind: RuntimeErrorKind::Failed,
+                message: String::from("yield outside of a generator"),
+                full_text: String::from(
+                    "yield can only be called while the body of a generator runs",
+                ),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            });
+        };
+
+        // The next turn never comes if this fails, which receiving tells.
+        let _ = turns.suspends.send(Suspend::Yield(value, state.fuel));
+
+        match turns.resumes.recv() {
+            Ok(Resume { fuel }) => {
+                state.fuel = fuel;
+
+                Ok(Value::Unit)
+            }
+            Err(_) => Err(RuntimeError {
+                kind: RuntimeErrorKind::Interrupted,
+                message: String::from("generator dropped"),
+                full_text: String::from("the generator was dropped before it finished"),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            }),
+        }
+    })
+}
+
+/// Prints through the evaluation that resumed the body.
+struct Forward(Sender<Suspend>);
+
+impl Printer for Forward {
+    fn print(&mut self, value: Value) -> io::Result<Value> {
+        match self.0.send(Suspend::Print(value.clone())) {
+            Ok(()) => Ok(value),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the generator was dropped",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slots;
+
+    #[test]
+    fn slots_are_given_back_once_dropped() {
+        let slots = Slots::new(2);
+
+        let first = slots.take().unwrap();
+        let _second = slots.take().unwrap();
+        assert!(slots.take().is_none());
+
+        drop(first);
+        assert!(slots.take().is_some());
+    }
+}
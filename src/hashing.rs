@@ -0,0 +1,179 @@
+use std::hash::Hasher;
+
+use siphasher::sip128::{Hasher128, SipHasher13};
+
+/// How the keys of memoized calls are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hashing {
+    /// SipHash-1-3 with keys of zero, so the same call has the same key in
+    /// every process, and caches can be persisted or shared.
+    #[default]
+    Stable,
+
+    /// SipHash-1-3 with a secret key, so programs can't craft calls whose
+    /// keys collide, like in a cache shared by untrusted programs. Keys are
+    /// still the same in every process given the same secret.
+    Keyed([u8; 16]),
+}
+
+impl Hashing {
+    /// A hasher to build a key with.
+    pub fn hasher(self) -> StableHasher {
+        match self {
+            Hashing::Stable => StableHasher::new(),
+            Hashing::Keyed(key) => StableHasher(SipHasher13::new_with_key(&key)),
+        }
+    }
+}
+
+/// A SipHash-1-3 hasher writing integers as little-endian bytes, and
+/// `usize`s and `isize`s as 64 bits, so values hash the same on every
+/// platform. Unlike [`DefaultHasher`](std::collections::hash_map::DefaultHasher),
+/// its algorithm is fixed, so hashes stay the same across Rust versions.
+#[derive(Debug, Clone)]
+pub struct StableHasher(SipHasher13);
+
+impl StableHasher {
+    /// Creates a new instance of [`StableHasher`], with keys of zero.
+    pub fn new() -> Self {
+        Self(SipHasher13::new())
+    }
+
+    /// The 128-bit hash of what was written so far.
+    pub fn finish128(&self) -> u128 {
+        self.0.finish128().as_u128()
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn write_u8(&mut self, int: u8) {
+        self.write(&[int]);
+    }
+
+    fn write_u16(&mut self, int: u16) {
+        self.write(&int.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, int: u32) {
+        self.write(&int.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, int: u64) {
+        self.write(&int.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, int: u128) {
+        self.write(&int.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, int: usize) {
+        self.write_u64(int as u64);
+    }
+
+    fn write_i8(&mut self, int: i8) {
+        self.write_u8(int as u8);
+    }
+
+    fn write_i16(&mut self, int: i16) {
+        self.write_u16(int as u16);
+    }
+
+    fn write_i32(&mut self, int: i32) {
+        self.write_u32(int as u32);
+    }
+
+    fn write_i64(&mut self, int: i64) {
+        self.write_u64(int as u64);
+    }
+
+    fn write_i128(&mut self, int: i128) {
+        self.write_u128(int as u128);
+    }
+
+    fn write_isize(&mut self, int: isize) {
+        self.write_u64(int as u64);
+    }
+}
+
+/// A hasher for keys that are uniformly distributed hashes already, like
+/// the keys of memoized calls, folding their bits instead of hashing them
+/// again. Other keys are mixed in like FxHash does, which is fast but only
+/// fit for keys no one crafts to collide.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyHasher(u64);
+
+impl KeyHasher {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    fn mix(&mut self, int: u64) {
+        self.0 = (self.0.rotate_left(5) ^ int).wrapping_mul(Self::SEED);
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut int = [0; 8];
+            int[..chunk.len()].copy_from_slice(chunk);
+            self.mix(u64::from_le_bytes(int));
+        }
+    }
+
+    fn write_u128(&mut self, int: u128) {
+        self.0 ^= int as u64 ^ (int >> 64) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+    use super::{Hashing, KeyHasher, StableHasher};
+
+    #[test]
+    fn hashes_are_fixed() {
+        let mut hasher = StableHasher::new();
+        hasher.write_usize(3);
+        hasher.write(b"fib");
+        hasher.write_i64(-1);
+
+        // Computed once; a different value means persisted caches broke.
+        assert_eq!(hasher.finish128(), 280177328780436085372069742103409953992);
+
+        let mut keyed = Hashing::Keyed([1; 16]).hasher();
+        keyed.write_usize(3);
+        keyed.write(b"fib");
+        keyed.write_i64(-1);
+        assert_ne!(keyed.finish128(), hasher.finish128());
+    }
+
+    #[test]
+    fn hashed_keys_are_folded() {
+        let build = BuildHasherDefault::<KeyHasher>::default();
+        let key = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210_u128;
+
+        assert_eq!(
+            build.hash_one(key),
+            0x0123_4567_89ab_cdef ^ 0xfedc_ba98_7654_3210
+        );
+        assert_ne!(build.hash_one("fib"), build.hash_one("fob"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Spans and events of evaluations for the `tracing` crate, when built
+//! with the `tracing` feature, and nothing otherwise.
+//!
+//! Calls are `call` spans at the info level, with the name of the function
+//! called when it was bound by a `let`, and where it was called from. Cache
+//! hits and misses are debug events, and errors ending an evaluation are
+//! warn events.
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+
+use crate::{ast::Location, interpreter::RuntimeError};
+
+/// Keeps the span of a call entered until it is dropped.
+#[cfg(feature = "tracing")]
+pub(crate) type CallSpan = tracing::span::EnteredSpan;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct CallSpan;
+
+/// Enters the span of a call of the function bound to `name`, made at
+/// `location`.
+#[cfg(feature = "tracing")]
+pub(crate) fn call(name: Option<&str>, location: &Location) -> CallSpan {
+    tracing::info_span!(
+        "call",
+        function = name,
+        filename = location.filename.as_str(),
+        start = location.start,
+        end = location.end,
+    )
+    .entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn call(name: Option<&str>, location: &Location) -> CallSpan {
+    CallSpan
+}
+
+/// A call of the function bound to `name`, defined at `location`, was
+/// found in the cache.
+pub(crate) fn cache_hit(name: Option<&str>, location: &Location) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        function = name,
+        filename = location.filename.as_str(),
+        start = location.start,
+        end = location.end,
+        "cache hit"
+    );
+}
+
+/// A call of the function bound to `name`, defined at `location`, is
+/// evaluated to be cached.
+pub(crate) fn cache_miss(name: Option<&str>, location: &Location) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        function = name,
+        filename = location.filename.as_str(),
+        start = location.start,
+        end = location.end,
+        "cache miss"
+    );
+}
+
+/// An evaluation failed with `error`.
+pub(crate) fn error(error: &RuntimeError) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        kind = ?error.kind,
+        filename = error.location.filename.as_str(),
+        start = error.location.start,
+        end = error.location.end,
+        "{}: {}",
+        error.message,
+        error.full_text
+    );
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use crate::{interpreter::Capture, parser::parse};
+
+    /// Records the spans created and the events emitted, as the function
+    /// of the span or the message of the event.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    /// The message and function fields of a span or event.
+    #[derive(Default)]
+    struct Text {
+        message: String,
+        function: String,
+    }
+
+    impl Text {
+        fn of(record: impl FnOnce(&mut Self)) -> String {
+            let mut text = Text::default();
+            record(&mut text);
+
+            format!("{} {}", text.message, text.function)
+                .trim()
+                .to_string()
+        }
+    }
+
+    impl Visit for Text {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "function" {
+                self.function = value.to_string();
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{value:?}");
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let text = Text::of(|text| span.record(text));
+            let mut recorded = self.0.lock().unwrap();
+            recorded.push(format!("{} {text}", span.metadata().name()));
+
+            span::Id::from_u64(recorded.len() as u64)
+        }
+
+        fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let text = Text::of(|text| event.record(text));
+            self.0.lock().unwrap().push(text);
+        }
+
+        fn enter(&self, _span: &span::Id) {}
+
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    #[test]
+    fn calls_cache_and_errors_are_traced() {
+        let recorder = Recorder::default();
+        let source = "let inc = fn (x) => { x + 1 }; let _ = inc(inc(1)); inc(true)";
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let file = parse(source, "tests").unwrap();
+            crate::run_file(file, &mut Capture::new()).unwrap_err();
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "cache miss inc",
+                "call inc",
+                "cache miss inc",
+                "call inc",
+                "cache miss inc",
+                "call inc",
+                "invalid addition: true cannot be added to 1",
+            ]
+        );
+    }
+}
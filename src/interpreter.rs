@@ -1,460 +1,2799 @@
 use std::{
-    cell::RefCell,
-    collections::{hash_map::DefaultHasher, HashMap},
-    fmt::Display,
-    hash::{Hash, Hasher},
-    rc::Rc,
+    borrow::Cow,
+    collections::HashSet,
+    fmt::{Debug, Display},
+    hash::{BuildHasherDefault, Hasher},
+    io::{BufWriter, ErrorKind, Stdout, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::ast::{
-    Binary, Call, Element, First, Function, If, Let, Location, Print, Second, Term, Var,
+use smallvec::SmallVec;
+
+use crate::{
+    ast::{
+        Binary, BinaryOp, Call, Cond, Element, First, Function, If, Layout, Let, Location, Print,
+        Second, Seq, Slot, Term, Var,
+    },
+    binary::Division,
+    cache::CacheStore,
+    collections::{Map, Set},
+    diagnostics::{Diagnostic, Diagnostics},
+    digest::Digest,
+    generator::Generator,
+    hashing::{Hashing, KeyHasher, StableHasher},
+    instrument::{self, CallSpan},
+    observe::EvalObserver,
+    progress::Progress,
+    stats::Stats,
+    symbol::Symbol,
+    text::Text,
+    trace::Trace,
+    warnings::{self, Warnings},
 };
 
 #[derive(Clone, Debug)]
 pub struct Closure {
-    parameters: Vec<Var>,
-    body: Box<Term>,
-    context: Rc<RefCell<Context>>,
+    name: Option<Arc<str>>,
+    parameters: Arc<[Var]>,
+    body: Arc<Term>,
+    context: Arc<Context>,
+    location: Location,
+
+    /// The names the closure is bound to that its body refers to, bound to
+    /// the closure itself on every call rather than in its context, so the
+    /// context never holds the closure and is never mutated once created.
+    itself: Vec<Symbol>,
+
+    /// The names the body refers to besides the parameters, the only ones
+    /// captured in its context, or `None` when it captured every binding.
+    free: Option<Arc<[Symbol]>>,
+
+    /// The frame of the body and the values captured for it, when the
+    /// function was resolved.
+    layout: Option<Arc<Layout>>,
+    captured: Arc<[Option<Value>]>,
+
+    /// The digest of the body, when the function was digested.
+    digest: Option<Digest>,
+
+    /// Whether the body prints nothing itself, when the function was
+    /// annotated with it.
+    pure: Option<bool>,
 }
 
-#[derive(Clone, Debug)]
-pub struct Tuple {
-    first: Box<Value>,
-    second: Box<Value>,
-}
+impl Closure {
+    /// The number of parameters of the function.
+    pub fn arity(&self) -> usize {
+        self.parameters.len()
+    }
 
-impl Display for Tuple {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let first = self.first.clone();
-        let second = self.second.clone();
+    /// Whether the body may refer to `name` through its captured context.
+    fn captures(&self, name: Symbol) -> bool {
+        self.free.as_ref().is_none_or(|free| free.contains(&name))
+    }
 
-        write!(f, "({first}, {second})")
+    /// The digest of the body, hashing it when the function wasn't
+    /// digested ahead of time.
+    fn digest(&self) -> Digest {
+        self.digest.unwrap_or_else(|| crate::digest::of(&self.body))
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum Value {
-    Closure(Closure),
-    Int(i64),
-    Str(String),
-    Bool(bool),
-    Tuple(Tuple),
-}
+    /// Whether the body prints nothing itself, walking it when the
+    /// function wasn't annotated with it ahead of time.
+    fn is_pure(&self) -> bool {
+        self.pure.unwrap_or_else(|| crate::purity::of(&self.body))
+    }
 
-impl Hash for Value {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        match self {
-            Self::Closure(_closure) => panic!("this should never be executed"),
-            Self::Int(int) => format!("Int({int})").hash(state),
-            Self::Str(string) => format!("Str({string})").hash(state),
-            Self::Bool(bool) => format!("Bool({bool})").hash(state),
-            Self::Tuple(tuple) => format!("Tuple({tuple})").hash(state),
-        }
+    /// The values the body may read besides its arguments and itself.
+    fn environment(&self) -> impl Iterator<Item = &Value> {
+        environment(
+            &self.context,
+            self.free.as_deref(),
+            &self.captured,
+            &self.itself,
+        )
     }
-}
 
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let value = match self {
-            Self::Closure(_closure) => String::from("[closure]"),
-            Self::Int(int) => int.to_string(),
-            Self::Str(str) => str.to_string(),
-            Self::Bool(bool) => bool.to_string(),
-            Self::Tuple(tuple) => {
-                format!(
-                    "({}, {})",
-                    tuple.first.to_string(),
-                    tuple.second.to_string()
-                )
-            }
-        };
+    /// The name of the binding the closure was first bound to, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 
-        f.write_str(&value)
+    /// The function the closure was created from. Its body is left as it
+    /// was annotated by the passes, unlike the function itself.
+    pub(crate) fn function(&self) -> Function {
+        Function {
+            parameters: self.parameters.clone(),
+            value: self.body.clone(),
+            location: self.location.clone(),
+            free: None,
+            layout: None,
+            digest: None,
+            pure: None,
+        }
     }
-}
 
-pub type Cache = std::collections::HashMap<String, Value>;
-pub type Context = HashMap<String, Value>;
+    /// The names bound to the closure itself on every call, including the
+    /// name a resolved body refers to itself by through its frame.
+    pub(crate) fn itself(&self) -> Vec<Symbol> {
+        let mut itself = self.itself.clone();
+        if let (Some(name), Some(layout)) = (&self.name, &self.layout) {
+            let name = Symbol::new(name);
+            if layout.recursive && !itself.contains(&name) {
+                itself.push(name);
+            }
+        }
 
-#[derive(Debug, Clone)]
-pub struct RuntimeError {
-    pub message: String,
-    pub full_text: String,
-    pub location: Location,
-}
+        itself
+    }
 
-fn eval_let<I: Printer>(
-    let_: Let,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    let name = let_.name.text;
+    /// The values captured for the frame of a resolved body, by the name
+    /// they were captured as, or `None` when the body wasn't resolved.
+    pub(crate) fn captured(&self) -> Option<Vec<(Symbol, Option<Value>)>> {
+        let layout = self.layout.as_ref()?;
+
+        Some(
+            layout
+                .captured_names
+                .iter()
+                .copied()
+                .zip(self.captured.iter().cloned())
+                .collect(),
+        )
+    }
 
-    match eval(let_.value, context, cache, io)? {
-        Value::Closure(closure) => {
-            let self_ = Value::Closure(Closure {
-                parameters: closure.parameters,
-                body: closure.body,
-                context: closure.context.clone(),
-            });
+    /// The bindings captured by name, innermost first.
+    pub(crate) fn context(&self) -> &Context {
+        &self.context
+    }
 
-            closure
-                .context
-                .borrow_mut()
-                .insert(name.clone(), self_.clone());
+    /// Whether the resolved body refers to the closure through its frame,
+    /// by the name of the closure.
+    pub(crate) fn is_recursive(&self) -> bool {
+        self.layout.as_ref().is_some_and(|layout| layout.recursive)
+    }
 
-            context.insert(name, self_.clone());
+    /// The closure of `function` created with the values `captured` for its
+    /// frame and `context`, as returned by [`captured`](Self::captured) and
+    /// [`context`](Self::context), going through the passes again.
+    ///
+    /// Resolved bodies are resolved inside a function taking the captured
+    /// names, and bound to `name` when `recursive`, so they come out as the
+    /// body of the closure was, down to its digest.
+    pub(crate) fn rebuild(
+        name: Option<&str>,
+        function: Function,
+        itself: Vec<Symbol>,
+        captured: Option<Vec<(Symbol, Option<Value>)>>,
+        recursive: bool,
+        context: Context,
+    ) -> Closure {
+        use crate::ast::build::{self, let_, var};
+
+        let mut term = Term::Function(function);
+        let values = match captured {
+            Some(captured) => {
+                let (names, values): (Vec<Symbol>, Vec<Option<Value>>) =
+                    captured.into_iter().unzip();
+                let body = match (name, recursive) {
+                    (Some(name), true) => let_(name, term).in_(var(name)),
+                    _ => term,
+                };
+                term = build::function(names.iter().map(|name| name.as_str()), body);
+                crate::resolve::resolve(&mut term);
+
+                Some(values)
+            }
+            None => None,
+        };
+        crate::free::annotate(&mut term);
+        crate::digest::annotate(&mut term);
+        crate::purity::annotate(&mut term);
+
+        // Unwraps the function from the function and the let around it.
+        if values.is_some() {
+            term = match &mut term {
+                Term::Function(wrapper) => Arc::unwrap_or_clone(std::mem::take(&mut wrapper.value)),
+                _ => unreachable!("resolved functions are wrapped"),
+            };
+        }
+        if let Term::Let(let_) = &mut term {
+            term = Arc::unwrap_or_clone(std::mem::take(&mut let_.value));
         }
-        value => {
-            context.insert(name, value);
+        let Term::Function(function) = &mut term else {
+            unreachable!("the function is only wrapped in a function and a let")
+        };
+        let function = std::mem::take(function);
+
+        Closure {
+            name: name.map(Arc::from),
+            parameters: function.parameters,
+            body: function.value,
+            context: Arc::new(context),
+            location: function.location,
+            itself,
+            free: function.free,
+            layout: function.layout,
+            captured: values.unwrap_or_default().into(),
+            digest: function.digest,
+            pure: function.pure,
         }
     }
+}
 
-    eval(let_.next, context, cache, io)
+/// The values a function body may read besides its arguments: the ones
+/// `captured` for its frame and the bindings of its `free` names in
+/// `context`, or every binding when they weren't annotated. The names in
+/// `itself` are left out, as they are bound to the function.
+fn environment<'a>(
+    context: &'a Context,
+    free: Option<&'a [Symbol]>,
+    captured: &'a [Option<Value>],
+    itself: &'a [Symbol],
+) -> impl Iterator<Item = &'a Value> {
+    let named: Box<dyn Iterator<Item = (Symbol, &Value)>> = match free {
+        Some(free) => Box::new(
+            free.iter()
+                .filter_map(|&name| Some((name, context.get(name)?))),
+        ),
+        None => Box::new(context.iter()),
+    };
+
+    captured.iter().flatten().chain(
+        named
+            .filter(|(name, _value)| !itself.contains(name))
+            .map(|(_name, value)| value),
+    )
 }
 
-fn cache_key(body: &Box<Term>, arguments: Vec<Value>) -> Option<String> {
-    let arguments = arguments
-        .into_iter()
-        .map(|argument| match argument {
-            Value::Closure(_) => None,
-            value => {
-                let mut s = DefaultHasher::new();
-                // TODO: is ok to define the hasher on each iteration?
-                value.hash(&mut s);
-                Some(s.finish().to_string())
-            }
-        })
-        .collect::<Option<Vec<String>>>()?;
+pub type NativeFunction =
+    dyn Fn(Vec<Value>, &Location) -> Result<Value, RuntimeError> + Send + Sync;
 
-    let mut s = DefaultHasher::new();
-    (*body.clone(), arguments).hash(&mut s);
+/// Calls a function value with the given arguments, letting natives call
+/// back into the functions they are given.
+pub type Apply<'a> = dyn FnMut(Value, Vec<Value>) -> Result<Value, RuntimeError> + 'a;
 
-    Some(s.finish().to_string())
+pub type HigherOrderFunction =
+    dyn Fn(Vec<Value>, &Location, &mut Apply) -> Result<Value, RuntimeError> + Send + Sync;
+
+pub type IntrospectiveFunction =
+    dyn Fn(Vec<Value>, &Location, &[CallFrame]) -> Result<Value, RuntimeError> + Send + Sync;
+
+pub(crate) type HostedFunction =
+    dyn Fn(Vec<Value>, &Location, &mut dyn Host) -> Result<Value, RuntimeError> + Send + Sync;
+
+#[derive(Clone)]
+enum Implementation {
+    Plain(Arc<NativeFunction>),
+    HigherOrder(Arc<HigherOrderFunction>),
+    Introspective(Arc<IntrospectiveFunction>),
+    Hosted(Arc<HostedFunction>),
 }
 
-fn eval_memo<I: Printer>(
-    body: Box<Term>,
-    arguments: Vec<Value>,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    match cache_key(&body, arguments.clone()) {
-        Some(cache_key) => match cache.get(&cache_key) {
-            Some(cached_value) => Ok(cached_value.clone()),
-            None => {
-                let value = eval(body, context, cache, io)?;
-                cache.insert(cache_key, value.clone());
+/// What tells a native apart from the other natives of its name in cache
+/// keys, which outlive the native and may be kept on disk.
+#[derive(Clone)]
+enum Identity {
+    /// A builtin, the same function in every run for its name.
+    Builtin,
 
-                Ok(value)
-            }
-        },
-        None => eval(body, context, cache, io),
-    }
+    /// A function made out of a value, like the results of `fix`, the same
+    /// function for its name and the value.
+    Wrapping(Arc<Value>),
+
+    /// A function of the host, that nothing tells apart from another one of
+    /// its name.
+    Opaque,
 }
 
-fn eval_call<I: Printer>(
-    call: Call,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    match eval(call.callee, context, cache, io)? {
-        Value::Closure(closure) => {
-            let mut new_context = closure.context.borrow_mut().clone();
-            let mut arguments = Vec::new();
+/// A call being evaluated, as natives created with
+/// [`Native::introspective`] and [`State::frames`] see it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CallFrame {
+    /// The name of the called function, when it was bound to one.
+    pub name: Option<Arc<str>>,
 
-            for (parameter, argument) in closure.parameters.clone().into_iter().zip(call.arguments)
-            {
-                let argument = eval(Box::new(argument), context, cache, io)?;
-                arguments.push(argument.clone());
+    /// Where the function was called from.
+    pub location: Location,
 
-                new_context.insert(parameter.text, argument);
-            }
+    /// How many more times, right inside this call, the same function was
+    /// called from the same place, folded into this frame in backtraces.
+    /// Always 0 in [`State::frames`].
+    pub repeated: usize,
+}
 
-            match closure.body.is_pure() {
-                true => eval_memo(closure.body, arguments, &mut new_context, cache, io),
-                false => eval(closure.body, &mut new_context, cache, io),
+/// Copies `frames`, outermost first, into a backtrace, folding each run of
+/// the same call made over and over into its first frame, so deep
+/// recursion leaves a short backtrace.
+pub(crate) fn backtrace(frames: &[CallFrame]) -> Vec<CallFrame> {
+    let mut backtrace: Vec<CallFrame> = Vec::new();
+    for frame in frames {
+        match backtrace.last_mut() {
+            Some(last) if last.name == frame.name && last.location == frame.location => {
+                last.repeated += 1 + frame.repeated;
             }
+            _ => backtrace.push(frame.clone()),
         }
-        value => Err(RuntimeError {
-            message: String::from("invalid function call"),
-            full_text: format!("{} cannot be called as a function", value),
-            location: call.location,
-        }),
     }
-}
-
-fn eval_if<I: Printer>(
-    if_: If,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    let condition_result = eval(if_.condition.clone(), context, cache, io)?;
-    let condition = match condition_result {
-        Value::Bool(bool) => Ok(bool),
-        _ => Err(RuntimeError {
-            message: String::from("invalid if condition"),
-            full_text: format!(
-                "{} can't be used as an if condition. use a boolean instead",
-                condition_result
-            ),
-            location: if_.condition.location().clone(),
-        }),
-    }?;
 
-    match condition {
-        true => eval(if_.then, context, cache, io),
-        false => eval(if_.otherwise, context, cache, io),
-    }
+    backtrace
 }
 
-fn eval_binary<I: Printer>(
-    binary: Binary,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    let lhs = eval(binary.lhs.clone(), context, cache, io)?;
-    let rhs = eval(binary.rhs.clone(), context, cache, io)?;
+/// Where natives are called from, the interpreter or the vm, giving them
+/// what they may need of it.
+pub(crate) trait Host {
+    /// Calls `callee` with `arguments`, for natives calling back into the
+    /// functions they are given.
+    fn apply(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError>;
 
-    lhs.binary_op(binary, rhs)
-}
+    /// The calls being evaluated, outermost first.
+    fn frames(&self) -> Cow<'_, [CallFrame]>;
 
-fn eval_var(var: Var, context: &mut Context) -> Result<Value, RuntimeError> {
-    context
-        .get(&var.text)
-        .ok_or(RuntimeError {
-            message: format!("unbound variable \"{}\"", var.text),
-            full_text: format!(
-                "variable \"{}\" was not defined in the current scope",
-                var.text
-            ),
-            location: var.location,
-        })
-        .map(|value| value.clone())
-}
+    /// Runs `generator` until it yields a value, as
+    /// [`Generator::resume`] does, with the fuel and printer of the host.
+    fn resume(&mut self, generator: &Generator) -> Result<Option<Value>, RuntimeError>;
 
-fn eval_tuple<I: Printer>(
-    tuple: crate::ast::Tuple,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    let first = eval(tuple.first, context, cache, io)?;
-    let second = eval(tuple.second, context, cache, io)?;
+    /// The state of the evaluation.
+    fn state(&mut self) -> &mut State;
+}
 
-    Ok(Value::Tuple(Tuple {
-        first: Box::new(first),
-        second: Box::new(second),
-    }))
+/// The number of arguments a [`Native`] function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    Between(usize, usize),
+    AtLeast(usize),
 }
 
-fn eval_first<I: Printer>(
-    first: First,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    match eval(first.value, context, cache, io)? {
-        Value::Tuple(Tuple { first, second: _ }) => Ok(*first),
-        _value => Err(RuntimeError {
-            message: String::from("invalid expression"),
-            full_text: String::from("cannot use first operation from anything but a tuple"),
-            location: first.location,
-        }),
+impl Arity {
+    pub fn accepts(&self, arguments: usize) -> bool {
+        match *self {
+            Arity::Exactly(arity) => arguments == arity,
+            Arity::Between(min, max) => (min..=max).contains(&arguments),
+            Arity::AtLeast(min) => arguments >= min,
+        }
     }
 }
 
-fn eval_second<I: Printer>(
-    second: Second,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    match eval(second.value, context, cache, io)? {
-        Value::Tuple(Tuple { first: _, second }) => Ok(*second),
-        _value => Err(RuntimeError {
-            message: String::from("invalid expression"),
-            full_text: String::from("cannot use second operation from anything but a tuple"),
-            location: second.location,
-        }),
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exactly(arity) => write!(f, "{arity}"),
+            Arity::Between(min, max) => write!(f, "{min} to {max}"),
+            Arity::AtLeast(min) => write!(f, "at least {min}"),
+        }
     }
 }
 
-pub struct IO;
-
-pub trait Printer {
-    fn print(&mut self, value: Value) -> Value;
+/// A function implemented by the host, like the builtins.
+#[derive(Clone)]
+pub struct Native {
+    name: String,
+    arity: Arity,
+    pure: bool,
+    identity: Identity,
+    implementation: Implementation,
 }
-impl Printer for IO {
-    fn print(&mut self, value: Value) -> Value {
-        println!("{}", &value);
 
-        value
+impl Native {
+    /// Creates a new instance of [`Native`] taking exactly `arity` arguments.
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Location) -> Result<Value, RuntimeError> + Send + Sync + 'static,
+    {
+        Self::with_arity(name, Arity::Exactly(arity), function)
     }
-}
-
-fn eval_print<I: Printer>(
-    print_: Print,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    let value = eval(print_.value, context, cache, io)?;
 
-    Ok(io.print(value))
-}
+    /// Creates a new instance of [`Native`] taking a varying number of
+    /// arguments.
+    pub fn with_arity<F>(name: &str, arity: Arity, function: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Location) -> Result<Value, RuntimeError> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            arity,
+            pure: true,
+            identity: Identity::Opaque,
+            implementation: Implementation::Plain(Arc::new(function)),
+        }
+    }
 
-fn eval_function(function: Function, context: &mut Context) -> Result<Value, RuntimeError> {
-    let context = Rc::new(RefCell::new(context.clone()));
+    /// Creates a new instance of [`Native`] taking exactly `arity`
+    /// arguments, that can call the functions it is given through the
+    /// [`Apply`] it is called with.
+    pub fn higher_order<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Location, &mut Apply) -> Result<Value, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            name: name.into(),
+            arity: Arity::Exactly(arity),
+            pure: true,
+            identity: Identity::Opaque,
+            implementation: Implementation::HigherOrder(Arc::new(function)),
+        }
+    }
 
-    Ok(Value::Closure(Closure {
-        parameters: function.parameters,
-        body: function.value.clone(),
-        context,
-    }))
-}
+    /// Creates a new instance of [`Native`] taking exactly `arity`
+    /// arguments, that can read the calls being evaluated when it is
+    /// called, outermost first.
+    pub fn introspective<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Location, &[CallFrame]) -> Result<Value, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            name: name.into(),
+            arity: Arity::Exactly(arity),
+            pure: true,
+            identity: Identity::Opaque,
+            implementation: Implementation::Introspective(Arc::new(function)),
+        }
+    }
 
-pub fn eval<I: Printer>(
-    term: Box<Term>,
-    context: &mut Context,
-    cache: &mut Cache,
-    io: &mut I,
-) -> Result<Value, RuntimeError> {
-    match *term {
-        Term::Let(let_) => eval_let(let_, context, cache, io),
-        Term::Int(int) => Ok(Value::Int(int.value)),
-        Term::Str(str) => Ok(Value::Str(str.value)),
-        Term::Bool(bool) => Ok(Value::Bool(bool.value)),
-        Term::Function(function) => eval_function(function, context),
-        Term::Call(call) => eval_call(call, context, cache, io),
-        Term::If(if_) => eval_if(if_, context, cache, io),
-        Term::Binary(binary) => eval_binary(binary, context, cache, io),
-        Term::Var(var) => eval_var(var, context),
-        Term::Tuple(tuple) => eval_tuple(tuple, context, cache, io),
-        Term::First(first) => eval_first(first, context, cache, io),
-        Term::Second(second) => eval_second(second, context, cache, io),
-        Term::Print(print) => eval_print(print, context, cache, io),
+    /// Creates a new instance of [`Native`] taking exactly `arity`
+    /// arguments, that gets the [`Host`] it is called from, like the
+    /// builtins driving generators.
+    pub(crate) fn hosted<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Location, &mut dyn Host) -> Result<Value, RuntimeError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            name: name.into(),
+            arity: Arity::Exactly(arity),
+            pure: true,
+            identity: Identity::Opaque,
+            implementation: Implementation::Hosted(Arc::new(function)),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::ast::{Location, Term, Tuple, Var};
+    /// Marks the function as impure, like reading the clock, so the
+    /// results of functions calling it are never memoized.
+    pub fn impure(mut self) -> Self {
+        self.pure = false;
+        self
+    }
 
-    use super::{eval, Cache, Context, Printer, Value};
+    /// Marks the function as a builtin, so calls of functions capturing it
+    /// are cached under its name, the same in every run.
+    pub(crate) fn builtin(mut self) -> Self {
+        self.identity = Identity::Builtin;
+        self
+    }
 
-    #[derive(Default)]
-    struct DummyIO(String);
+    /// Marks the function as made out of `value`, so calls of functions
+    /// capturing it are cached under its name and `value`.
+    pub(crate) fn wrapping(mut self, value: Value) -> Self {
+        self.identity = Identity::Wrapping(Arc::new(value));
+        self
+    }
 
-    impl Printer for DummyIO {
-        fn print(&mut self, value: super::Value) -> super::Value {
-            self.0.push_str(&format!("{}\n", value));
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-            value
+    /// The address of the function, telling apart natives created apart
+    /// for as long as they live. Addresses are reused once a native is
+    /// dropped, so they never identify natives in cache keys.
+    fn address(&self) -> usize {
+        match &self.implementation {
+            Implementation::Plain(function) => Arc::as_ptr(function) as *const () as usize,
+            Implementation::HigherOrder(function) => Arc::as_ptr(function) as *const () as usize,
+            Implementation::Introspective(function) => Arc::as_ptr(function) as *const () as usize,
+            Implementation::Hosted(function) => Arc::as_ptr(function) as *const () as usize,
         }
     }
 
-    fn location() -> Location {
-        Location {
-            start: 0,
-            end: 0,
-            filename: "tests".to_string(),
+    /// Calls the function with `arguments`, after checking there are as
+    /// many as it expects. Higher-order functions call back through `host`.
+    pub(crate) fn call(
+        &self,
+        arguments: Vec<Value>,
+        location: &Location,
+        host: &mut dyn Host,
+    ) -> Result<Value, RuntimeError> {
+        self.check_arity(arguments.len(), location)?;
+
+        match &self.implementation {
+            Implementation::Plain(function) => function(arguments, location),
+            Implementation::HigherOrder(function) => {
+                function(arguments, location, &mut |callee, arguments| {
+                    host.apply(callee, arguments)
+                })
+            }
+            Implementation::Introspective(function) => {
+                function(arguments, location, &host.frames())
+            }
+            Implementation::Hosted(function) => function(arguments, location, host),
         }
     }
 
-    fn int(int: i64) -> Term {
-        Term::Int(crate::ast::Int {
-            value: int,
-            location: location(),
-        })
+    pub(crate) fn check_arity(
+        &self,
+        arguments: usize,
+        location: &Location,
+    ) -> Result<(), RuntimeError> {
+        match self.arity.accepts(arguments) {
+            true => Ok(()),
+            false => Err(RuntimeError {
+                kind: RuntimeErrorKind::ArityMismatch,
+                message: String::from("invalid function call"),
+                full_text: format!(
+                    "{} expects {} argument(s) but got {}",
+                    self.name, self.arity, arguments
+                ),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            }),
+        }
     }
 
-    fn v_int(int: i64) -> Value {
-        Value::Int(int)
+    /// Whether both are the same native, created once and copied since.
+    pub(crate) fn same(&self, other: &Native) -> bool {
+        self.address() == other.address()
     }
+}
 
-    fn v_tuple(first: Value, second: Value) -> Value {
-        Value::Tuple(super::Tuple {
-            first: Box::new(first),
-            second: Box::new(second),
-        })
+impl Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native({})", self.name)
     }
+}
 
-    fn var(str: &str) -> Var {
-        Var {
-            text: str.to_string(),
-            location: location(),
+#[derive(Clone, Debug)]
+pub struct Tuple {
+    first: Arc<Value>,
+    second: Arc<Value>,
+
+    /// Approximate bytes of both halves, computed once as they never
+    /// change.
+    size: usize,
+}
+
+impl Tuple {
+    /// Creates a new instance of [`Tuple`].
+    pub fn new(first: Value, second: Value) -> Self {
+        Self {
+            size: first.heap_size().saturating_add(second.heap_size()),
+            first: Arc::new(first),
+            second: Arc::new(second),
         }
     }
 
-    fn let_(name: &str, value: Term, next: Term) -> Term {
-        Term::Let(crate::ast::Let {
-            name: var(name),
-            value: Box::new(value),
-            next: Box::new(next),
-            location: location(),
-        })
+    pub fn first(&self) -> &Value {
+        &self.first
     }
 
-    fn print_(value: Term) -> Term {
-        Term::Print(crate::ast::Print {
-            value: Box::new(value),
-            location: location(),
-        })
+    pub fn second(&self) -> &Value {
+        &self.second
     }
+}
 
-    fn tuple(first: Term, second: Term) -> Term {
-        Term::Tuple(Tuple {
-            first: Box::new(first),
-            second: Box::new(second),
-            location: location(),
-        })
+impl Display for Tuple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.first, self.second)
     }
+}
 
-    fn add(lhs: Term, rhs: Term) -> Term {
-        Term::Binary(super::Binary {
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-            op: crate::ast::BinaryOp::Add,
-            location: location(),
-        })
-    }
+#[derive(Clone, Debug)]
+pub enum Value {
+    Closure(Closure),
+    Native(Native),
+    Int(i64),
+    Str(Text),
+    Bool(bool),
+    Tuple(Tuple),
+    Map(Map),
+    Set(Set),
+    Generator(Generator),
+    Unit,
 
-    fn var_(text: &str) -> Term {
-        Term::Var(Var {
-            text: text.to_string(),
-            location: location(),
-        })
+    /// A closure of a program compiled for the [`vm`](crate::vm).
+    Compiled(Arc<crate::vm::Closure>),
+}
+
+impl Value {
+    /// Feeds the structure of the value to `state`: a tag for its variant
+    /// and then its fields. Structurally equal values hash the same,
+    /// regardless of the iteration order of their maps and sets. Returns
+    /// `None` for values that can't be hashed, like closures, anywhere
+    /// inside.
+    ///
+    /// Every part is written explicitly rather than through [`Hash`], so
+    /// with a [`StableHasher`] the hash is the same in every process.
+    pub fn structural_hash<H: Hasher>(&self, state: &mut H) -> Option<()> {
+        match self {
+            Self::Closure(_) | Self::Native(_) | Self::Generator(_) | Self::Compiled(_) => {
+                return None
+            }
+            Self::Int(int) => {
+                state.write_u8(0);
+                state.write_i64(*int);
+            }
+            Self::Str(str) => {
+                state.write_u8(1);
+                write_str(str, state);
+            }
+            Self::Bool(bool) => {
+                state.write_u8(2);
+                state.write_u8(*bool as u8);
+            }
+            Self::Tuple(tuple) => {
+                state.write_u8(3);
+                tuple.first().structural_hash(state)?;
+                tuple.second().structural_hash(state)?;
+            }
+            Self::Map(map) => {
+                let entries = map.iter().map(|(key, value)| {
+                    let mut entry = StableHasher::new();
+                    key.value().structural_hash(&mut entry)?;
+                    value.structural_hash(&mut entry)?;
+
+                    Some(entry.finish128())
+                });
+                state.write_u8(4);
+                state.write_u128(unordered(entries)?);
+            }
+            Self::Set(set) => {
+                let elements = set.iter().map(|key| {
+                    let mut element = StableHasher::new();
+                    key.value().structural_hash(&mut element)?;
+
+                    Some(element.finish128())
+                });
+                state.write_u8(5);
+                state.write_u128(unordered(elements)?);
+            }
+            Self::Unit => state.write_u8(6),
+        }
+
+        Some(())
     }
 
-    fn eq(l: Value, r: Value) -> bool {
-        match l.eq(&r, &location()).unwrap() {
-            Value::Bool(bool) => bool,
-            _ => panic!(),
+    /// The runtime type of the value, as seen by programs.
+    pub fn type_of(&self) -> Type {
+        match self {
+            Self::Closure(_) | Self::Native(_) | Self::Compiled(_) => Type::Closure,
+            Self::Int(_) => Type::Int,
+            Self::Str(_) => Type::Str,
+            Self::Bool(_) => Type::Bool,
+            Self::Tuple(_) => Type::Tuple,
+            Self::Map(_) => Type::Map,
+            Self::Set(_) => Type::Set,
+            Self::Generator(_) => Type::Generator,
+            Self::Unit => Type::Unit,
         }
     }
 
-    #[test]
-    fn print_inner_and_outer_scope() {
-        let mut io = DummyIO::default();
+    /// The name of the runtime type of the value, as seen by programs.
+    pub fn type_name(&self) -> &'static str {
+        self.type_of().name()
+    }
 
-        let let_ = let_("_", print_(int(1)), print_(int(2)));
-        let mut context = Context::new();
-        let mut cache = Cache::new();
-        let result = eval(Box::new(let_), &mut context, &mut cache, &mut io).unwrap();
+    /// Approximate number of bytes owned by this value, including the heap
+    /// allocations behind strings, tuples and collections. Closures only
+    /// account for their handle, as their context is shared. Values shared
+    /// by several tuples or collections are counted once for each.
+    ///
+    /// Tuples and collections keep their size up to date as they are
+    /// built, so it takes constant time.
+    pub fn heap_size(&self) -> usize {
+        let inner = match self {
+            Self::Str(str) => str.len(),
+            Self::Tuple(tuple) => tuple.size,
+            Self::Map(map) => map.heap_size(),
+            Self::Set(set) => set.heap_size(),
+            _value => 0,
+        };
 
-        assert!(eq(result, v_int(2)));
-        assert_eq!(io.0, "1\n2\n");
+        std::mem::size_of::<Value>().saturating_add(inner)
     }
+}
 
-    #[test]
-    fn print_inside_var_scope_and_var() {
-        let mut io = DummyIO::default();
+/// Feeds `str` to `state` prefixed by its length, so adjacent strings
+/// can't be split differently to hash the same.
+fn write_str<H: Hasher>(str: &str, state: &mut H) {
+    state.write_u64(str.len() as u64);
+    state.write(str.as_bytes());
+}
+
+/// Combines the hashes of the entries of a collection so that the result
+/// doesn't depend on the order they are visited in.
+fn unordered(mut hashes: impl Iterator<Item = Option<u128>>) -> Option<u128> {
+    hashes.try_fold(0u128, |combined, hash| Some(combined.wrapping_add(hash?)))
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Closure(_) | Self::Native(_) | Self::Compiled(_) => String::from("[closure]"),
+            Self::Int(int) => int.to_string(),
+            Self::Str(str) => str.to_string(),
+            Self::Bool(bool) => bool.to_string(),
+            Self::Tuple(tuple) => {
+                format!("({}, {})", tuple.first, tuple.second)
+            }
+            Self::Map(map) => map.to_string(),
+            Self::Set(set) => set.to_string(),
+            Self::Generator(_) => String::from("[generator]"),
+            Self::Unit => String::from("()"),
+        };
+
+        f.write_str(&value)
+    }
+}
+
+/// Values are equal when they have the same structure, as the `==` of
+/// programs compares them, extended to tuples and collections. Closures
+/// are equal to themselves only, so to their copies but not to another
+/// closure of the same function.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(l_int), Self::Int(r_int)) => l_int == r_int,
+            (Self::Str(l_str), Self::Str(r_str)) => l_str == r_str,
+            (Self::Bool(l_bool), Self::Bool(r_bool)) => l_bool == r_bool,
+            (Self::Unit, Self::Unit) => true,
+            (Self::Tuple(l_tuple), Self::Tuple(r_tuple)) => {
+                // As deep as the lists built out of tuples nest.
+                stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+                    l_tuple.first() == r_tuple.first() && l_tuple.second() == r_tuple.second()
+                })
+            }
+            (Self::Map(l_map), Self::Map(r_map)) => {
+                l_map.len() == r_map.len()
+                    && l_map
+                        .iter()
+                        .all(|(key, l_value)| r_map.get(key) == Some(l_value))
+            }
+            (Self::Set(l_set), Self::Set(r_set)) => {
+                l_set.len() == r_set.len() && l_set.iter().all(|key| r_set.contains(key))
+            }
+            (Self::Generator(l_generator), Self::Generator(r_generator)) => {
+                l_generator.same(r_generator)
+            }
+            (Self::Closure(l_closure), Self::Closure(r_closure)) => {
+                Arc::ptr_eq(&l_closure.body, &r_closure.body)
+                    && Arc::ptr_eq(&l_closure.context, &r_closure.context)
+                    && Arc::ptr_eq(&l_closure.captured, &r_closure.captured)
+            }
+            (Self::Native(l_native), Self::Native(r_native)) => l_native.same(r_native),
+            (Self::Compiled(l_closure), Self::Compiled(r_closure)) => {
+                Arc::ptr_eq(l_closure, r_closure)
+            }
+            (_l_value, _r_value) => false,
+        }
+    }
+}
+
+/// Values are ordered as the `<` of programs orders them: ints
+/// numerically, strs lexicographically, `false` before `true` and tuples
+/// component-wise. Other values are only ordered against values equal to
+/// them.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Self::Int(l_int), Self::Int(r_int)) => l_int.partial_cmp(r_int),
+            (Self::Str(l_str), Self::Str(r_str)) => l_str.partial_cmp(r_str),
+            (Self::Bool(l_bool), Self::Bool(r_bool)) => l_bool.partial_cmp(r_bool),
+            (Self::Tuple(l_tuple), Self::Tuple(r_tuple)) => {
+                stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+                    match l_tuple.first().partial_cmp(r_tuple.first())? {
+                        std::cmp::Ordering::Equal => l_tuple.second().partial_cmp(r_tuple.second()),
+                        ordering => Some(ordering),
+                    }
+                })
+            }
+            (l_value, r_value) => (l_value == r_value).then_some(std::cmp::Ordering::Equal),
+        }
+    }
+}
+
+/// Values are written as the JSON closest to them: ints as numbers, strs
+/// as strings, unit as null, tuples as arrays of two elements, maps as
+/// objects and sets as arrays. Closures and generators can't be written,
+/// failing the serialization wherever they are.
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeMap, SerializeSeq, SerializeTuple};
+
+        // As deep as the lists built out of tuples nest.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match self {
+            Self::Closure(_) | Self::Native(_) | Self::Compiled(_) | Self::Generator(_) => Err(
+                S::Error::custom(format!("a {} can't be serialized", self.type_name())),
+            ),
+            Self::Int(int) => serializer.serialize_i64(*int),
+            Self::Str(str) => serializer.serialize_str(str.as_str()),
+            Self::Bool(bool) => serializer.serialize_bool(*bool),
+            Self::Unit => serializer.serialize_unit(),
+            Self::Tuple(tuple) => {
+                let mut elements = serializer.serialize_tuple(2)?;
+                elements.serialize_element(tuple.first())?;
+                elements.serialize_element(tuple.second())?;
+                elements.end()
+            }
+            Self::Map(map) => {
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map.iter() {
+                    entries.serialize_entry(key.value(), value)?;
+                }
+                entries.end()
+            }
+            Self::Set(set) => {
+                let mut elements = serializer.serialize_seq(Some(set.len()))?;
+                for key in set.iter() {
+                    elements.serialize_element(key.value())?;
+                }
+                elements.end()
+            }
+        })
+    }
+}
+
+/// Results of memoized calls, by the 128-bit hash of the call. Hashes of
+/// different calls colliding is too unlikely to ever change the result of
+/// a program, unlike with the 64-bit hashes of the standard library.
+///
+/// Being hashes already, the keys aren't hashed again to find their entry.
+pub type Cache = std::collections::HashMap<u128, Value, BuildHasherDefault<KeyHasher>>;
+
+/// The variables in scope, as a chain of bindings from the innermost one
+/// outwards. Contexts share the bindings they were extended from, so
+/// capturing a context in a closure and extending it for a call don't
+/// depend on how many variables are in scope.
+///
+/// The variables of a [resolved](crate::resolve) function being evaluated
+/// are kept apart in its frame, read by slot rather than by name.
+#[derive(Clone, Default)]
+pub struct Context {
+    innermost: Option<Arc<Binding>>,
+    frame: Frame,
+}
+
+/// The slots of a resolved function being evaluated.
+#[derive(Clone, Default)]
+struct Frame {
+    /// Parameters and `let` bindings, unset until they are bound.
+    locals: Vec<Option<Value>>,
+    captured: Arc<[Option<Value>]>,
+
+    /// The closure being called, when its body refers to itself.
+    current: Option<Value>,
+}
+
+struct Binding {
+    name: Symbol,
+    value: Value,
+    outer: Option<Arc<Binding>>,
+}
+
+impl Context {
+    /// Creates a new, empty instance of [`Context`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds `value` to `name`, shadowing any previous binding of `name`
+    /// in this context only.
+    pub fn insert(&mut self, name: Symbol, value: Value) {
+        self.innermost = Some(Arc::new(Binding {
+            name,
+            value,
+            outer: self.innermost.take(),
+        }));
+    }
+
+    /// The value bound to `name`, looked up from the innermost binding.
+    pub fn get(&self, name: Symbol) -> Option<&Value> {
+        self.bindings()
+            .find(|binding| binding.name == name)
+            .map(|binding| &binding.value)
+    }
+
+    pub fn contains_key(&self, name: Symbol) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// A context holding only the bindings of `names` in scope, leaving
+    /// out the names that aren't bound.
+    pub fn capture(&self, names: &[Symbol]) -> Context {
+        let mut captured = Context::new();
+        for &name in names {
+            if let Some(value) = self.get(name) {
+                captured.insert(name, value.clone());
+            }
+        }
+
+        captured
+    }
+
+    /// A context for the body of a function laid out as `layout`, with
+    /// the bindings by name of this one and `arguments` bound to its first
+    /// `parameters` slots.
+    fn enter(
+        &self,
+        layout: &Layout,
+        parameters: usize,
+        arguments: &[Value],
+        captured: Arc<[Option<Value>]>,
+        current: Option<Value>,
+    ) -> Context {
+        let mut locals = vec![None; layout.locals];
+        for (local, argument) in locals.iter_mut().zip(arguments).take(parameters) {
+            *local = Some(argument.clone());
+        }
+
+        Context {
+            innermost: self.innermost.clone(),
+            frame: Frame {
+                locals,
+                captured,
+                current,
+            },
+        }
+    }
+
+    /// The value in `slot` of the frame, if it was bound.
+    fn slot(&self, slot: Slot) -> Option<&Value> {
+        match slot {
+            Slot::Local(index) => self.frame.locals.get(index)?.as_ref(),
+            Slot::Captured(index) => self.frame.captured.get(index)?.as_ref(),
+            Slot::Current => self.frame.current.as_ref(),
+        }
+    }
+
+    /// The values captured from the frame by a closure of a function laid
+    /// out as `layout`.
+    fn captures(&self, layout: &Layout) -> Arc<[Option<Value>]> {
+        layout
+            .captures
+            .iter()
+            .map(|slot| self.slot(*slot).cloned())
+            .collect()
+    }
+
+    /// The bindings in scope, leaving the shadowed ones out.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &Value)> {
+        let mut seen = HashSet::new();
+
+        self.bindings()
+            .filter(move |binding| seen.insert(binding.name))
+            .map(|binding| (binding.name, &binding.value))
+    }
+
+    /// The value bound the outermost to `name`, even when shadowed, like
+    /// the builtins installed before anything else.
+    pub(crate) fn outermost(&self, name: Symbol) -> Option<&Value> {
+        self.bindings()
+            .filter(|binding| binding.name == name)
+            .last()
+            .map(|binding| &binding.value)
+    }
+
+    fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        std::iter::successors(self.innermost.as_deref(), |binding| {
+            binding.outer.as_deref()
+        })
+    }
+}
+
+impl Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Drop for Context {
+    // Dropping long chains one binding at a time, rather than recursively,
+    // keeps it from overflowing the stack.
+    fn drop(&mut self) {
+        let mut innermost = self.innermost.take();
+
+        while let Some(binding) = innermost {
+            innermost = match Arc::try_unwrap(binding) {
+                Ok(mut binding) => binding.outer.take(),
+                Err(_shared) => None,
+            };
+        }
+    }
+}
+
+/// A handle that asks a running evaluation to stop. Evaluation checks
+/// it before every step, and fails with an "evaluation interrupted"
+/// error located at the term it was about to evaluate.
+#[derive(Debug, Default, Clone)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    /// Creates a new instance of [`Cancellation`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the evaluation to stop at the next step. It can be called
+    /// from any thread, like a signal handler.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag behind the handle, for compiled code to check.
+    #[cfg(feature = "jit")]
+    pub(crate) fn as_ptr(&self) -> *const bool {
+        self.0.as_ptr()
+    }
+
+    /// Cancels the evaluation once `timeout` elapses, unless the returned
+    /// sender is dropped before that.
+    pub fn cancel_after(&self, timeout: Duration) -> mpsc::Sender<()> {
+        let (finished, waiting) = mpsc::channel();
+        let cancellation = self.clone();
+
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = waiting.recv_timeout(timeout) {
+                cancellation.cancel();
+            }
+        });
+
+        finished
+    }
+}
+
+/// Which calls of pure functions are memoized.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Memoization {
+    /// Every call.
+    #[default]
+    Always,
+
+    /// Every call of a function until its cache was looked up `probe`
+    /// times, then only calls of the functions that proved worth it: at
+    /// least `min_hit_rate` of their calls were cached, and evaluating them
+    /// took at least `min_steps` on average. Memoizing a call costs hashing
+    /// its arguments and keeping its result, more than cheap functions that
+    /// are rarely called with the same arguments save.
+    Adaptive {
+        probe: u64,
+        min_hit_rate: f64,
+        min_steps: u64,
+    },
+}
+
+impl Memoization {
+    /// Adaptive memoization with thresholds fit for most programs.
+    pub fn adaptive() -> Self {
+        Memoization::Adaptive {
+            probe: 100,
+            min_hit_rate: 0.1,
+            min_steps: 20,
+        }
+    }
+}
+
+/// Nesting allowed by default by the CLI and the server, about a hundred
+/// thousand levels of non-tail recursion, taking around a gigabyte of
+/// memory.
+pub const DEFAULT_MAX_DEPTH: usize = 200_000;
+
+/// Mutable state shared by every evaluation step of a run: the
+/// memoization cache, the statistics collected along the way, the
+/// cancellation handle of the run, and the calls trace and progress
+/// reporting, if enabled.
+#[derive(Debug, Default)]
+pub struct State {
+    pub cache: Box<dyn CacheStore>,
+
+    /// How the keys of memoized calls are hashed.
+    pub hashing: Hashing,
+
+    /// Which calls of pure functions are memoized.
+    pub memoization: Memoization,
+    pub stats: Stats,
+    pub cancellation: Cancellation,
+    pub trace: Option<Trace>,
+    pub progress: Option<Progress>,
+
+    /// Reports the warnings about the terms given to [`eval`], like
+    /// bindings shadowing others, before evaluating them.
+    pub warnings: Option<Warnings>,
+
+    /// Collects the warnings about the terms given to [`eval`] and the
+    /// overflows of their evaluation, if set.
+    pub diagnostics: Option<Diagnostics>,
+
+    /// Called before and after every term is evaluated, if set.
+    pub observer: Option<Box<dyn EvalObserver>>,
+
+    /// How `/` and `%` round for operands of different signs.
+    pub division: Division,
+
+    /// How deep evaluations may nest before failing, unlimited when
+    /// `None`. Tail calls don't nest. See [`DEFAULT_MAX_DEPTH`].
+    pub max_depth: Option<usize>,
+    depth: usize,
+
+    /// How deep calls may nest before failing, unlimited when `None`.
+    /// Tail calls don't nest.
+    pub max_call_depth: Option<usize>,
+    frames: Vec<CallFrame>,
+
+    /// How many more steps evaluations may take before failing with
+    /// [`RuntimeErrorKind::OutOfFuel`], unlimited when `None`.
+    pub fuel: Option<u64>,
+
+    /// Approximate bytes the memoization cache and any value being built
+    /// may take together before failing, unlimited when `None`.
+    pub max_memory: Option<usize>,
+    cache_size: usize,
+
+    /// Compiles hot numeric functions to native code, if enabled.
+    #[cfg(feature = "jit")]
+    pub jit: Option<crate::jit::Jit>,
+
+    /// Whether something was printed or an impure native was called
+    /// since the evaluation of the current memoized body started, so its
+    /// result isn't cached.
+    pub(crate) impure: bool,
+}
+
+impl State {
+    /// Creates a new instance of [`State`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A state for an evaluation taking turns with this one on another
+    /// thread, like the body of a generator: with its settings and limits,
+    /// but a cache and statistics of its own.
+    pub(crate) fn nested(&self) -> State {
+        State {
+            hashing: self.hashing,
+            memoization: self.memoization,
+            cancellation: self.cancellation.clone(),
+            division: self.division,
+            max_depth: self.max_depth,
+            max_call_depth: self.max_call_depth,
+            fuel: self.fuel,
+            max_memory: self.max_memory,
+            ..State::default()
+        }
+    }
+
+    /// The calls being evaluated by [`eval`], outermost first. Calls in
+    /// tail position take the place of their caller.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// Whether calls of the function defined at `location` are memoized,
+    /// as decided by [`State::memoization`] from how its calls fared.
+    fn memoizes(&self, location: &Location) -> bool {
+        let Memoization::Adaptive {
+            probe,
+            min_hit_rate,
+            min_steps,
+        } = self.memoization
+        else {
+            return true;
+        };
+
+        match self.stats.functions.get(location) {
+            Some(function) if function.cache_hits + function.cache_misses >= probe => {
+                function.hit_rate() >= min_hit_rate && function.average_miss_steps() >= min_steps
+            }
+            _probing => true,
+        }
+    }
+
+    /// Consumes the fuel of a step evaluated at `location`, failing once
+    /// there's none left.
+    pub(crate) fn consume_fuel(&mut self, location: &Location) -> Result<(), RuntimeError> {
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError {
+                kind: RuntimeErrorKind::OutOfFuel,
+                message: String::from("out of fuel"),
+                full_text: format!(
+                    "the evaluation ran out of fuel after {} steps",
+                    self.stats.steps - 1
+                ),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            }),
+            Some(fuel) => {
+                *fuel -= 1;
+
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Fails when a call made at `location` would nest deeper than
+    /// [`State::max_call_depth`], given the calls nested at `depth`.
+    pub(crate) fn check_call_depth(
+        &self,
+        depth: usize,
+        location: &Location,
+    ) -> Result<(), RuntimeError> {
+        match self.max_call_depth {
+            Some(max_call_depth) if depth >= max_call_depth => Err(RuntimeError {
+                kind: RuntimeErrorKind::DepthExceeded,
+                message: String::from("maximum recursion depth exceeded"),
+                full_text: format!("maximum recursion depth {max_call_depth} exceeded"),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Passes `value`, built at `location`, through when it fits in the
+    /// memory left by the memoization cache, failing otherwise.
+    pub(crate) fn within_memory(
+        &self,
+        value: Value,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
+        let Some(max_memory) = self.max_memory else {
+            return Ok(value);
+        };
+
+        match self.cache_size.saturating_add(value.heap_size()) > max_memory {
+            true => Err(RuntimeError {
+                kind: RuntimeErrorKind::OutOfMemory,
+                message: String::from("out of memory"),
+                full_text: format!("the evaluation needed more than {max_memory} bytes of memory"),
+                location: location.clone(),
+                backtrace: Vec::new(),
+            }),
+            false => Ok(value),
+        }
+    }
+}
+
+/// The runtime type of a [`Value`], as seen by programs. Every kind of
+/// function is a closure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    Int,
+    Str,
+    Bool,
+    Unit,
+    Tuple,
+    Closure,
+    Map,
+    Set,
+    Generator,
+}
+
+impl Type {
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Str => "str",
+            Type::Bool => "bool",
+            Type::Unit => "unit",
+            Type::Tuple => "tuple",
+            Type::Closure => "closure",
+            Type::Map => "map",
+            Type::Set => "set",
+            Type::Generator => "generator",
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub full_text: String,
+    pub location: Location,
+
+    /// The calls being evaluated when the error happened, outermost first,
+    /// as [`State::frames`] were. Empty outside of any call.
+    pub backtrace: Vec<CallFrame>,
+}
+
+/// Why an evaluation failed, for embedders and tools that handle some
+/// failures differently from the rest, or match on them in tests rather
+/// than on the message of the error. Every kind has a stable
+/// [`code`](Self::code).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum RuntimeErrorKind {
+    /// A variable was referred to where it isn't bound.
+    UnboundVariable,
+
+    /// A value that isn't a function was called.
+    NotCallable,
+
+    /// A function was called with a number of arguments it doesn't take.
+    ArityMismatch,
+
+    /// An operation got a value of a type it can't operate on, like adding
+    /// a boolean or branching on an integer: it takes values of the
+    /// `expected` types there, but got one of the `found` type.
+    TypeMismatch {
+        expected: &'static [Type],
+        found: Type,
+    },
+
+    /// An integer was divided by zero, or its remainder by zero taken.
+    DivisionByZero,
+
+    /// An integer division overflowed, as dividing the smallest integer by
+    /// -1 does.
+    Overflow,
+
+    /// A builtin or host function got an argument it can't take, like a
+    /// closure as the key of a map.
+    InvalidArgument,
+
+    /// An `assert` failed.
+    AssertionFailed,
+
+    /// The evaluation nested deeper than allowed, likely from unbounded
+    /// recursion.
+    DepthExceeded,
+
+    /// The evaluation was cancelled before it finished.
+    Interrupted,
+
+    /// The output could not be written.
+    Output,
+
+    /// A host function failed, with the error it returned as the full text.
+    Failed,
+
+    /// The program took more steps than the fuel of the [`State`].
+    OutOfFuel,
+
+    /// The program ran for longer than [`eval_with_timeout`] allowed.
+    TimedOut,
+
+    /// The program built values larger than the memory allowed by the
+    /// [`State`].
+    OutOfMemory,
+}
+
+impl RuntimeErrorKind {
+    /// The code of the kind, like `E0004`, kept the same across releases,
+    /// so it can be looked up and matched on by tools.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::UnboundVariable => "E0001",
+            Self::NotCallable => "E0002",
+            Self::ArityMismatch => "E0003",
+            Self::TypeMismatch { .. } => "E0004",
+            Self::DivisionByZero => "E0005",
+            Self::Overflow => "E0006",
+            Self::InvalidArgument => "E0007",
+            Self::AssertionFailed => "E0008",
+            Self::DepthExceeded => "E0009",
+            Self::Interrupted => "E0010",
+            Self::Output => "E0011",
+            Self::Failed => "E0012",
+            Self::OutOfFuel => "E0013",
+            Self::TimedOut => "E0014",
+            Self::OutOfMemory => "E0015",
+        }
+    }
+}
+
+/// Binds `value` to `name` in `context`. Closures referring to `name` are
+/// also bound to it inside their own body, so they can call themselves
+/// recursively.
+pub fn bind(name: Symbol, value: Value, context: &mut Context) {
+    let value = match named(name, value) {
+        Value::Closure(mut closure) if closure.captures(name) => {
+            if !closure.itself.contains(&name) {
+                closure.itself.push(name);
+            }
+
+            Value::Closure(closure)
+        }
+        value => value,
+    };
+
+    context.insert(name, value);
+}
+
+/// Names an anonymous closure after the binding it is bound to, as it is
+/// shown in statistics and traces.
+fn named(name: Symbol, value: Value) -> Value {
+    match value {
+        Value::Closure(mut closure) => {
+            closure.name.get_or_insert_with(|| name.as_str().into());
+
+            Value::Closure(closure)
+        }
+        value => value,
+    }
+}
+
+fn eval_let<I: Printer>(
+    let_: &Let,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Tail, RuntimeError> {
+    let value = evaluate(&let_.value, context, state, io)?;
+
+    match let_.name.slot {
+        Some(Slot::Local(slot)) => context.frame.locals[slot] = Some(named(let_.name.text, value)),
+        _slot => bind(let_.name.text, value, context),
+    }
+
+    Ok(Tail::Term(let_.next.clone()))
+}
+
+/// The key a call of the body with `digest` is cached under, covering the
+/// values of the environment it reads besides `arguments`, so closures of
+/// the same function capturing different values are cached apart. `None`
+/// when some value can't be hashed.
+pub(crate) fn cache_key<'a>(
+    hashing: Hashing,
+    digest: Digest,
+    environment: impl Iterator<Item = &'a Value>,
+    arguments: &[Value],
+) -> Option<u128> {
+    let mut s = hashing.hasher();
+    s.write_u128(digest.as_u128());
+    for value in environment {
+        captured_hash(value, &mut s)?;
+    }
+    for argument in arguments {
+        argument.structural_hash(&mut s)?;
+    }
+
+    Some(s.finish128())
+}
+
+/// Feeds a captured value to `state`, like [`Value::structural_hash`]
+/// but telling functions apart: closures by their body and environment,
+/// builtins by their name, and natives made out of a value, like the
+/// results of `fix`, by their name and that value. Other natives of the
+/// host can't be told apart from one another, so `None` for them.
+fn captured_hash<H: Hasher>(value: &Value, state: &mut H) -> Option<()> {
+    match value {
+        Value::Closure(closure) => {
+            state.write_u8(7);
+            state.write_u128(closure.digest().as_u128());
+            for value in closure.environment() {
+                captured_hash(value, state)?;
+            }
+
+            Some(())
+        }
+        Value::Native(native) => {
+            state.write_u8(8);
+            write_str(&native.name, state);
+            match &native.identity {
+                Identity::Builtin => Some(()),
+                Identity::Wrapping(value) => captured_hash(value, state),
+                Identity::Opaque => None,
+            }
+        }
+        value => value.structural_hash(state),
+    }
+}
+
+fn eval_memo<I: Printer>(
+    closure: &Closure,
+    arguments: &[Value],
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let Some(cache_key) = cache_key(
+        state.hashing,
+        closure.digest(),
+        closure.environment(),
+        arguments,
+    ) else {
+        return evaluate(&closure.body, context, state, io);
+    };
+
+    let name = closure.name.as_deref();
+    if let Some(value) = cached(name, &closure.location, cache_key, state) {
+        return Ok(value);
+    }
+
+    let mut memoized = Memoized::default();
+    memoized.push(name, &closure.location, cache_key, state);
+
+    let result = evaluate(&closure.body, context, state, io);
+    memoized.finish(&result, state);
+
+    result
+}
+
+/// The value cached for a call of the function defined at `location`,
+/// recording the hit.
+fn cached(
+    name: Option<&str>,
+    location: &Location,
+    cache_key: u128,
+    state: &mut State,
+) -> Option<Value> {
+    let value = state.cache.get(cache_key)?;
+    state.stats.record_hit(name, location);
+    instrument::cache_hit(name, location);
+
+    Some(value)
+}
+
+/// A call missing from the cache, waiting for its result.
+struct Miss {
+    cache_key: u128,
+    name: Option<String>,
+    location: Location,
+    started_at: Instant,
+    started_at_step: u64,
+}
+
+/// The memoized calls whose result is the value an evaluation ends with,
+/// like calls made in tail position, cached together once it is known.
+#[derive(Default)]
+struct Memoized {
+    misses: Vec<Miss>,
+
+    /// Whether the enclosing evaluation was impure before the first miss,
+    /// which is restored once the results are cached.
+    enclosing_impure: bool,
+}
+
+impl Memoized {
+    fn push(
+        &mut self,
+        name: Option<&str>,
+        location: &Location,
+        cache_key: u128,
+        state: &mut State,
+    ) {
+        if self.misses.is_empty() {
+            self.enclosing_impure = std::mem::take(&mut state.impure);
+        }
+
+        self.misses.push(Miss {
+            cache_key,
+            name: name.map(String::from),
+            location: location.clone(),
+            started_at: Instant::now(),
+            started_at_step: state.stats.steps,
+        });
+    }
+
+    /// Caches `result` for every miss, unless something was printed or an
+    /// impure native was called since the first one.
+    fn finish(self, result: &Result<Value, RuntimeError>, state: &mut State) {
+        if self.misses.is_empty() {
+            return;
+        }
+
+        let impure = state.impure;
+        state.impure |= self.enclosing_impure;
+
+        let Ok(value) = result else {
+            return;
+        };
+
+        for miss in self.misses {
+            let retained = match impure {
+                true => 0,
+                false => std::mem::size_of::<u128>() + value.heap_size(),
+            };
+            state.stats.record_miss(
+                miss.name.as_deref(),
+                &miss.location,
+                miss.started_at.elapsed(),
+                state.stats.steps - miss.started_at_step,
+                retained,
+            );
+
+            if !impure {
+                state.cache_size = state.cache_size.saturating_add(retained);
+                state.cache.insert(miss.cache_key, value.clone());
+            }
+        }
+    }
+}
+
+fn eval_call<I: Printer>(
+    call: &Call,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+    memoized: &mut Memoized,
+) -> Result<Tail, RuntimeError> {
+    // A function called right where it is defined, like the immediately
+    // invoked functions of desugared code, can't escape the call. Its body
+    // is evaluated in a copy of the current context, without allocating a
+    // closure to hold it.
+    let callee = match &*call.callee {
+        Term::Function(function) if state.trace.is_none() => {
+            let arguments = call
+                .arguments
+                .iter()
+                .map(|argument| evaluate(argument, context, state, io))
+                .collect::<Result<Arguments, _>>()?;
+            check_arity(
+                None,
+                function.parameters.len(),
+                arguments.len(),
+                &call.location,
+            )?;
+
+            let captured = match &function.layout {
+                Some(layout) => context.captures(layout),
+                None => Arc::from([]),
+            };
+            let new_context = match &function.layout {
+                Some(layout) => context.enter(
+                    layout,
+                    function.parameters.len(),
+                    &arguments,
+                    captured.clone(),
+                    None,
+                ),
+                None => {
+                    let mut new_context = context.clone();
+                    for (parameter, argument) in function.parameters.iter().zip(&arguments) {
+                        new_context.insert(parameter.text, argument.clone());
+                    }
+
+                    new_context
+                }
+            };
+            state.stats.record_call(None, &function.location);
+
+            return Ok(tail_call(
+                None,
+                &function.location,
+                function.value.clone(),
+                function.digest,
+                function.pure,
+                new_context,
+                environment(context, function.free.as_deref(), &captured, &[]),
+                &arguments,
+                state,
+                memoized,
+            ));
+        }
+        callee => evaluate(callee, context, state, io)?,
+    };
+
+    // Natives given the wrong number of arguments fail before any of them
+    // is evaluated.
+    if let Value::Native(native) = &callee {
+        native.check_arity(call.arguments.len(), &call.location)?;
+    }
+
+    let arguments = call
+        .arguments
+        .iter()
+        .map(|argument| evaluate(argument, context, state, io))
+        .collect::<Result<Arguments, _>>()?;
+
+    // Traced calls are applied on their own, as their result is needed.
+    let traced = |closure: &Closure| {
+        state
+            .trace
+            .as_ref()
+            .is_some_and(|trace| trace.matches(closure.name.as_deref(), &call.location))
+    };
+
+    match callee {
+        Value::Closure(closure) if !traced(&closure) => {
+            check_arity(
+                closure.name.as_deref(),
+                closure.arity(),
+                arguments.len(),
+                &call.location,
+            )?;
+            if let Some(value) = jitted(&closure, &arguments, state) {
+                return Ok(Tail::Value(value));
+            }
+
+            let new_context = enter(&closure, &arguments, state);
+
+            Ok(tail_call(
+                closure.name.as_ref(),
+                &closure.location,
+                closure.body.clone(),
+                closure.digest,
+                closure.pure,
+                new_context,
+                closure.environment(),
+                &arguments,
+                state,
+                memoized,
+            ))
+        }
+        callee => apply(callee, arguments, &call.location, state, io).map(Tail::Value),
+    }
+}
+
+/// Continues with the body of the function defined at `location`, called
+/// with `arguments` in `environment`, unless the result of the call is
+/// cached. The body is hashed when its `digest` isn't known, and walked
+/// when whether it is `pure` isn't.
+#[allow(clippy::too_many_arguments)]
+fn tail_call<'a>(
+    name: Option<&Arc<str>>,
+    location: &Location,
+    body: Arc<Term>,
+    digest: Option<Digest>,
+    pure: Option<bool>,
+    new_context: Context,
+    environment: impl Iterator<Item = &'a Value>,
+    arguments: &[Value],
+    state: &mut State,
+    memoized: &mut Memoized,
+) -> Tail {
+    let pure = pure.unwrap_or_else(|| crate::purity::of(&body));
+    if pure && state.memoizes(location) {
+        let name = name.map(|name| &**name);
+        let digest = digest.unwrap_or_else(|| crate::digest::of(&body));
+        if let Some(cache_key) = cache_key(state.hashing, digest, environment, arguments) {
+            if let Some(value) = cached(name, location, cache_key, state) {
+                return Tail::Value(value);
+            }
+
+            instrument::cache_miss(name, location);
+            memoized.push(name, location, cache_key, state);
+        }
+    }
+
+    Tail::Call(body, new_context, name.cloned())
+}
+
+/// Fails unless a function named `name`, taking `parameters`, is called
+/// with as many `arguments`, as the VM does.
+fn check_arity(
+    name: Option<&str>,
+    parameters: usize,
+    arguments: usize,
+    location: &Location,
+) -> Result<(), RuntimeError> {
+    match parameters == arguments {
+        true => Ok(()),
+        false => Err(RuntimeError {
+            kind: RuntimeErrorKind::ArityMismatch,
+            message: String::from("invalid function call"),
+            full_text: format!(
+                "{} expects {} argument(s) but got {}",
+                name.unwrap_or("the function"),
+                parameters,
+                arguments
+            ),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+/// The context the body of `closure` is evaluated in when called with
+/// `arguments`, recording the call.
+fn enter(closure: &Closure, arguments: &[Value], state: &mut State) -> Context {
+    let new_context = match &closure.layout {
+        Some(layout) => closure.context.enter(
+            layout,
+            closure.parameters.len(),
+            arguments,
+            closure.captured.clone(),
+            layout.recursive.then(|| Value::Closure(closure.clone())),
+        ),
+        None => {
+            let mut new_context = Context::clone(&closure.context);
+            for &name in &closure.itself {
+                new_context.insert(name, Value::Closure(closure.clone()));
+            }
+            for (parameter, argument) in closure.parameters.iter().zip(arguments) {
+                new_context.insert(parameter.text, argument.clone());
+            }
+
+            new_context
+        }
+    };
+
+    state
+        .stats
+        .record_call(closure.name.as_deref(), &closure.location);
+
+    new_context
+}
+
+/// Runs a call of `closure` as native code, when the JIT is enabled and
+/// has compiled it. Native code neither takes steps nor tracks the depth of
+/// its calls, so it isn't used when either is limited.
+#[cfg(feature = "jit")]
+fn jitted(closure: &Closure, arguments: &[Value], state: &mut State) -> Option<Value> {
+    if state.fuel.is_some() || state.max_call_depth.is_some() {
+        return None;
+    }
+
+    let value = state.jit.as_mut()?.call(
+        closure.name.as_deref(),
+        &closure.parameters,
+        &closure.body,
+        &closure.location,
+        arguments,
+        state.division,
+        &state.cancellation,
+    )?;
+
+    state
+        .stats
+        .record_call(closure.name.as_deref(), &closure.location);
+
+    Some(value)
+}
+
+#[cfg(not(feature = "jit"))]
+fn jitted(_closure: &Closure, _arguments: &[Value], _state: &mut State) -> Option<Value> {
+    None
+}
+
+/// The arguments of a call, kept inline rather than allocated for calls
+/// of up to three arguments, which most calls are.
+pub(crate) type Arguments = SmallVec<[Value; 3]>;
+
+/// Calls `callee` with already evaluated `arguments`, as if called from
+/// `location`.
+pub(crate) fn apply<I: Printer>(
+    callee: Value,
+    arguments: Arguments,
+    location: &Location,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    match callee {
+        Value::Closure(closure) => {
+            check_arity(
+                closure.name.as_deref(),
+                closure.arity(),
+                arguments.len(),
+                location,
+            )?;
+            let traced = state.trace.as_ref().and_then(|trace| {
+                trace
+                    .matches(closure.name.as_deref(), location)
+                    .then(|| arguments.clone())
+            });
+
+            if traced.is_none() {
+                if let Some(value) = jitted(&closure, &arguments, state) {
+                    return Ok(value);
+                }
+            }
+
+            state.check_call_depth(state.frames.len(), location)?;
+            let mut new_context = enter(&closure, &arguments, state);
+            let _span = instrument::call(closure.name.as_deref(), location);
+
+            state.frames.push(CallFrame {
+                name: closure.name.clone(),
+                location: location.clone(),
+                repeated: 0,
+            });
+            let result = match closure.is_pure() && state.memoizes(&closure.location) {
+                true => eval_memo(&closure, &arguments, &mut new_context, state, io),
+                false => evaluate(&closure.body, &mut new_context, state, io),
+            };
+            state.frames.pop();
+
+            if let (Some(trace), Some(arguments)) = (&mut state.trace, traced) {
+                trace.record(closure.name.as_deref(), location, &arguments, &result);
+            }
+
+            result
+        }
+        Value::Native(native) => {
+            if !native.pure {
+                state.impure = true;
+            }
+            let _span = instrument::call(Some(&native.name), location);
+
+            let value = native.call(
+                arguments.into_vec(),
+                location,
+                &mut Evaluation {
+                    location,
+                    state,
+                    io,
+                },
+            )?;
+
+            state.within_memory(value, location)
+        }
+        value => Err(RuntimeError {
+            kind: RuntimeErrorKind::NotCallable,
+            message: String::from("invalid function call"),
+            full_text: format!("{} cannot be called as a function", value),
+            location: location.clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+/// The evaluation natives are called from by the interpreter.
+struct Evaluation<'a, I> {
+    location: &'a Location,
+    state: &'a mut State,
+    io: &'a mut I,
+}
+
+impl<I: Printer> Host for Evaluation<'_, I> {
+    fn apply(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        apply(callee, arguments.into(), self.location, self.state, self.io)
+    }
+
+    fn frames(&self) -> Cow<'_, [CallFrame]> {
+        Cow::Borrowed(&self.state.frames)
+    }
+
+    fn resume(&mut self, generator: &Generator) -> Result<Option<Value>, RuntimeError> {
+        generator.resume(None, self.location, self.state, self.io)
+    }
+
+    fn state(&mut self) -> &mut State {
+        self.state
+    }
+}
+
+fn eval_seq<I: Printer>(
+    seq: &Seq,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Tail, RuntimeError> {
+    let Some((last, terms)) = seq.terms.split_last() else {
+        return Ok(Tail::Value(Value::Unit));
+    };
+
+    for term in terms {
+        evaluate(term, context, state, io)?;
+    }
+
+    Ok(Tail::Term(Arc::new(last.clone())))
+}
+
+fn eval_if<I: Printer>(
+    if_: &If,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Tail, RuntimeError> {
+    match eval_condition(&if_.condition, context, state, io)? {
+        true => Ok(Tail::Term(if_.then.clone())),
+        false => Ok(Tail::Term(if_.otherwise.clone())),
+    }
+}
+
+fn eval_cond<I: Printer>(
+    cond: &Cond,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Tail, RuntimeError> {
+    for arm in &cond.arms {
+        if eval_condition(&arm.condition, context, state, io)? {
+            return Ok(Tail::Term(Arc::new(arm.then.clone())));
+        }
+    }
+
+    Ok(Tail::Term(cond.otherwise.clone()))
+}
+
+fn eval_condition<I: Printer>(
+    condition: &Term,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<bool, RuntimeError> {
+    match evaluate(condition, context, state, io)? {
+        Value::Bool(bool) => Ok(bool),
+        condition_result => Err(RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch {
+                expected: &[Type::Bool],
+                found: condition_result.type_of(),
+            },
+            message: String::from("invalid if condition"),
+            full_text: format!(
+                "{} can't be used as an if condition. use a boolean instead",
+                condition_result
+            ),
+            location: condition.location().clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+fn eval_binary<I: Printer>(
+    binary: &Binary,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let lhs = evaluate(&binary.lhs, context, state, io)?;
+
+    // `&&` and `||` only evaluate the right-hand side when it can still
+    // change the result, so it may rely on the left-hand side guard.
+    match (&binary.op, lhs) {
+        (BinaryOp::And, Value::Bool(false)) => Ok(Value::Bool(false)),
+        (BinaryOp::Or, Value::Bool(true)) => Ok(Value::Bool(true)),
+        (_op, lhs) => {
+            let rhs = evaluate(&binary.rhs, context, state, io)?;
+            let value = lhs.operate(&binary.op, &rhs, state.division, binary.lhs.location())?;
+            if let (Some(diagnostics), Value::Int(int)) = (&mut state.diagnostics, &value) {
+                if lhs.overflows(&binary.op, &rhs) {
+                    diagnostics.overflow(*int, &binary.location);
+                }
+            }
+
+            state.within_memory(value, &binary.location)
+        }
+    }
+}
+
+fn eval_var(var: &Var, context: &mut Context) -> Result<Value, RuntimeError> {
+    let value = match var.slot {
+        Some(slot) => context.slot(slot),
+        None => context.get(var.text),
+    };
+
+    value
+        .ok_or(RuntimeError {
+            kind: RuntimeErrorKind::UnboundVariable,
+            message: format!("unbound variable \"{}\"", var.text),
+            full_text: format!(
+                "variable \"{}\" was not defined in the current scope",
+                var.text
+            ),
+            location: var.location.clone(),
+            backtrace: Vec::new(),
+        })
+        .cloned()
+}
+
+fn eval_tuple<I: Printer>(
+    tuple: &crate::ast::Tuple,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let first = evaluate(&tuple.first, context, state, io)?;
+    let second = evaluate(&tuple.second, context, state, io)?;
+
+    state.within_memory(Value::Tuple(Tuple::new(first, second)), &tuple.location)
+}
+
+fn eval_first<I: Printer>(
+    first: &First,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    match evaluate(&first.value, context, state, io)? {
+        Value::Tuple(Tuple { first, .. }) => Ok(Arc::unwrap_or_clone(first)),
+        value => Err(RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch {
+                expected: &[Type::Tuple],
+                found: value.type_of(),
+            },
+            message: String::from("invalid expression"),
+            full_text: String::from("cannot use first operation from anything but a tuple"),
+            location: first.location.clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+fn eval_second<I: Printer>(
+    second: &Second,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    match evaluate(&second.value, context, state, io)? {
+        Value::Tuple(Tuple { second, .. }) => Ok(Arc::unwrap_or_clone(second)),
+        value => Err(RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch {
+                expected: &[Type::Tuple],
+                found: value.type_of(),
+            },
+            message: String::from("invalid expression"),
+            full_text: String::from("cannot use second operation from anything but a tuple"),
+            location: second.location.clone(),
+            backtrace: Vec::new(),
+        }),
+    }
+}
+
+/// When the default [`IO`] printer hands its output over to the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Flush {
+    /// Flush after every printed value, for interactive programs.
+    #[default]
+    EachPrint,
+
+    /// Keep the output buffered until [`Printer::flush`] is called.
+    Buffered,
+}
+
+/// The default printer, writing values to the standard output, or to
+/// another writer given with [`IO::with_writer`].
+///
+/// Every value is terminated by a single `\n`, and `\r\n` pairs inside
+/// printed strings are normalized to `\n`, so the output never mixes
+/// line endings regardless of the platform. Writing goes through
+/// [`std::io::Stdout`], which uses the console API on Windows consoles
+/// to render UTF-8 correctly.
+pub struct IO<W: Write = Stdout> {
+    flush: Flush,
+    stdout: BufWriter<W>,
+    closed: bool,
+}
+
+impl IO {
+    /// Default size in bytes of the output buffer.
+    pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+    /// Creates a new instance of [`IO`].
+    pub fn new(flush: Flush) -> Self {
+        Self::with_capacity(flush, Self::DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new instance of [`IO`] buffering up to `capacity` bytes
+    /// before writing to the standard output.
+    pub fn with_capacity(flush: Flush, capacity: usize) -> Self {
+        Self::with_writer(flush, capacity, std::io::stdout())
+    }
+}
+
+impl<W: Write> IO<W> {
+    /// Creates a new instance of [`IO`] writing to `writer` rather than
+    /// the standard output, buffering up to `capacity` bytes.
+    pub fn with_writer(flush: Flush, capacity: usize, writer: W) -> Self {
+        Self {
+            flush,
+            stdout: BufWriter::with_capacity(capacity, writer),
+            closed: false,
+        }
+    }
+
+    /// The writer the output is handed over to, holding what was flushed
+    /// so far.
+    pub fn writer(&self) -> &W {
+        self.stdout.get_ref()
+    }
+
+    /// Whether the reading end of the output went away, like when the
+    /// output is piped into `head`.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn track<T>(&mut self, result: std::io::Result<T>) -> std::io::Result<T> {
+        if let Err(error) = &result {
+            self.closed |= error.kind() == ErrorKind::BrokenPipe;
+        }
+
+        result
+    }
+}
+
+impl Default for IO {
+    fn default() -> Self {
+        Self::new(Flush::default())
+    }
+}
+
+/// Source of the current time for programs, so time dependent programs
+/// can be evaluated deterministically in tests.
+pub trait Clock: Send + Sync {
+    /// Milliseconds elapsed since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// The [`Clock`] of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// A [`Clock`] that only moves when told to. Clones share the same time.
+#[derive(Debug, Default, Clone)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a new instance of [`MockClock`], stopped at `now`.
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+pub trait Printer {
+    fn print(&mut self, value: Value) -> std::io::Result<Value>;
+
+    /// Writes out any output still buffered by the printer.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<P: Printer + ?Sized> Printer for &mut P {
+    fn print(&mut self, value: Value) -> std::io::Result<Value> {
+        (**self).print(value)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<W: Write> Printer for IO<W> {
+    fn print(&mut self, value: Value) -> std::io::Result<Value> {
+        let text = value.to_string().replace("\r\n", "\n");
+
+        let written = writeln!(self.stdout, "{text}");
+        self.track(written)?;
+
+        if self.flush == Flush::EachPrint {
+            Printer::flush(self)?;
+        }
+
+        Ok(value)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let flushed = self.stdout.flush();
+
+        self.track(flushed)
+    }
+}
+
+/// A printer keeping the output in memory, for embedders handling the
+/// output of a program themselves.
+#[derive(Debug, Default)]
+pub struct Capture {
+    output: String,
+}
+
+impl Capture {
+    /// Creates a new instance of [`Capture`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The output printed so far.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Takes the output printed so far, leaving the capture empty.
+    pub fn take(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Printer for Capture {
+    fn print(&mut self, value: Value) -> std::io::Result<Value> {
+        self.output.push_str(&format!("{value}\n"));
+
+        Ok(value)
+    }
+}
+
+fn eval_print<I: Printer>(
+    print_: &Print,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let value = evaluate(&print_.value, context, state, io)?;
+    state.impure = true;
+
+    io.print(value).map_err(|error| RuntimeError {
+        kind: RuntimeErrorKind::Output,
+        message: String::from("failed to print"),
+        full_text: format!("the printed value could not be written: {error}"),
+        location: print_.location.clone(),
+        backtrace: Vec::new(),
+    })
+}
+
+fn eval_function(function: &Function, context: &mut Context) -> Result<Value, RuntimeError> {
+    let captured = match &function.layout {
+        Some(layout) => context.captures(layout),
+        None => Arc::from([]),
+    };
+    let context = match &function.free {
+        Some(free) => context.capture(free),
+        None => context.clone(),
+    };
+
+    Ok(Value::Closure(Closure {
+        name: None,
+        parameters: function.parameters.clone(),
+        body: function.value.clone(),
+        context: Arc::new(context),
+        location: function.location.clone(),
+        itself: Vec::new(),
+        free: function.free.clone(),
+        layout: function.layout.clone(),
+        captured,
+        digest: function.digest,
+        pure: function.pure,
+    }))
+}
+
+/// Stack space left below which a new stack segment is allocated before
+/// evaluating a nested term.
+pub(crate) const RED_ZONE: usize = 256 * 1024;
+
+/// Size of the stack segments allocated for deep evaluations.
+pub(crate) const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// What is left of evaluating a term once its own work is done: its
+/// value, or a term in tail position whose value is the result.
+enum Tail {
+    Value(Value),
+    Term(Arc<Term>),
+
+    /// The body of a called closure, with the context of the call and the
+    /// name of the closure.
+    Call(Arc<Term>, Context, Option<Arc<str>>),
+}
+
+/// Evaluates `term` in `context`.
+///
+/// Terms in tail position, like the branches of an `if`, the `next` of a
+/// `let` and the bodies of called closures, are evaluated in a loop
+/// rather than recursively, so tail calls don't grow the stack.
+pub fn eval<I: Printer>(
+    term: Term,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    if let Some(warnings) = &mut state.warnings {
+        warnings.report(&term);
+    }
+    if let Some(diagnostics) = &mut state.diagnostics {
+        for warning in warnings::check(&term) {
+            diagnostics.push(Diagnostic::from(&warning));
+        }
+    }
+
+    let result = evaluate(&term, context, state, io);
+    if let Err(error) = &result {
+        instrument::error(error);
+    }
+
+    result
+}
+
+/// Evaluates `term` in `context` as [`eval`] does, borrowing the term so
+/// that evaluating its subterms doesn't clone them.
+fn evaluate<I: Printer>(
+    term: &Term,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    if state
+        .max_depth
+        .is_some_and(|max_depth| state.depth >= max_depth)
+    {
+        return Err(RuntimeError {
+            kind: RuntimeErrorKind::DepthExceeded,
+            message: String::from("maximum depth exceeded"),
+            full_text: format!(
+                "the evaluation nested more than {} terms deep, likely from unbounded recursion",
+                state.depth
+            ),
+            location: term.location().clone(),
+            backtrace: Vec::new(),
+        });
+    }
+
+    // Nested evaluations continue on stack segments allocated on the heap
+    // once the current one runs low, so deep recursion never overflows the
+    // stack of the host.
+    state.depth += 1;
+    let frames = state.frames.len();
+    let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+        let mut memoized = Memoized::default();
+        let mut tails = Vec::new();
+        let result = eval_tail(term, context, state, io, &mut memoized, &mut tails);
+        memoized.finish(&result, state);
+
+        if let Some(observer) = &mut state.observer {
+            let result = result.as_ref();
+            for tail in tails.iter().rev() {
+                observer.on_exit(tail, state.depth, result);
+            }
+            observer.on_exit(term, state.depth, result);
+        }
+
+        result
+    });
+    state.depth -= 1;
+
+    // The calls are kept by the error before they return, where it
+    // happened.
+    let result = result.map_err(|mut error| {
+        if error.backtrace.is_empty() {
+            error.backtrace = backtrace(&state.frames);
+        }
+
+        error
+    });
+    state.frames.truncate(frames);
+
+    result
+}
+
+/// Evaluates `term` in `context` as [`eval`] does, failing with a
+/// [`RuntimeErrorKind::TimedOut`] error once `timeout` elapses. The
+/// evaluation is stopped through the cancellation of `state`, which stays
+/// cancelled afterwards.
+pub fn eval_with_timeout<I: Printer>(
+    term: Term,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+    timeout: Duration,
+) -> Result<Value, RuntimeError> {
+    let started = Instant::now();
+    let watchdog = state.cancellation.cancel_after(timeout);
+    let result = eval(term, context, state, io);
+    drop(watchdog);
+
+    // The interruption keeps the calls it stopped, as any error does.
+    result.map_err(|error| {
+        match state.cancellation.is_cancelled() && started.elapsed() >= timeout {
+            true => RuntimeError {
+                kind: RuntimeErrorKind::TimedOut,
+                message: String::from("timed out"),
+                full_text: format!("the evaluation took longer than {timeout:?}"),
+                location: error.location,
+                backtrace: error.backtrace,
+            },
+            false => error,
+        }
+    })
+}
+
+fn eval_tail<I: Printer>(
+    term: &Term,
+    context: &mut Context,
+    state: &mut State,
+    io: &mut I,
+    memoized: &mut Memoized,
+    tails: &mut Vec<Arc<Term>>,
+) -> Result<Value, RuntimeError> {
+    let mut frame: Option<Context> = None;
+    // The span of the call whose body is being evaluated, replaced by the
+    // calls made in tail position like its frame.
+    let mut span: Option<CallSpan> = None;
+
+    // The term in tail position being evaluated, once the one given is.
+    let mut tail_term: Arc<Term>;
+    let mut term = term;
+
+    loop {
+        // Calls only nest when made outside of a called body, as the
+        // bodies of calls in tail position take the place of the caller.
+        let nested = frame.is_none();
+        let context = match &mut frame {
+            Some(frame) => frame,
+            None => &mut *context,
+        };
+
+        if let Some(observer) = &mut state.observer {
+            observer.on_enter(term, state.depth, context);
+        }
+
+        state.stats.steps += 1;
+
+        if let Some(progress) = &mut state.progress {
+            progress.tick(state.stats.steps, term.location());
+        }
+
+        if state.cancellation.is_cancelled() {
+            return Err(RuntimeError {
+                kind: RuntimeErrorKind::Interrupted,
+                message: String::from("evaluation interrupted"),
+                full_text: String::from("the evaluation was cancelled before it finished"),
+                location: term.location().clone(),
+                backtrace: Vec::new(),
+            });
+        }
+
+        state.consume_fuel(term.location())?;
+
+        let tail = match term {
+            Term::Let(let_) => eval_let(let_, context, state, io)?,
+            Term::Int(int) => Tail::Value(Value::Int(int.value)),
+            Term::Str(str) => Tail::Value(Value::Str(str.value.as_str().into())),
+            Term::Bool(bool) => Tail::Value(Value::Bool(bool.value)),
+            Term::Unit(_) => Tail::Value(Value::Unit),
+            Term::Seq(seq) => eval_seq(seq, context, state, io)?,
+            Term::Cond(cond) => eval_cond(cond, context, state, io)?,
+            Term::Function(function) => Tail::Value(eval_function(function, context)?),
+            Term::Call(call) => {
+                if nested {
+                    state.check_call_depth(state.frames.len(), &call.location)?;
+                }
+
+                eval_call(call, context, state, io, memoized)?
+            }
+            Term::If(if_) => eval_if(if_, context, state, io)?,
+            Term::Binary(binary) => Tail::Value(eval_binary(binary, context, state, io)?),
+            Term::Var(var) => Tail::Value(eval_var(var, context)?),
+            Term::Tuple(tuple) => Tail::Value(eval_tuple(tuple, context, state, io)?),
+            Term::First(first) => Tail::Value(eval_first(first, context, state, io)?),
+            Term::Second(second) => Tail::Value(eval_second(second, context, state, io)?),
+            Term::Print(print) => Tail::Value(eval_print(print, context, state, io)?),
+        };
+
+        match tail {
+            Tail::Value(value) => return Ok(value),
+            Tail::Term(next) => tail_term = next,
+            Tail::Call(body, called, name) => {
+                // Exits the span of the call replaced before entering the
+                // next one.
+                span.take();
+                span = Some(instrument::call(name.as_deref(), term.location()));
+
+                let called_from = CallFrame {
+                    name,
+                    location: term.location().clone(),
+                    repeated: 0,
+                };
+                match frame {
+                    Some(_) => {
+                        *state.frames.last_mut().expect("the call has a frame") = called_from
+                    }
+                    None => state.frames.push(called_from),
+                }
+
+                tail_term = body;
+                frame = Some(called);
+            }
+        }
+        // Exited by the observer along with the term given, once its
+        // value is known.
+        if state.observer.is_some() {
+            tails.push(tail_term.clone());
+        }
+        term = &tail_term;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    };
+
+    use smallvec::smallvec;
+
+    use crate::ast::{BinaryOp, Location, Term, Tuple, Var};
+
+    use super::{
+        eval, eval_with_timeout, Context, Flush, Memoization, MockClock, Native, Printer,
+        RuntimeErrorKind, State, Type, Value, IO,
+    };
+
+    #[derive(Default)]
+    struct DummyIO(String);
+
+    impl Printer for DummyIO {
+        fn print(&mut self, value: super::Value) -> std::io::Result<super::Value> {
+            self.0.push_str(&format!("{}\n", value));
+
+            Ok(value)
+        }
+    }
+
+    struct ClosedIO;
+
+    impl Printer for ClosedIO {
+        fn print(&mut self, _value: super::Value) -> std::io::Result<super::Value> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    fn location() -> Location {
+        Location {
+            start: 0,
+            end: 0,
+            filename: "tests".into(),
+        }
+    }
+
+    fn int(int: i64) -> Term {
+        Term::Int(crate::ast::Int {
+            value: int,
+            location: location(),
+        })
+    }
+
+    fn v_int(int: i64) -> Value {
+        Value::Int(int)
+    }
+
+    fn v_tuple(first: Value, second: Value) -> Value {
+        Value::Tuple(super::Tuple::new(first, second))
+    }
+
+    fn var(str: &str) -> Var {
+        Var {
+            text: str.into(),
+            location: location(),
+            slot: None,
+        }
+    }
+
+    fn let_(name: &str, value: Term, next: Term) -> Term {
+        Term::Let(crate::ast::Let {
+            name: var(name),
+            value: Arc::new(value),
+            next: Arc::new(next),
+            location: location(),
+        })
+    }
+
+    fn print_(value: Term) -> Term {
+        Term::Print(crate::ast::Print {
+            value: Arc::new(value),
+            location: location(),
+        })
+    }
+
+    fn tuple(first: Term, second: Term) -> Term {
+        Term::Tuple(Tuple {
+            first: Arc::new(first),
+            second: Arc::new(second),
+            location: location(),
+        })
+    }
+
+    fn add(lhs: Term, rhs: Term) -> Term {
+        Term::Binary(super::Binary {
+            lhs: Arc::new(lhs),
+            rhs: Arc::new(rhs),
+            op: crate::ast::BinaryOp::Add,
+            location: location(),
+        })
+    }
+
+    fn binary(lhs: Term, op: crate::ast::BinaryOp, rhs: Term) -> Term {
+        Term::Binary(super::Binary {
+            lhs: Arc::new(lhs),
+            rhs: Arc::new(rhs),
+            op,
+            location: location(),
+        })
+    }
+
+    fn bool_(value: bool) -> Term {
+        Term::Bool(crate::ast::Bool {
+            value,
+            location: location(),
+        })
+    }
+
+    fn var_(text: &str) -> Term {
+        Term::Var(Var {
+            text: text.into(),
+            location: location(),
+            slot: None,
+        })
+    }
+
+    fn function(parameters: &[&str], value: Term) -> Term {
+        Term::Function(crate::ast::Function {
+            parameters: parameters.iter().map(|parameter| var(parameter)).collect(),
+            value: Arc::new(value),
+            location: location(),
+            free: None,
+            layout: None,
+            digest: None,
+            pure: None,
+        })
+    }
+
+    fn if_(condition: Term, then: Term, otherwise: Term) -> Term {
+        Term::If(crate::ast::If {
+            condition: Arc::new(condition),
+            then: Arc::new(then),
+            otherwise: Arc::new(otherwise),
+            location: location(),
+        })
+    }
+
+    fn call(callee: Term, arguments: Vec<Term>) -> Term {
+        Term::Call(crate::ast::Call {
+            callee: Arc::new(callee),
+            arguments,
+            location: location(),
+        })
+    }
+
+    fn eq(l: Value, r: Value) -> bool {
+        match l.eq(&r, &location()).unwrap() {
+            Value::Bool(bool) => bool,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn print_inner_and_outer_scope() {
+        let mut io = DummyIO::default();
+
+        let let_ = let_("_", print_(int(1)), print_(int(2)));
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(let_, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, v_int(2)));
+        assert_eq!(io.0, "1\n2\n");
+    }
+
+    #[test]
+    fn print_inside_var_scope_and_var() {
+        let mut io = DummyIO::default();
 
         let let_ = let_(
             "tuple",
@@ -462,23 +2801,1036 @@ mod tests {
             print_(var_("tuple")),
         );
         let mut context = Context::new();
-        let mut cache = Cache::new();
-        let result = eval(Box::new(let_), &mut context, &mut cache, &mut io).unwrap();
+        let mut state = State::new();
+        let result = eval(let_, &mut context, &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), v_tuple(v_int(1), v_int(2)).to_string());
+        assert_eq!(io.0, "1\n2\n(1, 2)\n");
+    }
+
+    #[test]
+    fn print_sum_operation_and_operated() {
+        let mut io = DummyIO::default();
+
+        let print = print_(add(print_(int(1)), print_(int(2))));
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(print, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, v_int(3)));
+        assert_eq!(io.0, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn stats_per_function() {
+        let mut io = DummyIO::default();
+
+        let double = function(&["n"], add(var_("n"), var_("n")));
+        let calls = add(
+            call(var_("double"), vec![int(2)]),
+            call(var_("double"), vec![int(2)]),
+        );
+        let program = let_("double", double, calls);
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(program, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, v_int(8)));
+
+        let functions = state.stats.by_function();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].name.as_deref(), Some("double"));
+        assert_eq!(functions[0].calls, 2);
+        assert_eq!(functions[0].cache_hits, 1);
+        assert_eq!(functions[0].cache_misses, 1);
+        assert!(functions[0].retained_bytes > 0);
+    }
+
+    #[test]
+    fn and_short_circuits() {
+        let mut io = DummyIO::default();
+
+        let division = binary(int(1), BinaryOp::Div, int(0));
+        let guarded = binary(
+            bool_(false),
+            BinaryOp::And,
+            binary(print_(division), BinaryOp::Eq, int(1)),
+        );
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(guarded, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, Value::Bool(false)));
+        assert_eq!(io.0, "");
+    }
+
+    #[test]
+    fn or_short_circuits() {
+        let mut io = DummyIO::default();
+
+        let guarded = binary(bool_(true), BinaryOp::Or, print_(bool_(false)));
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(guarded, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, Value::Bool(true)));
+        assert_eq!(io.0, "");
+    }
+
+    #[test]
+    fn and_evaluates_rhs_when_needed() {
+        let mut io = DummyIO::default();
+
+        let both = binary(bool_(true), BinaryOp::And, print_(bool_(false)));
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(both, &mut context, &mut state, &mut io).unwrap();
+
+        assert!(eq(result, Value::Bool(false)));
+        assert_eq!(io.0, "false\n");
+    }
+
+    #[test]
+    fn call_native() {
+        let mut io = DummyIO::default();
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let mut state = State::new();
+        let typeof_ = call(var_("typeof"), vec![int(1)]);
+        let result = eval(typeof_, &mut context, &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "int");
+
+        let wrong_arity = call(var_("typeof"), vec![int(1), int(2)]);
+        let is_err = eval(wrong_arity, &mut context, &mut state, &mut io).is_err();
+
+        assert!(is_err);
+    }
+
+    #[test]
+    fn print_error_is_runtime_error() {
+        let mut io = ClosedIO;
+
+        let mut context = Context::new();
+        let mut state = State::new();
+        let result = eval(print_(int(1)), &mut context, &mut state, &mut io);
+
+        assert_eq!(result.unwrap_err().kind, RuntimeErrorKind::Output);
+    }
+
+    #[test]
+    fn printed_line_endings_are_normalized() {
+        let mut io = IO::with_writer(Flush::EachPrint, IO::DEFAULT_CAPACITY, Vec::new());
+
+        io.print(Value::Str("a\r\nb".into())).unwrap();
+        io.print(Value::Str("c\rd\n".into())).unwrap();
+
+        assert_eq!(io.writer(), b"a\nb\nc\rd\n\n");
+    }
+
+    #[test]
+    fn printed_values_are_flushed_as_asked() {
+        let mut io = IO::with_writer(Flush::EachPrint, IO::DEFAULT_CAPACITY, Vec::new());
+        io.print(Value::Int(1)).unwrap();
+
+        assert_eq!(io.writer(), b"1\n");
+
+        let mut io = IO::with_writer(Flush::Buffered, IO::DEFAULT_CAPACITY, Vec::new());
+        io.print(Value::Int(1)).unwrap();
+        io.print(Value::Int(2)).unwrap();
+
+        assert_eq!(io.writer(), b"");
+
+        io.flush().unwrap();
+
+        assert_eq!(io.writer(), b"1\n2\n");
+    }
+
+    #[test]
+    fn cancelled_evaluation_stops() {
+        let mut io = DummyIO::default();
+
+        let mut context = Context::new();
+        let mut state = State::new();
+        state.cancellation.cancel();
+        let result = eval(print_(int(1)), &mut context, &mut state, &mut io);
+
+        assert_eq!(result.unwrap_err().kind, RuntimeErrorKind::Interrupted);
+        assert_eq!(io.0, "");
+    }
+
+    #[test]
+    fn running_out_of_fuel_stops_infinite_loops() {
+        let mut io = DummyIO::default();
+
+        // let loop = fn () => loop(); loop()
+        let forever = let_(
+            "loop",
+            function(&[], call(var_("loop"), vec![])),
+            call(var_("loop"), vec![]),
+        );
+        let mut context = Context::new();
+        let mut state = State::new();
+        state.fuel = Some(1_000);
+        let error = eval(forever, &mut context, &mut state, &mut io).unwrap_err();
 
-        assert_eq!(result.to_string(), v_tuple(v_int(1), v_int(2)).to_string());
-        assert_eq!(io.0, "1\n2\n(1, 2)\n");
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfFuel);
+        assert_eq!(state.stats.steps, 1_001);
+        assert_eq!(state.fuel, Some(0));
     }
 
     #[test]
-    fn print_sum_operation_and_operated() {
+    fn values_larger_than_the_memory_limit_fail() {
         let mut io = DummyIO::default();
 
-        let print = print_(add(print_(int(1)), print_(int(2))));
+        // let grow = fn (s) => grow(s + s); grow("ab")
+        let ab = Term::Str(crate::ast::Str {
+            value: String::from("ab"),
+            location: location(),
+        });
+        let grow = let_(
+            "grow",
+            function(&["s"], call(var_("grow"), vec![add(var_("s"), var_("s"))])),
+            call(var_("grow"), vec![ab]),
+        );
         let mut context = Context::new();
-        let mut cache = Cache::new();
-        let result = eval(Box::new(print), &mut context, &mut cache, &mut io).unwrap();
+        let mut state = State::new();
+        state.max_memory = Some(1 << 20);
+        let error = eval(grow, &mut context, &mut state, &mut io).unwrap_err();
 
-        assert!(eq(result, v_int(3)));
-        assert_eq!(io.0, "1\n2\n3\n");
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfMemory);
+    }
+
+    #[test]
+    fn timed_out_evaluations_stop() {
+        let mut io = DummyIO::default();
+
+        // let loop = fn () => loop(); loop()
+        let forever = let_(
+            "loop",
+            function(&[], call(var_("loop"), vec![])),
+            call(var_("loop"), vec![]),
+        );
+        let mut context = Context::new();
+        let mut state = State::new();
+        let timeout = std::time::Duration::from_millis(50);
+        let error = eval_with_timeout(forever, &mut context, &mut state, &mut io, timeout);
+        let error = error.unwrap_err();
+
+        assert_eq!(error.kind, RuntimeErrorKind::TimedOut);
+        let names = error.backtrace.iter().map(|frame| frame.name.as_deref());
+        assert_eq!(names.collect::<Vec<_>>(), [Some("loop")]);
+    }
+
+    #[test]
+    fn clock_builtins() {
+        let mut io = DummyIO::default();
+
+        let clock = MockClock::new(1_000);
+        let mut context = Context::new();
+        crate::builtins::install_clock(&mut context, clock.clone());
+        let mut state = State::new();
+
+        let start = call(var_("now"), vec![]);
+        let start = eval(
+            let_("start", start, var_("start")),
+            &mut context,
+            &mut state,
+            &mut io,
+        );
+        assert_eq!(start.unwrap().to_string(), "1000");
+
+        clock.advance(250);
+        let elapsed = call(var_("elapsed"), vec![var_("start")]);
+        let elapsed = eval(elapsed, &mut context, &mut state, &mut io).unwrap();
+        assert_eq!(elapsed.to_string(), "250");
+    }
+
+    #[test]
+    fn elapsed_wraps_like_subtraction() {
+        let mut io = DummyIO::default();
+
+        let mut context = Context::new();
+        crate::builtins::install_clock(&mut context, MockClock::new(1_000));
+
+        let elapsed = call(var_("elapsed"), vec![int(i64::MIN)]);
+        let elapsed = eval(elapsed, &mut context, &mut State::new(), &mut io).unwrap();
+        assert_eq!(elapsed.to_string(), (i64::MIN + 1_000).to_string());
+    }
+
+    #[test]
+    fn closures_of_the_same_function_are_memoized_apart() {
+        // let make = fn (k) => fn (x) => x + k; (make(1)(1), make(2)(1))
+        let make = function(&["k"], function(&["x"], add(var_("x"), var_("k"))));
+        let made = |k| call(call(var_("make"), vec![int(k)]), vec![int(1)]);
+        let named = let_("make", make, tuple(made(1), made(2)));
+        let mut resolved = named.clone();
+        crate::resolve::resolve(&mut resolved);
+
+        for mut program in [named, resolved] {
+            crate::free::annotate(&mut program);
+            let mut io = DummyIO::default();
+            let result = eval(program, &mut Context::new(), &mut State::new(), &mut io);
+
+            assert_eq!(result.unwrap().to_string(), "(2, 3)");
+        }
+    }
+
+    #[test]
+    fn closures_capturing_different_natives_are_memoized_apart() {
+        let source = "let fa = fix(fn (self, n) => { n + 1 });
+                      let fb = fix(fn (self, n) => { n + 2 });
+                      let mk = fn (f) => { fn (x) => { f(x) } };
+                      let _ = print(mk(fa)(1));
+                      print(mk(fb)(1))";
+        let mut program = crate::parser::parse_term(source, "tests").unwrap();
+        crate::resolve::resolve(&mut program);
+        crate::free::annotate(&mut program);
+        crate::digest::annotate(&mut program);
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let mut io = DummyIO::default();
+        eval(program, &mut context, &mut State::new(), &mut io).unwrap();
+
+        assert_eq!(io.0, "2\n3\n");
+    }
+
+    #[test]
+    fn natives_dropped_and_created_again_are_memoized_apart() {
+        // Each fixed function is dropped before the next one is created,
+        // which may reuse its address.
+        let source = "let mk = fn (f) => { fn (x) => { f(x) } };
+                      let _ = print(mk(fix(fn (self, n) => { n + 1 }))(1));
+                      let _ = print(mk(fix(fn (self, n) => { n + 2 }))(1));
+                      print(mk(fix(fn (self, n) => { n + 3 }))(1))";
+        let mut program = crate::parser::parse_term(source, "tests").unwrap();
+        crate::resolve::resolve(&mut program);
+        crate::free::annotate(&mut program);
+        crate::digest::annotate(&mut program);
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let mut io = DummyIO::default();
+        eval(program, &mut context, &mut State::new(), &mut io).unwrap();
+
+        assert_eq!(io.0, "2\n3\n4\n");
+    }
+
+    #[test]
+    fn calls_capturing_host_natives_are_not_memoized() {
+        // let apply = fn (x) => step(x); apply(1), with another step every
+        // run, sharing the cache.
+        let mut state = State::new();
+        let mut run = |increment: i64| {
+            let native = Native::new("step", 1, move |arguments, _location| match &arguments[0] {
+                Value::Int(n) => Ok(Value::Int(n + increment)),
+                _value => unreachable!(),
+            });
+            let mut context = Context::new();
+            context.insert("step".into(), Value::Native(native));
+
+            let apply = function(&["x"], call(var_("step"), vec![var_("x")]));
+            let program = let_("apply", apply, call(var_("apply"), vec![int(1)]));
+            eval(program, &mut context, &mut state, &mut DummyIO::default()).unwrap()
+        };
+
+        assert_eq!(run(1).to_string(), "2");
+        assert_eq!(run(2).to_string(), "3");
+    }
+
+    #[test]
+    fn impure_calls_are_not_memoized() {
+        let mut io = DummyIO::default();
+
+        let counter = Arc::new(AtomicI64::new(0));
+        let count = Arc::clone(&counter);
+        let native = Native::new("count", 0, move |_arguments, _location| {
+            Ok(Value::Int(count.fetch_add(1, Ordering::Relaxed) + 1))
+        });
+        let mut context = Context::new();
+        context.insert("count".into(), Value::Native(native.impure()));
+        let mut state = State::new();
+
+        let next = function(&[], call(var_("count"), vec![]));
+        let calls = tuple(call(var_("next"), vec![]), call(var_("next"), vec![]));
+        let result = eval(let_("next", next, calls), &mut context, &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "(1, 2)");
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn adaptive_memoization_drops_functions_rarely_cached() {
+        // let id = fn (x) => x; (id(a), (id(b), (id(c), id(d))))
+        let run = |arguments: [i64; 4]| {
+            let calls = arguments
+                .iter()
+                .rev()
+                .map(|&n| call(var_("id"), vec![int(n)]))
+                .reduce(|calls, call| tuple(call, calls))
+                .unwrap();
+            let program = let_("id", function(&["x"], var_("x")), calls);
+
+            let mut state = State::new();
+            state.memoization = Memoization::Adaptive {
+                probe: 2,
+                min_hit_rate: 0.5,
+                min_steps: 0,
+            };
+            eval(
+                program,
+                &mut Context::new(),
+                &mut state,
+                &mut DummyIO::default(),
+            )
+            .unwrap();
+
+            state.stats
+        };
+
+        let distinct = run([1, 2, 3, 4]);
+        assert_eq!(distinct.calls, 4);
+        assert_eq!((distinct.cache_hits, distinct.cache_misses), (0, 2));
+
+        let repeated = run([1, 1, 1, 1]);
+        assert_eq!((repeated.cache_hits, repeated.cache_misses), (3, 1));
+    }
+
+    #[test]
+    fn calls_printing_are_not_memoized() {
+        // let say = fn (x) => print(x); let relay = fn (x) => say(x);
+        // let shout = fn (x) => let y = print(x); y;
+        // (relay(1), relay(1)); (shout(2), shout(2))
+        let say = function(&["x"], print_(var_("x")));
+        let relay = function(&["x"], call(var_("say"), vec![var_("x")]));
+        let shout = function(&["x"], let_("y", print_(var_("x")), var_("y")));
+        let twice = |name, n| {
+            tuple(
+                call(var_(name), vec![int(n)]),
+                call(var_(name), vec![int(n)]),
+            )
+        };
+        let program = let_(
+            "say",
+            say,
+            let_(
+                "relay",
+                relay,
+                let_("shout", shout, tuple(twice("relay", 1), twice("shout", 2))),
+            ),
+        );
+
+        let mut io = DummyIO::default();
+        eval(program, &mut Context::new(), &mut State::new(), &mut io).unwrap();
+
+        assert_eq!(io.0, "1\n1\n2\n2\n");
+    }
+
+    #[test]
+    fn generators_are_resumed_where_they_yielded() {
+        // The yields happen in a function defined outside of the body.
+        let source = "let count = fn (n) => { let _ = print(n); let _ = yield(n); count(n + 1) };
+                      let numbers = generator(fn () => { count(0) });
+                      let a = next(numbers);
+                      let _ = print(\"between\");
+                      (a, next(numbers))";
+        let program = crate::parser::parse_term(source, "tests").unwrap();
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let mut io = DummyIO::default();
+        let result = eval(program, &mut context, &mut State::new(), &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "((true, 0), (true, 1))");
+        assert_eq!(io.0, "0\nbetween\n1\n");
+    }
+
+    #[test]
+    fn generators_are_done_once_their_body_returns() {
+        let source = "let once = generator(fn () => { yield(1) });
+                      let a = next(once);
+                      let b = next(once);
+                      (a, (b, next(once)))";
+        let program = crate::parser::parse_term(source, "tests").unwrap();
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let result = eval(
+            program,
+            &mut context,
+            &mut State::new(),
+            &mut DummyIO::default(),
+        );
+
+        assert_eq!(
+            result.unwrap().to_string(),
+            "((true, 1), ((false, done), (false, done)))"
+        );
+    }
+
+    #[test]
+    fn generators_fail_as_their_body_does() {
+        let kind = |source, fuel| {
+            let term = crate::parser::parse_term(source, "tests").unwrap();
+            let mut context = Context::new();
+            crate::builtins::install(&mut context);
+            let mut state = State::new();
+            state.fuel = fuel;
+            let result = eval(term, &mut context, &mut state, &mut DummyIO::default());
+
+            result.unwrap_err().kind
+        };
+
+        assert_eq!(
+            kind("next(generator(fn () => { 1 + true }))", None),
+            RuntimeErrorKind::TypeMismatch {
+                expected: &[Type::Int, Type::Str],
+                found: Type::Bool,
+            }
+        );
+        // The body spends the fuel of the evaluation resuming it.
+        assert_eq!(
+            kind(
+                "let loop = fn (n) => { loop(n + 1) }; next(generator(fn () => { loop(0) }))",
+                Some(1_000),
+            ),
+            RuntimeErrorKind::OutOfFuel
+        );
+        assert_eq!(kind("yield(1)", None), RuntimeErrorKind::Failed);
+    }
+
+    #[test]
+    fn generators_fail_when_their_body_panics() {
+        let source = "next(generator(fn () => { boom() }))";
+        let program = crate::parser::parse_term(source, "tests").unwrap();
+
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+        let boom = Native::new("boom", 0, |_arguments, _location| panic!("boom"));
+        context.insert("boom".into(), Value::Native(boom));
+        let error = eval(
+            program,
+            &mut context,
+            &mut State::new(),
+            &mut DummyIO::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.kind, RuntimeErrorKind::Failed);
+        assert_eq!(error.full_text, "the body of the generator panicked: boom");
+    }
+
+    #[test]
+    fn deep_bodies_are_called() {
+        let source = format!(
+            "let f = fn (x) => {{ {} }}; f(1)",
+            vec!["x"; 40_000].join(" + ")
+        );
+        let program = crate::parser::parse_term(&source, "tests").unwrap();
+        let mut annotated = program.clone();
+        crate::purity::annotate(&mut annotated);
+
+        // Whether the body is pure is found out on the first call when it
+        // wasn't annotated.
+        for program in [program, annotated] {
+            let mut io = DummyIO::default();
+            let result = eval(program, &mut Context::new(), &mut State::new(), &mut io);
+
+            assert_eq!(result.unwrap().to_string(), "40000");
+        }
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let mut io = DummyIO::default();
+
+        // sum(n, acc) = if n == 0 then acc else sum(n - 1, acc + n)
+        let sum = function(
+            &["n", "acc"],
+            if_(
+                binary(var_("n"), BinaryOp::Eq, int(0)),
+                var_("acc"),
+                call(
+                    var_("sum"),
+                    vec![
+                        binary(var_("n"), BinaryOp::Sub, int(1)),
+                        add(var_("acc"), var_("n")),
+                    ],
+                ),
+            ),
+        );
+        let program = let_("sum", sum, call(var_("sum"), vec![int(20_000), int(0)]));
+        let mut state = State::new();
+        state.max_call_depth = Some(1);
+        let result = eval(program, &mut Context::new(), &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "200010000");
+        assert_eq!(state.stats.calls, 20_001);
+    }
+
+    fn sum_to(n: i64) -> Term {
+        // sum(n) = if n == 0 then 0 else n + sum(n - 1)
+        let sum = function(
+            &["n"],
+            if_(
+                binary(var_("n"), BinaryOp::Eq, int(0)),
+                int(0),
+                add(
+                    var_("n"),
+                    call(var_("sum"), vec![binary(var_("n"), BinaryOp::Sub, int(1))]),
+                ),
+            ),
+        );
+
+        let_("sum", sum, call(var_("sum"), vec![int(n)]))
+    }
+
+    #[test]
+    fn deep_recursion_grows_the_stack() {
+        let mut io = DummyIO::default();
+        let mut state = State::new();
+        let result = eval(sum_to(20_000), &mut Context::new(), &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "200010000");
+    }
+
+    #[test]
+    fn depth_is_limited() {
+        let mut io = DummyIO::default();
+        let mut state = State::new();
+        state.max_depth = Some(1_000);
+
+        let error = eval(sum_to(20_000), &mut Context::new(), &mut state, &mut io).unwrap_err();
+
+        assert_eq!(error.message, "maximum depth exceeded");
+        assert_eq!(error.kind, RuntimeErrorKind::DepthExceeded);
+
+        // The recursive calls are folded into the frame of the first one.
+        let [.., frame] = error.backtrace.as_slice() else {
+            panic!("expected the calls of sum in the backtrace")
+        };
+        assert!(error.backtrace.len() <= 2);
+        assert_eq!(frame.name.as_deref(), Some("sum"));
+        assert!(frame.repeated > 100);
+    }
+
+    #[test]
+    fn errors_are_matched_by_kind() {
+        let kind = |source| {
+            let term = crate::parser::parse_term(source, "tests").unwrap();
+            let mut context = Context::new();
+            crate::builtins::install(&mut context);
+            let result = eval(
+                term,
+                &mut context,
+                &mut State::new(),
+                &mut DummyIO::default(),
+            );
+
+            result.unwrap_err().kind
+        };
+        let mismatch = |expected, found| RuntimeErrorKind::TypeMismatch { expected, found };
+
+        assert_eq!(kind("x + 1"), RuntimeErrorKind::UnboundVariable);
+        assert_eq!(kind("let f = 1; f(2)"), RuntimeErrorKind::NotCallable);
+        assert_eq!(kind("assert(true)"), RuntimeErrorKind::ArityMismatch);
+        assert_eq!(
+            kind("1 + true"),
+            mismatch(&[Type::Int, Type::Str], Type::Bool)
+        );
+        assert_eq!(kind("(1, 2) - 1"), mismatch(&[Type::Int], Type::Tuple));
+        assert_eq!(
+            kind("if (1) { 2 } else { 3 }"),
+            mismatch(&[Type::Bool], Type::Int)
+        );
+        assert_eq!(kind("first(1)"), mismatch(&[Type::Tuple], Type::Int));
+        assert_eq!(kind("1 < \"a\""), mismatch(&[Type::Int], Type::Str));
+        assert_eq!(
+            kind("let f = fn () => { 1 }; f == f"),
+            mismatch(
+                &[Type::Int, Type::Str, Type::Bool, Type::Unit],
+                Type::Closure
+            )
+        );
+        assert_eq!(kind("10 / 0"), RuntimeErrorKind::DivisionByZero);
+        assert_eq!(
+            kind("assert(false, \"no\")"),
+            RuntimeErrorKind::AssertionFailed
+        );
+
+        assert_eq!(mismatch(&[Type::Bool], Type::Int).code(), "E0004");
+        assert_eq!(RuntimeErrorKind::UnboundVariable.code(), "E0001");
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let mut io = DummyIO::default();
+        let mut state = State::new();
+        state.max_call_depth = Some(100);
+
+        let error = eval(sum_to(20_000), &mut Context::new(), &mut state, &mut io).unwrap_err();
+
+        assert_eq!(error.full_text, "maximum recursion depth 100 exceeded");
+        assert!(eval(sum_to(99), &mut Context::new(), &mut state, &mut io).is_ok());
+    }
+
+    #[test]
+    fn annotated_closures_capture_only_what_they_use() {
+        let mut io = DummyIO::default();
+
+        // let unused = 1; let y = 2; let add = fn (x) => x + y; add
+        let mut program = let_(
+            "unused",
+            int(1),
+            let_(
+                "y",
+                int(2),
+                let_(
+                    "add",
+                    function(&["x"], add(var_("x"), var_("y"))),
+                    var_("add"),
+                ),
+            ),
+        );
+        crate::free::annotate(&mut program);
+        let closure = eval(program, &mut Context::new(), &mut State::new(), &mut io).unwrap();
+
+        let Value::Closure(closure) = closure else {
+            panic!("expected a closure, got {closure}")
+        };
+        let captured = &closure.context;
+        assert_eq!(captured.iter().count(), 1);
+        assert!(captured.contains_key("y".into()));
+
+        let mut recursive = sum_to(100);
+        crate::free::annotate(&mut recursive);
+        let result = eval(recursive, &mut Context::new(), &mut State::new(), &mut io);
+        assert_eq!(result.unwrap().to_string(), "5050");
+    }
+
+    #[test]
+    fn resolved_functions_read_their_slots() {
+        let mut io = DummyIO::default();
+
+        // let make = fn (x) => fn (y) => let z = x + y; z; make(2)(40)
+        let make = function(
+            &["x"],
+            function(&["y"], let_("z", add(var_("x"), var_("y")), var_("z"))),
+        );
+        let mut program = let_(
+            "make",
+            make,
+            call(call(var_("make"), vec![int(2)]), vec![int(40)]),
+        );
+        crate::resolve::resolve(&mut program);
+        let result = eval(program, &mut Context::new(), &mut State::new(), &mut io);
+        assert_eq!(result.unwrap().to_string(), "42");
+
+        // Recursive functions refer to themselves through their frame, not
+        // through a binding in their own context.
+        let countdown = function(
+            &["n"],
+            if_(
+                binary(var_("n"), BinaryOp::Eq, int(0)),
+                int(0),
+                call(
+                    var_("countdown"),
+                    vec![binary(var_("n"), BinaryOp::Sub, int(1))],
+                ),
+            ),
+        );
+        let mut program = let_("countdown", countdown, var_("countdown"));
+        crate::resolve::resolve(&mut program);
+        crate::free::annotate(&mut program);
+        let countdown = eval(program, &mut Context::new(), &mut State::new(), &mut io).unwrap();
+
+        let Value::Closure(closure) = &countdown else {
+            panic!("expected a closure, got {countdown}")
+        };
+        assert_eq!(closure.context.iter().count(), 0);
+
+        let result = super::apply(
+            countdown,
+            smallvec![v_int(100)],
+            &location(),
+            &mut State::new(),
+            &mut io,
+        );
+        assert_eq!(result.unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn evaluations_move_across_threads() {
+        fn send<T: Send + Sync>() {}
+        send::<Value>();
+        send::<Term>();
+        send::<Context>();
+
+        // let sum = fn (n) => ...; sum, evaluated on a worker thread and
+        // called back on this one.
+        let Term::Let(program) = &sum_to(0) else {
+            unreachable!("sum_to starts with a let")
+        };
+        let program = let_("sum", Term::clone(&program.value), var_("sum"));
+        let worker = std::thread::spawn(move || {
+            let mut state = State::new();
+            let sum = eval(
+                program,
+                &mut Context::new(),
+                &mut state,
+                &mut DummyIO::default(),
+            );
+
+            (sum.unwrap(), state)
+        });
+        let (sum, mut state) = worker.join().unwrap();
+
+        let result = super::apply(
+            sum,
+            smallvec![v_int(100)],
+            &location(),
+            &mut state,
+            &mut DummyIO::default(),
+        );
+        assert_eq!(result.unwrap().to_string(), "5050");
+    }
+
+    #[test]
+    fn recursive_closures_are_reclaimed() {
+        let mut io = DummyIO::default();
+
+        // let sum = fn (n) => ...; sum, both looked up by name and resolved
+        // to slots.
+        let Term::Let(program) = &sum_to(0) else {
+            unreachable!("sum_to starts with a let")
+        };
+        let named = let_("sum", Term::clone(&program.value), var_("sum"));
+        let mut resolved = named.clone();
+        crate::resolve::resolve(&mut resolved);
+
+        for program in [named, resolved] {
+            let sum = eval(program, &mut Context::new(), &mut State::new(), &mut io).unwrap();
+            let Value::Closure(closure) = &sum else {
+                panic!("expected a closure, got {sum}")
+            };
+            let context = Arc::downgrade(&closure.context);
+
+            let result = super::apply(
+                sum.clone(),
+                smallvec![v_int(10)],
+                &location(),
+                &mut State::new(),
+                &mut io,
+            );
+            assert_eq!(result.unwrap().to_string(), "55");
+
+            // The closure isn't bound inside its own context, so nothing
+            // keeps the context alive once the closure is dropped.
+            drop(sum);
+            assert!(context.upgrade().is_none());
+        }
+    }
+
+    #[test]
+    fn extended_contexts_share_their_bindings() {
+        let mut outer = Context::new();
+        outer.insert("x".into(), v_int(1));
+
+        let mut inner = outer.clone();
+        inner.insert("x".into(), v_int(2));
+        inner.insert("y".into(), v_int(3));
+
+        assert_eq!(outer.get("x".into()).unwrap().to_string(), "1");
+        assert!(!outer.contains_key("y".into()));
+        assert_eq!(inner.get("x".into()).unwrap().to_string(), "2");
+        assert_eq!(inner.iter().count(), 2);
+
+        // Long chains are dropped without overflowing the stack.
+        for n in 0..1_000_000 {
+            inner.insert("n".into(), v_int(n));
+        }
+        drop(inner);
+    }
+
+    #[test]
+    fn structural_hashes_ignore_the_order_of_entries() {
+        use crate::collections::{Key, Map};
+        use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+        fn hash(value: &Value) -> Option<u64> {
+            let mut hasher = DefaultHasher::new();
+            value.structural_hash(&mut hasher)?;
+
+            Some(hasher.finish())
+        }
+
+        let entries = (0..100).map(|n| (Key::new(v_int(n)).unwrap(), v_int(n * n)));
+        let ascending = entries
+            .clone()
+            .fold(Map::new(), |map, (key, value)| map.insert(key, value));
+        let descending = entries
+            .rev()
+            .fold(Map::new(), |map, (key, value)| map.insert(key, value));
+
+        assert_eq!(hash(&Value::Map(ascending)), hash(&Value::Map(descending)));
+        assert_ne!(hash(&v_int(1)), hash(&Value::Str("1".into())));
+
+        let native = Native::new("nothing", 0, |_arguments, _location| Ok(Value::Unit));
+        assert_eq!(hash(&v_tuple(v_int(1), Value::Native(native))), None);
+    }
+
+    #[test]
+    fn values_compare_structurally() {
+        assert_eq!(v_tuple(v_int(1), v_int(2)), v_tuple(v_int(1), v_int(2)));
+        assert_ne!(v_int(1), Value::Str("1".into()));
+        assert!(v_tuple(v_int(1), v_int(2)) < v_tuple(v_int(1), v_int(3)));
+        assert_eq!(v_int(1).partial_cmp(&Value::Unit), None);
+        assert_eq!(
+            Value::Unit.partial_cmp(&Value::Unit),
+            Some(std::cmp::Ordering::Equal)
+        );
+
+        let native = Value::Native(Native::new("nothing", 0, |_arguments, _location| {
+            Ok(Value::Unit)
+        }));
+        let other = Value::Native(Native::new("nothing", 0, |_arguments, _location| {
+            Ok(Value::Unit)
+        }));
+        assert_eq!(native, native.clone());
+        assert_ne!(native, other);
+    }
+
+    #[test]
+    fn values_serialize_to_json() {
+        use crate::collections::{Key, Map};
+
+        let map = Map::new().insert(Key::new(Value::Str("a".into())).unwrap(), Value::Unit);
+        let value = v_tuple(v_int(1), v_tuple(Value::Bool(true), Value::Map(map)));
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            r#"[1,[true,{"a":null}]]"#
+        );
+
+        let native = Native::new("nothing", 0, |_arguments, _location| Ok(Value::Unit));
+        let error = serde_json::to_string(&v_tuple(v_int(1), Value::Native(native)));
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "a closure can't be serialized"
+        );
+    }
+
+    #[test]
+    fn immediately_invoked_functions() {
+        let mut io = DummyIO::default();
+        let mut context = Context::new();
+        let mut state = State::new();
+
+        // let y = 40; (fn (x) => let z = x + y; z)(2)
+        let invoked = call(
+            function(&["x"], let_("z", add(var_("x"), var_("y")), var_("z"))),
+            vec![int(2)],
+        );
+        let program = let_("y", int(40), invoked);
+        let result = eval(program, &mut context, &mut state, &mut io).unwrap();
+
+        assert_eq!(result.to_string(), "42");
+        assert_eq!(state.stats.calls, 1);
+        assert!(!context.contains_key("x".into()) && !context.contains_key("z".into()));
+    }
+
+    #[test]
+    fn print_unit() {
+        let mut io = DummyIO::default();
+        let unit = Term::Unit(crate::ast::Unit {
+            location: location(),
+        });
+        let result = eval(
+            print_(unit),
+            &mut Context::new(),
+            &mut State::new(),
+            &mut io,
+        );
+
+        assert_eq!(result.unwrap().type_name(), "unit");
+        assert_eq!(io.0, "()\n");
+    }
+
+    #[test]
+    fn seq_yields_the_last_value() {
+        let mut io = DummyIO::default();
+        let seq = |terms| {
+            Term::Seq(crate::ast::Seq {
+                terms,
+                location: location(),
+            })
+        };
+
+        let printed = seq(vec![print_(int(1)), print_(int(2)), int(3)]);
+        let result = eval(printed, &mut Context::new(), &mut State::new(), &mut io);
+        assert_eq!(result.unwrap().to_string(), "3");
+        assert_eq!(io.0, "1\n2\n");
+
+        let empty = eval(seq(vec![]), &mut Context::new(), &mut State::new(), &mut io);
+        assert_eq!(empty.unwrap().to_string(), "()");
+    }
+
+    #[test]
+    fn cond_takes_the_first_arm_holding() {
+        let mut io = DummyIO::default();
+        let mut classify = |n: i64| {
+            let arm = |limit: i64, then: &str| crate::ast::Arm {
+                condition: binary(int(n), BinaryOp::Lt, int(limit)),
+                then: Term::Str(crate::ast::Str {
+                    value: then.to_string(),
+                    location: location(),
+                }),
+            };
+            let cond = Term::Cond(crate::ast::Cond {
+                arms: vec![arm(0, "negative"), arm(10, "small"), arm(100, "medium")],
+                otherwise: Arc::new(int(0)),
+                location: location(),
+            });
+
+            eval(cond, &mut Context::new(), &mut State::new(), &mut io)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(classify(-1), "negative");
+        assert_eq!(classify(5), "small");
+        assert_eq!(classify(50), "medium");
+        assert_eq!(classify(500), "0");
+    }
+
+    #[test]
+    fn fix_recurses_anonymously() {
+        let mut io = DummyIO::default();
+        let mut context = Context::new();
+        crate::builtins::install(&mut context);
+
+        // fix(fn (self, n) => if n == 0 then 1 else n * self(n - 1))(5)
+        let factorial = function(
+            &["self", "n"],
+            if_(
+                binary(var_("n"), BinaryOp::Eq, int(0)),
+                int(1),
+                binary(
+                    var_("n"),
+                    BinaryOp::Mul,
+                    call(var_("self"), vec![binary(var_("n"), BinaryOp::Sub, int(1))]),
+                ),
+            ),
+        );
+        let fixed = call(var_("fix"), vec![factorial]);
+        let result = eval(
+            call(fixed, vec![int(5)]),
+            &mut context,
+            &mut State::new(),
+            &mut io,
+        );
+
+        assert_eq!(result.unwrap().to_string(), "120");
     }
 }
@@ -0,0 +1,695 @@
+use std::{collections::HashMap, fmt::Debug, mem::offset_of};
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, StackSlotData,
+        StackSlotKind, UserFuncName,
+    },
+    settings::{self, Configurable},
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::{
+    ast::{BinaryOp, Location, Term, Var},
+    binary::Division,
+    interpreter::{Cancellation, Value},
+    symbol::Symbol,
+};
+
+/// Calls of a function after which it is compiled, if it can be.
+const HOT_CALLS: u32 = 100;
+
+/// How deep compiled functions may recurse before handing the call back
+/// to the interpreter, which grows its stack as needed.
+const MAX_DEPTH: i64 = 10_000;
+
+/// Stack space left below which a new stack segment is allocated before
+/// running compiled code, enough for [`MAX_DEPTH`] nested calls.
+const RED_ZONE: usize = 2 * 1024 * 1024;
+
+/// Size of the stack segments allocated for compiled code.
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Shared by the calls of a run of compiled code. `failed` is set when
+/// the run can't go on, like on overflows, and the call is then evaluated
+/// by the interpreter instead, which reports the error as usual.
+#[repr(C)]
+struct Guard {
+    failed: u8,
+    cancelled: *const bool,
+}
+
+/// `fn(arguments, guard, depth) -> result` of a compiled function.
+type Code = unsafe extern "C" fn(*const i64, *mut Guard, i64) -> i64;
+
+enum Entry {
+    Counting(u32),
+    Unsupported,
+    Compiled(Code),
+}
+
+/// The type of a term of a compiled function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Bool,
+}
+
+/// Compiles hot closures to native code with Cranelift.
+///
+/// Only functions of integers computing an integer are compiled: their
+/// bodies may use integer and boolean literals, arithmetic, comparisons,
+/// `if`, `let` and calls to themselves. Calls of any other function, or
+/// with arguments that aren't integers, are left to the interpreter.
+/// Compiled calls don't count steps, nor are they memoized.
+pub struct Jit {
+    module: JITModule,
+    context: Context,
+    builder: FunctionBuilderContext,
+
+    /// Functions by the location of their definition and the name they
+    /// call themselves by.
+    functions: HashMap<Location, Vec<(Option<String>, Entry)>>,
+}
+
+impl Debug for Jit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jit")
+            .field("functions", &self.functions.len())
+            .finish()
+    }
+}
+
+impl Jit {
+    /// Creates a new instance of [`Jit`] targeting the host, failing when
+    /// Cranelift doesn't support it.
+    pub fn new() -> Result<Self, String> {
+        let mut flags = settings::builder();
+        flags
+            .set("use_colocated_libcalls", "false")
+            .and_then(|()| flags.set("is_pic", "false"))
+            .and_then(|()| flags.set("opt_level", "speed"))
+            .map_err(|error| error.to_string())?;
+
+        let isa = cranelift_native::builder()
+            .map_err(|error| format!("the host is not supported by the JIT: {error}"))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|error| error.to_string())?;
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        Ok(Self {
+            context: module.make_context(),
+            module,
+            builder: FunctionBuilderContext::new(),
+            functions: HashMap::new(),
+        })
+    }
+
+    /// Runs a call of the function defined at `location` as native code,
+    /// once it is hot and compiled. Returns `None` when the interpreter
+    /// has to evaluate the call instead.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call(
+        &mut self,
+        name: Option<&str>,
+        parameters: &[Var],
+        body: &Term,
+        location: &Location,
+        arguments: &[Value],
+        division: Division,
+        cancellation: &Cancellation,
+    ) -> Option<Value> {
+        let functions = match self.functions.get_mut(location) {
+            Some(functions) => functions,
+            None => self.functions.entry(location.clone()).or_default(),
+        };
+        let index = match functions
+            .iter()
+            .position(|(function, _entry)| function.as_deref() == name)
+        {
+            Some(index) => index,
+            None => {
+                functions.push((name.map(String::from), Entry::Counting(0)));
+                functions.len() - 1
+            }
+        };
+
+        match &mut functions[index].1 {
+            Entry::Compiled(_code) => {}
+            Entry::Unsupported => return None,
+            Entry::Counting(calls) if *calls < HOT_CALLS => {
+                *calls += 1;
+                return None;
+            }
+            Entry::Counting(_calls) => {
+                let entry = match self.compile(name, parameters, body, division) {
+                    Some(code) => Entry::Compiled(code),
+                    None => Entry::Unsupported,
+                };
+                *self.entry(location, index) = entry;
+            }
+        }
+        let Entry::Compiled(code) = *self.entry(location, index) else {
+            return None;
+        };
+
+        let arguments = arguments
+            .iter()
+            .map(|argument| match argument {
+                Value::Int(int) => Some(*int),
+                _value => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if arguments.len() != parameters.len() {
+            return None;
+        }
+
+        let mut guard = Guard {
+            failed: 0,
+            cancelled: cancellation.as_ptr(),
+        };
+        let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || unsafe {
+            code(arguments.as_ptr(), &mut guard, 0)
+        });
+
+        // Failures are likely to repeat, and each of them would evaluate
+        // the call twice.
+        if guard.failed != 0 {
+            *self.entry(location, index) = Entry::Unsupported;
+
+            return None;
+        }
+
+        Some(Value::Int(result))
+    }
+
+    fn entry(&mut self, location: &Location, index: usize) -> &mut Entry {
+        let functions = self
+            .functions
+            .get_mut(location)
+            .expect("the function was called");
+
+        &mut functions[index].1
+    }
+
+    /// Compiles a function of integers computing an integer, if its body
+    /// only uses what compiled code supports.
+    fn compile(
+        &mut self,
+        name: Option<&str>,
+        parameters: &[Var],
+        body: &Term,
+        division: Division,
+    ) -> Option<Code> {
+        let mut scope = parameters
+            .iter()
+            .map(|parameter| (parameter.text, Kind::Int))
+            .collect();
+        if kind(body, &mut scope, name, parameters.len())? != Kind::Int {
+            return None;
+        }
+
+        let pointer = self.module.target_config().pointer_type();
+        let mut signature = self.module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I64));
+        signature.returns.push(AbiParam::new(types::I64));
+
+        let id = self.module.declare_anonymous_function(&signature).ok()?;
+
+        self.module.clear_context(&mut self.context);
+        self.context.func.signature = signature;
+        self.context.func.name = UserFuncName::user(0, id.as_u32());
+
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder);
+        let entry = builder.create_block();
+        let bail = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+
+        let [arguments, guard, depth] = builder.block_params(entry) else {
+            unreachable!("the signature takes three parameters")
+        };
+        let (arguments, guard, depth) = (*arguments, *guard, *depth);
+        let myself = self.module.declare_func_in_func(id, builder.func);
+
+        let mut lowering = Lowering {
+            builder,
+            scope: Vec::new(),
+            myself,
+            guard,
+            depth,
+            bail,
+            arity: parameters.len(),
+            division,
+        };
+
+        // Deep recursion and cancelled runs go back to the interpreter.
+        let too_deep =
+            lowering
+                .builder
+                .ins()
+                .icmp_imm(IntCC::SignedGreaterThanOrEqual, depth, MAX_DEPTH);
+        lowering.bail_if(too_deep);
+        let cancelled = lowering.builder.ins().load(
+            pointer,
+            MemFlags::trusted(),
+            guard,
+            offset_of!(Guard, cancelled) as i32,
+        );
+        let cancelled = lowering
+            .builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), cancelled, 0);
+        lowering.bail_if(cancelled);
+
+        for (index, parameter) in parameters.iter().enumerate() {
+            let argument = lowering.builder.ins().load(
+                types::I64,
+                MemFlags::trusted(),
+                arguments,
+                (index * 8) as i32,
+            );
+            lowering.scope.push((parameter.text, argument));
+        }
+
+        let result = lowering.term(body);
+        lowering.builder.ins().return_(&[result]);
+
+        let mut builder = lowering.builder;
+        builder.switch_to_block(bail);
+        let failed = builder.ins().iconst(types::I8, 1);
+        builder.ins().store(
+            MemFlags::trusted(),
+            failed,
+            guard,
+            offset_of!(Guard, failed) as i32,
+        );
+        let zero = builder.ins().iconst(types::I64, 0);
+        builder.ins().return_(&[zero]);
+
+        builder.seal_all_blocks();
+        builder.finalize();
+
+        self.module.define_function(id, &mut self.context).ok()?;
+        self.module.clear_context(&mut self.context);
+        self.module.finalize_definitions().ok()?;
+
+        let code = self.module.get_finalized_function(id);
+
+        Some(unsafe { std::mem::transmute::<*const u8, Code>(code) })
+    }
+}
+
+/// The type of `term` in a function named `name` taking `arity` integers,
+/// or `None` when it can't be compiled.
+fn kind(
+    term: &Term,
+    scope: &mut Vec<(Symbol, Kind)>,
+    name: Option<&str>,
+    arity: usize,
+) -> Option<Kind> {
+    match term {
+        Term::Int(_) => Some(Kind::Int),
+        Term::Bool(_) => Some(Kind::Bool),
+        Term::Var(var) => scope
+            .iter()
+            .rev()
+            .find(|(local, _kind)| *local == var.text)
+            .map(|(_local, kind)| *kind),
+        Term::Binary(binary) => {
+            let lhs = kind(&binary.lhs, scope, name, arity)?;
+            let rhs = kind(&binary.rhs, scope, name, arity)?;
+
+            match (&binary.op, lhs, rhs) {
+                (
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem,
+                    Kind::Int,
+                    Kind::Int,
+                ) => Some(Kind::Int),
+                (
+                    BinaryOp::Lt | BinaryOp::Lte | BinaryOp::Gt | BinaryOp::Gte,
+                    Kind::Int,
+                    Kind::Int,
+                ) => Some(Kind::Bool),
+                (BinaryOp::Eq | BinaryOp::Neq, lhs, rhs) if lhs == rhs => Some(Kind::Bool),
+                (BinaryOp::And | BinaryOp::Or, Kind::Bool, Kind::Bool) => Some(Kind::Bool),
+                _operation => None,
+            }
+        }
+        Term::If(if_) => {
+            let condition = kind(&if_.condition, scope, name, arity)?;
+            let then = kind(&if_.then, scope, name, arity)?;
+            let otherwise = kind(&if_.otherwise, scope, name, arity)?;
+
+            (condition == Kind::Bool && then == otherwise).then_some(then)
+        }
+        Term::Let(let_) => {
+            if let Term::Function(_) = *let_.value {
+                return None;
+            }
+
+            let value = kind(&let_.value, scope, name, arity)?;
+            scope.push((let_.name.text, value));
+            let next = kind(&let_.next, scope, name, arity);
+            scope.pop();
+
+            next
+        }
+        Term::Call(call) => {
+            let Term::Var(callee) = &*call.callee else {
+                return None;
+            };
+            let local = scope.iter().any(|(local, _kind)| *local == callee.text);
+            if local || name != Some(callee.text.as_str()) || call.arguments.len() != arity {
+                return None;
+            }
+
+            for argument in &call.arguments {
+                if kind(argument, scope, name, arity)? != Kind::Int {
+                    return None;
+                }
+            }
+
+            Some(Kind::Int)
+        }
+        _term => None,
+    }
+}
+
+/// Lowers the body of a function into Cranelift IR, once [`kind`] checked
+/// it can be compiled.
+struct Lowering<'a> {
+    builder: FunctionBuilder<'a>,
+    scope: Vec<(Symbol, cranelift_codegen::ir::Value)>,
+
+    /// The function itself, for recursive calls.
+    myself: FuncRef,
+    guard: cranelift_codegen::ir::Value,
+    depth: cranelift_codegen::ir::Value,
+
+    /// Marks the run as failed and returns.
+    bail: Block,
+    arity: usize,
+    division: Division,
+}
+
+impl Lowering<'_> {
+    /// Continues at [`Lowering::bail`] when `condition` holds.
+    fn bail_if(&mut self, condition: cranelift_codegen::ir::Value) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn term(&mut self, term: &Term) -> cranelift_codegen::ir::Value {
+        match term {
+            Term::Int(int) => self.builder.ins().iconst(types::I64, int.value),
+            Term::Bool(bool) => self.builder.ins().iconst(types::I8, i64::from(bool.value)),
+            Term::Var(var) => {
+                let (_name, value) = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(local, _value)| *local == var.text)
+                    .expect("variables are checked");
+
+                *value
+            }
+            Term::Let(let_) => {
+                let value = self.term(&let_.value);
+                self.scope.push((let_.name.text, value));
+                let next = self.term(&let_.next);
+                self.scope.pop();
+
+                next
+            }
+            Term::If(if_) => {
+                let condition = self.term(&if_.condition);
+                let then = self.builder.create_block();
+                let otherwise = self.builder.create_block();
+                let merge = self.builder.create_block();
+                self.builder
+                    .ins()
+                    .brif(condition, then, &[], otherwise, &[]);
+
+                self.builder.switch_to_block(then);
+                let value = self.term(&if_.then);
+                let kind = self.builder.func.dfg.value_type(value);
+                self.builder.append_block_param(merge, kind);
+                self.builder.ins().jump(merge, &[value]);
+
+                self.builder.switch_to_block(otherwise);
+                let value = self.term(&if_.otherwise);
+                self.builder.ins().jump(merge, &[value]);
+
+                self.builder.switch_to_block(merge);
+                self.builder.block_params(merge)[0]
+            }
+            Term::Call(call) => {
+                let arguments = call
+                    .arguments
+                    .iter()
+                    .map(|argument| self.term(argument))
+                    .collect::<Vec<_>>();
+
+                let pointer = match self.arity {
+                    0 => self.builder.ins().iconst(types::I64, 0),
+                    arity => {
+                        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+                            StackSlotKind::ExplicitSlot,
+                            (arity * 8) as u32,
+                            3,
+                        ));
+                        for (index, argument) in arguments.into_iter().enumerate() {
+                            self.builder
+                                .ins()
+                                .stack_store(argument, slot, (index * 8) as i32);
+                        }
+
+                        self.builder.ins().stack_addr(types::I64, slot, 0)
+                    }
+                };
+                let depth = self.builder.ins().iadd_imm(self.depth, 1);
+                let call = self
+                    .builder
+                    .ins()
+                    .call(self.myself, &[pointer, self.guard, depth]);
+                let result = self.builder.inst_results(call)[0];
+
+                let failed = self.builder.ins().load(
+                    types::I8,
+                    MemFlags::trusted(),
+                    self.guard,
+                    offset_of!(Guard, failed) as i32,
+                );
+                self.bail_if(failed);
+
+                result
+            }
+            Term::Binary(binary) => self.binary(&binary.op, &binary.lhs, &binary.rhs),
+            term => unreachable!("{term:?} is checked not to be compiled"),
+        }
+    }
+
+    fn binary(&mut self, op: &BinaryOp, lhs: &Term, rhs: &Term) -> cranelift_codegen::ir::Value {
+        let lhs = self.term(lhs);
+
+        // `&&` and `||` only evaluate the right-hand side when it can still
+        // change the result.
+        if let BinaryOp::And | BinaryOp::Or = op {
+            let evaluate = self.builder.create_block();
+            let merge = self.builder.create_block();
+            self.builder.append_block_param(merge, types::I8);
+
+            match op {
+                BinaryOp::And => self.builder.ins().brif(lhs, evaluate, &[], merge, &[lhs]),
+                _or => self.builder.ins().brif(lhs, merge, &[lhs], evaluate, &[]),
+            };
+
+            self.builder.switch_to_block(evaluate);
+            let rhs = self.term(rhs);
+            self.builder.ins().jump(merge, &[rhs]);
+
+            self.builder.switch_to_block(merge);
+            return self.builder.block_params(merge)[0];
+        }
+
+        let rhs = self.term(rhs);
+
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+                let (value, overflowed) = match op {
+                    BinaryOp::Add => self.builder.ins().sadd_overflow(lhs, rhs),
+                    BinaryOp::Sub => self.builder.ins().ssub_overflow(lhs, rhs),
+                    _mul => self.builder.ins().smul_overflow(lhs, rhs),
+                };
+                self.bail_if(overflowed);
+
+                value
+            }
+            BinaryOp::Div | BinaryOp::Rem => {
+                let by_zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+                self.bail_if(by_zero);
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, lhs, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let overflows = self.builder.ins().band(min, minus_one);
+                self.bail_if(overflows);
+
+                let quotient = self.builder.ins().sdiv(lhs, rhs);
+                let remainder = self.builder.ins().srem(lhs, rhs);
+
+                let (quotient, remainder) = match self.division {
+                    Division::Truncating => (quotient, remainder),
+                    Division::Flooring => {
+                        // Rounds down when the remainder and the divisor
+                        // have different signs.
+                        let inexact = self.builder.ins().icmp_imm(IntCC::NotEqual, remainder, 0);
+                        let signs = self.builder.ins().bxor(remainder, rhs);
+                        let different =
+                            self.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0);
+                        let adjust = self.builder.ins().band(inexact, different);
+
+                        let offset = self.builder.ins().uextend(types::I64, adjust);
+                        let floored = self.builder.ins().isub(quotient, offset);
+                        let shifted = self.builder.ins().iadd(remainder, rhs);
+                        let modulo = self.builder.ins().select(adjust, shifted, remainder);
+
+                        (floored, modulo)
+                    }
+                };
+
+                match op {
+                    BinaryOp::Div => quotient,
+                    _rem => remainder,
+                }
+            }
+            BinaryOp::Eq => self.builder.ins().icmp(IntCC::Equal, lhs, rhs),
+            BinaryOp::Neq => self.builder.ins().icmp(IntCC::NotEqual, lhs, rhs),
+            BinaryOp::Lt => self.builder.ins().icmp(IntCC::SignedLessThan, lhs, rhs),
+            BinaryOp::Lte => self
+                .builder
+                .ins()
+                .icmp(IntCC::SignedLessThanOrEqual, lhs, rhs),
+            BinaryOp::Gt => self.builder.ins().icmp(IntCC::SignedGreaterThan, lhs, rhs),
+            BinaryOp::Gte => self
+                .builder
+                .ins()
+                .icmp(IntCC::SignedGreaterThanOrEqual, lhs, rhs),
+            BinaryOp::And | BinaryOp::Or => unreachable!("short-circuits are lowered above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Jit, HOT_CALLS};
+    use crate::{
+        ast::{Binary, BinaryOp, Call, If, Int, Location, Term, Var},
+        binary::Division,
+        interpreter::{Cancellation, Value},
+    };
+
+    fn int(value: i64) -> Term {
+        Term::Int(Int {
+            value,
+            location: Location::default(),
+        })
+    }
+
+    fn var(text: &str) -> Var {
+        Var {
+            text: text.into(),
+            location: Location::default(),
+            slot: None,
+        }
+    }
+
+    fn binary(lhs: Term, op: BinaryOp, rhs: Term) -> Term {
+        Term::Binary(Binary {
+            lhs: Arc::new(lhs),
+            op,
+            rhs: Arc::new(rhs),
+            location: Location::default(),
+        })
+    }
+
+    /// `fn (n) => if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }`
+    fn fib() -> Term {
+        let fib = |n| {
+            Term::Call(Call {
+                callee: Arc::new(Term::Var(var("fib"))),
+                arguments: vec![binary(Term::Var(var("n")), BinaryOp::Sub, int(n))],
+                location: Location::default(),
+            })
+        };
+
+        Term::If(If {
+            condition: Arc::new(binary(Term::Var(var("n")), BinaryOp::Lt, int(2))),
+            then: Arc::new(Term::Var(var("n"))),
+            otherwise: Arc::new(binary(fib(1), BinaryOp::Add, fib(2))),
+            location: Location::default(),
+        })
+    }
+
+    fn call(jit: &mut Jit, name: &str, body: &Term, argument: Value) -> Option<Value> {
+        jit.call(
+            Some(name),
+            &[var("n")],
+            body,
+            &Location::default(),
+            &[argument],
+            Division::Truncating,
+            &Cancellation::new(),
+        )
+    }
+
+    #[test]
+    fn hot_functions_are_compiled() {
+        let mut jit = Jit::new().unwrap();
+        let body = fib();
+
+        for _call in 0..HOT_CALLS {
+            assert!(call(&mut jit, "fib", &body, Value::Int(10)).is_none());
+        }
+
+        let result = call(&mut jit, "fib", &body, Value::Int(30)).unwrap();
+        assert_eq!(result.to_string(), "832040");
+
+        assert!(call(&mut jit, "fib", &body, Value::Str("10".into())).is_none());
+    }
+
+    #[test]
+    fn unsupported_functions_are_interpreted() {
+        let mut jit = Jit::new().unwrap();
+        let body = Term::Print(crate::ast::Print {
+            value: Arc::new(Term::Var(var("n"))),
+            location: Location::default(),
+        });
+
+        for _call in 0..=HOT_CALLS {
+            assert!(call(&mut jit, "print", &body, Value::Int(1)).is_none());
+        }
+    }
+
+    #[test]
+    fn failures_fall_back_to_the_interpreter() {
+        let mut jit = Jit::new().unwrap();
+        let body = binary(int(1), BinaryOp::Div, Term::Var(var("n")));
+
+        for _call in 0..HOT_CALLS {
+            call(&mut jit, "inverse", &body, Value::Int(1));
+        }
+
+        assert!(call(&mut jit, "inverse", &body, Value::Int(0)).is_none());
+        assert!(call(&mut jit, "inverse", &body, Value::Int(1)).is_none());
+    }
+}
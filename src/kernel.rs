@@ -0,0 +1,524 @@
+use std::{process, thread};
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+
+use crate::{ast::Term, session::Session};
+
+/// Separates the routing identities from the message itself on the wire.
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+const PROTOCOL_VERSION: &str = "5.3";
+
+/// The connection file Jupyter writes for the kernel, naming the ports
+/// of every channel and the key messages are signed with.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Connection {
+    pub transport: String,
+    pub ip: String,
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    pub hb_port: u16,
+    pub key: String,
+    pub signature_scheme: String,
+}
+
+impl Connection {
+    fn endpoint(&self, port: u16) -> String {
+        format!("{}://{}:{}", self.transport, self.ip, port)
+    }
+}
+
+/// Signs and verifies messages with HMAC-SHA256. An empty key disables
+/// signing, as the protocol allows.
+#[derive(Debug, Clone)]
+pub struct Signer {
+    key: Option<Hmac<Sha256>>,
+}
+
+impl Signer {
+    /// Creates a new instance of [`Signer`] for the given scheme, only
+    /// `hmac-sha256` being supported.
+    pub fn new(scheme: &str, key: &str) -> Result<Self, String> {
+        if key.is_empty() {
+            return Ok(Self { key: None });
+        }
+
+        match scheme {
+            "hmac-sha256" => Ok(Self {
+                key: Some(Hmac::new_from_slice(key.as_bytes()).map_err(|error| error.to_string())?),
+            }),
+            scheme => Err(format!("unsupported signature scheme {scheme}")),
+        }
+    }
+
+    /// The hex encoded signature of the message parts.
+    pub fn sign(&self, parts: &[&[u8]]) -> String {
+        match &self.key {
+            Some(key) => {
+                let mut mac = key.clone();
+                for part in parts {
+                    mac.update(part);
+                }
+
+                hex::encode(mac.finalize().into_bytes())
+            }
+            None => String::new(),
+        }
+    }
+}
+
+/// A message of the Jupyter messaging protocol, with the identities of
+/// the socket it came from so replies can be routed back.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub identities: Vec<Vec<u8>>,
+    pub header: Json,
+    pub parent_header: Json,
+    pub metadata: Json,
+    pub content: Json,
+}
+
+impl Message {
+    /// Decodes the frames of a message received from a socket, checking
+    /// its signature.
+    pub fn decode(frames: Vec<Vec<u8>>, signer: &Signer) -> Result<Self, String> {
+        let delimiter = frames
+            .iter()
+            .position(|frame| frame == DELIMITER)
+            .ok_or("missing message delimiter")?;
+        let (identities, parts) = frames.split_at(delimiter);
+
+        let [_delimiter, signature, header, parent_header, metadata, content, ..] = parts else {
+            return Err(String::from("incomplete message"));
+        };
+
+        let expected = signer.sign(&[header, parent_header, metadata, content]);
+        if signature.as_slice() != expected.as_bytes() {
+            return Err(String::from("invalid message signature"));
+        }
+
+        let parse = |part: &[u8]| serde_json::from_slice::<Json>(part).map_err(|e| e.to_string());
+
+        Ok(Self {
+            identities: identities.to_vec(),
+            header: parse(header)?,
+            parent_header: parse(parent_header)?,
+            metadata: parse(metadata)?,
+            content: parse(content)?,
+        })
+    }
+
+    /// Encodes the message into signed frames, ready to be sent.
+    pub fn encode(&self, signer: &Signer) -> Vec<Vec<u8>> {
+        let header = self.header.to_string();
+        let parent_header = self.parent_header.to_string();
+        let metadata = self.metadata.to_string();
+        let content = self.content.to_string();
+
+        let signature = signer.sign(&[
+            header.as_bytes(),
+            parent_header.as_bytes(),
+            metadata.as_bytes(),
+            content.as_bytes(),
+        ]);
+
+        let mut frames = self.identities.clone();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.push(header.into_bytes());
+        frames.push(parent_header.into_bytes());
+        frames.push(metadata.into_bytes());
+        frames.push(content.into_bytes());
+
+        frames
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+}
+
+/// What answering a request produced: the reply to send back on the
+/// channel it came from, and the messages to publish on IOPub.
+#[derive(Debug)]
+pub struct Response {
+    pub reply: Option<Message>,
+    pub published: Vec<Message>,
+    pub shutdown: bool,
+}
+
+/// A Jupyter kernel evaluating each cell in a [`Session`], so bindings
+/// persist from one cell to the next.
+///
+/// Cells hold rinha JSON AST terms. The printed output is sent as a
+/// `stdout` stream and the value of the cell as its result.
+#[derive(Debug)]
+pub struct Kernel {
+    session: Session,
+    id: String,
+    execution_count: u64,
+    sent: u64,
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Self {
+            session: Session::new(),
+            id: format!("lipsum-{}", process::id()),
+            execution_count: 0,
+            sent: 0,
+        }
+    }
+}
+
+impl Kernel {
+    /// Creates a new instance of [`Kernel`], with a fresh session.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers a request received on the shell or control channel.
+    pub fn handle(&mut self, request: &Message) -> Response {
+        let mut published = vec![self.status(request, "busy")];
+        let mut shutdown = false;
+
+        let reply = match request.msg_type() {
+            "kernel_info_request" => Some(self.reply(request, "kernel_info_reply", kernel_info())),
+            "execute_request" => Some(self.execute(request, &mut published)),
+            "is_complete_request" => {
+                let code = request.content["code"].as_str().unwrap_or_default();
+                let status = match serde_json::from_str::<Term>(code) {
+                    Ok(_term) => "complete",
+                    Err(error) if error.is_eof() => "incomplete",
+                    Err(_error) => "invalid",
+                };
+
+                Some(self.reply(request, "is_complete_reply", json!({ "status": status })))
+            }
+            "shutdown_request" => {
+                shutdown = true;
+                let restart = request.content["restart"].clone();
+
+                Some(self.reply(
+                    request,
+                    "shutdown_reply",
+                    json!({ "status": "ok", "restart": restart }),
+                ))
+            }
+            // Requests the kernel doesn't know about are left unanswered,
+            // as the protocol recommends.
+            _msg_type => None,
+        };
+
+        published.push(self.status(request, "idle"));
+
+        Response {
+            reply,
+            published,
+            shutdown,
+        }
+    }
+
+    fn execute(&mut self, request: &Message, published: &mut Vec<Message>) -> Message {
+        let code = request.content["code"].as_str().unwrap_or_default();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+
+        if !silent {
+            self.execution_count += 1;
+        }
+        let execution_count = self.execution_count;
+
+        published.push(self.message(
+            request,
+            "execute_input",
+            json!({ "code": code, "execution_count": execution_count }),
+        ));
+
+        let result = serde_json::from_str::<Term>(code)
+            .map_err(|error| (String::from("SyntaxError"), format!("invalid AST: {error}")))
+            .and_then(|term| {
+                self.session
+                    .eval(term)
+                    .map_err(|error| (error.message, error.full_text))
+            });
+
+        let output = self.session.take_output();
+        if !output.is_empty() && !silent {
+            published.push(self.message(
+                request,
+                "stream",
+                json!({ "name": "stdout", "text": output }),
+            ));
+        }
+
+        match result {
+            Ok(value) => {
+                if !silent {
+                    published.push(self.message(
+                        request,
+                        "execute_result",
+                        json!({
+                            "execution_count": execution_count,
+                            "data": { "text/plain": value.to_string() },
+                            "metadata": {},
+                        }),
+                    ));
+                }
+
+                self.reply(
+                    request,
+                    "execute_reply",
+                    json!({
+                        "status": "ok",
+                        "execution_count": execution_count,
+                        "user_expressions": {},
+                    }),
+                )
+            }
+            Err((name, text)) => {
+                let error = json!({
+                    "ename": name,
+                    "evalue": text,
+                    "traceback": [text],
+                });
+                published.push(self.message(request, "error", error.clone()));
+
+                let mut content = error;
+                content["status"] = json!("error");
+                content["execution_count"] = json!(execution_count);
+
+                self.reply(request, "execute_reply", content)
+            }
+        }
+    }
+
+    fn status(&mut self, parent: &Message, state: &str) -> Message {
+        self.message(parent, "status", json!({ "execution_state": state }))
+    }
+
+    /// A message published on IOPub, using its type as the topic.
+    fn message(&mut self, parent: &Message, msg_type: &str, content: Json) -> Message {
+        let mut message = self.reply(parent, msg_type, content);
+        message.identities = vec![msg_type.as_bytes().to_vec()];
+
+        message
+    }
+
+    /// A message answering `parent`, routed back to its sender.
+    fn reply(&mut self, parent: &Message, msg_type: &str, content: Json) -> Message {
+        self.sent += 1;
+
+        Message {
+            identities: parent.identities.clone(),
+            header: json!({
+                "msg_id": format!("{}-{}", self.id, self.sent),
+                "session": self.id,
+                "username": "lipsum",
+                "msg_type": msg_type,
+                "version": PROTOCOL_VERSION,
+            }),
+            parent_header: parent.header.clone(),
+            metadata: json!({}),
+            content,
+        }
+    }
+}
+
+fn kernel_info() -> Json {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "lipsum",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "rinha",
+            "version": "1.0",
+            "mimetype": "application/json",
+            "file_extension": ".json",
+        },
+        "banner": "lipsum, a rinha interpreter",
+    })
+}
+
+/// Runs the kernel described by the connection file at `path`, as
+/// Jupyter starts it, until a shutdown is requested.
+pub fn serve(path: &str) -> Result<(), String> {
+    let connection = std::fs::read_to_string(path)
+        .map_err(|error| format!("failed to read the connection file {path}: {error}"))?;
+    let connection: Connection = serde_json::from_str(&connection)
+        .map_err(|error| format!("invalid connection file {path}: {error}"))?;
+    let signer = Signer::new(&connection.signature_scheme, &connection.key)?;
+
+    let context = zmq::Context::new();
+    let bind = |kind, port| -> Result<zmq::Socket, String> {
+        let socket = context.socket(kind).map_err(|error| error.to_string())?;
+        socket
+            .bind(&connection.endpoint(port))
+            .map_err(|error| format!("failed to bind port {port}: {error}"))?;
+
+        Ok(socket)
+    };
+
+    let shell = bind(zmq::ROUTER, connection.shell_port)?;
+    let control = bind(zmq::ROUTER, connection.control_port)?;
+    let iopub = bind(zmq::PUB, connection.iopub_port)?;
+    let _stdin = bind(zmq::ROUTER, connection.stdin_port)?;
+    let heartbeat = bind(zmq::REP, connection.hb_port)?;
+
+    // The heartbeat only echoes back what it receives, to show Jupyter the
+    // kernel is alive even while a cell is evaluating.
+    thread::spawn(move || {
+        while let Ok(ping) = heartbeat.recv_bytes(0) {
+            if heartbeat.send(ping, 0).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut kernel = Kernel::new();
+
+    loop {
+        let mut items = [
+            control.as_poll_item(zmq::POLLIN),
+            shell.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut items, -1).map_err(|error| error.to_string())?;
+
+        let readable = items
+            .iter()
+            .map(|item| item.is_readable())
+            .collect::<Vec<_>>();
+
+        for (socket, readable) in [&control, &shell].into_iter().zip(readable) {
+            if !readable {
+                continue;
+            }
+
+            let frames = socket
+                .recv_multipart(0)
+                .map_err(|error| error.to_string())?;
+
+            // Messages failing to decode, like the ones wrongly signed,
+            // are dropped without an answer.
+            let Ok(request) = Message::decode(frames, &signer) else {
+                continue;
+            };
+
+            let response = kernel.handle(&request);
+
+            for message in &response.published {
+                iopub
+                    .send_multipart(message.encode(&signer), 0)
+                    .map_err(|error| error.to_string())?;
+            }
+
+            if let Some(reply) = &response.reply {
+                socket
+                    .send_multipart(reply.encode(&signer), 0)
+                    .map_err(|error| error.to_string())?;
+            }
+
+            if response.shutdown {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Kernel, Message, Signer};
+
+    fn request(msg_type: &str, content: serde_json::Value) -> Message {
+        Message {
+            identities: vec![b"client".to_vec()],
+            header: json!({ "msg_id": "1", "msg_type": msg_type }),
+            parent_header: json!({}),
+            metadata: json!({}),
+            content,
+        }
+    }
+
+    fn published<'a>(messages: &'a [Message], msg_type: &str) -> Option<&'a Message> {
+        messages
+            .iter()
+            .find(|message| message.msg_type() == msg_type)
+    }
+
+    #[test]
+    fn messages_roundtrip_signed() {
+        let signer = Signer::new("hmac-sha256", "secret").unwrap();
+        let message = request("kernel_info_request", json!({}));
+
+        let frames = message.encode(&signer);
+        let decoded = Message::decode(frames.clone(), &signer).unwrap();
+        assert_eq!(decoded.identities, message.identities);
+        assert_eq!(decoded.header, message.header);
+
+        let other = Signer::new("hmac-sha256", "other").unwrap();
+        assert!(Message::decode(frames, &other).is_err());
+    }
+
+    #[test]
+    fn cells_share_bindings() {
+        let location = json!({ "start": 0, "end": 0, "filename": "cell" });
+        let int = json!({ "kind": "Int", "value": 1, "location": location });
+        let var = json!({ "kind": "Var", "text": "x", "location": location });
+        let define = json!({
+            "kind": "Let",
+            "name": { "text": "x", "location": location },
+            "value": int,
+            "next": var,
+            "location": location,
+        });
+        let print = json!({ "kind": "Print", "value": var, "location": location });
+
+        let mut kernel = Kernel::new();
+        let defined = kernel.handle(&request(
+            "execute_request",
+            json!({ "code": define.to_string() }),
+        ));
+        assert_eq!(defined.reply.unwrap().content["status"], "ok");
+
+        let printed = kernel.handle(&request(
+            "execute_request",
+            json!({ "code": print.to_string() }),
+        ));
+        let stream = published(&printed.published, "stream").unwrap();
+        assert_eq!(stream.content["text"], "1\n");
+
+        let reply = printed.reply.unwrap();
+        assert_eq!(reply.content["execution_count"], 2);
+        assert_eq!(reply.identities, vec![b"client".to_vec()]);
+    }
+
+    #[test]
+    fn unfinished_cells_are_incomplete() {
+        let mut kernel = Kernel::new();
+        let response = kernel.handle(&request(
+            "is_complete_request",
+            json!({ "code": r#"{ "kind": "Int","# }),
+        ));
+
+        assert_eq!(response.reply.unwrap().content["status"], "incomplete");
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut kernel = Kernel::new();
+        let response = kernel.handle(&request("execute_request", json!({ "code": "not an AST" })));
+
+        assert_eq!(response.reply.unwrap().content["status"], "error");
+        assert!(published(&response.published, "error").is_some());
+        assert_eq!(
+            response.published.last().unwrap().content["execution_state"],
+            "idle"
+        );
+    }
+}
@@ -1,3 +1,255 @@
 pub mod ast;
+#[cfg(feature = "async")]
+pub mod asynchronous;
 pub mod binary;
+pub mod binary_ast;
+pub mod builtins;
+pub mod bytecode;
+pub mod cache;
+pub mod collections;
+pub mod compiler;
+pub mod convert;
+pub mod daemon;
+pub mod debugger;
+pub mod determinism;
+pub mod diagnostics;
+pub mod digest;
+pub mod embed;
+mod encoding;
+pub mod equivalence;
+pub mod fold;
+pub mod free;
+pub mod generator;
+pub mod hashing;
+mod instrument;
 pub mod interpreter;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "kernel")]
+pub mod kernel;
+pub mod literate;
+pub mod load;
+pub mod observe;
+pub mod optimize;
+pub mod parser;
+pub mod pretty;
+pub mod progress;
+pub mod purity;
+pub mod register;
+pub mod resolve;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod snapshot;
+mod snippet;
+pub mod source_map;
+pub mod stats;
+pub mod symbol;
+pub mod text;
+pub mod trace;
+pub mod visit;
+pub mod vm;
+pub mod warnings;
+
+use std::fmt::Display;
+
+use crate::{
+    ast::{File, Location},
+    embed::Interpreter,
+    interpreter::{Printer, RuntimeError, Value},
+    parser::SyntaxError,
+    stats::Stats,
+};
+
+/// Why a program given as source didn't run to the end, from parsing it
+/// to evaluating it.
+#[derive(Debug, Clone)]
+pub enum Error {
+    Syntax(SyntaxError),
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    /// Where in the source the program went wrong.
+    pub fn location(&self) -> &Location {
+        match self {
+            Error::Syntax(error) => &error.location,
+            Error::Runtime(error) => &error.location,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Syntax(error) => write!(f, "syntax error: {error}"),
+            Error::Runtime(error) => {
+                let Location {
+                    start,
+                    end,
+                    filename,
+                } = &error.location;
+
+                write!(
+                    f,
+                    "{}: {} at {filename}:{start}..{end}",
+                    error.message, error.full_text
+                )?;
+
+                for frame in error.backtrace.iter().rev() {
+                    let Location {
+                        start,
+                        end,
+                        filename,
+                    } = &frame.location;
+                    let name = frame.name.as_deref().unwrap_or("an anonymous function");
+                    write!(f, "\n  in {name}, called at {filename}:{start}..{end}")?;
+                    if frame.repeated > 0 {
+                        write!(f, "\n  … {} more frames of {name}", frame.repeated)?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<SyntaxError> for Error {
+    fn from(error: SyntaxError) -> Self {
+        Error::Syntax(error)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Error::Runtime(error)
+    }
+}
+
+/// Parses and runs the source of a program, as [`run_file`] runs its AST.
+/// `filename` is only used in the locations of the AST and the errors.
+///
+/// ```
+/// use lipsum::interpreter::Capture;
+///
+/// let mut capture = Capture::new();
+/// lipsum::eval_source("print(1 + 2)", "main.rinha", &mut capture).unwrap();
+/// assert_eq!(capture.output(), "3\n");
+///
+/// let error = lipsum::eval_source("1 +", "main.rinha", &mut capture).unwrap_err();
+/// assert_eq!(
+///     error.to_string(),
+///     "syntax error: expected a term, found the end of the program at main.rinha:3..3"
+/// );
+/// ```
+pub fn eval_source(
+    source: &str,
+    filename: &str,
+    printer: &mut impl Printer,
+) -> Result<Value, Error> {
+    let file = parser::parse(source, filename)?;
+
+    Ok(run_file(file, printer)?)
+}
+
+/// Runs the program of `file` with the builtins in scope, printing with
+/// `printer`, like `lipsum run` does. See [`Interpreter`] to set limits or
+/// grant capabilities.
+///
+/// ```
+/// use lipsum::{ast::File, interpreter::Capture};
+///
+/// let file: File = serde_json::from_str(include_str!("../examples/fib.json")).unwrap();
+/// let mut capture = Capture::new();
+///
+/// lipsum::run_file(file, &mut capture).unwrap();
+/// assert_eq!(capture.output(), "55\n");
+/// ```
+pub fn run_file(file: File, printer: &mut impl Printer) -> Result<Value, RuntimeError> {
+    run_file_with_stats(file, printer).0
+}
+
+/// Runs the program of `file` like [`run_file`], along with the
+/// statistics of the run, whether it succeeded or not.
+pub fn run_file_with_stats(
+    file: File,
+    printer: &mut impl Printer,
+) -> (Result<Value, RuntimeError>, Stats) {
+    let mut interpreter = Interpreter::builder().printer(printer).build();
+    let result = interpreter.run(file);
+
+    (result, std::mem::take(&mut interpreter.state_mut().stats))
+}
+
+/// Runs the program of `file` like [`run_file`], printing with an async
+/// `printer`, without blocking the executor awaiting it. See
+/// [`Builder::run_async`](embed::Builder::run_async) to set limits or
+/// grant capabilities.
+#[cfg(feature = "async")]
+pub async fn eval_async(
+    file: File,
+    printer: impl asynchronous::AsyncPrinter,
+) -> Result<Value, RuntimeError> {
+    Interpreter::builder().run_async(file, printer).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::File,
+        builtins, compiler,
+        interpreter::{Capture, Context, State},
+        parser::parse_term,
+        vm,
+    };
+
+    #[test]
+    fn runtime_errors_of_sources_point_at_them() {
+        let source = "let f = fn (x) => { x + true };\nf(1)";
+        let error = super::eval_source(source, "main.rinha", &mut Capture::new()).unwrap_err();
+
+        let location = error.location();
+        assert!(source[location.start..].starts_with("x + true"));
+        assert!(matches!(error, super::Error::Runtime(_)));
+    }
+
+    #[test]
+    fn runtime_errors_keep_the_calls_they_happened_in() {
+        let source = "let inner = fn (x) => { x + true };\n\
+                      let outer = fn (x) => { let y = inner(x); y };\n\
+                      let _ = outer(1); 0";
+        let error = super::eval_source(source, "main.rinha", &mut Capture::new()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid addition: 1 cannot be added to true at main.rinha:24..25\n  \
+             in inner, called at main.rinha:68..76\n  \
+             in outer, called at main.rinha:91..99"
+        );
+
+        // The vm keeps the same calls.
+        let program = parse_term(source, "main.rinha").unwrap();
+        let mut context = Context::new();
+        builtins::install(&mut context);
+        let compiled = compiler::compile(program);
+        let vm_error = vm::run(&compiled, &context, &mut State::new(), &mut Capture::new());
+        let super::Error::Runtime(error) = error else {
+            unreachable!("the source is valid")
+        };
+        assert_eq!(vm_error.unwrap_err().backtrace, error.backtrace);
+    }
+
+    #[test]
+    fn files_run_with_their_stats() {
+        let file: File = serde_json::from_str(include_str!("../examples/fib.json")).unwrap();
+        let mut capture = Capture::new();
+
+        let (value, stats) = super::run_file_with_stats(file, &mut capture);
+        assert_eq!(value.unwrap().to_string(), "55");
+        assert_eq!(capture.output(), "55\n");
+        assert!(stats.calls > 0);
+    }
+}
@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+use crate::{ast::Term, session::Session};
+
+/// A fenced code block of a markdown document.
+#[derive(Debug, Clone)]
+struct Fenced {
+    /// The first word of the info string, like `rinha` in ```` ```rinha ````.
+    language: String,
+    content: String,
+
+    /// Line of the opening fence, starting from 1.
+    line: usize,
+
+    /// Byte range of the block, fences included.
+    span: Range<usize>,
+}
+
+/// A `rinha` code block and the output printed while evaluating it.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// Line of the opening fence, starting from 1.
+    pub line: usize,
+    pub output: String,
+}
+
+/// The result of running a literate program: the output of every block
+/// and the document with the outputs inlined.
+#[derive(Debug, Clone)]
+pub struct Evaluated {
+    pub blocks: Vec<Block>,
+    pub document: String,
+}
+
+/// Evaluates the fenced `rinha` code blocks of a markdown document in
+/// order, sharing one session so bindings carry over between blocks.
+///
+/// Blocks hold rinha JSON AST terms. The inlined document has the output
+/// of each block in an ```` ```output ```` block right after it,
+/// replacing the one from a previous run, so running it again is stable.
+pub fn run(markdown: &str) -> Result<Evaluated, String> {
+    let fenced = fenced(markdown);
+    let mut session = Session::new();
+    let mut blocks = Vec::new();
+    let mut document = String::new();
+    let mut copied = 0;
+
+    for (index, block) in fenced.iter().enumerate() {
+        if block.language != "rinha" {
+            continue;
+        }
+
+        let term = serde_json::from_str::<Term>(&block.content)
+            .map_err(|error| format!("block at line {}: invalid AST: {error}", block.line))?;
+        session.eval(term).map_err(|error| {
+            format!(
+                "block at line {}: {}: {}",
+                block.line, error.message, error.full_text
+            )
+        })?;
+        let output = session.take_output();
+
+        document.push_str(&markdown[copied..block.span.end]);
+        copied = block.span.end;
+
+        if !document.ends_with('\n') {
+            document.push('\n');
+        }
+
+        // The output of a previous run is replaced, along with the blank
+        // lines separating it from the block.
+        if let Some(previous) = fenced.get(index + 1) {
+            let between = &markdown[block.span.end..previous.span.start];
+            if previous.language == "output" && between.trim().is_empty() {
+                copied = previous.span.end;
+            }
+        }
+
+        if !output.is_empty() {
+            document.push_str("\n```output\n");
+            document.push_str(&output);
+            if !output.ends_with('\n') {
+                document.push('\n');
+            }
+            document.push_str("```\n");
+        }
+
+        blocks.push(Block {
+            line: block.line,
+            output,
+        });
+    }
+
+    document.push_str(&markdown[copied..]);
+
+    Ok(Evaluated { blocks, document })
+}
+
+/// The fence opening or closing a code block, like ```` ``` ```` or
+/// `~~~`, with the info string following it.
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let line = line.trim_start();
+    let marker = line
+        .chars()
+        .next()
+        .filter(|char| matches!(char, '`' | '~'))?;
+    let length = line.chars().take_while(|char| *char == marker).count();
+
+    match length >= 3 {
+        true => Some((marker, length, line[length..].trim())),
+        false => None,
+    }
+}
+
+fn fenced(markdown: &str) -> Vec<Fenced> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(char, usize, Fenced)> = None;
+    let mut offset = 0;
+
+    for (index, line) in markdown.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+
+        match (&mut open, fence(line)) {
+            (None, Some((marker, length, info))) => {
+                let block = Fenced {
+                    language: info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    content: String::new(),
+                    line: index + 1,
+                    span: start..offset,
+                };
+                open = Some((marker, length, block));
+            }
+            (Some((marker, length, _block)), Some((closing, closing_length, "")))
+                if closing == *marker && closing_length >= *length =>
+            {
+                let (_marker, _length, mut block) = open.take().expect("a block is open");
+                block.span.end = offset;
+                blocks.push(block);
+            }
+            (Some((_marker, _length, block)), _fence) => block.content.push_str(line),
+            (None, None) => {}
+        }
+    }
+
+    // A block left open runs until the end of the document.
+    if let Some((_marker, _length, mut block)) = open {
+        block.span.end = markdown.len();
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+
+    const LOCATION: &str = r#"{ "start": 0, "end": 0, "filename": "notes.md" }"#;
+
+    fn let_(name: &str, value: i64) -> String {
+        format!(
+            r#"{{ "kind": "Let", "location": {LOCATION},
+                 "name": {{ "text": "{name}", "location": {LOCATION} }},
+                 "value": {{ "kind": "Int", "value": {value}, "location": {LOCATION} }},
+                 "next": {{ "kind": "Int", "value": 0, "location": {LOCATION} }} }}"#
+        )
+    }
+
+    fn print(name: &str) -> String {
+        format!(
+            r#"{{ "kind": "Print", "location": {LOCATION},
+                 "value": {{ "kind": "Var", "text": "{name}", "location": {LOCATION} }} }}"#
+        )
+    }
+
+    #[test]
+    fn blocks_share_bindings() {
+        let markdown = format!(
+            "# Notes\n\n```rinha\n{}\n```\n\n```json\n{{}}\n```\n\n```rinha\n{}\n```\n",
+            let_("x", 7),
+            print("x")
+        );
+        let evaluated = run(&markdown).unwrap();
+
+        assert_eq!(evaluated.blocks.len(), 2);
+        assert_eq!(evaluated.blocks[0].output, "");
+        assert_eq!(evaluated.blocks[1].output, "7\n");
+        assert!(evaluated.document.ends_with("```\n\n```output\n7\n```\n"));
+    }
+
+    #[test]
+    fn inlining_is_stable() {
+        let markdown = format!("```rinha\n{}\n```\nThe end.\n", print("typeof"));
+        let once = run(&markdown).unwrap().document;
+        let twice = run(&once).unwrap().document;
+
+        assert!(once.contains("```output\n[closure]\n```\nThe end."));
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn errors_point_at_the_block() {
+        let markdown = format!("Intro\n\n```rinha\n{}\n```\n", print("missing"));
+        let error = run(&markdown).unwrap_err();
+
+        assert!(error.starts_with("block at line 3:"));
+    }
+}
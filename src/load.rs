@@ -0,0 +1,85 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::{ast::File, binary_ast};
+
+/// How many bytes are read between two calls to the progress callback.
+const REPORT_EVERY: u64 = 1 << 20;
+
+/// Reads the AST of a file from `reader` as it comes, rather than once
+/// all of it is in memory, from its JSON or its binary encoding written by
+/// [`File::to_binary`]. Names and filenames are interned as they are read,
+/// so each is allocated once however many times the AST repeats it.
+///
+/// `progress` is called with the number of bytes read so far every
+/// megabyte, and once the whole file is read.
+pub fn from_reader(reader: impl Read, progress: impl FnMut(u64)) -> Result<File, String> {
+    let mut reader = BufReader::new(Counting {
+        reader,
+        read: 0,
+        reported: 0,
+        progress,
+    });
+
+    let binary = reader
+        .fill_buf()
+        .map_err(|error| error.to_string())?
+        .starts_with(binary_ast::MAGIC);
+    let file = match binary {
+        true => File::from_reader_binary(&mut reader)?,
+        false => serde_json::from_reader(&mut reader).map_err(|error| error.to_string())?,
+    };
+
+    let mut counting = reader.into_inner();
+    (counting.progress)(counting.read);
+
+    Ok(file)
+}
+
+/// A reader counting the bytes read through it, reporting them every
+/// [`REPORT_EVERY`] bytes.
+struct Counting<R, F> {
+    reader: R,
+    read: u64,
+    reported: u64,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(u64)> Read for Counting<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.read += read as u64;
+
+        if self.read - self.reported >= REPORT_EVERY {
+            self.reported = self.read;
+            (self.progress)(self.read);
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_reader;
+    use crate::ast::{Element, File, Term};
+
+    #[test]
+    fn files_are_loaded_as_they_are_read() {
+        let json = include_str!("../examples/fib.json");
+
+        let mut reports = Vec::new();
+        let file = from_reader(json.as_bytes(), |read| reports.push(read)).unwrap();
+        assert_eq!(reports, [json.len() as u64]);
+
+        let parsed: File = serde_json::from_str(json).unwrap();
+        assert_eq!(file.expression, parsed.expression);
+
+        let Term::Let(let_) = &file.expression else {
+            unreachable!("the program starts with a let")
+        };
+        assert_eq!(let_.location.filename, let_.value.location().filename);
+
+        let binary = from_reader(file.to_binary().as_slice(), |_| {}).unwrap();
+        assert_eq!(binary.expression, parsed.expression);
+    }
+}
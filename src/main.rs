@@ -1,36 +1,725 @@
-use clap::Parser;
+use std::{
+    io::{BufRead, BufReader, IsTerminal, Read},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
 use lipsum::{
-    ast::File,
-    interpreter::{eval, Cache, Context, IO},
+    ast::{File, Term},
+    binary::Division,
+    builtins::{self, Capability},
+    bytecode,
+    cache::DiskCache,
+    compiler::{self, Program},
+    daemon::Daemon,
+    determinism,
+    diagnostics::Diagnostics,
+    digest,
+    equivalence::{self, Verdict},
+    free,
+    interpreter::{
+        eval, Capture, Context, Flush, Memoization, Printer, RuntimeError, State,
+        DEFAULT_MAX_DEPTH, IO,
+    },
+    literate, load,
+    optimize::optimize_reporting,
+    parser,
+    progress::{Progress, Report},
+    purity, register, resolve, schema,
+    source_map::SourceMap,
+    trace::Trace,
+    vm, Error,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Command {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+
+    /// The program to run: its JSON AST, its binary encoding, its
+    /// bytecode or, ending in `.rinha`, its source
     #[arg(short, long)]
     file: Option<String>,
+
+    /// Print per function call and memoization statistics to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Write every printed value out immediately, for interactive programs
+    #[arg(long)]
+    unbuffered: bool,
+
+    /// Allow the program to read and write files
+    #[arg(long)]
+    allow_fs: bool,
+
+    /// Allow the program to read environment variables
+    #[arg(long)]
+    allow_env: bool,
+
+    /// Trace to stderr the calls of the given functions or made from the
+    /// given source ranges, like `fib,main.rinha:120-250`. An empty list
+    /// traces every call
+    #[arg(long, value_name = "FILTERS")]
+    trace_calls: Option<String>,
+
+    /// Warn on stderr about suspicious bindings, like a `let` shadowing
+    /// another binding or never used, and integer overflows, once the
+    /// program ends
+    #[arg(long)]
+    warn: bool,
+
+    /// Report the progress of the run on stderr every given milliseconds
+    #[arg(long, value_name = "MILLIS", num_args = 0..=1, default_missing_value = "1000")]
+    progress: Option<u64>,
+
+    /// How `/` and `%` round for operands of different signs: towards zero
+    /// (`truncating`) or towards negative infinity (`flooring`)
+    #[arg(long, default_value_t = Division::Truncating)]
+    division: Division,
+
+    /// Run the program twice and fail if the runs differ in their result,
+    /// output or stats
+    #[arg(long)]
+    verify_determinism: bool,
+
+    /// How deep evaluations may nest, like non-tail recursive calls,
+    /// before failing. Zero means unlimited
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// How deep calls may nest before failing, where calls in tail position
+    /// don't nest. Unlimited by default
+    #[arg(long, value_name = "CALLS")]
+    max_call_depth: Option<usize>,
+
+    /// How many evaluation steps the program may take before failing.
+    /// Unlimited by default
+    #[arg(long, value_name = "STEPS")]
+    fuel: Option<u64>,
+
+    /// Approximate bytes the memoization cache and any value the program
+    /// builds may take before failing. Unlimited by default
+    #[arg(long, value_name = "BYTES")]
+    max_memory: Option<usize>,
+
+    /// Keep the results of memoized calls in DIR, so later runs of the same
+    /// program reuse them. Only for deterministic programs
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Stop memoizing the functions whose calls are rarely cached or cheap
+    /// to evaluate, once a hundred of their calls were looked up
+    #[arg(long)]
+    adaptive_memoization: bool,
+
+    /// How the program is run: walking its syntax tree (`tree`) or compiled
+    /// to bytecode for a stack machine (`vm`) or a register machine
+    /// (`register`), which neither memoize nor trace calls
+    #[arg(long, value_enum, default_value_t = Backend::Tree)]
+    backend: Backend,
+
+    /// Compile hot functions of integers to native code, when running on
+    /// the tree backend
+    #[cfg(feature = "jit")]
+    #[arg(long)]
+    jit: bool,
+
+    /// Fold constant expressions, resolve constant conditions and drop
+    /// unused bindings before running the program
+    #[arg(long)]
+    optimize: bool,
+
+    /// Size in bytes of the output buffer
+    #[arg(long, default_value_t = IO::DEFAULT_CAPACITY)]
+    buffer_size: usize,
+
+    /// Arguments given to the program as `args`, after `--`
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Tree,
+    Vm,
+    Register,
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no backend is skipped");
+
+        f.pad(value.get_name())
+    }
+}
+
+/// A program to run: its syntax tree, or its bytecode when it was
+/// compiled ahead of time.
+enum Loaded {
+    Tree(Term),
+    Compiled(Program),
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Evaluate in long-running sessions driven by JSON-RPC over stdio
+    Daemon,
+
+    /// Compile a program to bytecode, to be run later without parsing and
+    /// compiling it again
+    Compile {
+        path: String,
+
+        /// Where the bytecode is written
+        #[arg(short, long)]
+        output: String,
+    },
+
+    /// Encode the AST of a program in a compact binary format, read faster
+    /// than its JSON wherever a program is expected
+    Encode {
+        path: String,
+
+        /// Where the encoded AST is written
+        #[arg(short, long)]
+        output: String,
+    },
+
+    /// Print the AST of a program as rinha source
+    Source { path: String },
+
+    /// Check heuristically whether two programs are equivalent
+    Equiv { left: String, right: String },
+
+    /// Time programs on each of the given backends, to compare them
+    Bench {
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// The backends the programs run on
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_values_t = [Backend::Vm, Backend::Register]
+        )]
+        backends: Vec<Backend>,
+
+        /// How many times each program runs on each backend
+        #[arg(long, default_value_t = 5)]
+        runs: usize,
+    },
+
+    /// Evaluate the fenced rinha code blocks of a markdown file in order
+    RunMd {
+        path: String,
+
+        /// Rewrite the file with the output of each block inlined after it
+        #[arg(long)]
+        write: bool,
+    },
+
+    /// Run as a Jupyter kernel, using the given connection file
+    #[cfg(feature = "kernel")]
+    Kernel {
+        #[arg(long)]
+        connection_file: String,
+    },
+
+    /// Serve an HTTP API evaluating the programs posted to /run
+    #[cfg(feature = "server")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+
+        /// Share one memoization cache between all runs, so a run reuses
+        /// the results of calls made by the others
+        #[arg(long)]
+        share_cache: bool,
+
+        /// Number of requests evaluated at once, the others waiting for
+        /// their turn
+        #[arg(long, default_value_t = lipsum::server::default_workers())]
+        workers: std::num::NonZeroUsize,
+    },
 }
 
-static DEFAULT_PATH: &'static str = "/var/rinha/source.rinha.json";
+static DEFAULT_PATH: &str = "/var/rinha/source.rinha.json";
+
+/// Exit status of a run stopped by a signal, as shells report SIGINT.
+const INTERRUPTED: i32 = 130;
+
+/// Exit status of a run that failed with a runtime error.
+const FAILED: i32 = 1;
 
 fn main() -> Result<(), String> {
     let command = Command::parse();
+
+    match &command.subcommand {
+        #[cfg(feature = "kernel")]
+        Some(Subcommand::Kernel { connection_file }) => lipsum::kernel::serve(connection_file),
+        #[cfg(feature = "server")]
+        Some(Subcommand::Serve {
+            address,
+            share_cache,
+            workers,
+        }) => lipsum::server::serve(
+            address,
+            share_cache.then(lipsum::cache::SharedCache::new),
+            *workers,
+        ),
+        Some(Subcommand::Daemon) => Daemon::new()
+            .serve(std::io::stdin().lock(), std::io::stdout().lock())
+            .map_err(|error| error.to_string()),
+        Some(Subcommand::Compile { path, output }) => compile(path, output),
+        Some(Subcommand::Encode { path, output }) => encode(path, output),
+        Some(Subcommand::Source { path }) => source(path),
+        Some(Subcommand::Equiv { left, right }) => equiv(left, right),
+        Some(Subcommand::Bench {
+            paths,
+            backends,
+            runs,
+        }) => bench(paths, backends, *runs),
+        Some(Subcommand::RunMd { path, write }) => run_markdown(path, *write),
+        _ => run(command),
+    }
+}
+
+/// Renders a progress report as a status line, rewritten in place when
+/// stderr is a terminal.
+fn status_line(report: &Report) {
+    let location = &report.location;
+    let status = format!(
+        "{} steps, {:.0} steps/s, {:.1?} elapsed, at {}:{}..{}",
+        report.steps,
+        report.steps_per_second,
+        report.elapsed,
+        location.filename,
+        location.start,
+        location.end
+    );
+
+    match std::io::stderr().is_terminal() {
+        true => eprint!("\r\x1b[K{status}"),
+        false => eprintln!("{status}"),
+    }
+}
+
+/// Renders how much of the program was loaded as a status line, like
+/// [`status_line`].
+fn loading_line(read: u64, size: u64) {
+    let status = format!("loaded {} of {} KiB", read / 1024, size / 1024);
+
+    match std::io::stderr().is_terminal() {
+        true => eprint!("\r\x1b[K{status}"),
+        false => eprintln!("{status}"),
+    }
+}
+
+fn read_file(path: &str) -> Result<File, String> {
+    if is_source(path) {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+
+        return parse_source(path, &source);
+    }
+
+    let file = std::fs::File::open(path)
+        .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+
+    load::from_reader(file, |_| {}).map_err(|error| invalid_ast(path, error))
+}
+
+/// Whether `path` holds the source of a program rather than its AST.
+fn is_source(path: &str) -> bool {
+    path.ends_with(".rinha")
+}
+
+fn parse_source(path: &str, source: &str) -> Result<File, String> {
+    parser::parse(source, path).map_err(|error| format!("syntax error: {error}"))
+}
+
+/// Explains why the AST in `path` couldn't be read, pointing at the term
+/// out of shape when the file is valid JSON.
+fn invalid_ast(path: &str, error: String) -> String {
+    let diagnosed = std::fs::read(path)
+        .ok()
+        .and_then(|json| schema::diagnose(&json));
+
+    match diagnosed {
+        Some(malformed) => format!("invalid AST in {path}: {malformed}"),
+        None => format!("invalid AST in {path}: {error}"),
+    }
+}
+
+fn compile(path: &str, output: &str) -> Result<(), String> {
+    let program = compiler::compile(read_file(path)?.expression);
+
+    std::fs::write(output, program.serialize())
+        .map_err(|error| format!("failed to write file at {output}: {error}"))
+}
+
+fn encode(path: &str, output: &str) -> Result<(), String> {
+    std::fs::write(output, read_file(path)?.to_binary())
+        .map_err(|error| format!("failed to write file at {output}: {error}"))
+}
+
+fn source(path: &str) -> Result<(), String> {
+    println!("{}", read_file(path)?.expression.to_source());
+
+    Ok(())
+}
+
+fn equiv(left: &str, right: &str) -> Result<(), String> {
+    let verdict = equivalence::check(read_file(left)?.expression, read_file(right)?.expression);
+    println!("{verdict}");
+
+    if let Verdict::Different { .. } = verdict {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Runs every program `runs` times on each of the `backends`, printing
+/// the median and fastest of its runs and the steps they took. The
+/// backends take turns running a program, so a machine getting busier
+/// slows them alike. Fails unless they all print the same.
+fn bench(paths: &[String], backends: &[Backend], runs: usize) -> Result<(), String> {
+    println!(
+        "{:<32} {:<10} {:>12} {:>12} {:>12}",
+        "program", "backend", "median", "fastest", "steps"
+    );
+
+    for path in paths {
+        let mut entrypoint = read_file(path)?.expression;
+        resolve::resolve(&mut entrypoint);
+        free::annotate(&mut entrypoint);
+        digest::annotate(&mut entrypoint);
+        purity::annotate(&mut entrypoint);
+        let program = compiler::compile(entrypoint.clone());
+
+        let mut times = vec![Vec::with_capacity(runs); backends.len()];
+        let mut steps = vec![0; backends.len()];
+        let mut outputs = vec![String::new(); backends.len()];
+        for _ in 0..runs {
+            for (index, backend) in backends.iter().enumerate() {
+                let mut context = Context::new();
+                builtins::install(&mut context);
+                let mut state = State::new();
+                state.max_depth = Some(DEFAULT_MAX_DEPTH);
+                let mut capture = Capture::new();
+
+                let started = Instant::now();
+                let result = match backend {
+                    Backend::Tree => {
+                        eval(entrypoint.clone(), &mut context, &mut state, &mut capture)
+                    }
+                    Backend::Vm => vm::run(&program, &context, &mut state, &mut capture),
+                    Backend::Register => {
+                        register::run(&program, &context, &mut state, &mut capture)
+                    }
+                };
+                times[index].push(started.elapsed());
+
+                if let Err(error) = result {
+                    return Err(format!(
+                        "{path} failed on the {backend} backend: {}",
+                        Error::Runtime(error)
+                    ));
+                }
+                steps[index] = state.stats.steps;
+                outputs[index] = capture.take();
+            }
+        }
+
+        if let Some(index) = outputs.iter().position(|output| *output != outputs[0]) {
+            return Err(format!(
+                "{path} printed differently on the {} and {} backends",
+                backends[0], backends[index]
+            ));
+        }
+
+        for ((backend, times), steps) in backends.iter().zip(&mut times).zip(steps) {
+            times.sort();
+            let median = times.get(times.len() / 2).copied().unwrap_or_default();
+            let fastest = times.first().copied().unwrap_or_default();
+
+            println!("{path:<32} {backend:<10} {median:>12.1?} {fastest:>12.1?} {steps:>12}");
+        }
+    }
+
+    Ok(())
+}
+
+fn run_markdown(path: &str, write: bool) -> Result<(), String> {
+    let markdown = std::fs::read_to_string(path)
+        .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+    let evaluated = literate::run(&markdown).map_err(|error| format!("{path}: {error}"))?;
+
+    match write {
+        true => std::fs::write(path, evaluated.document)
+            .map_err(|error| format!("failed to write file at {path}: {error}")),
+        false => {
+            for block in evaluated.blocks {
+                print!("{}", block.output);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), String> {
     let path = match command.file {
         Some(path) => path,
         None => DEFAULT_PATH.to_string(),
     };
 
-    let file = std::fs::read_to_string(&path).expect(&format!("failed to read file at {}", &path));
+    let file = std::fs::File::open(&path)
+        .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut file = BufReader::new(file);
+    let compiled = file
+        .fill_buf()
+        .map_err(|error| format!("failed to read file at {path}: {error}"))?
+        .starts_with(bytecode::MAGIC);
+
+    // Programs compiled ahead of time by `compile` run on the vm as they
+    // are, without parsing and compiling their AST again.
+    let mut diagnostics = Diagnostics::new();
+    let mut sources = SourceMap::new();
+    let program = match compiled {
+        true => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)
+                .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+
+            Loaded::Compiled(
+                Program::deserialize(&bytes)
+                    .map_err(|error| format!("invalid program in {path}: {error}"))?,
+            )
+        }
+        false => {
+            let parsed_file = match is_source(&path) {
+                true => {
+                    let mut source = String::new();
+                    file.read_to_string(&mut source)
+                        .map_err(|error| format!("failed to read file at {path}: {error}"))?;
+                    let parsed_file = parse_source(&path, &source)?;
+                    sources.add(&path, source);
+
+                    parsed_file
+                }
+                false => {
+                    let parsed_file = load::from_reader(file, |read| {
+                        if command.progress.is_some() {
+                            loading_line(read, size);
+                        }
+                    })
+                    .map_err(|error| invalid_ast(&path, error))?;
+                    if command.progress.is_some() && std::io::stderr().is_terminal() {
+                        eprint!("\r\x1b[K");
+                    }
+
+                    parsed_file
+                }
+            };
+
+            let mut entrypoint = match command.optimize {
+                true => {
+                    optimize_reporting(parsed_file.expression, command.division, &mut diagnostics)
+                }
+                false => parsed_file.expression,
+            };
+            resolve::resolve(&mut entrypoint);
+            free::annotate(&mut entrypoint);
+            digest::annotate(&mut entrypoint);
+            purity::annotate(&mut entrypoint);
+
+            Loaded::Tree(entrypoint)
+        }
+    };
+    let backend = match program {
+        Loaded::Tree(_) => command.backend,
+        Loaded::Compiled(_) if command.backend == Backend::Register => Backend::Register,
+        Loaded::Compiled(_) => Backend::Vm,
+    };
+
+    let prepare = |context: &mut Context, state: &mut State| {
+        builtins::install(context);
+        builtins::define_args(context, command.args.clone());
+        if command.allow_fs {
+            builtins::grant(context, Capability::FileSystem);
+        }
+        if command.allow_env {
+            builtins::grant(context, Capability::Environment);
+        }
+        state.division = command.division;
+        state.max_depth = (command.max_depth > 0).then_some(command.max_depth);
+        state.max_call_depth = command.max_call_depth;
+        state.fuel = command.fuel;
+        state.max_memory = command.max_memory;
+        if command.adaptive_memoization {
+            state.memoization = Memoization::adaptive();
+        }
+    };
+
+    if backend != Backend::Tree
+        && (command.trace_calls.is_some()
+            || command.verify_determinism
+            || command.cache_dir.is_some())
+    {
+        return Err(String::from(
+            "--trace-calls, --verify-determinism and --cache-dir need the tree backend",
+        ));
+    }
+    if command.verify_determinism && command.cache_dir.is_some() {
+        return Err(String::from(
+            "--verify-determinism can't be combined with --cache-dir",
+        ));
+    }
 
-    let parsed_file: File = serde_json::from_str(&file).unwrap();
+    if command.verify_determinism {
+        let Loaded::Tree(entrypoint) = program else {
+            unreachable!("compiled programs run on the vm")
+        };
+        let outcome = determinism::verify(entrypoint, prepare)?;
+        print!("{}", outcome.output);
 
-    let entrypoint = Box::new(parsed_file.expression);
+        return outcome.result.map(|_value| ());
+    }
 
     let mut context = Context::new();
-    let mut cache = Cache::new();
-    let mut io = IO {};
-    let _ = eval(entrypoint, &mut context, &mut cache, &mut io).unwrap();
+    let mut state = State::new();
+    prepare(&mut context, &mut state);
+    if let (Some(directory), Loaded::Tree(entrypoint)) = (&command.cache_dir, &program) {
+        let cache = DiskCache::open(directory, digest::of(entrypoint), command.division)
+            .map_err(|error| format!("failed to open the cache: {error}"))?;
+        state.cache = Box::new(cache);
+    }
+    if let Some(filters) = &command.trace_calls {
+        let filters = Trace::parse_filters(filters)?;
+        state.trace = Some(Trace::new(filters, std::io::stderr()));
+    }
+    #[cfg(feature = "jit")]
+    if command.jit {
+        state.jit = Some(lipsum::jit::Jit::new()?);
+    }
+    if command.warn {
+        state.diagnostics = Some(diagnostics);
+    }
+    if let Some(millis) = command.progress {
+        state.progress = Some(Progress::new(Duration::from_millis(millis), status_line));
+    }
+    let flush = match command.unbuffered {
+        true => Flush::EachPrint,
+        false => Flush::Buffered,
+    };
+    let mut io = IO::with_capacity(flush, command.buffer_size);
+
+    // On SIGINT/SIGTERM evaluation stops at the next step, so the output
+    // can still be flushed and summarized. A second signal exits at once.
+    let cancellation = state.cancellation.clone();
+    ctrlc::set_handler(move || match cancellation.is_cancelled() {
+        true => std::process::exit(INTERRUPTED),
+        false => cancellation.cancel(),
+    })
+    .map_err(|error| format!("failed to install the signal handler: {error}"))?;
+
+    // Output buffered so far is written out even when evaluation fails.
+    let result = match program {
+        Loaded::Tree(entrypoint) if backend == Backend::Tree => {
+            eval(entrypoint, &mut context, &mut state, &mut io)
+        }
+        Loaded::Tree(entrypoint) => {
+            let program = compiler::compile(entrypoint);
+            match backend {
+                Backend::Register => register::run(&program, &context, &mut state, &mut io),
+                _vm => vm::run(&program, &context, &mut state, &mut io),
+            }
+        }
+        Loaded::Compiled(program) if backend == Backend::Register => {
+            register::run(&program, &context, &mut state, &mut io)
+        }
+        Loaded::Compiled(program) => vm::run(&program, &context, &mut state, &mut io),
+    };
+    let flushed = io.flush();
+    state
+        .cache
+        .flush()
+        .map_err(|error| format!("failed to write the cache: {error}"))?;
+
+    if state.progress.is_some() && std::io::stderr().is_terminal() {
+        eprint!("\r\x1b[K");
+    }
+
+    // The reader of the output went away, like when piping into `head`:
+    // stop quietly as standard Unix tools do.
+    if io.is_closed() {
+        return Ok(());
+    }
+
+    flushed.map_err(|error| format!("failed to write the output: {error}"))?;
+
+    for diagnostic in state
+        .diagnostics
+        .as_mut()
+        .map(Diagnostics::take)
+        .unwrap_or_default()
+    {
+        eprintln!("warning: {diagnostic}");
+    }
+
+    let result = match result {
+        Err(error) if state.cancellation.is_cancelled() => {
+            let location = error.location;
+            eprintln!(
+                "interrupted after {} steps, while evaluating {}:{}..{}",
+                state.stats.steps, location.filename, location.start, location.end
+            );
+
+            if command.stats {
+                eprint!("{}", state.stats);
+            }
+
+            std::process::exit(INTERRUPTED);
+        }
+        result => result,
+    };
+
+    if command.stats {
+        eprint!("{}", state.stats);
+    }
+
+    if let Err(error) = result {
+        eprint!("{}", report(error, &path, &mut sources));
+        std::process::exit(FAILED);
+    }
 
     Ok(())
 }
+
+/// Renders `error` under the line of source it happened at, as compilers
+/// do. Programs given as an AST refer to the file they were parsed from,
+/// looked for next to the program at `path` then where the run started;
+/// when it can't be found, the error is rendered with its byte offsets.
+fn report(error: RuntimeError, path: &str, sources: &mut SourceMap) -> String {
+    let filename = error.location.filename.as_str();
+    if sources.file(filename).is_none() {
+        let beside = Path::new(path).with_file_name(filename);
+        let source = std::fs::read_to_string(beside).or_else(|_| std::fs::read_to_string(filename));
+        if let Ok(source) = source {
+            sources.add(filename, source);
+        }
+    }
+
+    match sources.render(&error) {
+        Some(rendered) => rendered,
+        None => format!("error[{}]: {}\n", error.kind.code(), Error::Runtime(error)),
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Debug;
+
+use crate::{
+    ast::Term,
+    interpreter::{Context, RuntimeError, Value},
+};
+
+/// Observes the evaluation of every term by [`eval`](crate::interpreter::eval),
+/// for tracers, profilers or coverage tools built outside of the crate.
+/// Set it as the [`observer`](crate::interpreter::State::observer) of the
+/// state of a run.
+///
+/// Every term entered is exited once, terms nested in it exiting first.
+/// Terms in tail position, like the branches of an `if` or the bodies of
+/// calls, are entered at the depth of the term they are the tail of, and
+/// exit with it, innermost first, with the value of the last of them.
+///
+/// ```
+/// use lipsum::{
+///     ast::{File, Term},
+///     embed::Interpreter,
+///     interpreter::{Capture, Context},
+///     observe::EvalObserver,
+/// };
+///
+/// /// The deepest nesting of terms reached.
+/// struct Deepest(usize);
+///
+/// impl EvalObserver for Deepest {
+///     fn on_enter(&mut self, _term: &Term, depth: usize, _context: &Context) {
+///         self.0 = self.0.max(depth);
+///     }
+/// }
+///
+/// let file: File = serde_json::from_str(include_str!("../examples/sum.json")).unwrap();
+/// let mut interpreter = Interpreter::builder()
+///     .printer(Capture::new())
+///     .observer(Deepest(0))
+///     .build();
+///
+/// interpreter.run(file).unwrap();
+/// ```
+pub trait EvalObserver: Send {
+    /// Called before `term` is evaluated in `context`, `depth` terms deep.
+    fn on_enter(&mut self, _term: &Term, _depth: usize, _context: &Context) {}
+
+    /// Called once `term`, entered `depth` terms deep, evaluated to
+    /// `result`.
+    fn on_exit(&mut self, _term: &Term, _depth: usize, _result: Result<&Value, &RuntimeError>) {}
+}
+
+impl Debug for dyn EvalObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EvalObserver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::EvalObserver;
+    use crate::{
+        ast::{Element, Term},
+        embed::Interpreter,
+        interpreter::{Capture, Context, RuntimeError, Value},
+        parser::parse,
+    };
+
+    /// The terms entered and exited, as the source they were parsed from.
+    #[derive(Clone, Default)]
+    struct Events {
+        source: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Events {
+        fn text(&self, term: &Term) -> &'static str {
+            let location = term.location();
+
+            &self.source[location.start..location.end]
+        }
+    }
+
+    impl EvalObserver for Events {
+        fn on_enter(&mut self, term: &Term, depth: usize, _context: &Context) {
+            let event = format!("{depth} > {}", self.text(term));
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_exit(&mut self, term: &Term, depth: usize, result: Result<&Value, &RuntimeError>) {
+            let result = match result {
+                Ok(value) => value.to_string(),
+                Err(error) => error.message.clone(),
+            };
+            let event = format!("{depth} < {} = {result}", self.text(term));
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn terms_are_observed_entering_and_exiting() {
+        let source = "let x = 1 + 2; if (x == 3) { x } else { y }";
+        let events = Events {
+            source,
+            ..Events::default()
+        };
+
+        let mut interpreter = Interpreter::builder()
+            .printer(Capture::new())
+            .observer(events.clone())
+            .build();
+        interpreter.run(parse(source, "tests").unwrap()).unwrap();
+
+        assert_eq!(
+            *events.events.lock().unwrap(),
+            [
+                format!("1 > {source}"),
+                "2 > 1 + 2".to_string(),
+                "3 > 1".to_string(),
+                "3 < 1 = 1".to_string(),
+                "3 > 2".to_string(),
+                "3 < 2 = 2".to_string(),
+                "2 < 1 + 2 = 3".to_string(),
+                "1 > if (x == 3) { x } else { y }".to_string(),
+                "2 > x == 3".to_string(),
+                "3 > x".to_string(),
+                "3 < x = 3".to_string(),
+                "3 > 3".to_string(),
+                "3 < 3 = 3".to_string(),
+                "2 < x == 3 = true".to_string(),
+                "1 > x".to_string(),
+                "1 < x = 3".to_string(),
+                "1 < if (x == 3) { x } else { y } = 3".to_string(),
+                format!("1 < {source} = 3"),
+            ]
+        );
+    }
+}
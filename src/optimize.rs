@@ -0,0 +1,675 @@
+use std::{mem::take, sync::Arc};
+
+use crate::{
+    ast::{
+        Arm, Binary, BinaryOp, Bool, Call, Cond, Element, First, Function, If, Int, Let, Print,
+        Second, Seq, Str, Term, Tuple, Unit,
+    },
+    binary::Division,
+    diagnostics::Diagnostics,
+    interpreter::Value,
+    purity,
+    symbol::Symbol,
+};
+
+/// Simplifies `term` ahead of evaluation: binary operations over literals
+/// are folded into their result, conditions known ahead of time are
+/// resolved to the branch they take, small functions bound by `let` are
+/// inlined where they are called, and bindings never referenced are
+/// dropped when their value has no effects and can't fail. Folding follows the
+/// interpreter, with `division` rounding `/` and `%`, so the result of the
+/// program doesn't change, only the steps taken to get there.
+pub fn optimize(term: Term, division: Division) -> Term {
+    Optimizer {
+        division,
+        scopes: Vec::new(),
+        diagnostics: None,
+    }
+    .term(term)
+}
+
+/// Optimizes `term` as [`optimize`] does, reporting the bindings never
+/// referred to in `diagnostics`, whether their value is dropped or not.
+pub fn optimize_reporting(term: Term, division: Division, diagnostics: &mut Diagnostics) -> Term {
+    Optimizer {
+        division,
+        scopes: Vec::new(),
+        diagnostics: Some(diagnostics),
+    }
+    .term(term)
+}
+
+/// Functions bound by `let` whose body has at most this many terms are
+/// inlined where they are called.
+const INLINE_SIZE: usize = 16;
+
+struct Optimizer<'a> {
+    division: Division,
+
+    /// Bound names, innermost last, with the function bound to them when
+    /// its calls can be inlined.
+    scopes: Vec<(Symbol, Option<Arc<Function>>)>,
+    diagnostics: Option<&'a mut Diagnostics>,
+}
+
+impl Optimizer<'_> {
+    fn shared(&mut self, term: Term) -> Arc<Term> {
+        Arc::new(self.term(term))
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        match &mut term {
+            Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Var(_) => term,
+            Term::Seq(seq) => {
+                let seq = take(seq);
+
+                Term::Seq(Seq {
+                    terms: seq.terms.into_iter().map(|term| self.term(term)).collect(),
+                    location: seq.location,
+                })
+            }
+            Term::Call(call) => {
+                let call = take(call);
+
+                let callee = self.term(Arc::unwrap_or_clone(call.callee));
+                let arguments = call
+                    .arguments
+                    .into_iter()
+                    .map(|argument| self.term(argument))
+                    .collect::<Vec<_>>();
+
+                match self.inlined(&callee, &arguments) {
+                    // The arguments are bound in order before the body, as
+                    // the call would evaluate them.
+                    Some(function) => function.parameters.iter().zip(arguments).rev().fold(
+                        Term::clone(&function.value),
+                        |next, (parameter, argument)| {
+                            Term::Let(Let {
+                                name: parameter.clone(),
+                                value: Arc::new(argument),
+                                next: Arc::new(next),
+                                location: call.location.clone(),
+                            })
+                        },
+                    ),
+                    None => Term::Call(Call {
+                        callee: Arc::new(callee),
+                        arguments,
+                        location: call.location,
+                    }),
+                }
+            }
+            Term::Binary(binary) => {
+                let binary = take(binary);
+
+                fold(
+                    Binary {
+                        lhs: self.shared(Arc::unwrap_or_clone(binary.lhs)),
+                        op: binary.op,
+                        rhs: self.shared(Arc::unwrap_or_clone(binary.rhs)),
+                        location: binary.location,
+                    },
+                    self.division,
+                )
+            }
+            Term::Function(function) => {
+                let function = take(function);
+
+                let scopes = self.scopes.len();
+                self.scopes.extend(
+                    function
+                        .parameters
+                        .iter()
+                        .map(|parameter| (parameter.text, None)),
+                );
+                let value = self.shared(Arc::unwrap_or_clone(function.value));
+                self.scopes.truncate(scopes);
+
+                Term::Function(Function {
+                    parameters: function.parameters,
+                    value,
+                    location: function.location,
+                    free: None,
+                    layout: None,
+                    digest: None,
+                    pure: None,
+                })
+            }
+            Term::Let(let_) => {
+                let let_ = take(let_);
+
+                // Functions refer to themselves by the name they are bound to.
+                let name = let_.name.text;
+                if let Some(diagnostics) = &mut self.diagnostics {
+                    // Before inlining, which leaves the functions inlined
+                    // unreferenced.
+                    if !name.as_str().starts_with('_') && !references(&let_.next, name) {
+                        diagnostics.unused_binding(name.as_str(), &let_.name.location);
+                    }
+                }
+                let scopes = self.scopes.len();
+                self.scopes.push((name, None));
+                let value = self.term(Arc::unwrap_or_clone(let_.value));
+                self.scopes.truncate(scopes);
+
+                let inlinable = match &value {
+                    Term::Function(function) if is_inlinable(function, name) => {
+                        Some(Arc::new(function.clone()))
+                    }
+                    _value => None,
+                };
+                self.scopes.push((name, inlinable));
+                let next = self.term(Arc::unwrap_or_clone(let_.next));
+                self.scopes.truncate(scopes);
+
+                match self.is_discardable(&value) && !references(&next, let_.name.text) {
+                    true => next,
+                    false => Term::Let(Let {
+                        name: let_.name,
+                        value: Arc::new(value),
+                        next: Arc::new(next),
+                        location: let_.location,
+                    }),
+                }
+            }
+            Term::If(if_) => {
+                let if_ = take(if_);
+
+                match self.term(Arc::unwrap_or_clone(if_.condition)) {
+                    Term::Bool(Bool { value: true, .. }) => {
+                        self.term(Arc::unwrap_or_clone(if_.then))
+                    }
+                    Term::Bool(Bool { value: false, .. }) => {
+                        self.term(Arc::unwrap_or_clone(if_.otherwise))
+                    }
+                    condition => Term::If(If {
+                        condition: Arc::new(condition),
+                        then: self.shared(Arc::unwrap_or_clone(if_.then)),
+                        otherwise: self.shared(Arc::unwrap_or_clone(if_.otherwise)),
+                        location: if_.location,
+                    }),
+                }
+            }
+            Term::Cond(cond) => {
+                let cond = take(cond);
+
+                // Arms known to never be taken are dropped, and the first one
+                // known to be taken ends the chain.
+                let mut arms = Vec::new();
+                let mut otherwise = None;
+                for arm in cond.arms {
+                    match self.term(arm.condition) {
+                        Term::Bool(Bool { value: false, .. }) => {}
+                        Term::Bool(Bool { value: true, .. }) => {
+                            otherwise = Some(self.term(arm.then));
+                            break;
+                        }
+                        condition => arms.push(Arm {
+                            condition,
+                            then: self.term(arm.then),
+                        }),
+                    }
+                }
+                let otherwise =
+                    otherwise.unwrap_or_else(|| self.term(Arc::unwrap_or_clone(cond.otherwise)));
+
+                match arms.is_empty() {
+                    true => otherwise,
+                    false => Term::Cond(Cond {
+                        arms,
+                        otherwise: Arc::new(otherwise),
+                        location: cond.location,
+                    }),
+                }
+            }
+            Term::Print(print) => {
+                let print = take(print);
+
+                Term::Print(Print {
+                    value: self.shared(Arc::unwrap_or_clone(print.value)),
+                    location: print.location,
+                })
+            }
+            Term::First(first) => {
+                let first = take(first);
+
+                Term::First(First {
+                    value: self.shared(Arc::unwrap_or_clone(first.value)),
+                    location: first.location,
+                })
+            }
+            Term::Second(second) => {
+                let second = take(second);
+
+                Term::Second(Second {
+                    value: self.shared(Arc::unwrap_or_clone(second.value)),
+                    location: second.location,
+                })
+            }
+            Term::Tuple(tuple) => {
+                let tuple = take(tuple);
+
+                Term::Tuple(Tuple {
+                    first: self.shared(Arc::unwrap_or_clone(tuple.first)),
+                    second: self.shared(Arc::unwrap_or_clone(tuple.second)),
+                    location: tuple.location,
+                })
+            }
+        }
+    }
+
+    /// Whether evaluating `term` has no effect besides its value and can't
+    /// fail, so it can be skipped when the value isn't used. Calls, which
+    /// may print through natives or never return, and printing are kept,
+    /// and so are failures, like dividing by zero, adding a boolean or
+    /// referring to a name that isn't bound, which the program must still
+    /// report.
+    fn is_discardable(&mut self, term: &Term) -> bool {
+        match term {
+            Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Function(_) => true,
+            Term::Var(var) => self.scopes.iter().any(|(name, _)| *name == var.text),
+            // Operations over anything but literals may get operands they
+            // can't take, and those left over literals fail or overflow.
+            Term::Binary(binary) => match (literal(&binary.lhs), literal(&binary.rhs)) {
+                (Some(lhs), Some(rhs)) => lhs
+                    .operate(&binary.op, &rhs, self.division, &binary.location)
+                    .is_ok(),
+                _operands => false,
+            },
+            Term::Let(let_) => {
+                if !self.is_discardable(&let_.value) {
+                    return false;
+                }
+
+                self.scopes.push((let_.name.text, None));
+                let discardable = self.is_discardable(&let_.next);
+                self.scopes.pop();
+
+                discardable
+            }
+            Term::Seq(seq) => seq.terms.iter().all(|term| self.is_discardable(term)),
+            Term::Tuple(tuple) => {
+                self.is_discardable(&tuple.first) && self.is_discardable(&tuple.second)
+            }
+            // Conditions that aren't booleans and projections of values
+            // that aren't tuples fail.
+            Term::If(_)
+            | Term::Cond(_)
+            | Term::First(_)
+            | Term::Second(_)
+            | Term::Call(_)
+            | Term::Print(_) => false,
+        }
+    }
+
+    /// The function a call of `callee` with `arguments` can be replaced
+    /// by. It must take as many parameters as there are arguments, as
+    /// other calls fail at runtime, and still see the same bindings at the
+    /// call as where it was defined. No argument may refer to its own
+    /// parameter or one bound before it, as the parameters are bound one
+    /// by one.
+    fn inlined(&self, callee: &Term, arguments: &[Term]) -> Option<Arc<Function>> {
+        let Term::Var(var) = callee else {
+            return None;
+        };
+
+        let index = self
+            .scopes
+            .iter()
+            .rposition(|(name, _)| *name == var.text)?;
+        let function = self.scopes[index].1.clone()?;
+        if function.parameters.len() != arguments.len() {
+            return None;
+        }
+
+        let shadowed = self.scopes[index + 1..]
+            .iter()
+            .any(|(name, _function)| captures(&function, *name));
+        let captured = arguments.iter().enumerate().any(|(at, argument)| {
+            function.parameters[..=at]
+                .iter()
+                .any(|parameter| references(argument, parameter.text))
+        });
+
+        match !shadowed && !captured {
+            true => Some(function),
+            false => None,
+        }
+    }
+}
+
+/// Whether calls of `function`, bound to `name`, can be inlined: its body
+/// is small, pure and doesn't call the function itself.
+fn is_inlinable(function: &Function, name: Symbol) -> bool {
+    purity::of(&function.value) && size(&function.value) <= INLINE_SIZE && !captures(function, name)
+}
+
+/// The number of terms in `term`.
+fn size(term: &Term) -> usize {
+    let children = match term {
+        Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Var(_) => 0,
+        Term::Function(function) => size(&function.value),
+        Term::Let(let_) => size(&let_.value) + size(&let_.next),
+        Term::Call(call) => size(&call.callee) + call.arguments.iter().map(size).sum::<usize>(),
+        Term::Binary(binary) => size(&binary.lhs) + size(&binary.rhs),
+        Term::If(if_) => size(&if_.condition) + size(&if_.then) + size(&if_.otherwise),
+        Term::Cond(cond) => {
+            cond.arms
+                .iter()
+                .map(|arm| size(&arm.condition) + size(&arm.then))
+                .sum::<usize>()
+                + size(&cond.otherwise)
+        }
+        Term::Seq(seq) => seq.terms.iter().map(size).sum(),
+        Term::Print(print) => size(&print.value),
+        Term::First(first) => size(&first.value),
+        Term::Second(second) => size(&second.value),
+        Term::Tuple(tuple) => size(&tuple.first) + size(&tuple.second),
+    };
+
+    1 + children
+}
+
+/// Folds a binary operation over literals into its result, located where
+/// the operation was, as the interpreter would evaluate it. Operations that
+/// fail, like dividing by zero, are left for the runtime to report.
+pub fn fold(binary: Binary, division: Division) -> Term {
+    // `&&` and `||` don't evaluate the right-hand side when the left-hand
+    // side decides the result, whatever it is.
+    match (&binary.op, &*binary.lhs) {
+        (BinaryOp::And, Term::Bool(Bool { value: false, .. }))
+        | (BinaryOp::Or, Term::Bool(Bool { value: true, .. })) => {
+            return Arc::unwrap_or_clone(binary.lhs)
+        }
+        (_op, _lhs) => {}
+    }
+
+    let (Some(lhs), Some(rhs)) = (literal(&binary.lhs), literal(&binary.rhs)) else {
+        return Term::Binary(binary);
+    };
+
+    // Overflows are left for the runtime to report as well.
+    if lhs.overflows(&binary.op, &rhs) {
+        return Term::Binary(binary);
+    }
+
+    let location = binary.location.clone();
+    match lhs.operate(&binary.op, &rhs, division, binary.lhs.location()) {
+        Ok(Value::Int(value)) => Term::Int(Int { value, location }),
+        Ok(Value::Str(value)) => Term::Str(Str {
+            value: value.to_string(),
+            location,
+        }),
+        Ok(Value::Bool(value)) => Term::Bool(Bool { value, location }),
+        Ok(Value::Unit) => Term::Unit(Unit { location }),
+        _result => Term::Binary(binary),
+    }
+}
+
+/// Whether `name` occurs free in `term`, that is, not shadowed by a `let`
+/// or a parameter of the same name.
+fn references(term: &Term, name: Symbol) -> bool {
+    match term {
+        Term::Var(var) => var.text == name,
+        Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) => false,
+        Term::Function(function) => captures(function, name),
+        Term::Let(let_) => {
+            let_.name.text != name
+                && (references(&let_.value, name) || references(&let_.next, name))
+        }
+        Term::Call(call) => {
+            references(&call.callee, name)
+                || call
+                    .arguments
+                    .iter()
+                    .any(|argument| references(argument, name))
+        }
+        Term::Binary(binary) => references(&binary.lhs, name) || references(&binary.rhs, name),
+        Term::If(if_) => {
+            references(&if_.condition, name)
+                || references(&if_.then, name)
+                || references(&if_.otherwise, name)
+        }
+        Term::Cond(cond) => {
+            cond.arms
+                .iter()
+                .any(|arm| references(&arm.condition, name) || references(&arm.then, name))
+                || references(&cond.otherwise, name)
+        }
+        Term::Seq(seq) => seq.terms.iter().any(|term| references(term, name)),
+        Term::Print(print) => references(&print.value, name),
+        Term::First(first) => references(&first.value, name),
+        Term::Second(second) => references(&second.value, name),
+        Term::Tuple(tuple) => references(&tuple.first, name) || references(&tuple.second, name),
+    }
+}
+
+/// Whether `name` occurs free in the body of `function`.
+fn captures(function: &Function, name: Symbol) -> bool {
+    function
+        .parameters
+        .iter()
+        .all(|parameter| parameter.text != name)
+        && references(&function.value, name)
+}
+
+/// The value of a literal term.
+fn literal(term: &Term) -> Option<Value> {
+    match term {
+        Term::Int(int) => Some(Value::Int(int.value)),
+        Term::Str(str) => Some(Value::Str(str.value.as_str().into())),
+        Term::Bool(bool) => Some(Value::Bool(bool.value)),
+        Term::Unit(_) => Some(Value::Unit),
+        _term => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::{ast::Term, binary::Division};
+
+    fn term(json: &str) -> Term {
+        let location = r#"{ "start": 0, "end": 9, "filename": "tests" }"#;
+
+        serde_json::from_str(&json.replace("LOC", location)).unwrap()
+    }
+
+    fn int(value: i64) -> String {
+        format!(r#"{{ "kind": "Int", "value": {value}, "location": LOC }}"#)
+    }
+
+    fn var(name: &str) -> String {
+        format!(r#"{{ "kind": "Var", "text": "{name}", "location": LOC }}"#)
+    }
+
+    fn binary(lhs: &str, op: &str, rhs: &str) -> String {
+        format!(
+            r#"{{ "kind": "Binary", "lhs": {lhs}, "op": "{op}", "rhs": {rhs}, "location": LOC }}"#
+        )
+    }
+
+    fn if_(condition: &str, then: &str, otherwise: &str) -> String {
+        format!(
+            r#"{{ "kind": "If", "condition": {condition}, "then": {then},
+                 "otherwise": {otherwise}, "location": LOC }}"#
+        )
+    }
+
+    #[test]
+    fn literals_are_folded_in_place() {
+        // x * (-7 / 2)
+        let folded = optimize(
+            term(&binary(&var("x"), "Mul", &binary(&int(-7), "Div", &int(2)))),
+            Division::Flooring,
+        );
+
+        assert_eq!(folded, term(&binary(&var("x"), "Mul", &int(-4))));
+    }
+
+    #[test]
+    fn constant_conditions_take_their_branch() {
+        // if (1 < 2) { x } else { 1 / 0 }
+        let taken = optimize(
+            term(&if_(
+                &binary(&int(1), "Lt", &int(2)),
+                &var("x"),
+                &binary(&int(1), "Div", &int(0)),
+            )),
+            Division::Truncating,
+        );
+
+        assert_eq!(taken, term(&var("x")));
+    }
+
+    fn let_(name: &str, value: &str, next: &str) -> String {
+        format!(
+            r#"{{ "kind": "Let", "name": {{ "text": "{name}", "location": LOC }},
+                 "value": {value}, "next": {next}, "location": LOC }}"#
+        )
+    }
+
+    fn call(callee: &str, arguments: &[&str]) -> String {
+        let arguments = arguments.join(", ");
+
+        format!(
+            r#"{{ "kind": "Call", "callee": {callee}, "arguments": [{arguments}], "location": LOC }}"#
+        )
+    }
+
+    fn function(parameter: &str, value: &str) -> String {
+        format!(
+            r#"{{ "kind": "Function", "parameters": [{{ "text": "{parameter}", "location": LOC }}],
+                 "value": {value}, "location": LOC }}"#
+        )
+    }
+
+    #[test]
+    fn unused_bindings_without_effects_are_dropped() {
+        // let unused = fn (x) => x + 1; let used = 2; let called = f(); used
+        let program = let_(
+            "unused",
+            &function("x", &binary(&var("x"), "Add", &int(1))),
+            &let_(
+                "used",
+                &int(2),
+                &let_("called", &call(&var("f"), &[]), &var("used")),
+            ),
+        );
+
+        assert_eq!(
+            optimize(term(&program), Division::Truncating),
+            term(&let_(
+                "used",
+                &int(2),
+                &let_("called", &call(&var("f"), &[]), &var("used"))
+            ))
+        );
+    }
+
+    #[test]
+    fn unused_bindings_that_may_fail_are_kept() {
+        // let unused = 1 / 0; 1
+        let division = let_("unused", &binary(&int(1), "Div", &int(0)), &int(1));
+        // let unused = x + 1; 1
+        let operation = let_("unused", &binary(&var("x"), "Add", &int(1)), &int(1));
+        // let unused = nope; 1
+        let unbound = let_("unused", &var("nope"), &int(1));
+
+        for program in [division, operation, unbound] {
+            assert_eq!(
+                optimize(term(&program), Division::Truncating),
+                term(&program)
+            );
+        }
+
+        // let x = 1; let unused = (x, "a"); x
+        let bound = let_(
+            "x",
+            &int(1),
+            &let_(
+                "unused",
+                &format!(
+                    r#"{{ "kind": "Tuple", "first": {}, "second": {}, "location": LOC }}"#,
+                    var("x"),
+                    r#"{ "kind": "Str", "value": "a", "location": LOC }"#
+                ),
+                &var("x"),
+            ),
+        );
+
+        assert_eq!(
+            optimize(term(&bound), Division::Truncating),
+            term(&let_("x", &int(1), &var("x")))
+        );
+    }
+
+    #[test]
+    fn shadowed_names_are_not_references() {
+        // let x = 1; let x = 2; x
+        let program = let_("x", &int(1), &let_("x", &int(2), &var("x")));
+
+        assert_eq!(
+            optimize(term(&program), Division::Truncating),
+            term(&let_("x", &int(2), &var("x")))
+        );
+    }
+
+    #[test]
+    fn small_functions_are_inlined() {
+        // let inc = fn (x) => x + 1; inc(2)
+        let program = let_(
+            "inc",
+            &function("x", &binary(&var("x"), "Add", &int(1))),
+            &call(&var("inc"), &[&int(2)]),
+        );
+
+        assert_eq!(
+            optimize(term(&program), Division::Truncating),
+            term(&let_("x", &int(2), &binary(&var("x"), "Add", &int(1))))
+        );
+    }
+
+    #[test]
+    fn functions_are_not_inlined_where_shadowed_or_called_with_another_arity() {
+        // let y = 1; let f = fn (x) => x + y; let y = 2; f(y)
+        let shadowed = let_(
+            "y",
+            &int(1),
+            &let_(
+                "f",
+                &function("x", &binary(&var("x"), "Add", &var("y"))),
+                &let_("y", &int(2), &call(&var("f"), &[&var("y")])),
+            ),
+        );
+        // let f = fn (x) => f(x); f(0)
+        let recursive = let_(
+            "f",
+            &function("x", &call(&var("f"), &[&var("x")])),
+            &call(&var("f"), &[&int(0)]),
+        );
+
+        // let f = fn (x) => x; f(1, 2)
+        let extra = let_(
+            "f",
+            &function("x", &var("x")),
+            &call(&var("f"), &[&int(1), &int(2)]),
+        );
+        // let f = fn (x) => x; f()
+        let missing = let_("f", &function("x", &var("x")), &call(&var("f"), &[]));
+
+        for program in [shadowed, recursive, extra, missing] {
+            assert_eq!(
+                optimize(term(&program), Division::Truncating),
+                term(&program)
+            );
+        }
+    }
+
+    #[test]
+    fn failing_operations_are_left_for_the_runtime() {
+        let division = term(&binary(&int(1), "Div", &int(0)));
+
+        assert_eq!(optimize(division.clone(), Division::Truncating), division);
+    }
+}
@@ -0,0 +1,683 @@
+use std::{fmt::Display, sync::Arc};
+
+use crate::{
+    ast::{
+        Binary, BinaryOp, Bool, Call, Element, File, First, Function, If, Int, Let, Location,
+        Print, Second, Str, Term, Tuple, Unit, Var,
+    },
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    source_map::FileId,
+    symbol::Symbol,
+};
+
+/// Why source text couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+
+    /// Where in the source the error is, an empty range at its end when
+    /// it ended too early.
+    pub location: Location,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Location {
+            start,
+            end,
+            filename,
+        } = &self.location;
+
+        write!(f, "{} at {filename}:{start}..{end}", self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Parses the source of a rinha program, like `examples/fib.rinha`, into
+/// the AST its JSON would hold, with the locations as byte offsets into
+/// `source`.
+pub fn parse(source: &str, filename: &str) -> Result<File, SyntaxError> {
+    let expression = parse_term(source, filename)?;
+    let location = Location {
+        start: 0,
+        end: expression.location().end,
+        filename: expression.location().filename,
+    };
+
+    Ok(File {
+        name: filename.to_string(),
+        expression,
+        location,
+    })
+}
+
+/// Parses the source of a single term, like a cell of a notebook.
+pub fn parse_term(source: &str, filename: &str) -> Result<Term, SyntaxError> {
+    let filename = FileId::new(filename);
+    let tokens = Lexer {
+        source,
+        position: 0,
+        filename,
+    }
+    .tokens()?;
+
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        last_end: 0,
+        filename,
+    };
+    let term = parser.term()?;
+    parser.expect(&Token::End, "the end of the program")?;
+
+    Ok(term)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Int(&'a str),
+    Str(String),
+    Name(&'a str),
+    Let,
+    Fn,
+    If,
+    Else,
+    True,
+    False,
+    Print,
+    First,
+    Second,
+    Op(BinaryOp),
+    Assign,
+    Arrow,
+    Comma,
+    Semicolon,
+    OpenParen,
+    CloseParen,
+    OpenBrace,
+    CloseBrace,
+    End,
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Int(digits) => return write!(f, "the integer `{digits}`"),
+            Token::Str(text) => return write!(f, "the string {text:?}"),
+            Token::Name(name) => return write!(f, "`{name}`"),
+            Token::Let => "let",
+            Token::Fn => "fn",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Print => "print",
+            Token::First => "first",
+            Token::Second => "second",
+            Token::Op(op) => operator(op),
+            Token::Assign => "=",
+            Token::Arrow => "=>",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::OpenParen => "(",
+            Token::CloseParen => ")",
+            Token::OpenBrace => "{",
+            Token::CloseBrace => "}",
+            Token::End => return f.write_str("the end of the program"),
+        };
+
+        write!(f, "`{text}`")
+    }
+}
+
+/// How an operator is written.
+pub(crate) fn operator(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
+        BinaryOp::Eq => "==",
+        BinaryOp::Neq => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Lte => "<=",
+        BinaryOp::Gte => ">=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+/// How tightly an operator binds its operands, operators binding tighter
+/// being applied first. All of them are left-associative.
+pub(crate) fn precedence(op: &BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::Neq => 3,
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Gte => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+    }
+}
+
+/// A token and the bytes of the source it was read from.
+struct Spanned<'a> {
+    token: Token<'a>,
+    start: usize,
+    end: usize,
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+    filename: FileId,
+}
+
+impl<'a> Lexer<'a> {
+    fn error(&self, message: impl Into<String>, start: usize, end: usize) -> SyntaxError {
+        SyntaxError {
+            message: message.into(),
+            location: Location {
+                start,
+                end,
+                filename: self.filename,
+            },
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    /// The tokens of the whole source, ending with [`Token::End`].
+    fn tokens(mut self) -> Result<Vec<Spanned<'a>>, SyntaxError> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_trivia()?;
+            let start = self.position;
+            let token = self.token()?;
+            let end = self.position;
+
+            let ended = token == Token::End;
+            tokens.push(Spanned { token, start, end });
+            if ended {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    /// Skips whitespace and comments, `// to the end of the line` and
+    /// `/* in between */`.
+    fn skip_trivia(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                let length = trimmed.find("*/").ok_or_else(|| {
+                    self.error("unterminated comment", self.position, self.position + 2)
+                })?;
+                self.position += length + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<Token<'a>, SyntaxError> {
+        let rest = self.rest();
+        let Some(next) = rest.chars().next() else {
+            return Ok(Token::End);
+        };
+
+        if next.is_ascii_digit() {
+            let length = rest
+                .find(|char: char| !char.is_ascii_digit())
+                .unwrap_or(rest.len());
+            self.position += length;
+
+            return Ok(Token::Int(&rest[..length]));
+        }
+        if next.is_alphabetic() || next == '_' {
+            let length = rest
+                .find(|char: char| !(char.is_alphanumeric() || char == '_'))
+                .unwrap_or(rest.len());
+            self.position += length;
+
+            return Ok(match &rest[..length] {
+                "let" => Token::Let,
+                "fn" => Token::Fn,
+                "if" => Token::If,
+                "else" => Token::Else,
+                "true" => Token::True,
+                "false" => Token::False,
+                "print" => Token::Print,
+                "first" => Token::First,
+                "second" => Token::Second,
+                name => Token::Name(name),
+            });
+        }
+        if next == '"' {
+            return self.string();
+        }
+
+        const SYMBOLS: [(&str, Token); 21] = [
+            ("=>", Token::Arrow),
+            ("==", Token::Op(BinaryOp::Eq)),
+            ("!=", Token::Op(BinaryOp::Neq)),
+            ("<=", Token::Op(BinaryOp::Lte)),
+            (">=", Token::Op(BinaryOp::Gte)),
+            ("&&", Token::Op(BinaryOp::And)),
+            ("||", Token::Op(BinaryOp::Or)),
+            ("=", Token::Assign),
+            ("<", Token::Op(BinaryOp::Lt)),
+            (">", Token::Op(BinaryOp::Gt)),
+            ("+", Token::Op(BinaryOp::Add)),
+            ("-", Token::Op(BinaryOp::Sub)),
+            ("*", Token::Op(BinaryOp::Mul)),
+            ("/", Token::Op(BinaryOp::Div)),
+            ("%", Token::Op(BinaryOp::Rem)),
+            (",", Token::Comma),
+            (";", Token::Semicolon),
+            ("(", Token::OpenParen),
+            (")", Token::CloseParen),
+            ("{", Token::OpenBrace),
+            ("}", Token::CloseBrace),
+        ];
+        // Longer symbols come first, so `==` isn't read as two `=`.
+        for (symbol, token) in SYMBOLS {
+            if rest.starts_with(symbol) {
+                self.position += symbol.len();
+                return Ok(token);
+            }
+        }
+
+        Err(self.error(
+            format!("unexpected character {next:?}"),
+            self.position,
+            self.position + next.len_utf8(),
+        ))
+    }
+
+    /// Reads a string literal, with `\n`, `\r`, `\t`, `\"` and `\\` as
+    /// escapes.
+    fn string(&mut self) -> Result<Token<'a>, SyntaxError> {
+        let start = self.position;
+        let mut chars = self.rest().char_indices().skip(1);
+        let mut text = String::new();
+
+        while let Some((offset, char)) = chars.next() {
+            match char {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(Token::Str(text));
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, char)) => {
+                            let at = start + offset;
+                            return Err(self.error(
+                                format!("unknown escape `\\{char}`"),
+                                at,
+                                at + 1 + char.len_utf8(),
+                            ));
+                        }
+                        None => break,
+                    };
+                    text.push(escaped);
+                }
+                char => text.push(char),
+            }
+        }
+
+        Err(self.error("unterminated string", start, self.source.len()))
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Spanned<'a>>,
+    position: usize,
+
+    /// Where the last token consumed ends, where the term it ends does.
+    last_end: usize,
+    filename: FileId,
+}
+
+impl<'a> Parser<'a> {
+    fn location(&self, start: usize) -> Location {
+        Location {
+            start,
+            end: self.last_end,
+            filename: self.filename,
+        }
+    }
+
+    fn peek(&self) -> &Spanned<'a> {
+        &self.tokens[self.position]
+    }
+
+    fn advance(&mut self) -> &Spanned<'a> {
+        let spanned = &self.tokens[self.position];
+        self.last_end = spanned.end;
+        // The end is never consumed, so there is always a token to peek.
+        if spanned.token != Token::End {
+            self.position += 1;
+        }
+
+        spanned
+    }
+
+    fn unexpected(&self, expected: &str) -> SyntaxError {
+        let Spanned { token, start, end } = self.peek();
+
+        SyntaxError {
+            message: format!("expected {expected}, found {token}"),
+            location: Location {
+                start: *start,
+                end: *end,
+                filename: self.filename,
+            },
+        }
+    }
+
+    /// Consumes `token`, or fails saying `expected` was.
+    fn expect(&mut self, token: &Token, expected: &str) -> Result<usize, SyntaxError> {
+        match &self.peek().token == token {
+            true => Ok(self.advance().start),
+            false => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, SyntaxError> {
+        // As deep as the source nests, like the evaluation.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || self.binary(0))
+    }
+
+    /// Parses operands joined by operators binding at least as tightly as
+    /// `precedence`.
+    fn binary(&mut self, minimum: u8) -> Result<Term, SyntaxError> {
+        let mut lhs = self.call()?;
+
+        while let Token::Op(op) = &self.peek().token {
+            let precedence = precedence(op);
+            if precedence < minimum {
+                break;
+            }
+            let op = op.clone();
+            self.advance();
+
+            let rhs = self.binary(precedence + 1)?;
+            lhs = Term::Binary(Binary {
+                location: self.location(lhs.location().start),
+                lhs: Arc::new(lhs),
+                op,
+                rhs: Arc::new(rhs),
+            });
+        }
+
+        Ok(lhs)
+    }
+
+    fn call(&mut self) -> Result<Term, SyntaxError> {
+        let mut callee = self.primary()?;
+
+        while self.peek().token == Token::OpenParen {
+            self.advance();
+            let arguments = self.separated(Self::term, "an argument")?;
+            callee = Term::Call(Call {
+                location: self.location(callee.location().start),
+                callee: Arc::new(callee),
+                arguments,
+            });
+        }
+
+        Ok(callee)
+    }
+
+    /// Parses the elements of a list separated by commas, after its
+    /// opening parenthesis and up to its closing one.
+    fn separated<T>(
+        &mut self,
+        element: impl Fn(&mut Self) -> Result<T, SyntaxError>,
+        expected: &str,
+    ) -> Result<Vec<T>, SyntaxError> {
+        let mut elements = Vec::new();
+        while self.peek().token != Token::CloseParen {
+            elements.push(element(self)?);
+            if self.peek().token != Token::Comma {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(&Token::CloseParen, &format!("`,` or `)` after {expected}"))?;
+
+        Ok(elements)
+    }
+
+    fn primary(&mut self) -> Result<Term, SyntaxError> {
+        let Spanned { token, start, end } = self.peek();
+        let (token, start, end) = (token.clone(), *start, *end);
+
+        match token {
+            Token::Int(digits) => {
+                self.advance();
+                self.int(digits, false, start)
+            }
+            // Negative literals, as there is no negation.
+            Token::Op(BinaryOp::Sub)
+                if matches!(
+                    self.tokens.get(self.position + 1),
+                    Some(Spanned { token: Token::Int(_), start, .. }) if *start == end
+                ) =>
+            {
+                self.advance();
+                let Token::Int(digits) = self.advance().token else {
+                    unreachable!("the literal follows the sign")
+                };
+                self.int(digits, true, start)
+            }
+            Token::Str(value) => {
+                self.advance();
+                Ok(Term::Str(Str {
+                    value,
+                    location: self.location(start),
+                }))
+            }
+            Token::True | Token::False => {
+                self.advance();
+                Ok(Term::Bool(Bool {
+                    value: token == Token::True,
+                    location: self.location(start),
+                }))
+            }
+            Token::Name(_) => self.var().map(Term::Var),
+            Token::OpenParen => self.parenthesized(),
+            Token::OpenBrace => self.block(),
+            Token::Let => self.let_(),
+            Token::Fn => self.function(),
+            Token::If => self.if_(),
+            keyword @ (Token::Print | Token::First | Token::Second) => {
+                self.advance();
+                self.expect(&Token::OpenParen, &format!("`(` after {keyword}"))?;
+                let value = Arc::new(self.term()?);
+                self.expect(&Token::CloseParen, "`)`")?;
+
+                let location = self.location(start);
+                Ok(match keyword {
+                    Token::Print => Term::Print(Print { value, location }),
+                    Token::First => Term::First(First { value, location }),
+                    _second => Term::Second(Second { value, location }),
+                })
+            }
+            _token => Err(self.unexpected("a term")),
+        }
+    }
+
+    fn int(&self, digits: &str, negative: bool, start: usize) -> Result<Term, SyntaxError> {
+        let literal = match negative {
+            true => format!("-{digits}"),
+            false => digits.to_string(),
+        };
+        let location = self.location(start);
+
+        match literal.parse() {
+            Ok(value) => Ok(Term::Int(Int { value, location })),
+            Err(_) => Err(SyntaxError {
+                message: format!("the integer `{literal}` doesn't fit in 64 bits"),
+                location,
+            }),
+        }
+    }
+
+    fn var(&mut self) -> Result<Var, SyntaxError> {
+        let Spanned { token, start, .. } = self.peek();
+        let (Token::Name(name), start) = (token, *start) else {
+            return Err(self.unexpected("a name"));
+        };
+        let text = Symbol::new(name);
+        self.advance();
+
+        Ok(Var {
+            text,
+            location: self.location(start),
+            slot: None,
+        })
+    }
+
+    /// Parses `()`, `(term)` or the tuple `(first, second)`.
+    fn parenthesized(&mut self) -> Result<Term, SyntaxError> {
+        let start = self.expect(&Token::OpenParen, "`(`")?;
+        if self.peek().token == Token::CloseParen {
+            self.advance();
+            return Ok(Term::Unit(Unit {
+                location: self.location(start),
+            }));
+        }
+
+        let first = self.term()?;
+        if self.peek().token != Token::Comma {
+            self.expect(&Token::CloseParen, "`)` or `,`")?;
+            return Ok(first);
+        }
+        self.advance();
+        let second = self.term()?;
+        self.expect(
+            &Token::CloseParen,
+            "`)` after the second element of a tuple",
+        )?;
+
+        Ok(Term::Tuple(Tuple {
+            first: Arc::new(first),
+            second: Arc::new(second),
+            location: self.location(start),
+        }))
+    }
+
+    /// Parses `{ term }`, which is the term itself.
+    fn block(&mut self) -> Result<Term, SyntaxError> {
+        self.expect(&Token::OpenBrace, "`{`")?;
+        let term = self.term()?;
+        self.expect(&Token::CloseBrace, "`}`")?;
+
+        Ok(term)
+    }
+
+    fn let_(&mut self) -> Result<Term, SyntaxError> {
+        let start = self.expect(&Token::Let, "`let`")?;
+        let name = self.var()?;
+        self.expect(&Token::Assign, "`=` after the name")?;
+        let value = self.term()?;
+        self.expect(&Token::Semicolon, "`;` after the value")?;
+        let next = self.term()?;
+
+        Ok(Term::Let(Let {
+            name,
+            value: Arc::new(value),
+            next: Arc::new(next),
+            location: self.location(start),
+        }))
+    }
+
+    fn function(&mut self) -> Result<Term, SyntaxError> {
+        let start = self.expect(&Token::Fn, "`fn`")?;
+        self.expect(&Token::OpenParen, "`(` before the parameters")?;
+        let parameters = self.separated(Self::var, "a parameter")?;
+        self.expect(&Token::Arrow, "`=>` after the parameters")?;
+        let value = self.term()?;
+
+        Ok(Term::Function(Function {
+            parameters: parameters.into(),
+            value: Arc::new(value),
+            location: self.location(start),
+            free: None,
+            layout: None,
+            digest: None,
+            pure: None,
+        }))
+    }
+
+    fn if_(&mut self) -> Result<Term, SyntaxError> {
+        let start = self.expect(&Token::If, "`if`")?;
+        let condition = self.term()?;
+        let then = self.block()?;
+        self.expect(&Token::Else, "`else`")?;
+        let otherwise = match self.peek().token {
+            Token::If => self.if_()?,
+            _ => self.block()?,
+        };
+
+        Ok(Term::If(If {
+            condition: Arc::new(condition),
+            then: Arc::new(then),
+            otherwise: Arc::new(otherwise),
+            location: self.location(start),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_term};
+    use crate::ast::File;
+
+    #[test]
+    fn sources_parse_to_the_ast_of_their_json() {
+        for name in ["combination", "sum"] {
+            let source = std::fs::read_to_string(format!("examples/{name}.rinha")).unwrap();
+            let json = std::fs::read_to_string(format!("examples/{name}.json")).unwrap();
+            let expected: File = serde_json::from_str(&json).unwrap();
+
+            let parsed = parse(&source, &expected.name).unwrap();
+            assert_eq!(parsed.expression, expected.expression, "{name}");
+            assert_eq!(parsed.location, expected.location, "{name}");
+        }
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        let shown =
+            |source: &str| serde_json::to_value(parse_term(source, "tests").unwrap()).unwrap();
+
+        let term = shown("1 + 2 * 3 == 7 || -1 < x");
+        assert_eq!(term["op"], "Or");
+        assert_eq!(term["lhs"]["lhs"]["rhs"]["op"], "Mul");
+        assert_eq!(term["rhs"]["lhs"]["value"], -1);
+
+        let error = parse_term("let x = (1, 2;\nx", "tests").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected `)` after the second element of a tuple, found `;` at tests:13..14"
+        );
+    }
+}
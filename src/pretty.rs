@@ -0,0 +1,247 @@
+use crate::{
+    ast::Term,
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    parser::{operator, precedence},
+};
+
+/// How many spaces each level of blocks is indented by.
+const INDENT: usize = 2;
+
+impl Term {
+    /// Renders the term as rinha source, which [`parse`](crate::parser::parse)
+    /// reads back as the same term, locations aside.
+    ///
+    /// Sequences and conditional chains, which rinha has no syntax for,
+    /// are rendered as the `let _ = ...;` and `if` chains evaluating the
+    /// same way.
+    pub fn to_source(&self) -> String {
+        let mut pretty = Pretty {
+            source: String::new(),
+            indent: 0,
+        };
+        pretty.term(self);
+
+        pretty.source
+    }
+}
+
+/// Whether the term reads to the end of whatever follows it, like the
+/// body of a function, so it needs parentheses to be an operand or a
+/// callee.
+fn open_ended(term: &Term) -> bool {
+    matches!(
+        term,
+        Term::Let(_) | Term::Function(_) | Term::If(_) | Term::Seq(_) | Term::Cond(_)
+    )
+}
+
+struct Pretty {
+    source: String,
+    indent: usize,
+}
+
+impl Pretty {
+    fn newline(&mut self) {
+        self.source.push('\n');
+        self.source
+            .extend(std::iter::repeat_n(' ', self.indent * INDENT));
+    }
+
+    /// Renders `{ term }` over several lines, with the term indented.
+    fn block(&mut self, term: &Term) {
+        self.source.push('{');
+        self.indent += 1;
+        self.newline();
+        self.term(term);
+        self.indent -= 1;
+        self.newline();
+        self.source.push('}');
+    }
+
+    fn parenthesized(&mut self, term: &Term, parenthesize: bool) {
+        match parenthesize {
+            true => {
+                self.source.push('(');
+                self.term(term);
+                self.source.push(')');
+            }
+            false => self.term(term),
+        }
+    }
+
+    fn separated<'a>(&mut self, terms: impl IntoIterator<Item = &'a Term>) {
+        for (index, term) in terms.into_iter().enumerate() {
+            if index > 0 {
+                self.source.push_str(", ");
+            }
+            self.term(term);
+        }
+    }
+
+    /// Renders `let _ = term;` and a new line, for terms evaluated only for
+    /// their effects.
+    fn discarded(&mut self, term: &Term) {
+        self.source.push_str("let _ = ");
+        self.term(term);
+        self.source.push(';');
+        self.newline();
+    }
+
+    fn term(&mut self, term: &Term) {
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+            Term::Int(int) => self.source.push_str(&int.value.to_string()),
+            Term::Str(str) => {
+                self.source.push('"');
+                for char in str.value.chars() {
+                    match char {
+                        '\n' => self.source.push_str("\\n"),
+                        '\r' => self.source.push_str("\\r"),
+                        '\t' => self.source.push_str("\\t"),
+                        '"' => self.source.push_str("\\\""),
+                        '\\' => self.source.push_str("\\\\"),
+                        char => self.source.push(char),
+                    }
+                }
+                self.source.push('"');
+            }
+            Term::Bool(bool) => self.source.push_str(&bool.value.to_string()),
+            Term::Unit(_) => self.source.push_str("()"),
+            Term::Var(var) => self.source.push_str(var.text.as_str()),
+            Term::Function(function) => {
+                self.source.push_str("fn (");
+                for (index, parameter) in function.parameters.iter().enumerate() {
+                    if index > 0 {
+                        self.source.push_str(", ");
+                    }
+                    self.source.push_str(parameter.text.as_str());
+                }
+                self.source.push_str(") => ");
+                self.block(&function.value);
+            }
+            Term::Let(let_) => {
+                self.source.push_str("let ");
+                self.source.push_str(let_.name.text.as_str());
+                self.source.push_str(" = ");
+                self.term(&let_.value);
+                self.source.push(';');
+                self.newline();
+                self.term(&let_.next);
+            }
+            Term::Call(call) => {
+                let parenthesize =
+                    open_ended(&call.callee) || matches!(*call.callee, Term::Binary(_));
+                self.parenthesized(&call.callee, parenthesize);
+                self.source.push('(');
+                self.separated(&call.arguments);
+                self.source.push(')');
+            }
+            Term::Binary(binary) => {
+                let binding = precedence(&binary.op);
+                // Operators are left-associative, so an operand on the right
+                // binding as tightly needs parentheses, unlike on the left.
+                let looser = |operand: &Term, right: bool| match operand {
+                    Term::Binary(inner) => {
+                        let inner = precedence(&inner.op);
+                        inner < binding || (right && inner == binding)
+                    }
+                    operand => open_ended(operand),
+                };
+
+                self.parenthesized(&binary.lhs, looser(&binary.lhs, false));
+                self.source.push(' ');
+                self.source.push_str(operator(&binary.op));
+                self.source.push(' ');
+                self.parenthesized(&binary.rhs, looser(&binary.rhs, true));
+            }
+            Term::If(if_) => {
+                self.source.push_str("if (");
+                self.term(&if_.condition);
+                self.source.push_str(") ");
+                self.block(&if_.then);
+                self.source.push_str(" else ");
+                match &*if_.otherwise {
+                    otherwise @ Term::If(_) => self.term(otherwise),
+                    otherwise => self.block(otherwise),
+                }
+            }
+            Term::Cond(cond) => {
+                for arm in &cond.arms {
+                    self.source.push_str("if (");
+                    self.term(&arm.condition);
+                    self.source.push_str(") ");
+                    self.block(&arm.then);
+                    self.source.push_str(" else ");
+                }
+                self.block(&cond.otherwise);
+            }
+            Term::Seq(seq) => match seq.terms.split_last() {
+                Some((last, terms)) => {
+                    terms.iter().for_each(|term| self.discarded(term));
+                    self.term(last);
+                }
+                None => self.source.push_str("()"),
+            },
+            Term::Print(print) => {
+                self.source.push_str("print(");
+                self.term(&print.value);
+                self.source.push(')');
+            }
+            Term::First(first) => {
+                self.source.push_str("first(");
+                self.term(&first.value);
+                self.source.push(')');
+            }
+            Term::Second(second) => {
+                self.source.push_str("second(");
+                self.term(&second.value);
+                self.source.push(')');
+            }
+            Term::Tuple(tuple) => {
+                self.source.push('(');
+                self.separated([&*tuple.first, &*tuple.second]);
+                self.source.push(')');
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_term;
+
+    #[test]
+    fn programs_are_rendered_as_source() {
+        let source = std::fs::read_to_string("examples/fib.rinha").unwrap();
+        let term = parse_term(&source, "fib.rinha").unwrap();
+
+        assert_eq!(
+            term.to_source(),
+            "let fib = fn (n) => {\n  \
+               if (n < 2) {\n    \
+                 n\n  \
+               } else {\n    \
+                 fib(n - 1) + fib(n - 2)\n  \
+               }\n\
+             };\n\
+             print(fib(10))"
+        );
+    }
+
+    #[test]
+    fn rendered_source_parses_back_the_same() {
+        let sources = [
+            "a - (b - c) * (d + e)",
+            "(fn (x) => { x })(\"a \\\"quoted\\\"\\n\") + (if (a) { 1 } else { 2 })",
+            "f(let x = -1; x, (first((1, 2)), ()))",
+        ];
+
+        for source in sources {
+            let rendered = parse_term(source, "tests").unwrap().to_source();
+            let reparsed = parse_term(&rendered, "tests").unwrap();
+            assert_eq!(reparsed.to_source(), rendered);
+        }
+
+        let rendered = parse_term(sources[0], "tests").unwrap().to_source();
+        assert_eq!(rendered, sources[0]);
+    }
+}
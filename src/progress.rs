@@ -0,0 +1,128 @@
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use crate::ast::Location;
+
+/// How often, in steps, the clock is read to decide whether to report, as
+/// reading it on every step would slow the evaluation down.
+const CHECK_EVERY: u64 = 1024;
+
+/// A snapshot of a running evaluation.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Number of terms evaluated so far, the fuel consumed by the run.
+    pub steps: u64,
+
+    /// Steps evaluated per second since the previous report.
+    pub steps_per_second: f64,
+
+    pub elapsed: Duration,
+
+    /// The location of the term being evaluated.
+    pub location: Location,
+}
+
+/// Reports the progress of long runs to a callback, at most once every
+/// `interval`.
+pub struct Progress {
+    interval: Duration,
+    started_at: Instant,
+    reported_at: Instant,
+    reported_steps: u64,
+    callback: Box<dyn FnMut(&Report) + Send>,
+}
+
+impl Progress {
+    /// Creates a new instance of [`Progress`], calling `callback` at most
+    /// once every `interval`.
+    pub fn new(interval: Duration, callback: impl FnMut(&Report) + Send + 'static) -> Self {
+        let now = Instant::now();
+
+        Self {
+            interval,
+            started_at: now,
+            reported_at: now,
+            reported_steps: 0,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Called on every evaluation step, reporting when the interval since
+    /// the last report has elapsed.
+    pub fn tick(&mut self, steps: u64, location: &Location) {
+        if !steps.is_multiple_of(CHECK_EVERY) {
+            return;
+        }
+
+        let now = Instant::now();
+        let since_reported = now - self.reported_at;
+
+        if since_reported < self.interval {
+            return;
+        }
+
+        let report = Report {
+            steps,
+            steps_per_second: (steps - self.reported_steps) as f64
+                / since_reported.as_secs_f64().max(f64::EPSILON),
+            elapsed: now - self.started_at,
+            location: location.clone(),
+        };
+
+        self.reported_at = now;
+        self.reported_steps = steps;
+        (self.callback)(&report);
+    }
+}
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("interval", &self.interval)
+            .field("reported_steps", &self.reported_steps)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Progress, CHECK_EVERY};
+    use crate::ast::Location;
+
+    #[test]
+    fn reports_on_check_steps() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&reports);
+        let mut progress = Progress::new(Duration::ZERO, move |report| {
+            reported.lock().unwrap().push(report.clone())
+        });
+
+        progress.tick(CHECK_EVERY - 1, &Location::default());
+        progress.tick(CHECK_EVERY, &Location::new(3, 4, "tests"));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].steps, CHECK_EVERY);
+        assert_eq!(reports[0].location.start, 3);
+    }
+
+    #[test]
+    fn waits_for_the_interval() {
+        let reports = Arc::new(Mutex::new(0));
+        let reported = Arc::clone(&reports);
+        let mut progress = Progress::new(Duration::from_secs(3600), move |_report| {
+            *reported.lock().unwrap() += 1
+        });
+
+        progress.tick(CHECK_EVERY, &Location::default());
+
+        assert_eq!(*reports.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::{Function, Print, Term},
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    visit::TermVisitor,
+};
+
+/// Fills in whether the body of every function in `term` is pure, so
+/// calls decide whether to memoize without walking the body again on
+/// every call.
+///
+/// Each body is walked once, without looking into the functions it
+/// defines, so the whole term is walked once however deep its functions
+/// nest.
+pub fn annotate(term: &mut Term) {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+        Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) | Term::Var(_) => {}
+        Term::Function(function) => {
+            annotate(Arc::make_mut(&mut function.value));
+            function.pure = Some(of(&function.value));
+        }
+        Term::Let(let_) => {
+            annotate(Arc::make_mut(&mut let_.value));
+            annotate(Arc::make_mut(&mut let_.next));
+        }
+        Term::Call(call) => {
+            annotate(Arc::make_mut(&mut call.callee));
+            call.arguments.iter_mut().for_each(annotate);
+        }
+        Term::Binary(binary) => {
+            annotate(Arc::make_mut(&mut binary.lhs));
+            annotate(Arc::make_mut(&mut binary.rhs));
+        }
+        Term::If(if_) => {
+            annotate(Arc::make_mut(&mut if_.condition));
+            annotate(Arc::make_mut(&mut if_.then));
+            annotate(Arc::make_mut(&mut if_.otherwise));
+        }
+        Term::Cond(cond) => {
+            for arm in &mut cond.arms {
+                annotate(&mut arm.condition);
+                annotate(&mut arm.then);
+            }
+            annotate(Arc::make_mut(&mut cond.otherwise));
+        }
+        Term::Seq(seq) => seq.terms.iter_mut().for_each(annotate),
+        Term::Print(print) => annotate(Arc::make_mut(&mut print.value)),
+        Term::First(first) => annotate(Arc::make_mut(&mut first.value)),
+        Term::Second(second) => annotate(Arc::make_mut(&mut second.value)),
+        Term::Tuple(tuple) => {
+            annotate(Arc::make_mut(&mut tuple.first));
+            annotate(Arc::make_mut(&mut tuple.second));
+        }
+    })
+}
+
+/// Whether evaluating `term` prints nothing itself. Defining a function
+/// runs none of its body, so it is pure whatever the body does.
+///
+/// Calls are taken for pure, as what the called function does is only
+/// known once it runs. The interpreter is what keeps them out of the
+/// cache: the results of calls that printed or called an impure native
+/// along the way are never cached, whatever this tells.
+pub fn of(term: &Term) -> bool {
+    let mut prints = Prints(false);
+    prints.visit_term(term);
+
+    !prints.0
+}
+
+/// Whether a `print` was visited, outside of the functions defined.
+struct Prints(bool);
+
+impl TermVisitor for Prints {
+    fn visit_function(&mut self, _function: &Function) {}
+
+    fn visit_print(&mut self, _print: &Print) {
+        self.0 = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::Term, parser::parse_term};
+
+    /// Whether the function defined by `source` is annotated as pure.
+    fn pure(source: &str) -> Option<bool> {
+        let mut term = parse_term(source, "tests").unwrap();
+        super::annotate(&mut term);
+
+        match &term {
+            Term::Function(function) => function.pure,
+            term => panic!("expected a function, got {term:?}"),
+        }
+    }
+
+    #[test]
+    fn functions_are_pure_unless_their_body_prints() {
+        assert_eq!(pure("fn (x) => { x + 1 }"), Some(true));
+        assert_eq!(pure("fn (x) => { let _ = print(x); x }"), Some(false));
+        // What the functions defined in the body do is up to their calls.
+        assert_eq!(pure("fn (x) => { fn (y) => { print(y) } }"), Some(true));
+    }
+
+    #[test]
+    fn deep_bodies_are_annotated() {
+        let source = format!("fn (x) => {{ {} }}", vec!["x"; 40_000].join(" + "));
+
+        assert_eq!(pure(&source), Some(true));
+    }
+}
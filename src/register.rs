@@ -0,0 +1,1111 @@
+use std::{borrow::Cow, collections::HashMap, mem, sync::Arc};
+
+use crate::{
+    ast::{BinaryOp, Location},
+    compiler::{self, Capture, Program},
+    generator::Generator,
+    interpreter::{
+        self, Arguments, CallFrame, Context, Host, Printer, RuntimeError, RuntimeErrorKind, State,
+        Tuple, Type, Value, RED_ZONE, STACK_SEGMENT,
+    },
+    vm::{Closure, Variant},
+};
+
+/// Where an instruction of the register machine reads an operand from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A register of the running frame.
+    Register(usize),
+
+    /// A constant of [`Code::constants`], kept out of the instructions so
+    /// they stay small to copy.
+    Constant(usize),
+}
+
+/// An instruction of the register machine. Operands are read from the
+/// registers of the running frame, its locals first, or are constants,
+/// and results are written to one of its registers.
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    /// Copies an operand into a register.
+    Move {
+        to: usize,
+        from: Operand,
+    },
+
+    Captured {
+        to: usize,
+        index: usize,
+    },
+
+    /// Loads the running closure itself, so functions bound by a `let`
+    /// can call themselves.
+    Current {
+        to: usize,
+    },
+
+    Global {
+        to: usize,
+        index: usize,
+    },
+
+    /// Creates a closure of a function of [`Program::functions`],
+    /// capturing the values it refers to.
+    Closure {
+        to: usize,
+        index: usize,
+    },
+
+    /// Checks that a native callee takes the given number of arguments
+    /// before they are evaluated, as the interpreter does.
+    Callee {
+        callee: usize,
+        arguments: usize,
+    },
+
+    /// Calls the callee in a register with the arguments in the registers
+    /// right above it, writing the result in place of the callee.
+    Call {
+        callee: usize,
+        arguments: usize,
+    },
+
+    /// Calls in tail position, reusing the frame of the running function.
+    TailCall {
+        callee: usize,
+        arguments: usize,
+    },
+
+    Return(Operand),
+    Jump(usize),
+
+    /// Jumps when the condition doesn't hold.
+    JumpUnless(Operand, usize),
+
+    /// Jumps, keeping the left-hand side in its register, when it alone
+    /// decides the result of `&&` or `||`.
+    ShortCircuit {
+        op: BinaryOp,
+        lhs: usize,
+        target: usize,
+    },
+
+    Binary {
+        op: BinaryOp,
+        to: usize,
+        lhs: Operand,
+        rhs: Operand,
+    },
+
+    /// Jumps unless the comparison holds.
+    CompareJumpUnless {
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+        target: usize,
+    },
+
+    Tuple {
+        to: usize,
+        first: Operand,
+        second: Operand,
+    },
+
+    First {
+        to: usize,
+        from: Operand,
+    },
+
+    Second {
+        to: usize,
+        from: Operand,
+    },
+
+    Print {
+        to: usize,
+        from: Operand,
+    },
+}
+
+/// A function lowered to the code of the register machine.
+#[derive(Debug)]
+pub struct Code {
+    /// Number of registers of a frame: the locals of the function, then
+    /// one for each value its stack code holds at once.
+    pub registers: usize,
+
+    pub constants: Vec<Value>,
+    pub code: Vec<Instruction>,
+
+    /// The location of the term each instruction was compiled from, where
+    /// errors raised by it are reported.
+    pub locations: Vec<Location>,
+}
+
+/// Translates the stack code of `function` to the code of the register
+/// machine.
+///
+/// The value stack is followed along the stack code, each of its
+/// positions getting a register above the locals. Locals and constants
+/// pushed aren't copied to that register unless they have to, like when
+/// they are the arguments of a call, but read from where they are by the
+/// instruction using them.
+pub fn translate(function: &compiler::Function) -> Code {
+    let mut translation = Translation {
+        locals: function.locals,
+        operands: Vec::new(),
+        code: Code {
+            registers: function.locals,
+            constants: Vec::new(),
+            code: Vec::new(),
+            locations: Vec::new(),
+        },
+        depths: HashMap::new(),
+    };
+    // Where each instruction ended up, the end of the code included.
+    let mut moved = Vec::with_capacity(function.code.len() + 1);
+    let mut reachable = true;
+
+    for (ip, (instruction, location)) in function.code.iter().zip(&function.locations).enumerate() {
+        // Jumps land with every value in its register.
+        if let Some(&depth) = translation.depths.get(&ip) {
+            if reachable {
+                translation.spill(0, location);
+            }
+            translation.operands = (0..depth)
+                .map(|depth| Operand::Register(translation.register(depth)))
+                .collect();
+            reachable = true;
+        }
+
+        moved.push(translation.code.code.len());
+        // Like what follows a return, unless it is jumped to.
+        if reachable {
+            reachable = translation.instruction(instruction, location);
+        }
+    }
+    moved.push(translation.code.code.len());
+
+    let mut code = translation.code;
+    for instruction in &mut code.code {
+        if let Instruction::Jump(target)
+        | Instruction::JumpUnless(_, target)
+        | Instruction::ShortCircuit { target, .. }
+        | Instruction::CompareJumpUnless { target, .. } = instruction
+        {
+            *target = moved[*target];
+        }
+    }
+
+    code
+}
+
+/// A function being translated, with the values its stack code pushed.
+struct Translation {
+    locals: usize,
+
+    /// Where each value on the stack is, the top last.
+    operands: Vec<Operand>,
+
+    code: Code,
+
+    /// How many values are on the stack where jumps land.
+    depths: HashMap<usize, usize>,
+}
+
+impl Translation {
+    /// Translates one instruction of stack code, returning whether the one
+    /// after it is run next.
+    fn instruction(&mut self, instruction: &compiler::Instruction, location: &Location) -> bool {
+        use compiler::Instruction as Stack;
+
+        match instruction {
+            Stack::Push(value) => {
+                let constant = self.constant(value);
+                self.operands.push(constant);
+            }
+            Stack::Local(slot) => self.operands.push(Operand::Register(*slot)),
+            Stack::SetLocal(slot) => {
+                let from = self.pop();
+                // Values pushed from the local are read before it changes.
+                for depth in 0..self.operands.len() {
+                    if self.operands[depth] == Operand::Register(*slot) {
+                        self.spill_one(depth, location);
+                    }
+                }
+                if from != Operand::Register(*slot) {
+                    self.emit(Instruction::Move { to: *slot, from }, location);
+                }
+            }
+            Stack::Captured(index) => {
+                let to = self.push();
+                self.emit(Instruction::Captured { to, index: *index }, location);
+            }
+            Stack::Current => {
+                let to = self.push();
+                self.emit(Instruction::Current { to }, location);
+            }
+            Stack::Global(index) => {
+                let to = self.push();
+                self.emit(Instruction::Global { to, index: *index }, location);
+            }
+            Stack::Closure(index) => {
+                let to = self.push();
+                self.emit(Instruction::Closure { to, index: *index }, location);
+            }
+            Stack::Callee(arguments) => {
+                let depth = self.operands.len() - 1;
+                self.spill(depth, location);
+                let callee = self.register(depth);
+                self.emit(
+                    Instruction::Callee {
+                        callee,
+                        arguments: *arguments,
+                    },
+                    location,
+                );
+            }
+            Stack::Call(arguments) | Stack::TailCall(arguments) => {
+                let depth = self.operands.len() - arguments - 1;
+                self.spill(depth, location);
+                self.operands.truncate(depth);
+                let callee = self.push();
+
+                let (instruction, returns) = match instruction {
+                    Stack::Call(_arguments) => (
+                        Instruction::Call {
+                            callee,
+                            arguments: *arguments,
+                        },
+                        false,
+                    ),
+                    _tail_call => (
+                        Instruction::TailCall {
+                            callee,
+                            arguments: *arguments,
+                        },
+                        true,
+                    ),
+                };
+                self.emit(instruction, location);
+
+                return !returns;
+            }
+            Stack::Return => {
+                let value = self.pop();
+                self.emit(Instruction::Return(value), location);
+
+                return false;
+            }
+            Stack::Jump(target) => {
+                self.jump(*target, location);
+                self.emit(Instruction::Jump(*target), location);
+
+                return false;
+            }
+            Stack::JumpUnless(target) => {
+                let condition = self.pop();
+                self.jump(*target, location);
+                self.emit(Instruction::JumpUnless(condition, *target), location);
+            }
+            Stack::ShortCircuit(op, target) => {
+                self.jump(*target, location);
+                let lhs = self.register(self.operands.len() - 1);
+                self.emit(
+                    Instruction::ShortCircuit {
+                        op: op.clone(),
+                        lhs,
+                        target: *target,
+                    },
+                    location,
+                );
+            }
+            Stack::Binary(op) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let to = self.push();
+                self.emit(
+                    Instruction::Binary {
+                        op: op.clone(),
+                        to,
+                        lhs,
+                        rhs,
+                    },
+                    location,
+                );
+            }
+            Stack::BinaryConst(op, constant) => {
+                let rhs = self.constant(constant);
+                let lhs = self.pop();
+                let to = self.push();
+                self.emit(
+                    Instruction::Binary {
+                        op: op.clone(),
+                        to,
+                        lhs,
+                        rhs,
+                    },
+                    location,
+                );
+            }
+            Stack::CompareJumpUnless(op, constant, target) => {
+                let rhs = match constant {
+                    Some(constant) => self.constant(constant),
+                    None => self.pop(),
+                };
+                let lhs = self.pop();
+                self.jump(*target, location);
+                self.emit(
+                    Instruction::CompareJumpUnless {
+                        op: op.clone(),
+                        lhs,
+                        rhs,
+                        target: *target,
+                    },
+                    location,
+                );
+            }
+            Stack::Tuple => {
+                let second = self.pop();
+                let first = self.pop();
+                let to = self.push();
+                self.emit(Instruction::Tuple { to, first, second }, location);
+            }
+            Stack::First | Stack::Second | Stack::Print => {
+                let from = self.pop();
+                let to = self.push();
+                let instruction = match instruction {
+                    Stack::First => Instruction::First { to, from },
+                    Stack::Second => Instruction::Second { to, from },
+                    _print => Instruction::Print { to, from },
+                };
+                self.emit(instruction, location);
+            }
+            Stack::Pop => {
+                self.pop();
+            }
+        }
+
+        true
+    }
+
+    /// The register of the value at `depth` on the stack.
+    fn register(&self, depth: usize) -> usize {
+        self.locals + depth
+    }
+
+    /// Pushes a value written to its register, giving the register.
+    fn push(&mut self) -> usize {
+        let register = self.register(self.operands.len());
+        self.operands.push(Operand::Register(register));
+        self.code.registers = self.code.registers.max(register + 1);
+
+        register
+    }
+
+    fn constant(&mut self, value: &Value) -> Operand {
+        self.code.constants.push(value.clone());
+
+        Operand::Constant(self.code.constants.len() - 1)
+    }
+
+    fn pop(&mut self) -> Operand {
+        self.operands.pop().expect("the stack holds the operands")
+    }
+
+    /// Spills every value of the stack before jumping to `target`, which
+    /// then lands with as many values.
+    fn jump(&mut self, target: usize, location: &Location) {
+        self.spill(0, location);
+        self.depths.insert(target, self.operands.len());
+    }
+
+    /// Copies the values on the stack from `depth` up to their registers.
+    fn spill(&mut self, depth: usize, location: &Location) {
+        for depth in depth..self.operands.len() {
+            self.spill_one(depth, location);
+        }
+    }
+
+    fn spill_one(&mut self, depth: usize, location: &Location) {
+        let register = self.register(depth);
+        let from = mem::replace(&mut self.operands[depth], Operand::Register(register));
+        if from != Operand::Register(register) {
+            self.code.registers = self.code.registers.max(register + 1);
+            self.emit(Instruction::Move { to: register, from }, location);
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, location: &Location) {
+        self.code.code.push(instruction);
+        self.code.locations.push(location.clone());
+    }
+}
+
+/// A running call: the closure called, the next instruction and where its
+/// registers start on the value stack, right above the callee.
+struct Frame {
+    closure: Arc<Closure>,
+    ip: usize,
+    base: usize,
+
+    /// Where the call was made from, unless the frame is the entrypoint's.
+    called_from: Option<Location>,
+}
+
+/// Runs a compiled `program` on the register machine, looking its
+/// globals up in `context`, as [`vm::run`](crate::vm::run) runs it on the
+/// stack machine.
+pub fn run<I: Printer>(
+    program: &Program,
+    context: &Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let mut machine = Machine {
+        program,
+        globals: program
+            .globals
+            .iter()
+            .map(|name| context.get(*name).cloned())
+            .collect(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+        state,
+        io,
+    };
+
+    // The entrypoint runs in a frame of its own, without counting as a
+    // call.
+    let entrypoint = Arc::new(Closure {
+        function: program.functions[0].clone(),
+        captures: Vec::new(),
+    });
+    machine.stack.push(Value::Compiled(entrypoint.clone()));
+    machine
+        .stack
+        .resize(1 + entrypoint.function.registers().registers, Value::Unit);
+    machine.frames.push(Frame {
+        closure: entrypoint,
+        ip: 0,
+        base: 1,
+        called_from: None,
+    });
+
+    // The frames of the calls are left running when an error happens.
+    machine.execute(0).map_err(|mut error| {
+        if error.backtrace.is_empty() {
+            error.backtrace = interpreter::backtrace(&machine.call_frames());
+        }
+
+        error
+    })
+}
+
+/// Calls the compiled closure `callee` of `program` on the register
+/// machine, as [`vm::call`](crate::vm::call) does on the stack machine.
+pub(crate) fn call<I: Printer>(
+    program: &Program,
+    globals: Vec<Option<Value>>,
+    callee: Value,
+    location: &Location,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let mut machine = Machine {
+        program,
+        globals,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        state,
+        io,
+    };
+
+    machine
+        .call_value(callee, Vec::new(), location)
+        .map_err(|mut error| {
+            if error.backtrace.is_empty() {
+                error.backtrace = interpreter::backtrace(&machine.call_frames());
+            }
+
+            error
+        })
+}
+
+/// The machine natives are called from, with where they are called.
+struct Callback<'m, 'a, I> {
+    machine: &'m mut Machine<'a, I>,
+    location: &'m Location,
+}
+
+impl<I: Printer> Host for Callback<'_, '_, I> {
+    fn apply(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.machine.call_value(callee, arguments, self.location)
+    }
+
+    fn frames(&self) -> Cow<'_, [CallFrame]> {
+        Cow::Owned(self.machine.call_frames())
+    }
+
+    fn resume(&mut self, generator: &Generator) -> Result<Option<Value>, RuntimeError> {
+        let machine = &mut *self.machine;
+
+        generator.resume(
+            Some((machine.program, &machine.globals, Variant::Register)),
+            self.location,
+            machine.state,
+            machine.io,
+        )
+    }
+
+    fn state(&mut self) -> &mut State {
+        self.machine.state
+    }
+}
+
+struct Machine<'a, I> {
+    program: &'a Program,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    state: &'a mut State,
+    io: &'a mut I,
+}
+
+impl<I: Printer> Machine<'_, I> {
+    /// The calls running, outermost first, leaving out the entrypoint.
+    fn call_frames(&self) -> Vec<CallFrame> {
+        let frames = self.frames.iter().filter_map(|frame| {
+            Some(CallFrame {
+                name: frame.closure.function.name.as_deref().map(Arc::from),
+                location: frame.called_from.clone()?,
+                repeated: 0,
+            })
+        });
+
+        frames.collect()
+    }
+
+    /// Calls `callee` with `arguments` until it returns, like natives do
+    /// when they call back into the functions they are given.
+    fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
+        let floor = self.frames.len();
+        let at = self.stack.len();
+        let count = arguments.len();
+        self.stack.push(callee);
+        self.stack.extend(arguments);
+
+        match self.call(at, count, location)? {
+            true => stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || self.execute(floor)),
+            false => {
+                let value = mem::replace(&mut self.stack[at], Value::Unit);
+                self.stack.truncate(at);
+
+                Ok(value)
+            }
+        }
+    }
+
+    /// Calls the callee at `at` on the stack with the `arguments` values
+    /// right above it. A compiled closure gets a new frame and `true` is
+    /// returned; any other callee is applied right away, its result
+    /// written in its place.
+    fn call(
+        &mut self,
+        at: usize,
+        arguments: usize,
+        location: &Location,
+    ) -> Result<bool, RuntimeError> {
+        match &self.stack[at] {
+            Value::Compiled(closure) => {
+                let closure = closure.clone();
+                let function = &closure.function;
+
+                if arguments != function.arity {
+                    return Err(RuntimeError {
+                        kind: RuntimeErrorKind::ArityMismatch,
+                        message: String::from("invalid function call"),
+                        full_text: format!(
+                            "{} expects {} argument(s) but got {}",
+                            function.name.as_deref().unwrap_or("the function"),
+                            function.arity,
+                            arguments
+                        ),
+                        location: location.clone(),
+                        backtrace: Vec::new(),
+                    });
+                }
+
+                if self
+                    .state
+                    .max_depth
+                    .is_some_and(|max_depth| self.frames.len() >= max_depth)
+                {
+                    return Err(RuntimeError {
+                        kind: RuntimeErrorKind::DepthExceeded,
+                        message: String::from("maximum depth exceeded"),
+                        full_text: format!(
+                            "the calls nested more than {} frames deep, likely from unbounded recursion",
+                            self.frames.len()
+                        ),
+                        location: location.clone(),
+                        backtrace: Vec::new(),
+                    });
+                }
+
+                // The frames hold the entrypoint below the calls.
+                self.state
+                    .check_call_depth(self.frames.len().saturating_sub(1), location)?;
+
+                self.state
+                    .stats
+                    .record_call(function.name.as_deref(), &function.location);
+
+                // The registers of the caller above the call are free by
+                // now, so the frame takes their place.
+                let base = at + 1;
+                self.stack
+                    .resize(base + function.registers().registers, Value::Unit);
+                self.frames.push(Frame {
+                    closure,
+                    ip: 0,
+                    base,
+                    called_from: Some(location.clone()),
+                });
+
+                Ok(true)
+            }
+            _callee => {
+                let arguments = self.stack[at + 1..at + 1 + arguments]
+                    .iter_mut()
+                    .map(|argument| mem::replace(argument, Value::Unit))
+                    .collect::<Arguments>();
+                let callee = mem::replace(&mut self.stack[at], Value::Unit);
+
+                let result = match callee {
+                    Value::Native(native) => {
+                        let value = native.call(
+                            arguments.into_vec(),
+                            location,
+                            &mut Callback {
+                                machine: self,
+                                location,
+                            },
+                        )?;
+                        self.state.within_memory(value, location)?
+                    }
+                    callee => interpreter::apply(callee, arguments, location, self.state, self.io)?,
+                };
+                self.stack[at] = result;
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs instructions until the frame at `floor` returns, giving its
+    /// result.
+    fn execute(&mut self, floor: usize) -> Result<Value, RuntimeError> {
+        loop {
+            let frame = self.frames.last_mut().expect("a function is running");
+            let code = frame.closure.function.registers();
+            let ip = frame.ip;
+            let base = frame.base;
+            frame.ip += 1;
+
+            self.state.stats.steps += 1;
+
+            if let Some(progress) = &mut self.state.progress {
+                progress.tick(self.state.stats.steps, &code.locations[ip]);
+            }
+
+            if self.state.cancellation.is_cancelled() {
+                return Err(RuntimeError {
+                    kind: RuntimeErrorKind::Interrupted,
+                    message: String::from("evaluation interrupted"),
+                    full_text: String::from("the evaluation was cancelled before it finished"),
+                    location: code.locations[ip].clone(),
+                    backtrace: Vec::new(),
+                });
+            }
+
+            self.state.consume_fuel(&code.locations[ip])?;
+
+            match code.code[ip].clone() {
+                Instruction::Move { to, from } => {
+                    let value = self.read(base, from);
+                    self.stack[base + to] = value;
+                }
+                Instruction::Captured { to, index } => {
+                    let value = frame.closure.captures[index].clone();
+                    self.stack[base + to] = value;
+                }
+                Instruction::Current { to } => {
+                    let value = Value::Compiled(frame.closure.clone());
+                    self.stack[base + to] = value;
+                }
+                Instruction::Global { to, index } => match &self.globals[index] {
+                    Some(value) => self.stack[base + to] = value.clone(),
+                    None => {
+                        let name = &self.program.globals[index];
+
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::UnboundVariable,
+                            message: format!("unbound variable \"{name}\""),
+                            full_text: format!(
+                                "variable \"{name}\" was not defined in the current scope"
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        });
+                    }
+                },
+                Instruction::Closure { to, index } => {
+                    let function = self.program.functions[index].clone();
+                    let captures = function
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.stack[base + slot].clone(),
+                            Capture::Captured(index) => frame.closure.captures[*index].clone(),
+                            Capture::Current => Value::Compiled(frame.closure.clone()),
+                        })
+                        .collect();
+
+                    self.stack[base + to] =
+                        Value::Compiled(Arc::new(Closure { function, captures }));
+                }
+                Instruction::Callee { callee, arguments } => {
+                    // Natives given the wrong number of arguments fail
+                    // before any of them is evaluated.
+                    if let Value::Native(native) = &self.stack[base + callee] {
+                        native.check_arity(arguments, &code.locations[ip])?;
+                    }
+                }
+                Instruction::Call { callee, arguments } => {
+                    let location = self.location();
+                    self.call(base + callee, arguments, &location)?;
+                }
+                Instruction::TailCall { callee, arguments } => {
+                    let location = self.location();
+                    let at = base + callee;
+
+                    match &self.stack[at] {
+                        Value::Compiled(_) => {
+                            // The callee and its arguments take the place of
+                            // the returning frame.
+                            let frame = self.frames.pop().expect("a function is running");
+                            for offset in 0..=arguments {
+                                self.stack.swap(frame.base - 1 + offset, at + offset);
+                            }
+                            self.call(frame.base - 1, arguments, &location)?;
+                        }
+                        _callee => {
+                            self.call(at, arguments, &location)?;
+                            let value = mem::replace(&mut self.stack[at], Value::Unit);
+                            if let Some(value) = self.ret(floor, value) {
+                                return Ok(value);
+                            }
+                        }
+                    }
+                }
+                Instruction::Return(value) => {
+                    let value = self.read(base, value);
+                    if let Some(value) = self.ret(floor, value) {
+                        return Ok(value);
+                    }
+                }
+                Instruction::Jump(target) => frame.ip = target,
+                Instruction::JumpUnless(condition, target) => match self.read(base, condition) {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => self.frame().ip = target,
+                    condition => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Bool],
+                                found: condition.type_of(),
+                            },
+                            message: String::from("invalid if condition"),
+                            full_text: format!(
+                                "{} can't be used as an if condition. use a boolean instead",
+                                condition
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::ShortCircuit { op, lhs, target } => {
+                    // `&&` and `||` keep the left-hand side as their result
+                    // when it alone decides it.
+                    if let (BinaryOp::And, Value::Bool(false)) | (BinaryOp::Or, Value::Bool(true)) =
+                        (op, &self.stack[base + lhs])
+                    {
+                        frame.ip = target;
+                    }
+                }
+                Instruction::Binary { op, to, lhs, rhs } => {
+                    let lhs = self.read(base, lhs);
+                    let rhs = self.read(base, rhs);
+                    let value = self.operate(&lhs, &op, &rhs)?;
+                    self.stack[base + to] = value;
+                }
+                Instruction::CompareJumpUnless {
+                    op,
+                    lhs,
+                    rhs,
+                    target,
+                } => {
+                    let lhs = self.read(base, lhs);
+                    let rhs = self.read(base, rhs);
+                    // Comparisons only ever result in booleans.
+                    if let Value::Bool(false) = self.operate(&lhs, &op, &rhs)? {
+                        self.frame().ip = target;
+                    }
+                }
+                Instruction::Tuple { to, first, second } => {
+                    let tuple =
+                        Value::Tuple(Tuple::new(self.read(base, first), self.read(base, second)));
+                    self.stack[base + to] = self.state.within_memory(tuple, &self.location())?;
+                }
+                Instruction::First { to, from } => match self.read(base, from) {
+                    Value::Tuple(tuple) => self.stack[base + to] = tuple.first().clone(),
+                    value => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Tuple],
+                                found: value.type_of(),
+                            },
+                            message: String::from("invalid expression"),
+                            full_text: String::from(
+                                "cannot use first operation from anything but a tuple",
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::Second { to, from } => match self.read(base, from) {
+                    Value::Tuple(tuple) => self.stack[base + to] = tuple.second().clone(),
+                    value => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Tuple],
+                                found: value.type_of(),
+                            },
+                            message: String::from("invalid expression"),
+                            full_text: String::from(
+                                "cannot use second operation from anything but a tuple",
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::Print { to, from } => {
+                    let value = self.read(base, from);
+                    let value = self.io.print(value).map_err(|error| RuntimeError {
+                        kind: RuntimeErrorKind::Output,
+                        message: String::from("failed to print"),
+                        full_text: format!("the printed value could not be written: {error}"),
+                        location: self.location(),
+                        backtrace: Vec::new(),
+                    })?;
+                    self.stack[base + to] = value;
+                }
+            }
+        }
+    }
+
+    /// Returns from the running frame with `value`, giving it once the
+    /// frame at `floor` returned.
+    fn ret(&mut self, floor: usize, value: Value) -> Option<Value> {
+        let frame = self.frames.pop().expect("a function is running");
+
+        match self.frames.last() {
+            Some(caller) if self.frames.len() > floor => {
+                let registers = caller.closure.function.registers().registers;
+                self.stack.resize(caller.base + registers, Value::Unit);
+                self.stack[frame.base - 1] = value;
+
+                None
+            }
+            _floor => {
+                self.stack.truncate(frame.base - 1);
+
+                Some(value)
+            }
+        }
+    }
+
+    fn read(&self, base: usize, operand: Operand) -> Value {
+        match operand {
+            Operand::Register(register) => self.stack[base + register].clone(),
+            Operand::Constant(index) => {
+                let frame = self.frames.last().expect("a function is running");
+
+                frame.closure.function.registers().constants[index].clone()
+            }
+        }
+    }
+
+    /// Applies `op` to the operands of the instruction being run, noting
+    /// when it overflows.
+    fn operate(&mut self, lhs: &Value, op: &BinaryOp, rhs: &Value) -> Result<Value, RuntimeError> {
+        let location = self.location();
+        let value = lhs.operate(op, rhs, self.state.division, &location)?;
+        if let (Some(diagnostics), Value::Int(int)) = (&mut self.state.diagnostics, &value) {
+            if lhs.overflows(op, rhs) {
+                diagnostics.overflow(*int, &location);
+            }
+        }
+
+        self.state.within_memory(value, &location)
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a function is running")
+    }
+
+    /// The location of the instruction being run.
+    fn location(&self) -> Location {
+        let frame = self.frames.last().expect("a function is running");
+
+        frame.closure.function.registers().locations[frame.ip - 1].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Instruction, Operand};
+    use crate::{
+        ast::BinaryOp,
+        builtins,
+        compiler::compile,
+        interpreter::{Capture, Context, RuntimeError, State},
+        parser::parse_term,
+        vm,
+    };
+
+    /// Runs `source` on both machines, giving what each printed and
+    /// resulted in.
+    fn both(
+        source: &str,
+        state: impl Fn() -> State,
+    ) -> [(String, Result<String, RuntimeError>); 2] {
+        let program = compile(parse_term(source, "tests").unwrap());
+        let mut context = Context::new();
+        builtins::install(&mut context);
+
+        let mut stack = Capture::new();
+        let stacked = vm::run(&program, &context, &mut state(), &mut stack);
+        let mut registers = Capture::new();
+        let registered = run(&program, &context, &mut state(), &mut registers);
+
+        [
+            (stack.take(), stacked.map(|value| value.to_string())),
+            (registers.take(), registered.map(|value| value.to_string())),
+        ]
+    }
+
+    #[test]
+    fn stack_code_is_translated_to_registers() {
+        let source = "let f = fn (n) => { if (n < 2) { n } else { f(n - 1) } }; f(3)";
+        let program = compile(parse_term(source, "tests").unwrap());
+        let code = program.functions[1].registers();
+
+        // The local `n` is read where it is, the values on the stack are
+        // in the registers above it.
+        assert_eq!(code.registers, 3);
+        assert!(matches!(
+            code.code[..],
+            [
+                Instruction::CompareJumpUnless {
+                    op: BinaryOp::Lt,
+                    lhs: Operand::Register(0),
+                    rhs: Operand::Constant(0),
+                    target: 3,
+                },
+                Instruction::Move {
+                    to: 1,
+                    from: Operand::Register(0),
+                },
+                Instruction::Jump(6),
+                Instruction::Current { to: 1 },
+                Instruction::Binary {
+                    op: BinaryOp::Sub,
+                    to: 2,
+                    lhs: Operand::Register(0),
+                    rhs: Operand::Constant(1),
+                },
+                Instruction::TailCall {
+                    callee: 1,
+                    arguments: 1,
+                },
+                Instruction::Return(Operand::Register(1)),
+            ]
+        ));
+    }
+
+    #[test]
+    fn both_machines_run_alike() {
+        let sources = [
+            // Recursion, in and out of tail position.
+            "let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; print(fib(15))",
+            "let sum = fn (n, acc) => { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum(1000, 0)",
+            // Closures capturing locals, captures and themselves.
+            "let add = fn (x) => { fn (y) => { fn (z) => { x + y + z } } }; (add(1)(2)(3), typeof(add))",
+            // Locals bound again while their value is still being used.
+            "let x = 1; let y = x + { let x = 2; x * 10 }; (x, y)",
+            "let a = (1, \"b\"); let b = first(a) + 1; (second(a), b)",
+            // Short circuits, in and out of conditions.
+            "let t = fn (x) => { let _ = print(x); x }; (t(false) && t(true), t(true) || t(false))",
+            "let f = fn (x) => { if (x > 0 && x < 10) { \"in\" } else { \"out\" } }; (f(5), f(50))",
+            // Natives calling back into closures, and generators.
+            "fix(fn (self, n) => { if (n == 0) { 0 } else { self(n - 1) } })(3)",
+            "let g = generator(fn () => { let _ = yield(1); yield(2) }); let a = next(g); (a, next(g))",
+            // Errors, with the calls they happened in.
+            "let inner = fn (x) => { x + true }; let outer = fn (x) => { let y = inner(x); y }; outer(1)",
+            "let f = fn (a, b) => { a }; f(1)",
+            "typeof(1, 2)",
+            "if (1) { 2 } else { 3 }",
+        ];
+
+        for source in sources {
+            let [(stack_output, stacked), (registers_output, registered)] =
+                both(source, State::new);
+
+            assert_eq!(registers_output, stack_output, "{source}");
+            match (stacked, registered) {
+                (Ok(stacked), Ok(registered)) => assert_eq!(registered, stacked, "{source}"),
+                (Err(stacked), Err(registered)) => {
+                    assert_eq!(registered.full_text, stacked.full_text, "{source}");
+                    assert_eq!(registered.location, stacked.location, "{source}");
+                    assert_eq!(registered.backtrace, stacked.backtrace, "{source}");
+                }
+                (stacked, registered) => panic!("{source}: {stacked:?} but {registered:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        let source = "let sum = fn (n, acc) => { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum(100000, 0)";
+        let limited = || {
+            let mut state = State::new();
+            state.max_depth = Some(10);
+            state.max_call_depth = Some(1);
+
+            state
+        };
+
+        let [_stacked, (_output, registered)] = both(source, limited);
+
+        assert_eq!(registered.unwrap(), "5000050000");
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::{Function, Layout, Slot, Term},
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+    symbol::Symbol,
+};
+
+/// Resolves the variables inside the functions of `term` to the slots of
+/// their frames, so they are read by index instead of looked up by name.
+///
+/// Variables outside of any function, and the ones functions refer to from
+/// there, are left to be looked up by name, so the bindings of a
+/// [`Session`](crate::session::Session) still persist between evaluations.
+pub fn resolve(term: &mut Term) {
+    Resolver::default().term(term);
+}
+
+/// A function being resolved, with the bindings in scope.
+struct Scope {
+    layout: Layout,
+
+    /// Bound names with their slot, innermost last.
+    locals: Vec<(Symbol, usize)>,
+    slots: usize,
+
+    /// The name the function is bound to by its `let`, which refers to
+    /// the function itself inside its body.
+    bound_to: Option<Symbol>,
+}
+
+impl Scope {
+    fn new(bound_to: Option<Symbol>) -> Self {
+        Self {
+            layout: Layout {
+                locals: 0,
+                captures: Vec::new(),
+                captured_names: Vec::new(),
+                recursive: false,
+            },
+            locals: Vec::new(),
+            slots: 0,
+            bound_to,
+        }
+    }
+
+    fn bind(&mut self, name: Symbol) -> usize {
+        let slot = self.slots;
+        self.slots += 1;
+        self.layout.locals = self.layout.locals.max(self.slots);
+        self.locals.push((name, slot));
+
+        slot
+    }
+
+    fn unbind(&mut self) {
+        self.locals.pop();
+        self.slots -= 1;
+    }
+}
+
+#[derive(Default)]
+struct Resolver {
+    /// The functions being resolved, innermost last.
+    scopes: Vec<Scope>,
+}
+
+impl Resolver {
+    fn function(&mut self, function: &mut Function, bound_to: Option<Symbol>) {
+        let mut scope = Scope::new(bound_to);
+        for parameter in Arc::make_mut(&mut function.parameters).iter_mut() {
+            parameter.slot = Some(Slot::Local(scope.bind(parameter.text)));
+        }
+
+        self.scopes.push(scope);
+        self.term(Arc::make_mut(&mut function.value));
+        let scope = self.scopes.pop().expect("the function has a scope");
+
+        function.layout = Some(Arc::new(scope.layout));
+    }
+
+    /// Resolves `name` in the function at `depth`, capturing it from the
+    /// enclosing functions when needed.
+    fn resolve(&mut self, depth: usize, name: Symbol) -> Option<Slot> {
+        let scope = &mut self.scopes[depth];
+
+        if let Some((_name, slot)) = scope.locals.iter().rev().find(|(local, _)| *local == name) {
+            return Some(Slot::Local(*slot));
+        }
+        if scope.bound_to == Some(name) {
+            scope.layout.recursive = true;
+            return Some(Slot::Current);
+        }
+        if let Some(index) = scope
+            .layout
+            .captured_names
+            .iter()
+            .position(|captured| *captured == name)
+        {
+            return Some(Slot::Captured(index));
+        }
+        if depth == 0 {
+            return None;
+        }
+
+        let capture = self.resolve(depth - 1, name)?;
+
+        let scope = &mut self.scopes[depth];
+        scope.layout.captures.push(capture);
+        scope.layout.captured_names.push(name);
+
+        Some(Slot::Captured(scope.layout.captures.len() - 1))
+    }
+
+    fn term(&mut self, term: &mut Term) {
+        // As deep as the term nests.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+            Term::Int(_) | Term::Str(_) | Term::Bool(_) | Term::Unit(_) => {}
+            Term::Var(var) => {
+                var.slot = match self.scopes.len() {
+                    0 => None,
+                    depth => self.resolve(depth - 1, var.text),
+                };
+            }
+            Term::Let(let_) => {
+                match Arc::make_mut(&mut let_.value) {
+                    Term::Function(function) => self.function(function, Some(let_.name.text)),
+                    value => self.term(value),
+                }
+
+                match self.scopes.last_mut() {
+                    Some(scope) => {
+                        let_.name.slot = Some(Slot::Local(scope.bind(let_.name.text)));
+                        self.term(Arc::make_mut(&mut let_.next));
+                        self.scopes
+                            .last_mut()
+                            .expect("the let is inside a function")
+                            .unbind();
+                    }
+                    None => self.term(Arc::make_mut(&mut let_.next)),
+                }
+            }
+            Term::Function(function) => self.function(function, None),
+            Term::Call(call) => {
+                self.term(Arc::make_mut(&mut call.callee));
+                for argument in &mut call.arguments {
+                    self.term(argument);
+                }
+            }
+            Term::Binary(binary) => {
+                self.term(Arc::make_mut(&mut binary.lhs));
+                self.term(Arc::make_mut(&mut binary.rhs));
+            }
+            Term::If(if_) => {
+                self.term(Arc::make_mut(&mut if_.condition));
+                self.term(Arc::make_mut(&mut if_.then));
+                self.term(Arc::make_mut(&mut if_.otherwise));
+            }
+            Term::Cond(cond) => {
+                for arm in &mut cond.arms {
+                    self.term(&mut arm.condition);
+                    self.term(&mut arm.then);
+                }
+                self.term(Arc::make_mut(&mut cond.otherwise));
+            }
+            Term::Seq(seq) => {
+                for term in &mut seq.terms {
+                    self.term(term);
+                }
+            }
+            Term::Print(print) => self.term(Arc::make_mut(&mut print.value)),
+            Term::First(first) => self.term(Arc::make_mut(&mut first.value)),
+            Term::Second(second) => self.term(Arc::make_mut(&mut second.value)),
+            Term::Tuple(tuple) => {
+                self.term(Arc::make_mut(&mut tuple.first));
+                self.term(Arc::make_mut(&mut tuple.second));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+    use crate::ast::{Slot, Term};
+
+    fn term(json: &str) -> Term {
+        serde_json::from_str(&json.replace("LOC", r#"{ "start": 0, "end": 0, "filename": "" }"#))
+            .unwrap()
+    }
+
+    #[test]
+    fn variables_inside_functions_are_resolved_to_slots() {
+        // let y = 1; let f = fn (x) => fn () => f(x + y); f
+        let mut program = term(
+            r#"{ "kind": "Let", "location": LOC,
+                 "name": { "text": "y", "location": LOC },
+                 "value": { "kind": "Int", "value": 1, "location": LOC },
+                 "next": { "kind": "Let", "location": LOC,
+                   "name": { "text": "f", "location": LOC },
+                   "value": { "kind": "Function", "location": LOC,
+                     "parameters": [{ "text": "x", "location": LOC }],
+                     "value": { "kind": "Function", "location": LOC, "parameters": [],
+                       "value": { "kind": "Call", "location": LOC,
+                         "callee": { "kind": "Var", "text": "f", "location": LOC },
+                         "arguments": [{ "kind": "Binary", "op": "Add", "location": LOC,
+                           "lhs": { "kind": "Var", "text": "x", "location": LOC },
+                           "rhs": { "kind": "Var", "text": "y", "location": LOC } }] } } },
+                   "next": { "kind": "Var", "text": "f", "location": LOC } } }"#,
+        );
+        resolve(&mut program);
+
+        let Term::Let(y) = &program else {
+            unreachable!("the program starts with a let")
+        };
+        let Term::Let(f) = &*y.next else {
+            unreachable!("y is followed by a let")
+        };
+        let Term::Function(outer) = &*f.value else {
+            unreachable!("f is a function")
+        };
+        let Term::Function(inner) = &*outer.value else {
+            unreachable!("f returns a function")
+        };
+        let Term::Call(call) = &*inner.value else {
+            unreachable!("the inner function makes a call")
+        };
+        let Term::Binary(sum) = &call.arguments[0] else {
+            unreachable!("the argument is a sum")
+        };
+
+        let outer = outer.layout.as_ref().unwrap();
+        assert_eq!((outer.locals, outer.recursive), (1, true));
+        assert_eq!(
+            inner.layout.as_ref().unwrap().captures,
+            [Slot::Current, Slot::Local(0)]
+        );
+        assert!(matches!(*call.callee, Term::Var(ref f) if f.slot == Some(Slot::Captured(0))));
+        assert!(matches!(*sum.lhs, Term::Var(ref x) if x.slot == Some(Slot::Captured(1))));
+        assert!(matches!(*sum.rhs, Term::Var(ref y) if y.slot.is_none()));
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt::Display;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    ast::Location,
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+};
+
+/// Kinds of terms, as the `kind` field of their JSON names them.
+const KINDS: [&str; 16] = [
+    "Int", "Str", "Bool", "Unit", "Var", "Function", "Let", "Call", "Binary", "If", "Cond", "Seq",
+    "Print", "First", "Second", "Tuple",
+];
+
+/// Operators, as the `op` field of binary terms names them.
+const OPS: [&str; 13] = [
+    "Add", "Sub", "Mul", "Div", "Rem", "Eq", "Neq", "Lt", "Gt", "Lte", "Gte", "And", "Or",
+];
+
+/// Where the JSON of an AST doesn't have the shape of one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Malformed {
+    pub message: String,
+
+    /// The path to the offending value from the root of the file, like
+    /// `expression.next.arguments[1]`.
+    pub path: String,
+
+    /// The kind of the innermost term the offending value is in.
+    pub kind: Option<String>,
+
+    /// The location of the innermost term around the offending value
+    /// having one that could be read.
+    pub location: Option<Location>,
+}
+
+impl Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.path)?;
+        if let Some(kind) = &self.kind {
+            write!(f, ", in a {kind}")?;
+        }
+        if let Some(location) = &self.location {
+            let Location {
+                start,
+                end,
+                filename,
+            } = location;
+            write!(f, ", near {filename}:{start}..{end}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Explains why `json` can't be read as an AST, when it is valid JSON the
+/// AST deserializer refused, as its errors only tell the line and column
+/// of the offending value, which says little for files on a single line.
+///
+/// Returns `None` when `json` isn't valid JSON, where the deserializer's
+/// error is as good as it gets, or when it is a well formed AST.
+pub fn diagnose(json: &[u8]) -> Option<Malformed> {
+    let value = serde_json::from_slice::<Value>(json).ok()?;
+
+    Checker::default().file(&value).err()
+}
+
+enum Segment {
+    Field(&'static str),
+    Index(usize),
+}
+
+/// A term the checker is in.
+struct Node<'a> {
+    kind: &'a str,
+    location: Option<Location>,
+}
+
+/// Walks the JSON of an AST the way it is deserialized, keeping track of
+/// where it is to explain the first value out of shape.
+#[derive(Default)]
+struct Checker<'a> {
+    path: Vec<Segment>,
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&self, message: impl Into<String>) -> Malformed {
+        let mut path = String::from("$");
+        for segment in &self.path {
+            match segment {
+                Segment::Field(name) => path += &format!(".{name}"),
+                Segment::Index(index) => path += &format!("[{index}]"),
+            }
+        }
+
+        Malformed {
+            message: message.into(),
+            path,
+            kind: self.nodes.last().map(|node| node.kind.to_string()),
+            location: self
+                .nodes
+                .iter()
+                .rev()
+                .find_map(|node| node.location.clone()),
+        }
+    }
+
+    fn object(
+        &self,
+        value: &'a Value,
+        expected: &str,
+    ) -> Result<&'a Map<String, Value>, Malformed> {
+        value
+            .as_object()
+            .ok_or_else(|| self.error(format!("expected {expected}, found {}", describe(value))))
+    }
+
+    /// Checks the field `name` of `object` with `check`.
+    fn field(
+        &mut self,
+        object: &'a Map<String, Value>,
+        name: &'static str,
+        check: impl FnOnce(&mut Self, &'a Value) -> Result<(), Malformed>,
+    ) -> Result<(), Malformed> {
+        let value = object
+            .get(name)
+            .ok_or_else(|| self.error(format!("missing field `{name}`")))?;
+
+        self.path.push(Segment::Field(name));
+        check(self, value)?;
+        self.path.pop();
+
+        Ok(())
+    }
+
+    /// Checks every element of the array in the field `name` of `object`
+    /// with `check`.
+    fn elements(
+        &mut self,
+        object: &'a Map<String, Value>,
+        name: &'static str,
+        check: impl Fn(&mut Self, &'a Value) -> Result<(), Malformed>,
+    ) -> Result<(), Malformed> {
+        self.field(object, name, |checker, value| {
+            let elements = value.as_array().ok_or_else(|| {
+                checker.error(format!("expected an array, found {}", describe(value)))
+            })?;
+            for (index, element) in elements.iter().enumerate() {
+                checker.path.push(Segment::Index(index));
+                check(checker, element)?;
+                checker.path.pop();
+            }
+
+            Ok(())
+        })
+    }
+
+    fn file(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        let object = self.object(value, "a file")?;
+        self.field(object, "name", Self::string)?;
+        self.field(object, "expression", Self::term)?;
+        self.field(object, "location", Self::location)
+    }
+
+    fn term(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        // As deep as the AST nests, like the deserializer.
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            let object = self.object(value, "a term")?;
+            let kind = match object.get("kind") {
+                Some(Value::String(kind)) => kind.as_str(),
+                Some(kind) => {
+                    return Err(self.error(format!(
+                        "expected the kind of a term, found {}",
+                        describe(kind)
+                    )))
+                }
+                None => return Err(self.error("missing field `kind`")),
+            };
+            let location = object
+                .get("location")
+                .and_then(|location| Location::deserialize(location).ok());
+            self.nodes.push(Node { kind, location });
+
+            match kind {
+                "Int" => self.field(object, "value", Self::integer)?,
+                "Str" => self.field(object, "value", Self::string)?,
+                "Bool" => self.field(object, "value", Self::boolean)?,
+                "Unit" => {}
+                "Var" => self.field(object, "text", Self::string)?,
+                "Function" => {
+                    self.elements(object, "parameters", Self::var)?;
+                    self.field(object, "value", Self::term)?;
+                }
+                "Let" => {
+                    self.field(object, "name", Self::var)?;
+                    self.field(object, "value", Self::term)?;
+                    self.field(object, "next", Self::term)?;
+                }
+                "Call" => {
+                    self.field(object, "callee", Self::term)?;
+                    self.elements(object, "arguments", Self::term)?;
+                }
+                "Binary" => {
+                    self.field(object, "lhs", Self::term)?;
+                    self.field(object, "op", Self::op)?;
+                    self.field(object, "rhs", Self::term)?;
+                }
+                "If" => {
+                    self.field(object, "condition", Self::term)?;
+                    self.field(object, "then", Self::term)?;
+                    self.field(object, "otherwise", Self::term)?;
+                }
+                "Cond" => {
+                    self.elements(object, "arms", Self::arm)?;
+                    self.field(object, "otherwise", Self::term)?;
+                }
+                "Seq" => self.elements(object, "terms", Self::term)?,
+                "Print" | "First" | "Second" => self.field(object, "value", Self::term)?,
+                "Tuple" => {
+                    self.field(object, "first", Self::term)?;
+                    self.field(object, "second", Self::term)?;
+                }
+                kind => {
+                    return Err(self.error(format!(
+                        "unknown kind `{kind}`, expected one of {}",
+                        KINDS.join(", ")
+                    )))
+                }
+            }
+            self.field(object, "location", Self::location)?;
+
+            self.nodes.pop();
+            Ok(())
+        })
+    }
+
+    fn arm(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        let object = self.object(value, "an arm")?;
+        self.field(object, "condition", Self::term)?;
+        self.field(object, "then", Self::term)
+    }
+
+    fn var(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        let object = self.object(value, "a variable")?;
+        self.field(object, "text", Self::string)?;
+        self.field(object, "location", Self::location)
+    }
+
+    fn op(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        match value.as_str() {
+            Some(op) if OPS.contains(&op) => Ok(()),
+            _op => Err(self.error(format!(
+                "expected one of the operators {}, found {}",
+                OPS.join(", "),
+                describe(value)
+            ))),
+        }
+    }
+
+    fn location(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        let object = self.object(value, "a location")?;
+        self.field(object, "start", Self::unsigned)?;
+        self.field(object, "end", Self::unsigned)?;
+        self.field(object, "filename", Self::string)
+    }
+
+    fn string(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        self.expect(value.is_string(), "a string", value)
+    }
+
+    fn boolean(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        self.expect(value.is_boolean(), "a boolean", value)
+    }
+
+    fn integer(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        self.expect(value.is_i64(), "a 64-bit integer", value)
+    }
+
+    fn unsigned(&mut self, value: &'a Value) -> Result<(), Malformed> {
+        self.expect(value.is_u64(), "an unsigned integer", value)
+    }
+
+    fn expect(&self, holds: bool, expected: &str, value: &Value) -> Result<(), Malformed> {
+        match holds {
+            true => Ok(()),
+            false => Err(self.error(format!("expected {expected}, found {}", describe(value)))),
+        }
+    }
+}
+
+/// What a JSON value is, like "a string", for error messages.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("null"),
+        Value::Bool(bool) => format!("the boolean {bool}"),
+        Value::Number(number) => format!("the number {number}"),
+        Value::String(string) => format!("the string {string:?}"),
+        Value::Array(_) => String::from("an array"),
+        Value::Object(_) => String::from("an object"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diagnose;
+
+    const LOC: &str = r#"{ "start": 3, "end": 9, "filename": "main.rinha" }"#;
+
+    fn file(expression: &str) -> String {
+        format!(r#"{{ "name": "main.rinha", "expression": {expression}, "location": {LOC} }}"#)
+            .replace("LOC", LOC)
+    }
+
+    #[test]
+    fn well_formed_files_are_not_diagnosed() {
+        assert_eq!(diagnose(include_bytes!("../examples/fib.json")), None);
+        assert_eq!(diagnose(b"{ not json"), None);
+    }
+
+    #[test]
+    fn malformed_terms_are_found_by_their_path() {
+        // print(f(1, x)) with the location of `x` left out.
+        let json = file(
+            r#"{ "kind": "Print", "location": LOC,
+                 "value": { "kind": "Call", "location": LOC,
+                   "callee": { "kind": "Var", "text": "f", "location": LOC },
+                   "arguments": [
+                     { "kind": "Int", "value": 1, "location": LOC },
+                     { "kind": "Var", "text": "x" } ] } }"#,
+        );
+        let malformed = diagnose(json.as_bytes()).unwrap();
+
+        assert_eq!(malformed.path, "$.expression.value.arguments[1]");
+        assert_eq!(malformed.kind.as_deref(), Some("Var"));
+        assert_eq!(
+            malformed.to_string(),
+            "missing field `location` at $.expression.value.arguments[1], in a Var, \
+             near main.rinha:3..9"
+        );
+
+        let json = file(r#"{ "kind": "Lett", "location": LOC }"#);
+        let malformed = diagnose(json.as_bytes()).unwrap();
+        assert!(malformed.message.starts_with("unknown kind `Lett`"));
+        assert_eq!(malformed.path, "$.expression");
+    }
+}
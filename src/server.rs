@@ -0,0 +1,364 @@
+use std::{
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    ast::File,
+    builtins,
+    cache::SharedCache,
+    diagnostics::Diagnostics,
+    digest, free,
+    interpreter::{
+        eval, eval_with_timeout, Capture, Context, RuntimeErrorKind, State, DEFAULT_MAX_DEPTH,
+    },
+    parser, purity, resolve,
+};
+
+/// Request header limiting how long a run may take, in milliseconds.
+pub const TIMEOUT_HEADER: &str = "X-Timeout-Ms";
+
+/// Request header limiting how many evaluation steps a run may take.
+pub const FUEL_HEADER: &str = "X-Fuel";
+
+/// Filename of the programs posted as rinha source, as their errors refer
+/// to it.
+pub const SOURCE_FILENAME: &str = "main.rinha";
+
+/// Limits applied to a single run, read from the request headers.
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    pub timeout: Option<Duration>,
+    pub fuel: Option<u64>,
+}
+
+impl Limits {
+    fn from_request(request: &Request) -> Result<Self, String> {
+        let mut limits = Limits::default();
+
+        for header in request.headers() {
+            if header.field.equiv(TIMEOUT_HEADER) {
+                let millis =
+                    header.value.as_str().parse::<u64>().map_err(|_| {
+                        format!("{TIMEOUT_HEADER} must be a number of milliseconds")
+                    })?;
+                limits.timeout = Some(Duration::from_millis(millis));
+            }
+            if header.field.equiv(FUEL_HEADER) {
+                let fuel = header
+                    .value
+                    .as_str()
+                    .parse::<u64>()
+                    .map_err(|_| format!("{FUEL_HEADER} must be a number of steps"))?;
+                limits.fuel = Some(fuel);
+            }
+        }
+
+        Ok(limits)
+    }
+}
+
+/// Serves the evaluation API on `address`, like `127.0.0.1:8080`.
+///
+/// `POST /run` takes a rinha JSON AST, as the CLI reads it, or rinha
+/// source, named [`SOURCE_FILENAME`] in its errors, and answers with a
+/// JSON object holding the resulting `value` and its `type`, the printed
+/// `output`, the run `stats`, the `error` if it failed along with its
+/// `error_code`, and the `diagnostics` of the run, like overflows.
+/// Requests are evaluated by a pool of `workers` threads, so a burst of
+/// requests waits for a worker rather than starting a thread each. Given a
+/// `cache`, all runs memoize their calls in it, so a run reuses the
+/// results of the others.
+pub fn serve(
+    address: &str,
+    cache: Option<SharedCache>,
+    workers: NonZeroUsize,
+) -> Result<(), String> {
+    let server = Server::http(address).map_err(|error| error.to_string())?;
+
+    work(Arc::new(server), cache, workers);
+
+    Ok(())
+}
+
+/// The number of workers of the pool by default, one for each processor,
+/// as evaluations keep them busy.
+pub fn default_workers() -> NonZeroUsize {
+    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
+
+/// Answers the requests of `server` on `workers` threads, each taking the
+/// next request once it answered the last one.
+fn work(server: Arc<Server>, cache: Option<SharedCache>, workers: NonZeroUsize) {
+    let workers = (0..workers.get())
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let cache = cache.clone();
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    // A request failing the interpreter is dropped, which
+                    // answers it with a 500, without taking the worker.
+                    let _ =
+                        panic::catch_unwind(AssertUnwindSafe(|| handle(request, cache.clone())));
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(mut request: Request, cache: Option<SharedCache>) {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Post, "/run") => run_request(&mut request, cache),
+        (_method, "/run") => (405, json!({ "error": "only POST is allowed" })),
+        (_method, _url) => (404, json!({ "error": "not found" })),
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("the content type header is valid");
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+
+    // The client may be gone already, there is no one left to tell.
+    let _ = request.respond(response);
+}
+
+fn run_request(request: &mut Request, cache: Option<SharedCache>) -> (u16, serde_json::Value) {
+    let limits = match Limits::from_request(request) {
+        Ok(limits) => limits,
+        Err(error) => return (400, json!({ "error": error })),
+    };
+
+    let mut body = String::new();
+    if let Err(error) = request.as_reader().read_to_string(&mut body) {
+        return (400, json!({ "error": error.to_string() }));
+    }
+
+    // Bodies that aren't JSON at all are taken for rinha source.
+    let file = match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(_json) => {
+            serde_json::from_str::<File>(&body).map_err(|error| format!("invalid AST: {error}"))
+        }
+        Err(_error) => {
+            parser::parse(&body, SOURCE_FILENAME).map_err(|error| format!("syntax error: {error}"))
+        }
+    };
+
+    match file {
+        Ok(file) => (200, run(file, &limits, cache)),
+        Err(error) => (400, json!({ "error": error })),
+    }
+}
+
+/// Evaluates `file` within `limits`, nesting up to [`DEFAULT_MAX_DEPTH`]
+/// as the CLI does by default, memoizing its calls in `cache` when given,
+/// returning the response body the server would send for it.
+pub fn run(mut file: File, limits: &Limits, cache: Option<SharedCache>) -> serde_json::Value {
+    resolve::resolve(&mut file.expression);
+    free::annotate(&mut file.expression);
+    digest::annotate(&mut file.expression);
+    purity::annotate(&mut file.expression);
+
+    let mut context = Context::new();
+    builtins::install(&mut context);
+    let mut state = State::new();
+    state.max_depth = Some(DEFAULT_MAX_DEPTH);
+    state.fuel = limits.fuel;
+    state.diagnostics = Some(Diagnostics::new());
+    if let Some(cache) = cache {
+        state.cache = Box::new(cache);
+    }
+    let mut capture = Capture::new();
+
+    let result = match limits.timeout {
+        Some(timeout) => eval_with_timeout(
+            file.expression,
+            &mut context,
+            &mut state,
+            &mut capture,
+            timeout,
+        ),
+        None => eval(file.expression, &mut context, &mut state, &mut capture),
+    };
+    let timed_out = matches!(&result, Err(error) if error.kind == RuntimeErrorKind::TimedOut);
+
+    let (value, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(error) => (None, Some(error)),
+    };
+
+    json!({
+        "value": value.as_ref().map(|value| value.to_string()),
+        "type": value.as_ref().map(|value| value.type_name()),
+        // Null for values that can't be written as JSON, like closures.
+        "json": value.as_ref().and_then(|value| serde_json::to_value(value).ok()),
+        "output": capture.take(),
+        "stats": {
+            "steps": state.stats.steps,
+            "calls": state.stats.calls,
+            "cache_hits": state.stats.cache_hits,
+            "cache_misses": state.stats.cache_misses,
+        },
+        "timed_out": timed_out,
+        "error_code": error.as_ref().map(|error| error.kind.code()),
+        "error": error,
+        "diagnostics": state.diagnostics.as_ref().map(Diagnostics::as_slice),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        num::NonZeroUsize,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use tiny_http::Server;
+
+    use super::{run, work, Limits};
+    use crate::{ast::File, cache::SharedCache};
+
+    fn file(expression: &str) -> File {
+        let location = r#"{ "start": 0, "end": 0, "filename": "tests" }"#;
+        let file =
+            format!(r#"{{ "name": "tests", "location": {location}, "expression": {expression} }}"#);
+
+        serde_json::from_str(&file).unwrap()
+    }
+
+    #[test]
+    fn run_captures_output() {
+        let location = r#"{ "start": 0, "end": 0, "filename": "tests" }"#;
+        let print = format!(
+            r#"{{ "kind": "Print", "location": {location},
+                 "value": {{ "kind": "Int", "value": 1, "location": {location} }} }}"#
+        );
+        let response = run(file(&print), &Limits::default(), None);
+
+        assert_eq!(response["value"], "1");
+        assert_eq!(response["type"], "int");
+        assert_eq!(response["json"], 1);
+        assert_eq!(response["output"], "1\n");
+        assert!(response["error"].is_null());
+        assert_eq!(response["diagnostics"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn run_reports_errors() {
+        let location = r#"{ "start": 3, "end": 4, "filename": "tests" }"#;
+        let var = format!(r#"{{ "kind": "Var", "text": "x", "location": {location} }}"#);
+        let limits = Limits {
+            timeout: Some(Duration::from_secs(10)),
+            fuel: None,
+        };
+        let response = run(file(&var), &limits, None);
+
+        assert!(response["value"].is_null());
+        assert_eq!(response["error"]["location"]["start"], 3);
+        assert_eq!(response["error"]["kind"], "UnboundVariable");
+        assert_eq!(response["error_code"], "E0001");
+        assert_eq!(response["timed_out"], false);
+    }
+
+    #[test]
+    fn runs_share_the_cache_given() {
+        let location = r#"{ "start": 0, "end": 0, "filename": "tests" }"#;
+        // let f = fn (x) => x; f(1)
+        let program = format!(
+            r#"{{ "kind": "Let", "location": {location},
+                 "name": {{ "text": "f", "location": {location} }},
+                 "value": {{ "kind": "Function", "location": {location},
+                   "parameters": [{{ "text": "x", "location": {location} }}],
+                   "value": {{ "kind": "Var", "text": "x", "location": {location} }} }},
+                 "next": {{ "kind": "Call", "location": {location},
+                   "callee": {{ "kind": "Var", "text": "f", "location": {location} }},
+                   "arguments": [{{ "kind": "Int", "value": 1, "location": {location} }}] }} }}"#
+        );
+        let cache = SharedCache::new();
+
+        let first = run(file(&program), &Limits::default(), Some(cache.clone()));
+        let second = run(file(&program), &Limits::default(), Some(cache));
+
+        assert_eq!(first["stats"]["cache_misses"], 1);
+        assert_eq!(second["stats"]["cache_hits"], 1);
+        assert_eq!(second["value"], "1");
+    }
+
+    /// Posts `body` to `/run` on `address`, giving the whole response.
+    fn post(address: SocketAddr, body: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /run HTTP/1.1\r\nHost: tests\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    #[test]
+    fn workers_answer_requests_in_turn() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        thread::spawn(move || work(Arc::new(server), None, NonZeroUsize::MIN));
+
+        let body = r#"{ "name": "tests", "location": { "start": 0, "end": 0, "filename": "tests" },
+                        "expression": { "kind": "Int", "value": 1,
+                          "location": { "start": 0, "end": 0, "filename": "tests" } } }"#;
+        // The only worker answers every request, one after the other.
+        for _ in 0..3 {
+            let response = post(address, body);
+
+            assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+            assert!(response.contains(r#""value":"1""#), "{response}");
+        }
+    }
+
+    #[test]
+    fn requests_take_source_too() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        thread::spawn(move || work(Arc::new(server), None, NonZeroUsize::MIN));
+
+        let response = post(address, "let x = 20; print(x + 1)");
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains(r#""output":"21\n""#), "{response}");
+
+        let response = post(address, "let x = ;");
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        assert!(response.contains("syntax error"), "{response}");
+
+        // JSON that isn't an AST isn't taken for source.
+        let response = post(address, "[1, 2]");
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        assert!(response.contains("invalid AST"), "{response}");
+    }
+
+    #[test]
+    fn runs_nest_as_deep_as_the_cli_allows() {
+        let source = "let sum = fn (n) => { if (n == 0) { 0 } else { n + sum(n - 1) } };\n\
+                      sum(1000000)";
+        let file = crate::parser::parse(source, "tests").unwrap();
+        let response = run(file, &Limits::default(), None);
+
+        assert_eq!(response["error"]["kind"], "DepthExceeded");
+    }
+}
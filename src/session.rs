@@ -0,0 +1,233 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    ast::Term,
+    builtins, digest, free,
+    interpreter::{bind, cache_key, eval, Capture, Context, RuntimeError, State, Value},
+    purity, resolve,
+    snapshot::Snapshot,
+    symbol::Symbol,
+};
+
+/// An interactive evaluation session, keeping its bindings, memoization
+/// cache and output between evaluations, like a REPL or a notebook.
+#[derive(Debug)]
+pub struct Session {
+    context: Context,
+    state: State,
+    output: Capture,
+
+    /// Values of the top-level bindings of programs reevaluated so far, by
+    /// the key of their definition.
+    bindings: HashMap<u128, Value>,
+    reused: u64,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        let mut context = Context::new();
+        builtins::install(&mut context);
+
+        Self {
+            context,
+            state: State::new(),
+            output: Capture::new(),
+            bindings: HashMap::new(),
+            reused: 0,
+        }
+    }
+}
+
+impl Session {
+    /// Creates a new instance of [`Session`], with the builtins in scope.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluates `term` and binds the result to `name` for the next
+    /// evaluations, returning the bound value.
+    pub fn define(&mut self, name: &str, term: Term) -> Result<Value, RuntimeError> {
+        let value = self.eval(term)?;
+        bind(Symbol::new(name), value.clone(), &mut self.context);
+
+        Ok(value)
+    }
+
+    /// Evaluates `term` with the bindings of the session in scope.
+    pub fn eval(&mut self, mut term: Term) -> Result<Value, RuntimeError> {
+        resolve::resolve(&mut term);
+        free::annotate(&mut term);
+        digest::annotate(&mut term);
+        purity::annotate(&mut term);
+
+        eval(term, &mut self.context, &mut self.state, &mut self.output)
+    }
+
+    /// Evaluates `program`, a whole program that may have changed since it
+    /// was last evaluated, reusing the values of the top-level bindings
+    /// whose definition and the values it refers to are unchanged, like an
+    /// editor would on every edit.
+    ///
+    /// Only pure definitions that didn't print or call impure natives are
+    /// reused. The bindings of the program don't persist in the session.
+    pub fn reevaluate(&mut self, mut program: Term) -> Result<Value, RuntimeError> {
+        resolve::resolve(&mut program);
+        free::annotate(&mut program);
+        digest::annotate(&mut program);
+        purity::annotate(&mut program);
+
+        let mut context = self.context.clone();
+        let mut term = program;
+        while let Term::Let(let_) = &mut term {
+            let let_ = std::mem::take(let_);
+            let mut value = Arc::unwrap_or_clone(let_.value);
+            let key = binding_key(&mut value, &context, &self.state);
+
+            let value = match key.and_then(|key| self.bindings.get(&key)) {
+                Some(value) => {
+                    self.reused += 1;
+                    value.clone()
+                }
+                None => {
+                    let enclosing_impure = std::mem::take(&mut self.state.impure);
+                    let value = eval(value, &mut context, &mut self.state, &mut self.output);
+                    let impure = std::mem::replace(&mut self.state.impure, enclosing_impure);
+
+                    let value = value?;
+                    if let (Some(key), false) = (key, impure) {
+                        self.bindings.insert(key, value.clone());
+                    }
+
+                    value
+                }
+            };
+
+            bind(let_.name.text, value, &mut context);
+            term = Arc::unwrap_or_clone(let_.next);
+        }
+
+        eval(term, &mut context, &mut self.state, &mut self.output)
+    }
+
+    /// How many bindings [`reevaluate`](Self::reevaluate) reused instead of
+    /// evaluating them again.
+    pub fn reused(&self) -> u64 {
+        self.reused
+    }
+
+    /// The value bound to `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.context.get(Symbol::new(name))
+    }
+
+    /// The bindings of the session sorted by name, leaving the builtins out.
+    pub fn bindings(&self) -> Vec<(&str, &Value)> {
+        let mut bindings = self
+            .context
+            .iter()
+            .filter(|(_name, value)| !matches!(value, Value::Native(_)))
+            .map(|(name, value)| (name.as_str(), value))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(name, _value)| *name);
+
+        bindings
+    }
+
+    /// Takes the output printed since the last call.
+    pub fn take_output(&mut self) -> String {
+        self.output.take()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Takes a [`Snapshot`] of the bindings and cache of the session, to
+    /// [resume](Self::resume) it later.
+    pub fn snapshot(&self) -> Result<Snapshot, String> {
+        Snapshot::take(&self.context, &self.state)
+    }
+
+    /// Resumes the session `snapshot` was taken of, with its bindings and
+    /// cache. Its output and reused bindings aren't kept.
+    pub fn resume(snapshot: &Snapshot) -> Result<Self, String> {
+        let mut session = Self::new();
+        snapshot.restore(&mut session.context, &mut session.state)?;
+
+        Ok(session)
+    }
+}
+
+/// The key the value of a top-level binding defined as `value` is reused
+/// under, or `None` when it isn't reused.
+///
+/// Functions are left out, as evaluating them only creates a closure.
+fn binding_key(value: &mut Term, context: &Context, state: &State) -> Option<u128> {
+    if matches!(value, Term::Function(_)) || !purity::of(value) {
+        return None;
+    }
+
+    let mut names = free::variables(value).into_iter().collect::<Vec<_>>();
+    names.sort_by_key(|name| name.as_str());
+    let values = names
+        .into_iter()
+        .map(|name| context.get(name))
+        .collect::<Option<Vec<_>>>()?;
+
+    cache_key(state.hashing, digest::of(value), values.into_iter(), &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::ast::{
+        build::{int, let_, print, var},
+        Term,
+    };
+
+    #[test]
+    fn definitions_persist() {
+        let mut session = Session::new();
+        session.define("x", int(1)).unwrap();
+
+        let value = session.eval(print(var("x"))).unwrap();
+
+        assert_eq!(value.to_string(), "1");
+        assert_eq!(session.take_output(), "1\n");
+        assert_eq!(session.take_output(), "");
+    }
+
+    #[test]
+    fn bindings_leave_builtins_out() {
+        let mut session = Session::new();
+        session.define("answer", int(42)).unwrap();
+
+        let bindings = session.bindings();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].0, "answer");
+    }
+
+    #[test]
+    fn unchanged_bindings_are_reused() {
+        // let a = 1 + 2; let b = print(a); <last>
+        let program = |first: i64, last: Term| {
+            let_("a", int(first) + int(2)).in_(let_("b", print(var("a"))).in_(last))
+        };
+        let mut session = Session::new();
+
+        let value = session.reevaluate(program(1, var("b"))).unwrap();
+        assert_eq!(value.to_string(), "3");
+        assert_eq!(session.reused(), 0);
+
+        let value = session.reevaluate(program(1, var("a") + var("b"))).unwrap();
+        assert_eq!(value.to_string(), "6");
+        assert_eq!(session.reused(), 1);
+
+        // Bindings that print are evaluated again.
+        assert_eq!(session.take_output(), "3\n3\n");
+
+        session.reevaluate(program(2, var("b"))).unwrap();
+        assert_eq!(session.reused(), 1);
+        assert!(session.get("a").is_none());
+    }
+}
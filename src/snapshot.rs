@@ -0,0 +1,340 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::Function,
+    collections::{Key, Map, Set},
+    interpreter::{Closure, Context, Native, State, Tuple, Value},
+    symbol::Symbol,
+};
+
+/// A checkpoint of the bindings and memoized calls of evaluations, to
+/// resume them later or on another machine. Snapshots are written and read
+/// with serde, as JSON for instance.
+///
+/// Closures are kept as their function, with the values they captured, and
+/// are restored as they were, so their calls hit the restored cache.
+/// Natives are kept by name, and restored from the bindings of the same
+/// name where the snapshot is restored, like the builtins. Only natives
+/// bound the outermost under their own name can be kept, as others, like
+/// the ones made by `fix`, would be restored as a different native.
+///
+/// ```
+/// use lipsum::{ast::build::{function, int, var}, session::Session, snapshot::Snapshot};
+///
+/// let mut session = Session::new();
+/// session.define("double", function(["x"], var("x") * int(2))).unwrap();
+///
+/// let json = serde_json::to_string(&session.snapshot().unwrap()).unwrap();
+/// let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+///
+/// let mut resumed = Session::resume(&snapshot).unwrap();
+/// let value = resumed.eval(var("double").call([int(21)])).unwrap();
+/// assert_eq!(value.to_string(), "42");
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Outermost first, leaving out the natives bound to their own name.
+    bindings: Vec<(Symbol, Snapped)>,
+
+    /// By the key of the call, written in hexadecimal.
+    cache: Vec<(String, Snapped)>,
+}
+
+/// A value as kept in a snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Snapped {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+    Unit,
+    Tuple(Box<(Snapped, Snapped)>),
+    Map(Vec<(Snapped, Snapped)>),
+    Set(Vec<Snapped>),
+    Native(String),
+    Closure {
+        name: Option<String>,
+        function: Function,
+        itself: Vec<Symbol>,
+
+        /// The values captured for the frame of the body, when resolved.
+        captured: Option<Vec<(Symbol, Option<Snapped>)>>,
+        recursive: bool,
+
+        /// The bindings captured by name, outermost first.
+        context: Vec<(Symbol, Snapped)>,
+    },
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the bindings of `context` and of the calls
+    /// cached by `state`, when its cache can list them. Cached values that
+    /// can't be kept are left out, but bindings that can't fail it, like
+    /// generators.
+    pub fn take(context: &Context, state: &State) -> Result<Self, String> {
+        let mut bindings = context
+            .iter()
+            .filter(|(name, value)| {
+                !matches!(value, Value::Native(native) if native.name() == name.as_str() && is_bound(native, context))
+            })
+            .map(|(name, value)| Ok((name, snap(value, context)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        bindings.reverse();
+
+        let cache = state
+            .cache
+            .entries()
+            .into_iter()
+            .filter_map(|(key, value)| Some((format!("{key:032x}"), snap(&value, context).ok()?)))
+            .collect();
+
+        Ok(Self { bindings, cache })
+    }
+
+    /// Binds the bindings of the snapshot in `context` and caches its calls
+    /// in the cache of `state`. Natives are looked up in `context`, failing
+    /// the restore when they aren't bound there.
+    pub fn restore(&self, context: &mut Context, state: &mut State) -> Result<(), String> {
+        for (name, snapped) in &self.bindings {
+            let value = restore(snapped, context)?;
+            context.insert(*name, value);
+        }
+
+        for (key, snapped) in &self.cache {
+            let key = u128::from_str_radix(key, 16)
+                .map_err(|_| format!("invalid cache key {key} in the snapshot"))?;
+            state.cache.insert(key, restore(snapped, context)?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `native` is the one bound the outermost under its name in
+/// `context`, like a builtin, so it is restored by its name.
+fn is_bound(native: &Native, context: &Context) -> bool {
+    matches!(
+        context.outermost(Symbol::new(native.name())),
+        Some(Value::Native(bound)) if bound.same(native)
+    )
+}
+
+/// The value kept as snapped, with its natives checked against the ones
+/// bound in `context`.
+fn snap(value: &Value, context: &Context) -> Result<Snapped, String> {
+    // As deep as the lists built out of tuples nest.
+    stacker::maybe_grow(
+        crate::interpreter::RED_ZONE,
+        crate::interpreter::STACK_SEGMENT,
+        || {
+            let snapped = match value {
+                Value::Int(int) => Snapped::Int(*int),
+                Value::Str(str) => Snapped::Str(str.as_str().to_string()),
+                Value::Bool(bool) => Snapped::Bool(*bool),
+                Value::Unit => Snapped::Unit,
+                Value::Tuple(tuple) => {
+                    Snapped::Tuple(Box::new((
+                        snap(tuple.first(), context)?,
+                        snap(tuple.second(), context)?,
+                    )))
+                }
+                Value::Map(map) => Snapped::Map(
+                    map.iter()
+                        .map(|(key, value)| Ok((snap(key.value(), context)?, snap(value, context)?)))
+                        .collect::<Result<_, String>>()?,
+                ),
+                Value::Set(set) => Snapped::Set(
+                    set.iter()
+                        .map(|key| snap(key.value(), context))
+                        .collect::<Result<_, _>>()?,
+                ),
+                Value::Native(native) if is_bound(native, context) => {
+                    Snapped::Native(native.name().to_string())
+                }
+                Value::Native(native) => {
+                    return Err(format!(
+                        "the native {} isn't the one bound to its name, so it can't be kept in a snapshot",
+                        native.name()
+                    ))
+                }
+                Value::Closure(closure) => snap_closure(closure, context)?,
+                Value::Generator(_) | Value::Compiled(_) => {
+                    return Err(format!(
+                        "a {} can't be kept in a snapshot",
+                        value.type_name()
+                    ))
+                }
+            };
+
+            Ok(snapped)
+        },
+    )
+}
+
+fn snap_closure(closure: &Closure, context: &Context) -> Result<Snapped, String> {
+    let captured = match closure.captured() {
+        Some(captured) => Some(
+            captured
+                .iter()
+                .map(|(name, value)| {
+                    let snapped = value.as_ref().map(|value| snap(value, context));
+                    Ok((*name, snapped.transpose()?))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        None => None,
+    };
+    let mut bindings = closure
+        .context()
+        .iter()
+        .map(|(name, value)| Ok((name, snap(value, context)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    bindings.reverse();
+
+    Ok(Snapped::Closure {
+        name: closure.name().map(String::from),
+        function: closure.function(),
+        itself: closure.itself(),
+        captured,
+        recursive: closure.is_recursive(),
+        context: bindings,
+    })
+}
+
+/// The value kept as `snapped`, with the natives bound in `context`.
+fn restore(snapped: &Snapped, context: &Context) -> Result<Value, String> {
+    stacker::maybe_grow(
+        crate::interpreter::RED_ZONE,
+        crate::interpreter::STACK_SEGMENT,
+        || {
+            let key = |snapped| {
+                Key::new(restore(snapped, context)?)
+                    .ok_or_else(|| String::from("a key of the snapshot can't be a key"))
+            };
+
+            let value = match snapped {
+                Snapped::Int(int) => Value::Int(*int),
+                Snapped::Str(str) => Value::Str(str.as_str().into()),
+                Snapped::Bool(bool) => Value::Bool(*bool),
+                Snapped::Unit => Value::Unit,
+                Snapped::Tuple(tuple) => Value::Tuple(Tuple::new(
+                    restore(&tuple.0, context)?,
+                    restore(&tuple.1, context)?,
+                )),
+                Snapped::Map(entries) => {
+                    Value::Map(entries.iter().try_fold(Map::new(), |map, (k, v)| {
+                        Ok::<_, String>(map.insert(key(k)?, restore(v, context)?))
+                    })?)
+                }
+                Snapped::Set(keys) => Value::Set(
+                    keys.iter()
+                        .try_fold(Set::new(), |set, k| Ok::<_, String>(set.insert(key(k)?)))?,
+                ),
+                Snapped::Native(name) => match context.get(Symbol::new(name)) {
+                    Some(native @ Value::Native(_)) => native.clone(),
+                    _ => return Err(format!("the native {name} of the snapshot isn't bound")),
+                },
+                Snapped::Closure {
+                    name,
+                    function,
+                    itself,
+                    captured,
+                    recursive,
+                    context: bindings,
+                } => {
+                    let captured = match captured {
+                        Some(captured) => Some(
+                            captured
+                                .iter()
+                                .map(|(name, snapped)| {
+                                    let value = snapped
+                                        .as_ref()
+                                        .map(|snapped| restore(snapped, context))
+                                        .transpose()?;
+
+                                    Ok((*name, value))
+                                })
+                                .collect::<Result<_, String>>()?,
+                        ),
+                        None => None,
+                    };
+                    let mut captured_context = Context::new();
+                    for (name, snapped) in bindings {
+                        captured_context.insert(*name, restore(snapped, context)?);
+                    }
+
+                    Value::Closure(Closure::rebuild(
+                        name.as_deref(),
+                        function.clone(),
+                        itself.clone(),
+                        captured,
+                        *recursive,
+                        captured_context,
+                    ))
+                }
+            };
+
+            Ok(value)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::{parser::parse_term, session::Session};
+
+    fn roundtrip(session: &Session) -> Session {
+        let json = serde_json::to_string(&session.snapshot().unwrap()).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+
+        Session::resume(&snapshot).unwrap()
+    }
+
+    #[test]
+    fn sessions_resume_with_their_bindings_and_cache() {
+        let mut session = Session::new();
+        let source = "let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+                      let add = fn (a) => { fn (b) => { a + b } };
+                      let inc = add(1);
+                      let fib20 = fib(20);
+                      fib20";
+        session.eval(parse_term(source, "tests").unwrap()).unwrap();
+
+        let mut resumed = roundtrip(&session);
+        let mut eval = |source| {
+            let value = resumed.eval(parse_term(source, "tests").unwrap());
+            value.unwrap().to_string()
+        };
+        assert_eq!(eval("inc(fib(10))"), "56");
+        assert_eq!(eval("add(2)(3) + fib20"), "6770");
+
+        // The calls of fib made before the snapshot are still cached.
+        let steps = resumed.state().stats.steps;
+        assert_eq!(
+            resumed
+                .eval(parse_term("fib(20)", "tests").unwrap())
+                .unwrap()
+                .to_string(),
+            "6765"
+        );
+        assert!(resumed.state().stats.steps - steps < 10);
+    }
+
+    #[test]
+    fn natives_other_than_the_ones_bound_to_their_name_are_not_kept() {
+        let mut session = Session::new();
+        let source = "let fa = fix(fn (self, n) => { if (n == 0) { 1 } else { n * self(n - 1) } });
+                      fa(3)";
+        session.eval(parse_term(source, "tests").unwrap()).unwrap();
+
+        let error = session.snapshot().unwrap_err();
+        assert!(error.contains("the native fix"), "{error}");
+
+        // Shadowing a builtin with another native doesn't alias it either.
+        let mut session = Session::new();
+        let source = "let fix = fix(fn (self, n) => { n }); 0";
+        session.eval(parse_term(source, "tests").unwrap()).unwrap();
+        assert!(session.snapshot().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Renders errors under the line of source they happened at, as compilers
+//! do, rather than as byte offsets.
+
+use std::fmt::Write;
+
+use crate::{
+    ast::Location,
+    interpreter::RuntimeError,
+    source_map::{Position, SourceFile},
+};
+
+impl RuntimeError {
+    /// Renders the error, with the code of its kind, under the line of
+    /// `source` it happened at, its span underlined and labeled with the
+    /// full text of the error, then the calls it happened in, innermost
+    /// first. `source` is the text the program was parsed from.
+    ///
+    /// ```
+    /// use lipsum::{interpreter::Capture, parser::parse};
+    ///
+    /// let source = "let inc = fn (x) => { x + 1 };\ninc(true)";
+    /// let file = parse(source, "main.rinha").unwrap();
+    /// let error = lipsum::run_file(file, &mut Capture::new()).unwrap_err();
+    ///
+    /// assert_eq!(
+    ///     error.render(source),
+    ///     "error[E0004]: invalid addition
+    ///  --> main.rinha:1:23
+    ///   |
+    /// 1 | let inc = fn (x) => { x + 1 };
+    ///   |                       ^ true cannot be added to 1
+    ///   = in inc, called at main.rinha:2:1
+    /// "
+    /// );
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_in(&SourceFile::new(source))
+    }
+
+    /// Renders the error as [`render`](Self::render) does, with the source
+    /// of `file`.
+    pub(crate) fn render_in(&self, file: &SourceFile) -> String {
+        let mut rendered = render(
+            file,
+            &self.location,
+            self.kind.code(),
+            &self.message,
+            &self.full_text,
+        );
+
+        // Innermost first, as the calls return.
+        for frame in self.backtrace.iter().rev() {
+            let location = &frame.location;
+            let at = match location.filename == self.location.filename {
+                true => {
+                    let Position { line, column } = file.position(location.start);
+                    format!("{}:{line}:{column}", location.filename)
+                }
+                false => format!("{}:{}..{}", location.filename, location.start, location.end),
+            };
+            let name = frame.name.as_deref().unwrap_or("an anonymous function");
+            let _ = writeln!(rendered, "  = in {name}, called at {at}");
+            if frame.repeated > 0 {
+                let _ = writeln!(rendered, "  = … {} more frames of {name}", frame.repeated);
+            }
+        }
+
+        rendered
+    }
+}
+
+/// Renders `message`, along with the error `code`, with the line of `file`
+/// holding the start of `location`, underlining the span up to the end of
+/// that line and labeling it with `label`.
+fn render(
+    file: &SourceFile,
+    location: &Location,
+    code: &str,
+    message: &str,
+    label: &str,
+) -> String {
+    let span = file.span(location);
+    let Position { line, column } = file.position(span.start);
+    let text = file.line(line).unwrap_or_default();
+    let gutter = " ".repeat(line.to_string().len());
+
+    // Tabs are kept under tabs, so the carets line up however wide they
+    // are shown.
+    let indent = text
+        .chars()
+        .take(column - 1)
+        .map(|char| if char == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let underlined = file.text()[span].lines().next().unwrap_or_default();
+    let carets = underlined.chars().count().max(1);
+
+    let mut rendered = format!("error[{code}]: {message}\n");
+    let _ = writeln!(
+        rendered,
+        "{gutter}--> {}:{line}:{column}",
+        location.filename
+    );
+    let _ = writeln!(rendered, "{gutter} |");
+    let _ = writeln!(rendered, "{line} | {text}");
+    let _ = writeln!(
+        rendered,
+        "{gutter} | {indent}{} {label}",
+        "^".repeat(carets)
+    );
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Location,
+        interpreter::{CallFrame, RuntimeError, RuntimeErrorKind},
+    };
+
+    fn error(start: usize, end: usize) -> RuntimeError {
+        RuntimeError {
+            kind: RuntimeErrorKind::UnboundVariable,
+            message: String::from("unbound variable \"y\""),
+            full_text: String::from("not defined"),
+            location: Location::new(start, end, "main.rinha"),
+            backtrace: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn errors_are_underlined_in_their_line() {
+        let source = "let x = 1;\n\tlet é = x;\n\tprint(y + é)\n";
+
+        assert_eq!(
+            error(31, 32).render(source),
+            [
+                "error[E0001]: unbound variable \"y\"",
+                " --> main.rinha:3:8",
+                "  |",
+                "3 | \tprint(y + é)",
+                "  | \t      ^ not defined\n",
+            ]
+            .join("\n")
+        );
+
+        // Spans over several lines are underlined to the end of the first,
+        // and spans past the end of the source at its end.
+        assert_eq!(
+            error(0, 25).render(source).lines().nth(4),
+            Some("  | ^^^^^^^^^^ not defined")
+        );
+        assert_eq!(
+            error(99, 99).render(source).lines().nth(1),
+            Some(" --> main.rinha:4:1")
+        );
+    }
+
+    #[test]
+    fn repeated_calls_are_rendered_once() {
+        let mut error = error(0, 1);
+        error.backtrace = vec![CallFrame {
+            name: Some("loop".into()),
+            location: Location::new(0, 1, "main.rinha"),
+            repeated: 199_999,
+        }];
+
+        assert_eq!(
+            error.render("x").lines().skip(5).collect::<Vec<_>>(),
+            [
+                "  = in loop, called at main.rinha:1:1",
+                "  = … 199999 more frames of loop",
+            ]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    ops::Range,
+};
+
+use crate::{ast::Location, interpreter::RuntimeError, symbol::Symbol};
+
+/// The name of a source file, interned like a [`Symbol`], so the locations
+/// of every element of a file share it rather than repeating its name.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct FileId(Symbol);
+
+impl FileId {
+    /// The id of the file named `filename`, interning it the first time it
+    /// is seen.
+    pub fn new(filename: &str) -> Self {
+        Self(Symbol::new(filename))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        self.0.as_str()
+    }
+}
+
+impl From<&str> for FileId {
+    fn from(filename: &str) -> Self {
+        Self::new(filename)
+    }
+}
+
+impl Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Where in source text a byte offset is, both counted from 1, with columns
+/// counted in chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The text of a source file, along with where each of its lines starts.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    text: String,
+
+    /// The offset of the start of every line, the first at 0.
+    lines: Vec<usize>,
+}
+
+impl SourceFile {
+    /// Creates a new instance of [`SourceFile`].
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(newline, _)| newline + 1))
+            .collect();
+
+        Self { text, lines }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Where `offset` is, offsets past the end of the text being at its end.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = self.floor(offset);
+        let line = self.lines.partition_point(|start| *start <= offset) - 1;
+        let column = self.text[self.lines[line]..offset].chars().count() + 1;
+
+        Position {
+            line: line + 1,
+            column,
+        }
+    }
+
+    /// The text of the line numbered `line`, counting from 1, without its
+    /// line ending.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let range = self.line_range(line.checked_sub(1)?)?;
+
+        Some(self.text[range].trim_end_matches(['\n', '\r']))
+    }
+
+    /// The text `location` spans, cut short at the end of the text.
+    pub fn snippet(&self, location: &Location) -> &str {
+        &self.text[self.span(location)]
+    }
+
+    /// The byte range `location` spans, cut short at the end of the text
+    /// and at char boundaries.
+    pub(crate) fn span(&self, location: &Location) -> Range<usize> {
+        let start = self.floor(location.start);
+
+        start..self.floor(location.end.max(start))
+    }
+
+    /// The byte range of the line at `index`, counting from 0, with its
+    /// line ending.
+    fn line_range(&self, index: usize) -> Option<Range<usize>> {
+        let start = *self.lines.get(index)?;
+        let end = self.lines.get(index + 1).copied();
+
+        Some(start..end.unwrap_or(self.text.len()))
+    }
+
+    /// The nearest char boundary at or before `offset`.
+    fn floor(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        offset
+    }
+}
+
+/// The text of the source files programs were parsed from, by their
+/// [`FileId`], to translate the byte offsets of their [`Location`]s to
+/// lines and columns, as error messages and debuggers show them, for
+/// programs made of several files.
+///
+/// ```
+/// use lipsum::{ast::Location, source_map::{Position, SourceMap}};
+///
+/// let mut sources = SourceMap::new();
+/// sources.add("main.rinha", "let x = 1;\nprint(x)");
+///
+/// let location = Location::new(17, 18, "main.rinha");
+/// assert_eq!(sources.start(&location), Some(Position { line: 2, column: 7 }));
+/// assert_eq!(sources.snippet(&location), Some("x"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: HashMap<FileId, SourceFile>,
+}
+
+impl SourceMap {
+    /// Creates a new instance of [`SourceMap`], with no file loaded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `text` as the source of the file named `filename`, replacing
+    /// what was loaded for it before, returning the id of the file its
+    /// locations refer to it by.
+    pub fn add(&mut self, filename: &str, text: impl Into<String>) -> FileId {
+        let file = FileId::new(filename);
+        self.files.insert(file, SourceFile::new(text));
+
+        file
+    }
+
+    /// The source of `file`, if loaded.
+    pub fn file(&self, file: impl Into<FileId>) -> Option<&SourceFile> {
+        self.files.get(&file.into())
+    }
+
+    /// The ids of the files loaded, in no particular order.
+    pub fn files(&self) -> impl Iterator<Item = FileId> + '_ {
+        self.files.keys().copied()
+    }
+
+    /// Where `location` starts, if its file is loaded.
+    pub fn start(&self, location: &Location) -> Option<Position> {
+        let file = self.file(location.filename)?;
+
+        Some(file.position(location.start))
+    }
+
+    /// Where `location` ends, if its file is loaded.
+    pub fn end(&self, location: &Location) -> Option<Position> {
+        let file = self.file(location.filename)?;
+
+        Some(file.position(location.end))
+    }
+
+    /// The text `location` spans, if its file is loaded.
+    pub fn snippet(&self, location: &Location) -> Option<&str> {
+        let file = self.file(location.filename)?;
+
+        Some(file.snippet(location))
+    }
+
+    /// Renders `error` as [`RuntimeError::render`] does, with the source
+    /// of the file it happened in, if loaded.
+    pub fn render(&self, error: &RuntimeError) -> Option<String> {
+        let file = self.file(error.location.filename)?;
+
+        Some(error.render_in(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileId, Position, SourceFile, SourceMap};
+    use crate::{
+        ast::{Element, Location},
+        parser::parse_term,
+    };
+
+    #[test]
+    fn offsets_translate_to_lines_and_columns() {
+        let file = SourceFile::new("let é = 1;\r\n\nprint(é)\n");
+        let position = |line, column| Position { line, column };
+
+        assert_eq!(file.position(0), position(1, 1));
+        assert_eq!(file.position(6), position(1, 6));
+        assert_eq!(file.position(13), position(2, 1));
+        assert_eq!(file.position(20), position(3, 7));
+        assert_eq!(file.position(99), position(4, 1));
+
+        assert_eq!(file.line(1), Some("let é = 1;"));
+        assert_eq!(file.line(2), Some(""));
+        assert_eq!(file.line(4), Some(""));
+        assert_eq!(file.line(5), None);
+        assert_eq!(file.line(0), None);
+
+        let location = Location::new(20, 99, "main.rinha");
+        assert_eq!(file.snippet(&location), "é)\n");
+    }
+
+    #[test]
+    fn locations_refer_to_the_file_they_are_in() {
+        let mut sources = SourceMap::new();
+        let main = sources.add("main.rinha", "let x = 1;\nlist(x)");
+        let list = sources.add("list.rinha", "fn (x) => { (x, ()) }");
+
+        let term = parse_term("list(x)", "main.rinha").unwrap();
+        assert_eq!(term.location().filename, main);
+        assert_ne!(main, list);
+        assert_eq!(FileId::new("list.rinha").to_string(), "list.rinha");
+
+        let location = Location::new(12, 19, "list.rinha");
+        assert_eq!(sources.snippet(&location), Some("(x, ())"));
+        assert_eq!(
+            sources.start(&location),
+            Some(Position {
+                line: 1,
+                column: 13
+            })
+        );
+        assert_eq!(sources.snippet(&Location::new(0, 1, "other.rinha")), None);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+use crate::ast::Location;
+
+/// Memoization profile of a single function, identified by the
+/// location of its definition and the name it was bound to.
+#[derive(Debug, Default, Clone)]
+pub struct FunctionStats {
+    /// The name of the `let` binding holding the function, if any.
+    pub name: Option<String>,
+
+    /// The location of the function definition in the source code.
+    pub location: Location,
+
+    pub calls: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+
+    /// Total time spent evaluating the body on cache misses.
+    pub miss_time: Duration,
+
+    /// Total steps taken evaluating the body on cache misses.
+    pub miss_steps: u64,
+
+    /// Approximate bytes held by the cache entries of this function.
+    pub retained_bytes: usize,
+}
+
+impl FunctionStats {
+    /// Ratio of memoized calls answered straight from the cache.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.cache_hits + self.cache_misses;
+
+        match lookups {
+            0 => 0.0,
+            lookups => self.cache_hits as f64 / lookups as f64,
+        }
+    }
+
+    /// Average steps it takes to evaluate the body when it is not cached,
+    /// which unlike its time is the same on every run.
+    pub fn average_miss_steps(&self) -> u64 {
+        self.miss_steps.checked_div(self.cache_misses).unwrap_or(0)
+    }
+
+    /// Average time it takes to evaluate the body when it is not cached.
+    pub fn average_miss_cost(&self) -> Duration {
+        match self.cache_misses {
+            0 => Duration::ZERO,
+            misses => self.miss_time / misses as u32,
+        }
+    }
+}
+
+/// Statistics collected during an evaluation, with a per function
+/// breakdown of how effective memoization was.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Number of terms evaluated.
+    pub steps: u64,
+    pub calls: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub functions: HashMap<Location, FunctionStats>,
+}
+
+impl Stats {
+    /// Creates a new instance of [`Stats`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn function(&mut self, name: Option<&str>, location: &Location) -> &mut FunctionStats {
+        let function = self
+            .functions
+            .entry(location.clone())
+            .or_insert_with(|| FunctionStats {
+                location: location.clone(),
+                ..FunctionStats::default()
+            });
+
+        if function.name.is_none() {
+            function.name = name.map(String::from);
+        }
+
+        function
+    }
+
+    pub fn record_call(&mut self, name: Option<&str>, location: &Location) {
+        self.calls += 1;
+        self.function(name, location).calls += 1;
+    }
+
+    pub fn record_hit(&mut self, name: Option<&str>, location: &Location) {
+        self.cache_hits += 1;
+        self.function(name, location).cache_hits += 1;
+    }
+
+    pub fn record_miss(
+        &mut self,
+        name: Option<&str>,
+        location: &Location,
+        cost: Duration,
+        steps: u64,
+        retained_bytes: usize,
+    ) {
+        self.cache_misses += 1;
+
+        let function = self.function(name, location);
+        function.cache_misses += 1;
+        function.miss_time += cost;
+        function.miss_steps += steps;
+        function.retained_bytes += retained_bytes;
+    }
+
+    /// Per function statistics, the most called functions first.
+    pub fn by_function(&self) -> Vec<&FunctionStats> {
+        let mut functions = self.functions.values().collect::<Vec<_>>();
+        functions.sort_by(|l, r| {
+            r.calls
+                .cmp(&l.calls)
+                .then_with(|| l.location.start.cmp(&r.location.start))
+        });
+
+        functions
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "steps: {}, calls: {}, cache hits: {}, cache misses: {}",
+            self.steps, self.calls, self.cache_hits, self.cache_misses
+        )?;
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>8} {:>12} {:>12}",
+            "function", "calls", "hits", "hit rate", "avg miss", "retained"
+        )?;
+
+        for function in self.by_function() {
+            let name = function.name.as_deref().unwrap_or("<anonymous>");
+            let location = &function.location;
+
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>10} {:>7.1}% {:>12} {:>11}B",
+                format!("{name} ({}:{})", location.filename, location.start),
+                function.calls,
+                function.cache_hits,
+                function.hit_rate() * 100.0,
+                format!("{:?}", function.average_miss_cost()),
+                function.retained_bytes,
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    sync::{LazyLock, Mutex},
+};
+
+/// An interned name, like the name of a variable. Symbols of the same
+/// text are equal, so they are compared and hashed as integers, and only
+/// resolved back to their text to be shown.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// The texts of every symbol, which live as long as the program.
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    texts: Vec<&'static str>,
+}
+
+/// The empty text is interned first, so its symbol, the default one, is
+/// known without looking it up.
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| {
+    Mutex::new(Interner {
+        symbols: HashMap::from([("", Symbol(0))]),
+        texts: vec![""],
+    })
+});
+
+impl Symbol {
+    /// The symbol of `text`, interning it the first time it is seen.
+    pub fn new(text: &str) -> Self {
+        let mut interner = INTERNER.lock().expect("the interner is never poisoned");
+
+        if let Some(symbol) = interner.symbols.get(text) {
+            return *symbol;
+        }
+
+        let text: &'static str = Box::leak(text.to_string().into_boxed_str());
+        let symbol = Symbol(interner.texts.len() as u32);
+        interner.texts.push(text);
+        interner.symbols.insert(text, symbol);
+
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let interner = INTERNER.lock().expect("the interner is never poisoned");
+
+        interner.texts[self.0 as usize]
+    }
+}
+
+impl Default for Symbol {
+    /// The symbol of the empty text.
+    fn default() -> Self {
+        Symbol(0)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Self {
+        Symbol::new(text)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Interned straight from the text the deserializer holds, so names
+        // seen before, like most of them, aren't copied into a `String`.
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Symbol;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a name")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Symbol, E> {
+                Ok(Symbol::new(text))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbol;
+
+    #[test]
+    fn symbols_of_the_same_text_are_equal() {
+        let fib = Symbol::new("fib");
+
+        assert_eq!(fib, Symbol::new("fib"));
+        assert_ne!(fib, Symbol::new("fibs"));
+        assert_eq!(fib.as_str(), "fib");
+        assert_eq!(format!("{fib} {fib:?}"), "fib \"fib\"");
+        assert_eq!(Symbol::default(), Symbol::new(""));
+    }
+}
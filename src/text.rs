@@ -0,0 +1,213 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Display},
+    hash::Hash,
+    ops::Deref,
+    sync::{Arc, LazyLock, OnceLock},
+};
+
+/// Strings shorter than this once concatenated are copied together, as
+/// linking them in a rope would take more memory than the copy.
+const COPY_BELOW: usize = 256;
+
+/// An immutable string value. Concatenating long strings links them in a
+/// rope instead of copying them, so building a string piece by piece takes
+/// linear time. A rope is flattened into a single string, kept alongside
+/// it, the first time it is read as a whole.
+#[derive(Clone)]
+pub struct Text(Arc<Node>);
+
+enum Node {
+    Flat(Box<str>),
+    Concat {
+        left: Text,
+        right: Text,
+        len: usize,
+        flat: OnceLock<Box<str>>,
+    },
+}
+
+static EMPTY: LazyLock<Text> = LazyLock::new(|| Text(Arc::new(Node::Flat("".into()))));
+
+impl Text {
+    /// Creates a new, empty instance of [`Text`].
+    pub fn new() -> Self {
+        EMPTY.clone()
+    }
+
+    /// The text of `self` followed by `other`.
+    pub fn concat(&self, other: &Text) -> Text {
+        if other.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return other.clone();
+        }
+
+        let len = self.len() + other.len();
+        if len < COPY_BELOW {
+            return Text::from(format!("{self}{other}"));
+        }
+
+        Text(Arc::new(Node::Concat {
+            left: self.clone(),
+            right: other.clone(),
+            len,
+            flat: OnceLock::new(),
+        }))
+    }
+
+    /// The length of the text in bytes.
+    pub fn len(&self) -> usize {
+        match &*self.0 {
+            Node::Flat(str) => str.len(),
+            Node::Concat { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The whole text, flattening the rope on the first call.
+    pub fn as_str(&self) -> &str {
+        match &*self.0 {
+            Node::Flat(str) => str,
+            Node::Concat { flat, .. } => flat.get_or_init(|| self.chunks().collect()),
+        }
+    }
+
+    /// The pieces the text is made of, in order. Ropes are walked without
+    /// recursion, however long the chain of concatenations.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        let mut pending = vec![self];
+
+        std::iter::from_fn(move || loop {
+            match &*pending.pop()?.0 {
+                Node::Flat(str) => return Some(&**str),
+                Node::Concat { flat, .. } if flat.get().is_some() => {
+                    return flat.get().map(|flat| &**flat)
+                }
+                Node::Concat { left, right, .. } => {
+                    pending.push(right);
+                    pending.push(left);
+                }
+            }
+        })
+    }
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Node {
+    // Dropping long ropes one node at a time, rather than recursively,
+    // keeps it from overflowing the stack.
+    fn drop(&mut self) {
+        let Node::Concat { left, right, .. } = self else {
+            return;
+        };
+        let mut pending = vec![std::mem::take(left), std::mem::take(right)];
+
+        while let Some(text) = pending.pop() {
+            if let Ok(Node::Concat { left, right, .. }) = &mut Arc::try_unwrap(text.0) {
+                pending.push(std::mem::take(left));
+                pending.push(std::mem::take(right));
+            }
+        }
+    }
+}
+
+impl From<&str> for Text {
+    fn from(str: &str) -> Self {
+        Text(Arc::new(Node::Flat(str.into())))
+    }
+}
+
+impl From<String> for Text {
+    fn from(string: String) -> Self {
+        Text(Arc::new(Node::Flat(string.into_boxed_str())))
+    }
+}
+
+impl Deref for Text {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl Debug for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Text {}
+
+impl PartialOrd for Text {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Text {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for Text {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Text, COPY_BELOW};
+
+    #[test]
+    fn long_texts_are_concatenated_into_ropes() {
+        let piece = Text::from("x".repeat(COPY_BELOW));
+        let mut text = Text::new();
+        for _ in 0..100_000 {
+            text = text.concat(&piece);
+        }
+
+        assert_eq!(text.len(), 100_000 * COPY_BELOW);
+        assert_eq!(text.chunks().count(), 100_000);
+        assert!(text.as_str().bytes().all(|byte| byte == b'x'));
+        assert_eq!(text.to_string().len(), text.len());
+
+        // Long ropes are dropped without overflowing the stack.
+        drop(text);
+    }
+
+    #[test]
+    fn texts_compare_by_their_contents() {
+        let long = "y".repeat(COPY_BELOW);
+        let rope = Text::from("a").concat(&Text::from(long.as_str()));
+        let flat = Text::from(format!("a{long}"));
+
+        assert_eq!(rope, flat);
+        assert!(Text::from("a") < Text::from("b"));
+        assert_eq!(Text::from("ab"), Text::from("a").concat(&Text::from("b")));
+    }
+}
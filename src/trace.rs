@@ -0,0 +1,164 @@
+use std::{fmt::Debug, io::Write, str::FromStr};
+
+use crate::{
+    ast::Location,
+    interpreter::{RuntimeError, Value},
+};
+
+/// Selects the calls written out when tracing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Calls of the functions bound to this name.
+    Function(String),
+
+    /// Calls made from a range of byte offsets of a file, like
+    /// `main.rinha:120-250`.
+    Range {
+        filename: String,
+        start: usize,
+        end: usize,
+    },
+}
+
+impl Filter {
+    fn matches(&self, name: Option<&str>, call_site: &Location) -> bool {
+        match self {
+            Filter::Function(function) => name == Some(function.as_str()),
+            Filter::Range {
+                filename,
+                start,
+                end,
+            } => {
+                call_site.filename.as_str() == filename
+                    && call_site.start >= *start
+                    && call_site.end <= *end
+            }
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let Some((filename, range)) = filter.rsplit_once(':') else {
+            return Ok(Filter::Function(filter.to_string()));
+        };
+
+        let invalid = || format!("invalid range {range} of {filename}, expected start-end");
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+
+        Ok(Filter::Range {
+            filename: filename.to_string(),
+            start: start.parse().map_err(|_| invalid())?,
+            end: end.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Writes a line for every traced function call, with its arguments and
+/// result, once it returns.
+pub struct Trace {
+    filters: Vec<Filter>,
+    sink: Box<dyn Write + Send>,
+}
+
+impl Trace {
+    /// Creates a new instance of [`Trace`] writing the calls matching any
+    /// of `filters` to `sink`. Without filters every call is traced.
+    pub fn new(filters: Vec<Filter>, sink: impl Write + Send + 'static) -> Self {
+        Self {
+            filters,
+            sink: Box::new(sink),
+        }
+    }
+
+    /// Parses a comma separated list of filters, like `fib,main.rinha:120-250`.
+    pub fn parse_filters(filters: &str) -> Result<Vec<Filter>, String> {
+        filters
+            .split(',')
+            .map(str::trim)
+            .filter(|filter| !filter.is_empty())
+            .map(Filter::from_str)
+            .collect()
+    }
+
+    pub fn matches(&self, name: Option<&str>, call_site: &Location) -> bool {
+        self.filters.is_empty()
+            || self
+                .filters
+                .iter()
+                .any(|filter| filter.matches(name, call_site))
+    }
+
+    pub fn record(
+        &mut self,
+        name: Option<&str>,
+        call_site: &Location,
+        arguments: &[Value],
+        result: &Result<Value, RuntimeError>,
+    ) {
+        let arguments = arguments
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let result = match result {
+            Ok(value) => value.to_string(),
+            Err(error) => format!("error: {}", error.message),
+        };
+
+        // Tracing is best effort, it never fails the evaluation.
+        let _ = writeln!(
+            self.sink,
+            "{}:{}..{} {}({arguments}) = {result}",
+            call_site.filename,
+            call_site.start,
+            call_site.end,
+            name.unwrap_or("<anonymous>"),
+        );
+    }
+}
+
+impl Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trace")
+            .field("filters", &self.filters)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Trace};
+    use crate::ast::Location;
+
+    #[test]
+    fn parse_filters() {
+        let filters = Trace::parse_filters("fib, main.rinha:120-250").unwrap();
+
+        assert_eq!(
+            filters,
+            vec![
+                Filter::Function(String::from("fib")),
+                Filter::Range {
+                    filename: String::from("main.rinha"),
+                    start: 120,
+                    end: 250,
+                },
+            ]
+        );
+        assert!(Trace::parse_filters("main.rinha:120").is_err());
+    }
+
+    #[test]
+    fn matches_names_and_ranges() {
+        let filters = Trace::parse_filters("fib,main.rinha:120-250").unwrap();
+        let trace = Trace::new(filters, std::io::sink());
+
+        assert!(trace.matches(Some("fib"), &Location::new(0, 1, "other.rinha")));
+        assert!(trace.matches(None, &Location::new(130, 140, "main.rinha")));
+        assert!(!trace.matches(Some("sum"), &Location::new(10, 20, "main.rinha")));
+        assert!(Trace::new(vec![], std::io::sink()).matches(None, &Location::default()));
+    }
+}
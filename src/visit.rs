@@ -0,0 +1,215 @@
+use crate::{
+    ast::{
+        Binary, Bool, Call, Cond, First, Function, If, Int, Let, Print, Second, Seq, Str, Term,
+        Tuple, Unit, Var,
+    },
+    interpreter::{RED_ZONE, STACK_SEGMENT},
+};
+
+/// Walks a term, with a hook for every kind of node. Each hook visits the
+/// children of its node by default, through the `walk_` function of the
+/// same name, so a visitor only overrides the hooks of the nodes it cares
+/// about, calling the `walk_` function to keep walking below them.
+///
+/// ```
+/// use lipsum::{ast::{build::{int, var}, Call}, visit::{walk_call, TermVisitor}};
+///
+/// struct Calls(usize);
+///
+/// impl TermVisitor for Calls {
+///     fn visit_call(&mut self, call: &Call) {
+///         self.0 += 1;
+///         walk_call(self, call);
+///     }
+/// }
+///
+/// let mut calls = Calls(0);
+/// calls.visit_term(&var("f").call([var("g").call([int(1)])]));
+/// assert_eq!(calls.0, 2);
+/// ```
+pub trait TermVisitor {
+    fn visit_term(&mut self, term: &Term) {
+        walk_term(self, term);
+    }
+
+    fn visit_int(&mut self, _int: &Int) {}
+
+    fn visit_str(&mut self, _str: &Str) {}
+
+    fn visit_bool(&mut self, _bool: &Bool) {}
+
+    fn visit_unit(&mut self, _unit: &Unit) {}
+
+    /// Visits a variable referred to, rather than bound.
+    fn visit_var(&mut self, _var: &Var) {}
+
+    /// Visits a name bound by a `let` or as a parameter, before the terms
+    /// it is bound in.
+    fn visit_binding(&mut self, _var: &Var) {}
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function);
+    }
+
+    fn visit_let(&mut self, let_: &Let) {
+        walk_let(self, let_);
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        walk_call(self, call);
+    }
+
+    fn visit_binary(&mut self, binary: &Binary) {
+        walk_binary(self, binary);
+    }
+
+    fn visit_if(&mut self, if_: &If) {
+        walk_if(self, if_);
+    }
+
+    fn visit_cond(&mut self, cond: &Cond) {
+        walk_cond(self, cond);
+    }
+
+    fn visit_seq(&mut self, seq: &Seq) {
+        walk_seq(self, seq);
+    }
+
+    fn visit_print(&mut self, print: &Print) {
+        walk_print(self, print);
+    }
+
+    fn visit_first(&mut self, first: &First) {
+        walk_first(self, first);
+    }
+
+    fn visit_second(&mut self, second: &Second) {
+        walk_second(self, second);
+    }
+
+    fn visit_tuple(&mut self, tuple: &Tuple) {
+        walk_tuple(self, tuple);
+    }
+}
+
+/// Calls the hook of the kind of `term`.
+pub fn walk_term<V: TermVisitor + ?Sized>(visitor: &mut V, term: &Term) {
+    // As deep as the term nests.
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || match term {
+        Term::Int(int) => visitor.visit_int(int),
+        Term::Str(str) => visitor.visit_str(str),
+        Term::Bool(bool) => visitor.visit_bool(bool),
+        Term::Unit(unit) => visitor.visit_unit(unit),
+        Term::Var(var) => visitor.visit_var(var),
+        Term::Function(function) => visitor.visit_function(function),
+        Term::Let(let_) => visitor.visit_let(let_),
+        Term::Call(call) => visitor.visit_call(call),
+        Term::Binary(binary) => visitor.visit_binary(binary),
+        Term::If(if_) => visitor.visit_if(if_),
+        Term::Cond(cond) => visitor.visit_cond(cond),
+        Term::Seq(seq) => visitor.visit_seq(seq),
+        Term::Print(print) => visitor.visit_print(print),
+        Term::First(first) => visitor.visit_first(first),
+        Term::Second(second) => visitor.visit_second(second),
+        Term::Tuple(tuple) => visitor.visit_tuple(tuple),
+    })
+}
+
+/// Visits the parameters and then the body.
+pub fn walk_function<V: TermVisitor + ?Sized>(visitor: &mut V, function: &Function) {
+    function
+        .parameters
+        .iter()
+        .for_each(|parameter| visitor.visit_binding(parameter));
+    visitor.visit_term(&function.value);
+}
+
+/// Visits the name, the value and then the term the name is bound in.
+/// The name comes first as it is bound in its own value, so functions can
+/// refer to themselves.
+pub fn walk_let<V: TermVisitor + ?Sized>(visitor: &mut V, let_: &Let) {
+    visitor.visit_binding(&let_.name);
+    visitor.visit_term(&let_.value);
+    visitor.visit_term(&let_.next);
+}
+
+pub fn walk_call<V: TermVisitor + ?Sized>(visitor: &mut V, call: &Call) {
+    visitor.visit_term(&call.callee);
+    call.arguments
+        .iter()
+        .for_each(|argument| visitor.visit_term(argument));
+}
+
+pub fn walk_binary<V: TermVisitor + ?Sized>(visitor: &mut V, binary: &Binary) {
+    visitor.visit_term(&binary.lhs);
+    visitor.visit_term(&binary.rhs);
+}
+
+pub fn walk_if<V: TermVisitor + ?Sized>(visitor: &mut V, if_: &If) {
+    visitor.visit_term(&if_.condition);
+    visitor.visit_term(&if_.then);
+    visitor.visit_term(&if_.otherwise);
+}
+
+pub fn walk_cond<V: TermVisitor + ?Sized>(visitor: &mut V, cond: &Cond) {
+    for arm in &cond.arms {
+        visitor.visit_term(&arm.condition);
+        visitor.visit_term(&arm.then);
+    }
+    visitor.visit_term(&cond.otherwise);
+}
+
+pub fn walk_seq<V: TermVisitor + ?Sized>(visitor: &mut V, seq: &Seq) {
+    seq.terms.iter().for_each(|term| visitor.visit_term(term));
+}
+
+pub fn walk_print<V: TermVisitor + ?Sized>(visitor: &mut V, print: &Print) {
+    visitor.visit_term(&print.value);
+}
+
+pub fn walk_first<V: TermVisitor + ?Sized>(visitor: &mut V, first: &First) {
+    visitor.visit_term(&first.value);
+}
+
+pub fn walk_second<V: TermVisitor + ?Sized>(visitor: &mut V, second: &Second) {
+    visitor.visit_term(&second.value);
+}
+
+pub fn walk_tuple<V: TermVisitor + ?Sized>(visitor: &mut V, tuple: &Tuple) {
+    visitor.visit_term(&tuple.first);
+    visitor.visit_term(&tuple.second);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TermVisitor;
+    use crate::ast::{File, Var};
+
+    /// The names bound and referred to, in the order they are visited.
+    #[derive(Default)]
+    struct Names {
+        bound: Vec<&'static str>,
+        referred: Vec<&'static str>,
+    }
+
+    impl TermVisitor for Names {
+        fn visit_var(&mut self, var: &Var) {
+            self.referred.push(var.text.as_str());
+        }
+
+        fn visit_binding(&mut self, var: &Var) {
+            self.bound.push(var.text.as_str());
+        }
+    }
+
+    #[test]
+    fn every_node_is_visited_in_order() {
+        let file: File = serde_json::from_str(include_str!("../examples/fib.json")).unwrap();
+
+        let mut names = Names::default();
+        names.visit_term(&file.expression);
+
+        assert_eq!(names.bound, ["fib", "n"]);
+        assert_eq!(names.referred, ["n", "n", "fib", "n", "fib", "n", "fib"]);
+    }
+}
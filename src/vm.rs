@@ -0,0 +1,838 @@
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{
+    ast::{BinaryOp, Location},
+    compiler::{Capture, Function, Instruction, Program},
+    generator::Generator,
+    interpreter::{
+        self, Arguments, CallFrame, Context, Host, Printer, RuntimeError, RuntimeErrorKind, State,
+        Tuple, Type, Value, RED_ZONE, STACK_SEGMENT,
+    },
+};
+
+/// A function of a compiled [`Program`] with the values it captured.
+#[derive(Debug)]
+pub struct Closure {
+    pub(crate) function: Arc<Function>,
+    pub(crate) captures: Vec<Value>,
+}
+
+impl Closure {
+    /// The number of parameters of the function.
+    pub fn arity(&self) -> usize {
+        self.function.arity
+    }
+}
+
+/// The machine running compiled code, on which what it calls back into,
+/// like the bodies of generators, runs too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variant {
+    Stack,
+    Register,
+}
+
+/// A running call: the closure called, the next instruction and where its
+/// locals start on the value stack, right above the callee.
+struct Frame {
+    closure: Arc<Closure>,
+    ip: usize,
+    base: usize,
+
+    /// Where the call was made from, unless the frame is the entrypoint's.
+    called_from: Option<Location>,
+}
+
+/// Runs a compiled `program`, looking its globals up in `context`.
+///
+/// Calls don't nest on the stack of the host, so recursion is only
+/// limited by [`State::max_depth`] and [`State::max_call_depth`], and calls
+/// in tail position reuse the
+/// frame of the caller. Unlike [`eval`](interpreter::eval), results of
+/// pure functions aren't memoized and calls aren't traced.
+pub fn run<I: Printer>(
+    program: &Program,
+    context: &Context,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let mut machine = Machine {
+        program,
+        globals: program
+            .globals
+            .iter()
+            .map(|name| context.get(*name).cloned())
+            .collect(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+        state,
+        io,
+    };
+
+    // The entrypoint runs in a frame of its own, without counting as a
+    // call.
+    let entrypoint = Arc::new(Closure {
+        function: program.functions[0].clone(),
+        captures: Vec::new(),
+    });
+    machine.stack.push(Value::Compiled(entrypoint.clone()));
+    machine
+        .stack
+        .resize(1 + entrypoint.function.locals, Value::Unit);
+    machine.frames.push(Frame {
+        closure: entrypoint,
+        ip: 0,
+        base: 1,
+        called_from: None,
+    });
+
+    // The frames of the calls are left running when an error happens.
+    machine.execute(0).map_err(|mut error| {
+        if error.backtrace.is_empty() {
+            error.backtrace = interpreter::backtrace(&machine.call_frames());
+        }
+
+        error
+    })
+}
+
+/// Calls the compiled closure `callee` of `program`, with no arguments,
+/// as called from `location`. The closure runs with the `globals` of the
+/// run it was created in, as resolved from its context.
+pub(crate) fn call<I: Printer>(
+    program: &Program,
+    globals: Vec<Option<Value>>,
+    callee: Value,
+    location: &Location,
+    state: &mut State,
+    io: &mut I,
+) -> Result<Value, RuntimeError> {
+    let mut machine = Machine {
+        program,
+        globals,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        state,
+        io,
+    };
+
+    machine
+        .call_value(callee, Vec::new(), location)
+        .map_err(|mut error| {
+            if error.backtrace.is_empty() {
+                error.backtrace = interpreter::backtrace(&machine.call_frames());
+            }
+
+            error
+        })
+}
+
+/// The machine natives are called from, with where they are called.
+struct Callback<'m, 'a, I> {
+    machine: &'m mut Machine<'a, I>,
+    location: &'m Location,
+}
+
+impl<I: Printer> Host for Callback<'_, '_, I> {
+    fn apply(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.machine.call_value(callee, arguments, self.location)
+    }
+
+    fn frames(&self) -> Cow<'_, [CallFrame]> {
+        Cow::Owned(self.machine.call_frames())
+    }
+
+    fn resume(&mut self, generator: &Generator) -> Result<Option<Value>, RuntimeError> {
+        let machine = &mut *self.machine;
+
+        generator.resume(
+            Some((machine.program, &machine.globals, Variant::Stack)),
+            self.location,
+            machine.state,
+            machine.io,
+        )
+    }
+
+    fn state(&mut self) -> &mut State {
+        self.machine.state
+    }
+}
+
+struct Machine<'a, I> {
+    program: &'a Program,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    state: &'a mut State,
+    io: &'a mut I,
+}
+
+impl<I: Printer> Machine<'_, I> {
+    /// The calls running, outermost first, leaving out the entrypoint.
+    fn call_frames(&self) -> Vec<CallFrame> {
+        let frames = self.frames.iter().filter_map(|frame| {
+            Some(CallFrame {
+                name: frame.closure.function.name.as_deref().map(Arc::from),
+                location: frame.called_from.clone()?,
+                repeated: 0,
+            })
+        });
+
+        frames.collect()
+    }
+
+    /// Calls `callee` with `arguments` until it returns, like natives do
+    /// when they call back into the functions they are given.
+    fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        location: &Location,
+    ) -> Result<Value, RuntimeError> {
+        let floor = self.frames.len();
+        let count = arguments.len();
+        self.stack.push(callee);
+        self.stack.extend(arguments);
+
+        match self.call(count, location)? {
+            true => stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || self.execute(floor)),
+            false => Ok(self.pop()),
+        }
+    }
+
+    /// Calls the callee below `arguments` values on the stack. A compiled
+    /// closure gets a new frame and `true` is returned; any other callee
+    /// is applied right away, replaced on the stack by its result.
+    fn call(&mut self, arguments: usize, location: &Location) -> Result<bool, RuntimeError> {
+        let callee_at = self.stack.len() - arguments - 1;
+
+        match &self.stack[callee_at] {
+            Value::Compiled(closure) => {
+                let closure = closure.clone();
+                let function = &closure.function;
+
+                if arguments != function.arity {
+                    return Err(RuntimeError {
+                        kind: RuntimeErrorKind::ArityMismatch,
+                        message: String::from("invalid function call"),
+                        full_text: format!(
+                            "{} expects {} argument(s) but got {}",
+                            function.name.as_deref().unwrap_or("the function"),
+                            function.arity,
+                            arguments
+                        ),
+                        location: location.clone(),
+                        backtrace: Vec::new(),
+                    });
+                }
+
+                if self
+                    .state
+                    .max_depth
+                    .is_some_and(|max_depth| self.frames.len() >= max_depth)
+                {
+                    return Err(RuntimeError {
+                        kind: RuntimeErrorKind::DepthExceeded,
+                        message: String::from("maximum depth exceeded"),
+                        full_text: format!(
+                            "the calls nested more than {} frames deep, likely from unbounded recursion",
+                            self.frames.len()
+                        ),
+                        location: location.clone(),
+                        backtrace: Vec::new(),
+                    });
+                }
+
+                // The frames hold the entrypoint below the calls.
+                self.state
+                    .check_call_depth(self.frames.len().saturating_sub(1), location)?;
+
+                self.state
+                    .stats
+                    .record_call(function.name.as_deref(), &function.location);
+
+                let base = callee_at + 1;
+                self.stack.resize(base + function.locals, Value::Unit);
+                self.frames.push(Frame {
+                    closure,
+                    ip: 0,
+                    base,
+                    called_from: Some(location.clone()),
+                });
+
+                Ok(true)
+            }
+            _callee => {
+                let arguments = self.stack.drain(callee_at + 1..).collect::<Arguments>();
+                let callee = self.pop();
+
+                let result = match callee {
+                    Value::Native(native) => {
+                        let value = native.call(
+                            arguments.into_vec(),
+                            location,
+                            &mut Callback {
+                                machine: self,
+                                location,
+                            },
+                        )?;
+                        self.state.within_memory(value, location)?
+                    }
+                    callee => interpreter::apply(callee, arguments, location, self.state, self.io)?,
+                };
+                self.stack.push(result);
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs instructions until the frame at `floor` returns, giving its
+    /// result.
+    fn execute(&mut self, floor: usize) -> Result<Value, RuntimeError> {
+        loop {
+            let frame = self.frames.last_mut().expect("a function is running");
+            let function = &frame.closure.function;
+            let ip = frame.ip;
+            frame.ip += 1;
+
+            self.state.stats.steps += 1;
+
+            if let Some(progress) = &mut self.state.progress {
+                progress.tick(self.state.stats.steps, &function.locations[ip]);
+            }
+
+            if self.state.cancellation.is_cancelled() {
+                return Err(RuntimeError {
+                    kind: RuntimeErrorKind::Interrupted,
+                    message: String::from("evaluation interrupted"),
+                    full_text: String::from("the evaluation was cancelled before it finished"),
+                    location: function.locations[ip].clone(),
+                    backtrace: Vec::new(),
+                });
+            }
+
+            self.state.consume_fuel(&function.locations[ip])?;
+
+            match function.code[ip].clone() {
+                Instruction::Push(value) => self.stack.push(value),
+                Instruction::Local(slot) => {
+                    let value = self.stack[frame.base + slot].clone();
+                    self.stack.push(value);
+                }
+                Instruction::SetLocal(slot) => {
+                    let base = frame.base;
+                    let value = self.pop();
+                    self.stack[base + slot] = value;
+                }
+                Instruction::Captured(index) => {
+                    let value = frame.closure.captures[index].clone();
+                    self.stack.push(value);
+                }
+                Instruction::Current => {
+                    let value = Value::Compiled(frame.closure.clone());
+                    self.stack.push(value);
+                }
+                Instruction::Global(index) => match &self.globals[index] {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => {
+                        let name = &self.program.globals[index];
+
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::UnboundVariable,
+                            message: format!("unbound variable \"{name}\""),
+                            full_text: format!(
+                                "variable \"{name}\" was not defined in the current scope"
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        });
+                    }
+                },
+                Instruction::Closure(index) => {
+                    let function = self.program.functions[index].clone();
+                    let captures = function
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.stack[frame.base + slot].clone(),
+                            Capture::Captured(index) => frame.closure.captures[*index].clone(),
+                            Capture::Current => Value::Compiled(frame.closure.clone()),
+                        })
+                        .collect();
+
+                    self.stack
+                        .push(Value::Compiled(Arc::new(Closure { function, captures })));
+                }
+                Instruction::Callee(arguments) => {
+                    // Natives given the wrong number of arguments fail
+                    // before any of them is evaluated.
+                    if let Some(Value::Native(native)) = self.stack.last() {
+                        native.check_arity(arguments, &function.locations[ip])?;
+                    }
+                }
+                Instruction::Call(arguments) => {
+                    let location = self.location();
+                    self.call(arguments, &location)?;
+                }
+                Instruction::TailCall(arguments) => {
+                    let location = self.location();
+                    let callee_at = self.stack.len() - arguments - 1;
+
+                    match &self.stack[callee_at] {
+                        Value::Compiled(_) => {
+                            // The callee and its arguments take the place of
+                            // the returning frame.
+                            let frame = self.frames.pop().expect("a function is running");
+                            self.stack.drain(frame.base - 1..callee_at);
+                            self.call(arguments, &location)?;
+                        }
+                        _callee => {
+                            self.call(arguments, &location)?;
+                            if let Some(value) = self.ret(floor) {
+                                return Ok(value);
+                            }
+                        }
+                    }
+                }
+                Instruction::Return => {
+                    if let Some(value) = self.ret(floor) {
+                        return Ok(value);
+                    }
+                }
+                Instruction::Jump(target) => frame.ip = target,
+                Instruction::JumpUnless(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => self.frame().ip = target,
+                    condition => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Bool],
+                                found: condition.type_of(),
+                            },
+                            message: String::from("invalid if condition"),
+                            full_text: format!(
+                                "{} can't be used as an if condition. use a boolean instead",
+                                condition
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::ShortCircuit(op, target) => {
+                    // `&&` and `||` keep the left-hand side as their result
+                    // when it alone decides it.
+                    if let (BinaryOp::And, Some(Value::Bool(false)))
+                    | (BinaryOp::Or, Some(Value::Bool(true))) = (op, self.stack.last())
+                    {
+                        frame.ip = target;
+                    }
+                }
+                Instruction::Binary(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let value = self.operate(&lhs, &op, &rhs)?;
+                    self.stack.push(value);
+                }
+                Instruction::BinaryConst(op, rhs) => {
+                    let lhs = self.pop();
+                    let value = self.operate(&lhs, &op, &rhs)?;
+                    self.stack.push(value);
+                }
+                Instruction::CompareJumpUnless(op, rhs, target) => {
+                    let rhs = rhs.unwrap_or_else(|| self.pop());
+                    let lhs = self.pop();
+                    // Comparisons only ever result in booleans.
+                    if let Value::Bool(false) = self.operate(&lhs, &op, &rhs)? {
+                        self.frame().ip = target;
+                    }
+                }
+                Instruction::Tuple => {
+                    let second = self.pop();
+                    let first = self.pop();
+                    let tuple = Value::Tuple(Tuple::new(first, second));
+                    self.stack
+                        .push(self.state.within_memory(tuple, &self.location())?);
+                }
+                Instruction::First => match self.pop() {
+                    Value::Tuple(tuple) => self.stack.push(tuple.first().clone()),
+                    value => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Tuple],
+                                found: value.type_of(),
+                            },
+                            message: String::from("invalid expression"),
+                            full_text: String::from(
+                                "cannot use first operation from anything but a tuple",
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::Second => match self.pop() {
+                    Value::Tuple(tuple) => self.stack.push(tuple.second().clone()),
+                    value => {
+                        return Err(RuntimeError {
+                            kind: RuntimeErrorKind::TypeMismatch {
+                                expected: &[Type::Tuple],
+                                found: value.type_of(),
+                            },
+                            message: String::from("invalid expression"),
+                            full_text: String::from(
+                                "cannot use second operation from anything but a tuple",
+                            ),
+                            location: self.location(),
+                            backtrace: Vec::new(),
+                        })
+                    }
+                },
+                Instruction::Print => {
+                    let value = self.pop();
+                    let value = self.io.print(value).map_err(|error| RuntimeError {
+                        kind: RuntimeErrorKind::Output,
+                        message: String::from("failed to print"),
+                        full_text: format!("the printed value could not be written: {error}"),
+                        location: self.location(),
+                        backtrace: Vec::new(),
+                    })?;
+                    self.stack.push(value);
+                }
+                Instruction::Pop => {
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    /// Returns from the running frame with the value on top of the stack,
+    /// giving it once the frame at `floor` returned.
+    fn ret(&mut self, floor: usize) -> Option<Value> {
+        let value = self.pop();
+        let frame = self.frames.pop().expect("a function is running");
+        self.stack.truncate(frame.base - 1);
+
+        match self.frames.len() == floor {
+            true => Some(value),
+            false => {
+                self.stack.push(value);
+                None
+            }
+        }
+    }
+
+    /// Applies `op` to the operands of the instruction being run, noting
+    /// when it overflows.
+    fn operate(&mut self, lhs: &Value, op: &BinaryOp, rhs: &Value) -> Result<Value, RuntimeError> {
+        let location = self.location();
+        let value = lhs.operate(op, rhs, self.state.division, &location)?;
+        if let (Some(diagnostics), Value::Int(int)) = (&mut self.state.diagnostics, &value) {
+            if lhs.overflows(op, rhs) {
+                diagnostics.overflow(*int, &location);
+            }
+        }
+
+        self.state.within_memory(value, &location)
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a function is running")
+    }
+
+    /// The location of the instruction being run.
+    fn location(&self) -> Location {
+        let frame = self.frames.last().expect("a function is running");
+
+        frame.closure.function.locations[frame.ip - 1].clone()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack holds the operands")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::run;
+    use crate::{
+        ast::{Binary, BinaryOp, Call, Function, If, Int, Let, Location, Term, Tuple, Var},
+        builtins,
+        compiler::compile,
+        interpreter::{Capture, Context, RuntimeErrorKind, State, Value},
+    };
+
+    fn int(value: i64) -> Term {
+        Term::Int(Int {
+            value,
+            location: Location::default(),
+        })
+    }
+
+    fn var(text: &str) -> Term {
+        Term::Var(Var {
+            text: text.into(),
+            location: Location::default(),
+            slot: None,
+        })
+    }
+
+    fn binary(lhs: Term, op: BinaryOp, rhs: Term) -> Term {
+        Term::Binary(Binary {
+            lhs: Arc::new(lhs),
+            op,
+            rhs: Arc::new(rhs),
+            location: Location::default(),
+        })
+    }
+
+    fn function(parameters: &[&str], value: Term) -> Term {
+        Term::Function(Function {
+            parameters: parameters
+                .iter()
+                .map(|parameter| Var {
+                    text: (*parameter).into(),
+                    location: Location::default(),
+                    slot: None,
+                })
+                .collect(),
+            value: Arc::new(value),
+            location: Location::default(),
+            free: None,
+            layout: None,
+            digest: None,
+            pure: None,
+        })
+    }
+
+    fn call(callee: Term, arguments: Vec<Term>) -> Term {
+        Term::Call(Call {
+            callee: Arc::new(callee),
+            arguments,
+            location: Location::default(),
+        })
+    }
+
+    fn let_(name: &str, value: Term, next: Term) -> Term {
+        Term::Let(Let {
+            name: Var {
+                text: name.into(),
+                location: Location::default(),
+                slot: None,
+            },
+            value: Arc::new(value),
+            next: Arc::new(next),
+            location: Location::default(),
+        })
+    }
+
+    fn execute(term: Term, state: &mut State) -> Result<Value, String> {
+        let mut context = Context::new();
+        builtins::install(&mut context);
+
+        run(&compile(term), &context, state, &mut Capture::new()).map_err(|error| error.full_text)
+    }
+
+    /// `let sum = fn (n, acc) => if (n == 0) { acc } else { sum(n - 1, acc + n) }; sum(n, 0)`
+    fn sum_to(n: i64) -> Term {
+        let body = Term::If(If {
+            condition: Arc::new(binary(var("n"), BinaryOp::Eq, int(0))),
+            then: Arc::new(var("acc")),
+            otherwise: Arc::new(call(
+                var("sum"),
+                vec![
+                    binary(var("n"), BinaryOp::Sub, int(1)),
+                    binary(var("acc"), BinaryOp::Add, var("n")),
+                ],
+            )),
+            location: Location::default(),
+        });
+
+        let_(
+            "sum",
+            function(&["n", "acc"], body),
+            call(var("sum"), vec![int(n), int(0)]),
+        )
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        let mut state = State::new();
+        state.max_depth = Some(10);
+        state.max_call_depth = Some(1);
+
+        let result = execute(sum_to(100_000), &mut state).unwrap();
+
+        assert_eq!(result.to_string(), "5000050000");
+        assert_eq!(state.stats.calls, 100_001);
+    }
+
+    #[test]
+    fn closures_capture_their_environment() {
+        // let add = fn (x) => fn (y) => x + y; (add(1)(2), typeof(add))
+        let term = let_(
+            "add",
+            function(
+                &["x"],
+                function(&["y"], binary(var("x"), BinaryOp::Add, var("y"))),
+            ),
+            Term::Tuple(Tuple {
+                first: Arc::new(call(call(var("add"), vec![int(1)]), vec![int(2)])),
+                second: Arc::new(call(var("typeof"), vec![var("add")])),
+                location: Location::default(),
+            }),
+        );
+
+        let result = execute(term, &mut State::new()).unwrap();
+
+        assert_eq!(result.to_string(), "(3, closure)");
+    }
+
+    #[test]
+    fn natives_call_back_into_closures() {
+        // fix(fn (self, n) => if (n == 0) { 0 } else { self(n - 1) })(3)
+        let body = Term::If(If {
+            condition: Arc::new(binary(var("n"), BinaryOp::Eq, int(0))),
+            then: Arc::new(int(0)),
+            otherwise: Arc::new(call(
+                var("self"),
+                vec![binary(var("n"), BinaryOp::Sub, int(1))],
+            )),
+            location: Location::default(),
+        });
+        let term = call(
+            call(var("fix"), vec![function(&["self", "n"], body)]),
+            vec![int(3)],
+        );
+
+        let result = execute(term, &mut State::new()).unwrap();
+
+        assert_eq!(result.to_string(), "0");
+    }
+
+    #[test]
+    fn depth_counts_the_calls_being_evaluated() {
+        // let down = fn (n) => if (n == 0) { depth() } else { 1 + down(n - 1) }; down(3)
+        // where the tail variant calls `down(n - 1)` alone.
+        let down = |recurse: fn(Term) -> Term| {
+            let body = Term::If(If {
+                condition: Arc::new(binary(var("n"), BinaryOp::Eq, int(0))),
+                then: Arc::new(call(var("depth"), vec![])),
+                otherwise: Arc::new(recurse(call(
+                    var("down"),
+                    vec![binary(var("n"), BinaryOp::Sub, int(1))],
+                ))),
+                location: Location::default(),
+            });
+
+            let_(
+                "down",
+                function(&["n"], body),
+                call(var("down"), vec![int(3)]),
+            )
+        };
+        let nested = down(|call| binary(int(1), BinaryOp::Add, call));
+        let tail = down(|call| call);
+
+        for (term, expected) in [(nested, "7"), (tail, "1")] {
+            let mut context = Context::new();
+            builtins::install(&mut context);
+            let walked = crate::interpreter::eval(
+                term.clone(),
+                &mut context,
+                &mut State::new(),
+                &mut Capture::new(),
+            );
+
+            assert_eq!(walked.unwrap().to_string(), expected);
+            assert_eq!(
+                execute(term, &mut State::new()).unwrap().to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut state = State::new();
+
+        assert_eq!(
+            execute(call(var("nope"), vec![]), &mut state).unwrap_err(),
+            "variable \"nope\" was not defined in the current scope"
+        );
+        assert_eq!(
+            execute(call(function(&["x"], var("x")), vec![]), &mut state).unwrap_err(),
+            "the function expects 1 argument(s) but got 0"
+        );
+        assert_eq!(
+            execute(
+                call(var("typeof"), vec![var("nope"), var("nope")]),
+                &mut state
+            )
+            .unwrap_err(),
+            "typeof expects 1 argument(s) but got 2"
+        );
+    }
+
+    #[test]
+    fn both_backends_check_the_arity_of_closures() {
+        // let f = fn (a, b) => a; f(1) and let f = fn (a) => a; f(1, 2)
+        let missing = let_(
+            "f",
+            function(&["a", "b"], var("a")),
+            call(var("f"), vec![int(1)]),
+        );
+        let extra = let_(
+            "f",
+            function(&["a"], var("a")),
+            call(var("f"), vec![int(1), int(2)]),
+        );
+        // (fn (a) => a)(1, 2)
+        let immediate = call(function(&["a"], var("a")), vec![int(1), int(2)]);
+
+        for (term, expected) in [
+            (missing, "f expects 2 argument(s) but got 1"),
+            (extra, "f expects 1 argument(s) but got 2"),
+            (immediate, "the function expects 1 argument(s) but got 2"),
+        ] {
+            let mut context = Context::new();
+            builtins::install(&mut context);
+            let walked = crate::interpreter::eval(
+                term.clone(),
+                &mut context,
+                &mut State::new(),
+                &mut Capture::new(),
+            )
+            .unwrap_err();
+
+            assert_eq!(walked.kind, RuntimeErrorKind::ArityMismatch);
+            assert_eq!(walked.full_text, expected);
+            assert_eq!(execute(term, &mut State::new()).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn both_backends_resume_generators() {
+        let source = "let count = fn (n) => { let _ = print(n); let _ = yield(n); count(n + 1) };
+                      let numbers = generator(fn () => { count(0) });
+                      let a = next(numbers);
+                      (a, next(numbers))";
+        let term = crate::parser::parse_term(source, "tests").unwrap();
+
+        let mut context = Context::new();
+        builtins::install(&mut context);
+        let mut walked = Capture::new();
+        let walked_result =
+            crate::interpreter::eval(term.clone(), &mut context, &mut State::new(), &mut walked);
+        let mut ran = Capture::new();
+        let ran_result = run(&compile(term), &context, &mut State::new(), &mut ran);
+
+        assert_eq!(walked_result.unwrap().to_string(), "((true, 0), (true, 1))");
+        assert_eq!(ran_result.unwrap().to_string(), "((true, 0), (true, 1))");
+        assert_eq!(walked.output(), "0\n1\n");
+        assert_eq!(ran.output(), walked.output());
+    }
+}
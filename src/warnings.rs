@@ -0,0 +1,254 @@
+use std::fmt::{Debug, Display};
+
+use crate::{
+    ast::{Function, Let, Location, Term, Var},
+    symbol::Symbol,
+    visit::TermVisitor,
+};
+
+/// Something suspicious about a program that doesn't stop it from running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+
+    /// The name bound again.
+    pub name: Symbol,
+
+    /// Where the name is bound again.
+    pub location: Location,
+
+    /// Where the name was bound first.
+    pub shadowed: Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A `let` or a parameter binds a name already bound in the same
+    /// function, or a `let` binds one bound around the function.
+    ShadowedBinding,
+
+    /// A parameter binds a name bound around the function, which the
+    /// function can no longer capture.
+    ShadowedCapture,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Location {
+            start,
+            end,
+            filename,
+        } = &self.location;
+        let shadowed = &self.shadowed;
+        let what = match self.kind {
+            WarningKind::ShadowedBinding => "the binding",
+            WarningKind::ShadowedCapture => "the variable it would capture,",
+        };
+
+        write!(
+            f,
+            "`{}` at {filename}:{start}..{end} shadows {what} bound at {}:{}..{}",
+            self.name, shadowed.filename, shadowed.start, shadowed.end
+        )
+    }
+}
+
+/// Reports the warnings about programs evaluated with a
+/// [`State`](crate::interpreter::State) to a callback, before they run.
+pub struct Warnings {
+    callback: Box<dyn FnMut(&Warning) + Send>,
+}
+
+impl Warnings {
+    /// Creates a new instance of [`Warnings`], calling `callback` with every
+    /// warning.
+    pub fn new(callback: impl FnMut(&Warning) + Send + 'static) -> Self {
+        Self {
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Reports the warnings about `term`.
+    pub fn report(&mut self, term: &Term) {
+        check(term).iter().for_each(&mut self.callback);
+    }
+}
+
+impl Debug for Warnings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Warnings").finish_non_exhaustive()
+    }
+}
+
+/// The warnings about `term`, in the order of the bindings they are about.
+///
+/// Only the bindings of `term` itself are known, not the ones of the
+/// context it is evaluated in, like builtins.
+pub fn check(term: &Term) -> Vec<Warning> {
+    let mut checker = Checker {
+        scopes: vec![Vec::new()],
+        warnings: Vec::new(),
+    };
+    checker.visit_term(term);
+
+    checker.warnings
+}
+
+struct Checker {
+    /// The names bound in each function being checked, innermost last,
+    /// with the outside of any function first.
+    scopes: Vec<Vec<(Symbol, Location)>>,
+    warnings: Vec<Warning>,
+}
+
+impl Checker {
+    /// Binds `var` in the innermost scope, warning when it shadows a name
+    /// already bound, as a parameter when `parameter`.
+    fn bind(&mut self, var: &Var, parameter: bool) {
+        let (innermost, enclosing) = self.scopes.split_last().expect("there is a scope");
+        let bound = |scope: &[(Symbol, Location)]| {
+            scope
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == var.text)
+                .map(|(_, location)| location.clone())
+        };
+
+        let shadowed = match bound(innermost) {
+            Some(location) => Some((WarningKind::ShadowedBinding, location)),
+            None => {
+                enclosing.iter().rev().find_map(|scope| bound(scope)).map(
+                    |location| match parameter {
+                        true => (WarningKind::ShadowedCapture, location),
+                        false => (WarningKind::ShadowedBinding, location),
+                    },
+                )
+            }
+        };
+        if let Some((kind, shadowed)) = shadowed {
+            self.warnings.push(Warning {
+                kind,
+                name: var.text,
+                location: var.location.clone(),
+                shadowed,
+            });
+        }
+
+        let innermost = self.scopes.last_mut().expect("there is a scope");
+        innermost.push((var.text, var.location.clone()));
+    }
+}
+
+impl TermVisitor for Checker {
+    fn visit_function(&mut self, function: &Function) {
+        self.scopes.push(Vec::new());
+        for parameter in function.parameters.iter() {
+            self.bind(parameter, true);
+        }
+        self.visit_term(&function.value);
+        self.scopes.pop();
+    }
+
+    fn visit_let(&mut self, let_: &Let) {
+        // The name is bound in its own value, so functions can refer to
+        // themselves.
+        self.bind(&let_.name, false);
+        self.visit_term(&let_.value);
+        self.visit_term(&let_.next);
+        self.scopes.last_mut().expect("there is a scope").pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{check, WarningKind, Warnings};
+    use crate::{
+        ast::Term,
+        interpreter::{eval, Capture, Context, State},
+    };
+
+    /// Parses the JSON of a term, where `LOC(n)` is the location of the
+    /// character at `n`.
+    fn term(json: &str) -> Term {
+        let mut expanded = String::new();
+        let mut rest = json;
+        while let Some((before, after)) = rest.split_once("LOC(") {
+            let (start, after) = after.split_once(')').unwrap();
+            let start = start.parse::<usize>().unwrap();
+            expanded += before;
+            expanded += &format!(
+                r#"{{ "filename": "main.rinha", "start": {start}, "end": {} }}"#,
+                start + 1
+            );
+            rest = after;
+        }
+        expanded += rest;
+
+        serde_json::from_str(&expanded).unwrap()
+    }
+
+    /// let x = 1; let f = fn (x) => { let y = x; let y = y; y }; f(x)
+    fn shadowing() -> Term {
+        term(
+            r#"{ "kind": "Let", "location": LOC(0),
+                 "name": { "text": "x", "location": LOC(4) },
+                 "value": { "kind": "Int", "value": 1, "location": LOC(8) },
+                 "next": { "kind": "Let", "location": LOC(11),
+                   "name": { "text": "f", "location": LOC(15) },
+                   "value": { "kind": "Function", "location": LOC(19),
+                     "parameters": [{ "text": "x", "location": LOC(23) }],
+                     "value": { "kind": "Let", "location": LOC(31),
+                       "name": { "text": "y", "location": LOC(35) },
+                       "value": { "kind": "Var", "text": "x", "location": LOC(39) },
+                       "next": { "kind": "Let", "location": LOC(42),
+                         "name": { "text": "y", "location": LOC(46) },
+                         "value": { "kind": "Var", "text": "y", "location": LOC(50) },
+                         "next": { "kind": "Var", "text": "y", "location": LOC(53) } } } },
+                   "next": { "kind": "Call", "location": LOC(58),
+                     "callee": { "kind": "Var", "text": "f", "location": LOC(58) },
+                     "arguments": [{ "kind": "Var", "text": "x", "location": LOC(60) }] } } }"#,
+        )
+    }
+
+    #[test]
+    fn shadowed_bindings_are_warned_about() {
+        let warnings = check(&shadowing());
+
+        let found = warnings
+            .iter()
+            .map(|warning| (warning.kind, warning.location.start, warning.shadowed.start))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (WarningKind::ShadowedCapture, 23, 4),
+                (WarningKind::ShadowedBinding, 46, 35)
+            ]
+        );
+        assert_eq!(
+            warnings[1].to_string(),
+            "`y` at main.rinha:46..47 shadows the binding bound at main.rinha:35..36"
+        );
+    }
+
+    #[test]
+    fn warnings_are_reported_before_evaluating() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut state = State::new();
+        state.warnings = Some(Warnings::new({
+            let reported = reported.clone();
+            move |warning| reported.lock().unwrap().push(warning.name.to_string())
+        }));
+
+        let value = eval(
+            shadowing(),
+            &mut Context::new(),
+            &mut state,
+            &mut Capture::new(),
+        );
+        assert_eq!(value.unwrap().to_string(), "1");
+        assert_eq!(*reported.lock().unwrap(), ["x", "y"]);
+    }
+}
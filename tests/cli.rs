@@ -0,0 +1,99 @@
+use std::process::{Command, Output};
+
+/// Runs the CLI on `source`, written to a file of its own named after
+/// `name`, with `arguments`.
+fn run(name: &str, source: &str, arguments: &[&str]) -> Output {
+    let path = std::env::temp_dir().join(format!("lipsum-{name}-{}.rinha", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lipsum"))
+        .arg("--file")
+        .arg(&path)
+        .args(arguments)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    output
+}
+
+#[test]
+fn runtime_errors_are_rendered_under_their_source() {
+    let output = run("error", "let x = print(1);\nx + true", &[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert!(
+        stderr.starts_with("error[E0004]: invalid addition\n"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("2 | x + true\n"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn limits_stop_the_program_cleanly() {
+    let source = "let loop = fn (n) => { 1 + loop(n + 1) }; loop(0)";
+
+    for arguments in [&["--fuel", "100"][..], &["--max-call-depth", "10"][..]] {
+        let output = run("limit", source, arguments);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr.starts_with("error[E"), "{stderr}");
+        assert!(stderr.contains("1 | let loop"), "{stderr}");
+        assert!(!stderr.contains("panicked"), "{stderr}");
+    }
+}
+
+#[test]
+fn printed_output_is_flushed_with_normalized_line_endings() {
+    let source = "let _ = print(\"a\\r\\nb\"); print(1)";
+
+    for arguments in [&[][..], &["--unbuffered"][..]] {
+        let output = run("print", source, arguments);
+
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n1\n");
+    }
+}
+
+#[test]
+fn backends_are_compared_on_the_same_programs() {
+    let fib = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/fib.rinha");
+    let output = Command::new(env!("CARGO_BIN_EXE_lipsum"))
+        .args([
+            "bench",
+            fib,
+            "--runs",
+            "1",
+            "--backends",
+            "tree,vm,register",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let backends = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().nth(1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(backends, ["tree", "vm", "register"], "{stdout}");
+}
+
+#[test]
+fn programs_run_on_the_register_machine() {
+    let output = run(
+        "register",
+        "let x = print(1);\nx + true",
+        &["--backend", "register"],
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert!(stderr.contains("2 | x + true\n"), "{stderr}");
+}